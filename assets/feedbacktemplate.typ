@@ -0,0 +1,42 @@
+//#let title = "Final Exam Maths101"
+//#let students = ((id: "12345", name: "", version: "1", score: "17 / 20", percentage: "85.0%", grade: "B", rows: (("Q1", "A", "A", "1"),), image: "img-0.png"),)
+
+#set page("a4", margin: (x: 1.5cm, y: 1.5cm))
+#set text(size: 10pt)
+
+#for (i, student) in students.enumerate() {
+  if i > 0 {
+    pagebreak()
+  }
+
+  align(center)[#text(size: 16pt)[*#title*]]
+
+  table(
+    columns: (auto, 1fr),
+    stroke: none,
+    inset: 4pt,
+    [*Student ID:*], student.id,
+    ..if student.name != "" { ([*Name:*], student.name) } else { () },
+    [*Version:*], student.version,
+    [*Score:*], student.score + " (" + student.percentage + ")",
+    ..if student.grade != "" { ([*Grade:*], student.grade) } else { () },
+  )
+
+  v(0.5cm)
+
+  // wrong answers are highlighted, questions we could not grade are marked
+  // for the examiner
+  columns(2, table(
+    columns: (auto, 1fr, 1fr, auto),
+    align: (left, center, center, center),
+    fill: (x, y) => if y > 0 {
+      let points = student.rows.at(y - 1).at(3)
+      if points == "0" { rgb("#fbe3e3") } else if points == "" { rgb("#fdf0d5") }
+    },
+    table.header([*Question*], [*Your answer*], [*Correct*], [*Points*]),
+    ..student.rows.flatten(),
+  ))
+
+  pagebreak()
+  align(center, image(student.image, height: 100%))
+}
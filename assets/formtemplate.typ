@@ -3,18 +3,37 @@
 //#let num_answers = 7
 //#let num_versions = 4
 //#let title=[Final Exam Maths101]
+//#let num_name_cols = 12
+//#let name_digits = false
+//#let num_numeric_qs = 3
+//#let numeric_cols = 4
+//#let num_teacher_qs = 2
+//#let teacher_max_points = 10
+//#let option_labels = range(num_qs).map(i => if i < 10 { ("T", "F") } else { () })
 
 #set page("a4", margin: (x: 1.5cm, top: 0.5cm, bottom: 0.7cm))
 
+// A-Z, then AA, AB, ... like spreadsheet columns
 #let index_to_letter(i) = {
-  let letters = ("A", "B", "C", "D", "E", "F", "G", "H", "I", "J")
+  let letters = "ABCDEFGHIJKLMNOPQRSTUVWXYZ".clusters()
   if i < letters.len() {
     letters.at(i)
   } else {
-    "?"
+    index_to_letter(calc.div-euclid(i, 26) - 1) + letters.at(calc.rem(i, 26))
   }
 }
 
+// the labels of the options of question q_num, letters unless given otherwise
+#let labels_for(q_num) = {
+  let labels = option_labels.at(q_num - 1, default: ())
+  if labels.len() > 0 {
+    labels
+  } else {
+    range(num_answers).map(index_to_letter)
+  }
+}
+#let max_options = range(1, num_qs + 1).fold(1, (m, i) => calc.max(m, labels_for(i).len()))
+
 #let bubble(id: none, body) = {
   [#metadata((type: "bubble", id: id)) #circle(
     inset: 1pt,
@@ -29,14 +48,26 @@
   bubble(id: "version-" + version)[#version]
 }
 
-#let mcq_bubble(q_num, option) = {
-  bubble(id: "mcq-" + str(q_num) + "-" + option)[#option]
+#let mcq_bubble(q_num, index, label) = {
+  bubble(id: "mcq-" + str(q_num) + "-" + str(index))[#label]
 }
 
 #let id_bubble(col, digit) = {
   bubble(id: "id-" + str(col) + "-" + str(digit))[#digit]
 }
 
+#let name_bubble(col, symbol) = {
+  bubble(id: "name-" + str(col) + "-" + symbol)[#symbol]
+}
+
+#let numeric_bubble(q_num, col, symbol, label) = {
+  bubble(id: "num-" + str(q_num) + "-" + str(col) + "-" + symbol)[#label]
+}
+
+#let teacher_bubble(q_num, points) = {
+  bubble(id: "teacher-" + str(q_num) + "-" + str(points))[#points]
+}
+
 #let annulus = [#circle(
   fill: black,
   inset: 12%,
@@ -44,36 +75,34 @@
 )]
 
 
-#let mcq_rows_1 = for i in range(1, calc.min(26, num_qs + 1)) {
-  let row_items = ([#i.],)
-  for j in range(num_answers) {
-    row_items.push(mcq_bubble(i, index_to_letter(j)))
+// questions with fewer options are padded, so that all rows of a table have
+// the same number of cells
+#let mcq_row(q_num) = {
+  let labels = labels_for(q_num)
+  let row_items = ([#q_num.],)
+  for (j, label) in labels.enumerate() {
+    row_items.push(mcq_bubble(q_num, j, label))
+  }
+  for j in range(labels.len(), max_options) {
+    row_items.push([ ])
   }
   row_items
 }
 
+#let mcq_rows_1 = for i in range(1, calc.min(26, num_qs + 1)) {
+  mcq_row(i)
+}
+
 #let mcq_rows_2 = for i in range(26, calc.min(51, num_qs + 1)) {
-  let row_items = ([#i.],)
-  for j in range(num_answers) {
-    row_items.push(mcq_bubble(i, index_to_letter(j)))
-  }
-  row_items
+  mcq_row(i)
 }
 
 #let mcq_rows_3 = for i in range(51, calc.min(76, num_qs + 1)) {
-  let row_items = ([#i.],)
-  for j in range(num_answers) {
-    row_items.push(mcq_bubble(i, index_to_letter(j)))
-  }
-  row_items
+  mcq_row(i)
 }
 
 #let mcq_rows_4 = for i in range(76, calc.min(101, num_qs + 1)) {
-  let row_items = ([#i.],)
-  for j in range(num_answers) {
-    row_items.push(mcq_bubble(i, index_to_letter(j)))
-  }
-  row_items
+  mcq_row(i)
 }
 
 // Generate version bubbles
@@ -102,6 +131,167 @@
   // Flatten the rows into a single sequence
   all_rows.flatten()
 }
+// Generate the name grid: one column per letter with the symbols A-Z (and
+// optionally 0-9) below
+#let name_symbols = {
+  let symbols = "ABCDEFGHIJKLMNOPQRSTUVWXYZ".clusters()
+  if name_digits {
+    symbols + "0123456789".clusters()
+  } else {
+    symbols
+  }
+}
+#let name_rows = {
+  let header = ([Name:],)
+  for i in range(num_name_cols) {
+    header.push([ ])
+  }
+
+  let all_rows = (header,)
+  for symbol in name_symbols {
+    let row = ([ ],)
+    for col in range(num_name_cols) {
+      row.push(name_bubble(col, symbol))
+    }
+    all_rows.push(row)
+  }
+
+  all_rows.flatten()
+}
+#let name_grid = table(
+  columns: num_name_cols + 1,
+  inset: 2.5pt,
+  align: (x,y) => if x==0{(right)} else {(horizon)},
+  stroke: (x, y) => if x == 0 {
+      none
+    } else if y == 0 {
+      (left: 1pt, top: 1pt, bottom: 1pt, right: if x == num_name_cols { 1pt } else { none })
+    } else {
+      (
+        left: 1pt,
+        bottom: if y == name_symbols.len() { 1pt } else { none },
+        right: if x == num_name_cols { 1pt } else { none },
+      )
+    },
+  ..name_rows
+)
+
+// Generate a numeric question: the columns are filled out like the ID, the
+// first column has a minus sign and all others a decimal point
+#let numeric_table(q_num) = {
+  let header = ([N#q_num:],)
+  let minus_row = ([ ],)
+  let dot_row = ([ ],)
+  for col in range(numeric_cols) {
+    header.push([ ])
+    minus_row.push(if col == 0 { numeric_bubble(q_num, col, "minus", [−]) } else { [ ] })
+    dot_row.push(if col > 0 { numeric_bubble(q_num, col, "dot", [.]) } else { [ ] })
+  }
+
+  let all_rows = (header, minus_row, dot_row)
+  for digit in range(10) {
+    let row = ([ ],)
+    for col in range(numeric_cols) {
+      row.push(numeric_bubble(q_num, col, str(digit), [#digit]))
+    }
+    all_rows.push(row)
+  }
+
+  table(
+    columns: numeric_cols + 1,
+    inset: 2.5pt,
+    align: (x,y) => if x==0{(right)} else {(horizon)},
+    stroke: (x, y) => if x == 0 {
+        none
+      } else if y == 0 {
+        (left: 1pt, top: 1pt, bottom: 1pt, right: if x == numeric_cols { 1pt } else { none })
+      } else {
+        (
+          left: 1pt,
+          bottom: if y == 12 { 1pt } else { none },
+          right: if x == numeric_cols { 1pt } else { none },
+        )
+      },
+    ..all_rows.flatten()
+  )
+}
+
+#let numeric_grid = if num_numeric_qs > 0 {
+  (
+    [Please write and shade your numeric answers here:],
+    grid(
+      columns: calc.min(num_numeric_qs, 5),
+      column-gutter: 0.5cm,
+      row-gutter: 0.3cm,
+      ..range(1, num_numeric_qs + 1).map(numeric_table)
+    ),
+  )
+} else {
+  ()
+}
+
+// The examiner area: one row of point bubbles per hand-scored field, in a
+// dashed box so that students leave it alone
+#let teacher_area = if num_teacher_qs > 0 {
+  let rows = for i in range(1, num_teacher_qs + 1) {
+    let row_items = ([T#i:],)
+    for points in range(teacher_max_points + 1) {
+      row_items.push(teacher_bubble(i, points))
+    }
+    row_items
+  }
+  (
+    block(
+      width: 100%,
+      inset: 6pt,
+      stroke: (paint: black, thickness: 1pt, dash: "dashed"),
+      [
+        *For examiner use only*
+        #table(
+          columns: teacher_max_points + 2,
+          align: right,
+          stroke: none,
+          inset: 4pt,
+          ..rows
+        )
+      ],
+    ),
+  )
+} else {
+  ()
+}
+
+#let id_table = table(
+  columns: num_idqs + 1,
+  align: (x,y) => if x==0{(right)} else {(horizon)},
+  stroke: (x, y) => if x == 0 {
+      none  // No borders for ID column
+    } else if y == 0 {
+      if x == num_idqs {
+         (left: 1pt, top: 1pt, bottom: 1pt, right: 1pt)  // Last column of freetext
+      } else {
+         (left: 1pt, top: 1pt, bottom: 1pt)  // Other freetext columns
+      }
+    } else {
+      if x == num_idqs {
+         (left: 1pt, bottom: if y == 10 { 1pt } else { none }, right: 1pt)  // Last digit column
+      } else {
+         (left: 1pt, bottom: if y == 10 { 1pt } else { none })  // Other digit columns
+      }
+   },
+  ..id_rows
+)
+
+#let version_table = if num_versions > 1 {
+  table(
+    columns: num_versions + 1,
+    align: horizon,
+    stroke: none,
+    inset: 10pt,
+    ..version_items
+  )
+}
+
 #grid(
   columns: (1fr),
   row-gutter: 0.5cm,
@@ -113,60 +303,46 @@
     [*#title*],
     [#annulus],
   ),
-  grid(
-    columns: (1fr, 1fr),
-    [ #table(
-        columns: num_idqs + 1,
-        align: (x,y) => if x==0{(right)} else {(horizon)},
-        stroke: (x, y) => if x == 0 {
-            none  // No borders for ID column
-          } else if y == 0 {
-            if x == num_idqs {
-               (left: 1pt, top: 1pt, bottom: 1pt, right: 1pt)  // Last column of freetext
-            } else {
-               (left: 1pt, top: 1pt, bottom: 1pt)  // Other freetext columns
-            }
-          } else {
-            if x == num_idqs {
-               (left: 1pt, bottom: if y == 10 { 1pt } else { none }, right: 1pt)  // Last digit column
-            } else {
-               (left: 1pt, bottom: if y == 10 { 1pt } else { none })  // Other digit columns
-            }
-         },
-       ..id_rows
-      ) ],
-    [
-      #table(
-        columns: 2,
-        align: horizon,
-        inset: 10pt,
-        stroke: none,
-        [Name:], table.cell(align: bottom, line(length: 4cm)),
-        [Section:], table.cell(align: bottom, line(length: 4cm)) 
-      )
-
-      #if num_versions > 1 {
-        table(
-          columns: num_versions + 1,
+  if num_name_cols > 0 {
+    // the name grid is tall, so it goes next to the ID grid and the version
+    // moves below the ID grid
+    grid(
+      columns: (1fr, 1fr),
+      [
+        #id_table
+        #version_table
+        #text(size: 8pt)[If you make a mistake, do *NOT* mark it with X or use an eraser. Instead, use blanco or ask for a new bubble sheet.]
+      ],
+      name_grid,
+    )
+  } else {
+    grid(
+      columns: (1fr, 1fr),
+      id_table,
+      [
+        #table(
+          columns: 2,
           align: horizon,
-          stroke: none,
           inset: 10pt,
-          ..version_items
+          stroke: none,
+          [Name:], table.cell(align: bottom, line(length: 4cm)),
+          [Section:], table.cell(align: bottom, line(length: 4cm)) 
         )
-      }
 
-      
-      #v(1cm)
-      If you make a mistake, do *NOT* mark it with X or use an eraser. Instead, use blanco or ask for a new bubble sheet.
-      #v(1cm)
+        #version_table
+
+        #v(1cm)
+        If you make a mistake, do *NOT* mark it with X or use an eraser. Instead, use blanco or ask for a new bubble sheet.
+        #v(1cm)
       ],
-  ),
+    )
+  },
   [Please shade your answers to the questions here:],
   grid(
     columns: (1fr, 1fr, 1fr, 1fr),
     align: (left, center, center, right),
     [#table(
-       columns: num_answers + 1,
+       columns: max_options + 1,
        rows: (auto, auto, auto, auto, auto),
        align: right,
        stroke: none,
@@ -174,7 +350,7 @@
        ..mcq_rows_1
     )],
     [#table(
-       columns: num_answers + 1,
+       columns: max_options + 1,
        rows: (auto, auto, auto, auto, auto),
        align: right,
        stroke: none,
@@ -182,7 +358,7 @@
        ..mcq_rows_2
     )],
     [#table(
-       columns: num_answers + 1,
+       columns: max_options + 1,
        rows: (auto, auto, auto, auto, auto),
        align: right,
        stroke: none,
@@ -190,7 +366,7 @@
        ..mcq_rows_3
     )],
     [#table(
-       columns: num_answers + 1,
+       columns: max_options + 1,
        rows: (auto, auto, auto, auto, auto),
        align: right,
        stroke: none,
@@ -198,6 +374,8 @@
        ..mcq_rows_4
     )]
   ),
+  ..numeric_grid,
+  ..teacher_area,
   align(right)[#v(1fr) #annulus],
 )
 
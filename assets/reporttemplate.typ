@@ -0,0 +1,41 @@
+//#let title = "Final Exam Maths101"
+//#let summary = (scans: "40", issues: "1", noid: "1", noversion: "0")
+//#let flagged = (("page3", "", "17"),)
+//#let pages = ((heading: "NOID – page3", image: "page-0.jpg"),)
+// Either the cover page, with `pages` empty, or a part of the sheets, with
+// `summary` set to none. The parts are typeset a few sheets at a time and
+// merged, with one bookmark per sheet.
+
+#set page("a4", margin: (x: 1cm, y: 1cm))
+#set text(size: 10pt)
+#show heading: set text(size: 11pt)
+
+#if summary != none {
+  align(center)[#text(size: 16pt)[*#title*]]
+
+  table(
+    columns: (auto, auto),
+    stroke: none,
+    inset: 4pt,
+    [*Scans:*], summary.scans,
+    [*To grade by hand:*], summary.issues,
+    [*Without student ID:*], summary.noid,
+    [*Without version:*], summary.noversion,
+  )
+
+  if flagged.len() > 0 {
+    v(0.5cm)
+    heading(level: 2, outlined: false, bookmarked: false)[Sheets to grade by hand]
+    table(
+      columns: (auto, auto, auto),
+      table.header([*Scan*], [*Student ID*], [*Score*]),
+      ..flagged.flatten(),
+    )
+  }
+}
+
+#for p in pages {
+  pagebreak(weak: true)
+  heading(level: 1, outlined: false, bookmarked: false, p.heading)
+  block(height: 1fr, width: 100%, align(center, image(p.image, width: 100%, height: 100%, fit: "contain")))
+}
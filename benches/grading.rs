@@ -0,0 +1,106 @@
+//! Timings of the steps of grading a page, over the sample scans in
+//! `tests/assets`. Run with `cargo bench`; a name as argument only runs the
+//! benchmarks containing it, e.g. `cargo bench -- align`.
+
+use autograder::image_helpers::binary_image_from_file;
+use autograder::point::Point;
+use autograder::scan::Scan;
+use autograder::template::{key_from_json, Template};
+use autograder::template_scan::TemplateScan;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SCANS: [&str; 4] = [
+    "tests/assets/scanner-jpg-0001.jpg",
+    "tests/assets/scanner-jpg-0002.jpg",
+    "tests/assets/scanner-jpg-0003.jpg",
+    "tests/assets/scanner-jpg-0004.jpg",
+];
+
+// runs `f` for at least a second and prints the median time per call, which
+// other load on the machine changes less than the mean
+fn bench(filter: &Option<String>, name: &str, mut f: impl FnMut()) {
+    if filter
+        .as_ref()
+        .is_some_and(|filter| !name.contains(filter.as_str()))
+    {
+        return;
+    }
+    f();
+    let start = Instant::now();
+    let mut times = Vec::new();
+    while start.elapsed() < Duration::from_secs(1) || times.len() < 10 {
+        let call = Instant::now();
+        f();
+        times.push(call.elapsed());
+    }
+    times.sort();
+    println!(
+        "{:<24} {:>10.3} ms",
+        name,
+        times[times.len() / 2].as_secs_f64() * 1000.0
+    );
+}
+
+fn main() {
+    // cargo passes `--bench` along with the filter
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+
+    let template =
+        Template::from_json(std::fs::File::open("tests/assets/template.json").unwrap()).unwrap();
+    let key = key_from_json(std::fs::File::open("tests/assets/key.json").unwrap()).unwrap();
+    let images: Vec<_> = SCANS
+        .iter()
+        .map(|path| binary_image_from_file(&path.to_string()).unwrap())
+        .collect();
+    let scans: Vec<TemplateScan> = images
+        .iter()
+        .map(|image| TemplateScan::new(&template, Scan::new(image.clone())))
+        .collect();
+
+    bench(&filter, "blackness/boxes", || {
+        for scan in &scans {
+            for question in &template.questions {
+                black_box(question.blacknesses_rounded(scan));
+            }
+        }
+    });
+    bench(&filter, "blackness/large", || {
+        for scan in &scans {
+            let (w, h) = scan.scan.image().dimensions();
+            let centre = Point { x: w / 2, y: h / 2 };
+            black_box(scan.scan.blackness(
+                Point {
+                    x: centre.x - 200,
+                    y: centre.y - 200,
+                },
+                Point {
+                    x: centre.x + 200,
+                    y: centre.y + 200,
+                },
+            ));
+        }
+    });
+    bench(&filter, "align", || {
+        for image in &images {
+            black_box(TemplateScan::new(&template, Scan::new(image.clone())));
+        }
+    });
+    bench(&filter, "answers", || {
+        for scan in &scans {
+            black_box(scan.answers());
+        }
+    });
+    bench(&filter, "report", || {
+        for (i, scan) in scans.iter().enumerate() {
+            black_box(scan.generate_image_report(&key, &format!("page{}", i + 1)));
+        }
+    });
+    // everything a page goes through once it is a binary image
+    bench(&filter, "page", || {
+        for (i, image) in images.iter().enumerate() {
+            let scan = TemplateScan::new(&template, Scan::new(image.clone()));
+            black_box(scan.generate_image_report(&key, &format!("page{}", i + 1)));
+        }
+    });
+}
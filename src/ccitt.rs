@@ -0,0 +1,587 @@
+// A decoder for CCITT fax images (ITU-T T.4 and T.6), which scanners use for
+// bilevel images in TIFF files and PDF streams (CCITTFaxDecode, and the MMR
+// regions of JBIG2).
+use crate::error::Error;
+use image::{GrayImage, Luma};
+
+/// How the rows of a fax image are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaxCoding {
+    /// Modified Huffman (TIFF compression 2): one-dimensional rows without
+    /// EOL codes, every row starts on a byte boundary.
+    ModifiedHuffman,
+    /// Group 3 (T.4): rows are preceded by EOL codes, and with
+    /// `two_dimensional` a tag bit after the EOL says how the row is coded.
+    Group3 { two_dimensional: bool },
+    /// Group 4 (T.6): every row is coded relative to the one above.
+    Group4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaxOptions {
+    pub coding: FaxCoding,
+    pub width: u32,
+    pub height: u32,
+    /// every row starts on a byte boundary (`EncodedByteAlign` in PDF)
+    pub byte_align: bool,
+}
+
+// the terminating codes of the runs 0 to 63
+const WHITE_TERMINATING: &str = "\
+    00110101 000111 0111 1000 1011 1100 1110 1111 10011 10100 00111 01000 001000 000011 \
+    110100 110101 101010 101011 0100111 0001100 0001000 0010111 0000011 0000100 0101000 \
+    0101011 0010011 0100100 0011000 00000010 00000011 00011010 00011011 00010010 00010011 \
+    00010100 00010101 00010110 00010111 00101000 00101001 00101010 00101011 00101100 \
+    00101101 00000100 00000101 00001010 00001011 01010010 01010011 01010100 01010101 \
+    00100100 00100101 01011000 01011001 01011010 01011011 01001010 01001011 00110010 \
+    00110011 00110100";
+
+const BLACK_TERMINATING: &str = "\
+    0000110111 010 11 10 011 0011 0010 00011 000101 000100 0000100 0000101 0000111 \
+    00000100 00000111 000011000 0000010111 0000011000 0000001000 00001100111 00001101000 \
+    00001101100 00000110111 00000101000 00000010111 00000011000 000011001010 000011001011 \
+    000011001100 000011001101 000001101000 000001101001 000001101010 000001101011 \
+    000011010010 000011010011 000011010100 000011010101 000011010110 000011010111 \
+    000001101100 000001101101 000011011010 000011011011 000001010100 000001010101 \
+    000001010110 000001010111 000001100100 000001100101 000001010010 000001010011 \
+    000000100100 000000110111 000000111000 000000100111 000000101000 000001011000 \
+    000001011001 000000101011 000000101100 000001011010 000001100110 000001100111";
+
+// the make-up codes of the runs 64, 128, ..., 1728
+const WHITE_MAKEUP: &str = "\
+    11011 10010 010111 0110111 00110110 00110111 01100100 01100101 01101000 01100111 \
+    011001100 011001101 011010010 011010011 011010100 011010101 011010110 011010111 \
+    011011000 011011001 011011010 011011011 010011000 010011001 010011010 011000 \
+    010011011";
+
+const BLACK_MAKEUP: &str = "\
+    0000001111 000011001000 000011001001 000001011011 000000110011 000000110100 \
+    000000110101 0000001101100 0000001101101 0000001001010 0000001001011 0000001001100 \
+    0000001001101 0000001110010 0000001110011 0000001110100 0000001110101 0000001110110 \
+    0000001110111 0000001010010 0000001010011 0000001010100 0000001010101 0000001011010 \
+    0000001011011 0000001100100 0000001100101";
+
+// the make-up codes of the runs 1792, 1856, ..., 2560 for both colours
+const EXTENDED_MAKEUP: &str = "\
+    00000001000 00000001100 00000001101 000000010010 000000010011 000000010100 \
+    000000010101 000000010110 000000010111 000000011100 000000011101 000000011110 \
+    000000011111";
+
+// no run length code is longer than this
+const LOOKUP_BITS: u32 = 13;
+
+// `(run, code length)` for all values of the next `LOOKUP_BITS` bits, a code
+// length of 0 marks an invalid code
+struct RunTable(Vec<(u16, u8)>);
+
+impl RunTable {
+    fn new(terminating: &str, makeup: &str) -> Self {
+        let codes = terminating
+            .split_whitespace()
+            .enumerate()
+            .chain(
+                makeup
+                    .split_whitespace()
+                    .enumerate()
+                    .map(|(i, code)| (64 * (i + 1), code)),
+            )
+            .chain(
+                EXTENDED_MAKEUP
+                    .split_whitespace()
+                    .enumerate()
+                    .map(|(i, code)| (1792 + 64 * i, code)),
+            );
+
+        let mut lookup = vec![(0, 0); 1 << LOOKUP_BITS];
+        for (run, code) in codes {
+            let len = code.len() as u32;
+            let value = u32::from_str_radix(code, 2).unwrap();
+            let start = (value << (LOOKUP_BITS - len)) as usize;
+            for entry in &mut lookup[start..start + (1 << (LOOKUP_BITS - len))] {
+                *entry = (run as u16, len as u8);
+            }
+        }
+        RunTable(lookup)
+    }
+
+    // a complete run, i.e. any number of make-up codes and a terminating code
+    fn read_run(&self, reader: &mut BitReader) -> Result<u32, String> {
+        let mut total = 0;
+        loop {
+            let (run, len) = self.0[reader.peek(LOOKUP_BITS) as usize];
+            if len == 0 {
+                return Err(format!("invalid run length code at bit {}", reader.pos));
+            }
+            reader.consume(len as u32);
+            total += run as u32;
+            if run < 64 {
+                return Ok(total);
+            }
+        }
+    }
+}
+
+struct Tables {
+    white: RunTable,
+    black: RunTable,
+}
+
+impl Tables {
+    fn new() -> Self {
+        Tables {
+            white: RunTable::new(WHITE_TERMINATING, WHITE_MAKEUP),
+            black: RunTable::new(BLACK_TERMINATING, BLACK_MAKEUP),
+        }
+    }
+
+    fn run(&self, white: bool) -> &RunTable {
+        if white {
+            &self.white
+        } else {
+            &self.black
+        }
+    }
+}
+
+// most significant bit first, reading past the end gives zeros
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    // the next `n` bits for 1 <= n <= 24
+    fn peek(&self, n: u32) -> u32 {
+        let byte = self.pos / 8;
+        let mut window = 0u32;
+        for i in 0..4 {
+            window = window << 8 | *self.data.get(byte + i).unwrap_or(&0) as u32;
+        }
+        (window << (self.pos % 8)) >> (32 - n)
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.pos += n as usize;
+    }
+
+    fn read_bit(&mut self) -> u32 {
+        let bit = self.peek(1);
+        self.consume(1);
+        bit
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len() * 8
+    }
+
+    // Skips fill bits and an EOL code (eleven zeros and a one) if there is
+    // one, otherwise nothing is consumed.
+    fn skip_eol(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek(1) == 0 && !self.at_end() {
+            self.consume(1);
+        }
+        if self.pos - start >= 11 && !self.at_end() {
+            self.consume(1);
+            true
+        } else {
+            self.pos = start;
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Pass,
+    Horizontal,
+    Vertical(i64),
+    EndOfLine,
+}
+
+fn read_mode(reader: &mut BitReader) -> Result<Mode, String> {
+    let bits = reader.peek(7);
+    let (mode, len) = if bits >> 6 == 1 {
+        (Mode::Vertical(0), 1)
+    } else if bits >> 4 == 0b011 {
+        (Mode::Vertical(1), 3)
+    } else if bits >> 4 == 0b010 {
+        (Mode::Vertical(-1), 3)
+    } else if bits >> 4 == 0b001 {
+        (Mode::Horizontal, 3)
+    } else if bits >> 3 == 0b0001 {
+        (Mode::Pass, 4)
+    } else if bits >> 1 == 0b000011 {
+        (Mode::Vertical(2), 6)
+    } else if bits >> 1 == 0b000010 {
+        (Mode::Vertical(-2), 6)
+    } else if bits == 0b0000011 {
+        (Mode::Vertical(3), 7)
+    } else if bits == 0b0000010 {
+        (Mode::Vertical(-3), 7)
+    } else if reader.peek(12) == 1 {
+        (Mode::EndOfLine, 12)
+    } else {
+        return Err(format!("unsupported coding mode at bit {}", reader.pos));
+    };
+    reader.consume(len);
+    Ok(mode)
+}
+
+// A row is given by the positions where the colour changes, starting with
+// white. All positions are at most the width.
+fn decode_1d(reader: &mut BitReader, tables: &Tables, width: u32) -> Result<Vec<u32>, String> {
+    let mut changes = Vec::new();
+    let mut pos = 0;
+    let mut white = true;
+    while pos < width {
+        pos = (pos + tables.run(white).read_run(reader)?).min(width);
+        changes.push(pos);
+        white = !white;
+    }
+    Ok(changes)
+}
+
+fn decode_2d(
+    reader: &mut BitReader,
+    tables: &Tables,
+    reference: &[u32],
+    width: u32,
+) -> Result<Vec<u32>, String> {
+    let mut changes = Vec::new();
+    // a0 starts on an imaginary white pixel in front of the row
+    let mut a0: i64 = -1;
+    let mut white = true;
+    // all changes in front of this index are at or before a0
+    let mut first = 0;
+
+    while a0 < width as i64 {
+        while first < reference.len() && reference[first] as i64 <= a0 {
+            first += 1;
+        }
+        // b1 is the first change on the reference row after a0 to the
+        // opposite colour of a0, i.e. to black if a0 is white; b2 the next one
+        let b1_index = (first..reference.len())
+            .find(|i| (i % 2 == 0) == white)
+            .unwrap_or(reference.len());
+        let b1 = reference.get(b1_index).copied().unwrap_or(width);
+        let b2 = reference.get(b1_index + 1).copied().unwrap_or(width);
+
+        match read_mode(reader)? {
+            Mode::Pass => a0 = b2 as i64,
+            Mode::Horizontal => {
+                let start = a0.max(0) as u32;
+                let a1 = (start + tables.run(white).read_run(reader)?).min(width);
+                let a2 = (a1 + tables.run(!white).read_run(reader)?).min(width);
+                changes.push(a1);
+                changes.push(a2);
+                a0 = a2 as i64;
+            }
+            Mode::Vertical(delta) => {
+                let a1 = b1 as i64 + delta;
+                if a1 < a0.max(0) || a1 > width as i64 {
+                    return Err(format!("invalid vertical mode at bit {}", reader.pos));
+                }
+                changes.push(a1 as u32);
+                a0 = a1;
+                white = !white;
+            }
+            Mode::EndOfLine => return Err(format!("unexpected EOL at bit {}", reader.pos)),
+        }
+    }
+    Ok(changes)
+}
+
+// `None` at the end of the data
+fn decode_row(
+    reader: &mut BitReader,
+    tables: &Tables,
+    reference: &[u32],
+    options: &FaxOptions,
+) -> Result<Option<Vec<u32>>, String> {
+    match options.coding {
+        FaxCoding::ModifiedHuffman => {
+            reader.align();
+            if reader.at_end() {
+                return Ok(None);
+            }
+            decode_1d(reader, tables, options.width).map(Some)
+        }
+        FaxCoding::Group3 { two_dimensional } => {
+            let eol = reader.skip_eol();
+            if !eol && options.byte_align {
+                reader.align();
+            }
+            // a second EOL right away is the end of the page
+            let rtc = if two_dimensional {
+                reader.peek(13) == 0b1_0000_0000_0001
+            } else {
+                reader.peek(12) == 1
+            };
+            if reader.at_end() || (eol && rtc) {
+                return Ok(None);
+            }
+            if !two_dimensional || reader.read_bit() == 1 {
+                decode_1d(reader, tables, options.width).map(Some)
+            } else {
+                decode_2d(reader, tables, reference, options.width).map(Some)
+            }
+        }
+        FaxCoding::Group4 => {
+            if options.byte_align {
+                reader.align();
+            }
+            // the end of the data or EOFB
+            if reader.at_end() || reader.peek(12) == 1 {
+                return Ok(None);
+            }
+            decode_2d(reader, tables, reference, options.width).map(Some)
+        }
+    }
+}
+
+/// Decodes a fax image into black (0) and white (255) pixels. Rows missing at
+/// the end are white, as some encoders leave them out.
+pub fn decode_fax(data: &[u8], options: &FaxOptions) -> Result<GrayImage, Error> {
+    decode_rows(data, options).map_err(Error::Decode)
+}
+
+pub(crate) fn decode_rows(data: &[u8], options: &FaxOptions) -> Result<GrayImage, String> {
+    if options.width == 0 || options.height == 0 {
+        return Err(format!(
+            "invalid fax image size {}x{}",
+            options.width, options.height
+        ));
+    }
+    let tables = Tables::new();
+    let mut reader = BitReader::new(data);
+    let mut image = GrayImage::from_pixel(options.width, options.height, Luma([255]));
+    let mut reference = Vec::new();
+
+    for y in 0..options.height {
+        let row = match decode_row(&mut reader, &tables, &reference, options) {
+            // the zeros after the end of the data can look like codes
+            Ok(Some(_)) if reader.pos > data.len() * 8 => {
+                Err(format!("row {} runs past the end of the data", y))
+            }
+            row => row,
+        };
+        let row = match row {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            // a damaged scan is still better than no scan
+            Err(e) if y > 0 => {
+                log::warn!("fax image is cut off after {} rows: {}", y, e);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut x = 0;
+        let mut white = true;
+        for &change in row.iter().chain(std::iter::once(&options.width)) {
+            if !white {
+                for pixel_x in x..change {
+                    image.put_pixel(pixel_x, y, Luma([0]));
+                }
+            }
+            x = change;
+            white = !white;
+        }
+        reference = row;
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a simple pattern with runs of all kinds of lengths
+    fn pattern(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let black = (x * x / 7 + y * 3) % 11 < 4 || (y % 17 == 3 && x > 100);
+            Luma([if black { 0 } else { 255 }])
+        })
+    }
+
+    // the codes for a run as a bit string
+    fn run_code(run: u32, white: bool) -> String {
+        let (terminating, makeup) = if white {
+            (WHITE_TERMINATING, WHITE_MAKEUP)
+        } else {
+            (BLACK_TERMINATING, BLACK_MAKEUP)
+        };
+        let terminating: Vec<&str> = terminating.split_whitespace().collect();
+        let makeup: Vec<&str> = makeup.split_whitespace().collect();
+        let mut code = String::new();
+        let mut run = run;
+        while run >= 64 {
+            let chunk = (run / 64).min(27);
+            code += makeup[chunk as usize - 1];
+            run -= chunk * 64;
+        }
+        code + terminating[run as usize]
+    }
+
+    fn encode_1d(image: &GrayImage, eol: bool) -> Vec<u8> {
+        let mut bits = String::new();
+        for y in 0..image.height() {
+            if eol {
+                bits += "000000000001";
+            } else {
+                bits += &"0".repeat((8 - bits.len() % 8) % 8);
+            }
+            let mut white = true;
+            let mut x = 0;
+            while x < image.width() {
+                let run = (x..image.width())
+                    .take_while(|&x| (image.get_pixel(x, y)[0] == 255) == white)
+                    .count() as u32;
+                bits += &run_code(run, white);
+                x += run;
+                white = !white;
+            }
+        }
+        bits += &"0".repeat((8 - bits.len() % 8) % 8);
+        bits.as_bytes()
+            .chunks(8)
+            .map(|byte| u8::from_str_radix(std::str::from_utf8(byte).unwrap(), 2).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn one_dimensional() {
+        let image = pattern(1800, 40);
+        for (coding, eol) in [
+            (FaxCoding::ModifiedHuffman, false),
+            (
+                FaxCoding::Group3 {
+                    two_dimensional: false,
+                },
+                true,
+            ),
+        ] {
+            let options = FaxOptions {
+                coding,
+                width: 1800,
+                height: 40,
+                byte_align: false,
+            };
+            let decoded = decode_fax(&encode_1d(&image, eol), &options).unwrap();
+            assert_eq!(decoded, image);
+        }
+    }
+
+    #[test]
+    fn group4() {
+        let image = pattern(300, 50);
+        let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+        for y in 0..image.height() {
+            let pels = (0..image.width()).map(|x| match image.get_pixel(x, y)[0] {
+                0 => fax::Color::Black,
+                _ => fax::Color::White,
+            });
+            encoder.encode_line(pels, 300).unwrap();
+        }
+        let data = encoder.finish().unwrap().finish();
+
+        let options = FaxOptions {
+            coding: FaxCoding::Group4,
+            width: 300,
+            height: 50,
+            byte_align: false,
+        };
+        assert_eq!(decode_fax(&data, &options).unwrap(), image);
+
+        // rows after the end of the data stay white
+        let taller = FaxOptions {
+            height: 60,
+            ..options
+        };
+        let decoded = decode_fax(&data, &taller).unwrap();
+        assert!((0..300).all(|x| decoded.get_pixel(x, 55)[0] == 255));
+        assert!(decode_fax(&[0x02, 0x00], &options).is_err());
+    }
+
+    #[test]
+    fn broken_data() {
+        let image = pattern(120, 30);
+        let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+        for y in 0..image.height() {
+            let pels = (0..image.width()).map(|x| match image.get_pixel(x, y)[0] {
+                0 => fax::Color::Black,
+                _ => fax::Color::White,
+            });
+            encoder.encode_line(pels, 120).unwrap();
+        }
+        let fixtures = [
+            (FaxCoding::Group4, encoder.finish().unwrap().finish()),
+            (FaxCoding::ModifiedHuffman, encode_1d(&image, false)),
+            (
+                FaxCoding::Group3 {
+                    two_dimensional: false,
+                },
+                encode_1d(&image, true),
+            ),
+        ];
+
+        // cut off data gives the rows before the cut and white below, or an
+        // error if not even the first row is there
+        for (coding, data) in &fixtures {
+            let options = FaxOptions {
+                coding: *coding,
+                width: 120,
+                height: 30,
+                byte_align: false,
+            };
+            for length in 0..data.len() {
+                let Ok(decoded) = decode_fax(&data[..length], &options) else {
+                    assert!(length < data.len() / 10, "{:?} {}", coding, length);
+                    continue;
+                };
+                let rows = (0..30)
+                    .take_while(|&y| (0..120).all(|x| decoded[(x, y)] == image[(x, y)]))
+                    .count() as u32;
+                assert!(
+                    (rows..30).all(|y| (0..120).all(|x| decoded[(x, y)][0] == 255)),
+                    "{:?} {}",
+                    coding,
+                    length
+                );
+            }
+        }
+
+        // garbage must neither panic nor hang
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut random = move |below: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as usize
+        };
+        for _ in 0..500 {
+            let (coding, data) = &fixtures[random(fixtures.len())];
+            let mut data = data.clone();
+            for _ in 0..1 + random(4) {
+                let position = random(data.len());
+                data[position] = random(256) as u8;
+            }
+            let options = FaxOptions {
+                coding: *coding,
+                width: 120,
+                height: 30,
+                byte_align: random(2) == 1,
+            };
+            let _ = decode_fax(&data, &options);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+
+/// The colours of the annotated report images. Colours are written as
+/// `"#rrggbb"` in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColourScheme {
+    #[serde(with = "hex_colour")]
+    pub correct_foreground: Rgb<u8>,
+    #[serde(with = "hex_colour")]
+    pub correct_background: Rgb<u8>,
+    #[serde(with = "hex_colour")]
+    pub wrong_foreground: Rgb<u8>,
+    #[serde(with = "hex_colour")]
+    pub wrong_background: Rgb<u8>,
+    #[serde(with = "hex_colour")]
+    pub highlight_background: Rgb<u8>,
+    #[serde(with = "hex_colour")]
+    pub highlight_foreground: Rgb<u8>,
+    /// Draw cues that survive black and white printing: a ring around the
+    /// correct answer, a cross over wrong marks, ticks and crosses next to
+    /// numeric questions and hatching over everything that needs attention.
+    #[serde(default)]
+    pub cues: bool,
+}
+
+pub const STD_COLOUR_SCHEME: ColourScheme = ColourScheme {
+    correct_foreground: Rgb([30u8, 220u8, 30u8]),
+    correct_background: Rgb([220u8, 255u8, 220u8]),
+    wrong_foreground: Rgb([255u8, 50u8, 50u8]),
+    wrong_background: Rgb([255u8, 220u8, 220u8]),
+    highlight_background: Rgb([255u8, 215u8, 0u8]),
+    highlight_foreground: Rgb([255u8, 110u8, 0u8]),
+    cues: false,
+};
+
+// blue and vermilion from the Okabe-Ito palette, which can be told apart with
+// all common forms of colour blindness
+pub const COLOURBLIND_COLOUR_SCHEME: ColourScheme = ColourScheme {
+    correct_foreground: Rgb([0u8, 114u8, 178u8]),
+    correct_background: Rgb([204u8, 227u8, 240u8]),
+    wrong_foreground: Rgb([213u8, 94u8, 0u8]),
+    wrong_background: Rgb([247u8, 223u8, 204u8]),
+    highlight_background: Rgb([240u8, 228u8, 66u8]),
+    highlight_foreground: Rgb([204u8, 121u8, 167u8]),
+    cues: true,
+};
+
+// shades of gray that stay apart on a black and white printer
+pub const PRINT_COLOUR_SCHEME: ColourScheme = ColourScheme {
+    correct_foreground: Rgb([0u8, 0u8, 0u8]),
+    correct_background: Rgb([235u8, 235u8, 235u8]),
+    wrong_foreground: Rgb([90u8, 90u8, 90u8]),
+    wrong_background: Rgb([255u8, 255u8, 255u8]),
+    highlight_background: Rgb([200u8, 200u8, 200u8]),
+    highlight_foreground: Rgb([40u8, 40u8, 40u8]),
+    cues: true,
+};
+
+impl Default for ColourScheme {
+    fn default() -> Self {
+        STD_COLOUR_SCHEME
+    }
+}
+
+impl ColourScheme {
+    pub const PRESETS: [&'static str; 3] = ["standard", "colourblind", "print"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(STD_COLOUR_SCHEME),
+            "colourblind" => Some(COLOURBLIND_COLOUR_SCHEME),
+            "print" => Some(PRINT_COLOUR_SCHEME),
+            _ => None,
+        }
+    }
+}
+
+/// Parses colours like `#1edc1e`.
+pub fn parse_colour(input: &str) -> Option<Rgb<u8>> {
+    let hex = input.trim().strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(1)?, channel(2)?]))
+}
+
+mod hex_colour {
+    use image::Rgb;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(colour: &Rgb<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!(
+            "#{:02x}{:02x}{:02x}",
+            colour[0], colour[1], colour[2]
+        ))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgb<u8>, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        super::parse_colour(&input).ok_or(D::Error::custom(format!("invalid colour '{}'", input)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let json = serde_json::to_string(&STD_COLOUR_SCHEME).unwrap();
+        assert!(json.contains("\"correct_foreground\":\"#1edc1e\""));
+        let scheme: ColourScheme = serde_json::from_str(&json).unwrap();
+        assert_eq!(scheme, STD_COLOUR_SCHEME);
+
+        let mut custom = json.replace("#1edc1e", "#0000FF");
+        custom = custom.replace(",\"cues\":false", "");
+        let scheme: ColourScheme = serde_json::from_str(&custom).unwrap();
+        assert_eq!(scheme.correct_foreground, Rgb([0, 0, 255]));
+        assert!(!scheme.cues);
+
+        assert!(serde_json::from_str::<ColourScheme>(&json.replace("#1edc1e", "green")).is_err());
+        assert_eq!(parse_colour("#12345"), None);
+        assert_eq!(ColourScheme::preset("print"), Some(PRINT_COLOUR_SCHEME));
+        assert!(ColourScheme::PRESETS
+            .iter()
+            .all(|name| ColourScheme::preset(name).is_some()));
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+/// Everything that can go wrong in this crate. A page that cannot be decoded
+/// or aligned does not end a batch: it becomes an issue of its report, see
+/// `ImageReport::unreadable`.
+#[derive(Debug)]
+pub enum Error {
+    /// An image, page or container that cannot be decoded.
+    Decode(String),
+    /// The three circles of a scan cannot be found.
+    Alignment,
+    /// A template that cannot be read or is not valid.
+    Template(String),
+    /// A key that cannot be read or does not fit the template.
+    Key(String),
+    /// An invalid option, like a grading scale or a naming pattern. The
+    /// message is meant to be shown next to the input field.
+    Settings(String),
+    /// The results cannot be written, e.g. a PDF that fails to typeset.
+    Output(String),
+    /// The run was stopped with a `CancellationToken`.
+    Cancelled,
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Decode(message) => write!(f, "could not decode image: {}", message),
+            Error::Alignment => write!(f, "could not find the circles of the scan"),
+            Error::Template(message) => write!(f, "invalid template: {}", message),
+            Error::Key(message) => write!(f, "invalid key: {}", message),
+            Error::Settings(message) => write!(f, "{}", message),
+            Error::Output(message) => write!(f, "could not write results: {}", message),
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => Error::Io(e),
+            e => Error::Decode(e.to_string()),
+        }
+    }
+}
+
+impl From<tiff::TiffError> for Error {
+    fn from(e: tiff::TiffError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<pdf::PdfError> for Error {
+    fn from(e: pdf::PdfError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => Error::Io(e),
+            e => Error::Decode(e.to_string()),
+        }
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Output(e.to_string())
+    }
+}
+
+impl<W> From<csv::IntoInnerError<W>> for Error {
+    fn from(e: csv::IntoInnerError<W>) -> Self {
+        Error::Io(e.into_error())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::Output(e.to_string())
+    }
+}
+
+// templates and keys are read with their own error, see `Template::from_json`
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Output(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let e = Error::Settings("empty grading scale".into());
+        assert_eq!(e.to_string(), "empty grading scale");
+        let e: Error = std::io::Error::new(std::io::ErrorKind::NotFound, "gone").into();
+        assert!(matches!(e, Error::Io(_)));
+        assert_eq!(e.to_string(), "gone");
+        assert_eq!(
+            Error::Decode("truncated".into()).to_string(),
+            "could not decode image: truncated"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use crate::error::Error;
+use crate::grading::percentage;
+use crate::pdf_merge::{PdfMerger, Spool, Spooled};
+use crate::report::ImageReport;
+use crate::sink::RecordSink;
+use crate::template::{option_letter, CorrectAnswer, ExamKey, Template};
+use crate::typst_helpers::{document_to_pdf, typst_string, TypstWrapper};
+use std::cmp::Ordering;
+
+/// Whether feedback is written as one PDF per student or as a single PDF with
+/// all students sorted by ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedbackMode {
+    PerStudent,
+    Merged,
+}
+
+// An answer of the report, written like a key, the way the table shows it:
+// the labels of the marked options separated by commas and numbers without
+// their brackets, blank if nothing was marked.
+fn readable(answer: &str) -> String {
+    if let Some(number) = answer.strip_prefix('[') {
+        return number.trim_end_matches(']').to_string();
+    }
+    let options = answer.trim_start_matches('(').trim_end_matches(')');
+    let mut labels = Vec::new();
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => labels.push(chars.by_ref().take_while(|&c| c != '}').collect()),
+            '_' => {}
+            c => labels.push(c.to_string()),
+        }
+    }
+    labels.join(",")
+}
+
+/// One line of the feedback table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedbackRow {
+    pub question: String,
+    pub response: String,
+    pub correct: String,
+    // blank if the question could not be graded
+    pub points: Option<u32>,
+}
+
+/// Everything we tell a student about their sheet, except for the image.
+#[derive(Debug, Clone)]
+pub struct FeedbackEntry {
+    pub identifier: String,
+    pub sid: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<u32>,
+    pub score: u32,
+    pub max_score: u32,
+    pub grade: Option<String>,
+    pub rows: Vec<FeedbackRow>,
+}
+
+impl FeedbackEntry {
+    pub fn new(report: &ImageReport, template: &Template, key: &ExamKey) -> Self {
+        let num_mcqs = template.questions.len();
+        let correct_answers = report.version.and_then(|v| key.get(v as usize));
+        let points = |i: usize| report.scores.get(i).copied().flatten();
+
+        let mut rows = Vec::new();
+        for i in 0..num_mcqs + template.numeric_questions.len() {
+            let labels = template.option_labels(i);
+            let label = |n: &u32| {
+                labels
+                    .get(*n as usize)
+                    .cloned()
+                    .unwrap_or_else(|| option_letter(*n))
+            };
+            let correct = match correct_answers.and_then(|answers| answers.get(i)) {
+                Some(CorrectAnswer::Exactly(n)) => label(n),
+                Some(CorrectAnswer::OneOf(options)) => {
+                    options.iter().map(label).collect::<Vec<_>>().join(" or ")
+                }
+                Some(answer) => answer.to_string(),
+                None => "?".to_string(),
+            };
+            let question = if i < num_mcqs {
+                format!("Q{}", i + 1)
+            } else {
+                format!("N{}", i - num_mcqs + 1)
+            };
+            rows.push(FeedbackRow {
+                question,
+                response: report
+                    .answers
+                    .get(i)
+                    .map(|a| readable(a))
+                    .unwrap_or_default(),
+                correct,
+                points: points(i),
+            });
+        }
+        let offset = num_mcqs + template.numeric_questions.len();
+        for j in 0..template.teacher_questions.len() {
+            rows.push(FeedbackRow {
+                question: format!("T{}", j + 1),
+                response: String::new(),
+                correct: String::new(),
+                points: points(offset + j),
+            });
+        }
+
+        Self {
+            identifier: report.identifier.clone(),
+            sid: report.sid.clone(),
+            name: report.name.clone(),
+            version: report.version,
+            score: report.score(),
+            max_score: template.max_score(),
+            grade: None,
+            rows,
+        }
+    }
+
+    /// Filename (without prefix) of the feedback PDF of this student.
+    pub fn filename(&self) -> String {
+        format!(
+            "feedback-{}-{}.pdf",
+            self.sid.clone().unwrap_or("NOID".to_string()),
+            self.identifier
+        )
+    }
+
+    fn to_typst(&self, image_path: &str) -> String {
+        let rows: String = self
+            .rows
+            .iter()
+            .map(|row| {
+                format!(
+                    "({}, {}, {}, {}),",
+                    typst_string(&row.question),
+                    typst_string(&row.response),
+                    typst_string(&row.correct),
+                    typst_string(&row.points.map(|p| p.to_string()).unwrap_or_default()),
+                )
+            })
+            .collect();
+        let percent = percentage(self.score, self.max_score)
+            .map(|p| format!("{:.1}%", p))
+            .unwrap_or_default();
+
+        format!(
+            "(id: {}, name: {}, version: {}, score: {}, percentage: {}, grade: {}, rows: ({}), image: {}),",
+            typst_string(self.sid.as_deref().unwrap_or("?")),
+            typst_string(self.name.as_deref().unwrap_or("")),
+            typst_string(&self.version.map(|v| (v + 1).to_string()).unwrap_or("?".into())),
+            typst_string(&format!("{} / {}", self.score, self.max_score)),
+            typst_string(&percent),
+            typst_string(self.grade.as_deref().unwrap_or("")),
+            rows,
+            typst_string(image_path),
+        )
+    }
+
+    fn bookmark(&self) -> String {
+        format!(
+            "{} – {}",
+            self.sid.as_deref().unwrap_or("NOID"),
+            self.identifier
+        )
+    }
+}
+
+fn id_order(a: &FeedbackEntry, b: &FeedbackEntry) -> Ordering {
+    (a.sid.is_none(), &a.sid, &a.identifier).cmp(&(b.sid.is_none(), &b.sid, &b.identifier))
+}
+
+/// Sorts by student ID, sheets without an ID come last.
+pub fn sort_by_id(entries: &mut [FeedbackEntry]) {
+    entries.sort_by(id_order);
+}
+
+pub fn feedback_typst(entry: &FeedbackEntry, title: &str) -> String {
+    let tmpl = include_str!("../assets/feedbacktemplate.typ");
+    format!(
+        "#let title = {}\n#let students = ({})\n{}",
+        typst_string(title),
+        entry.to_typst("img.png"),
+        tmpl
+    )
+}
+
+/// The feedback of one student: the table of answers, then the sheet.
+pub fn feedback_pdf(
+    entry: &FeedbackEntry,
+    image_png: Vec<u8>,
+    title: &str,
+) -> Result<Vec<u8>, Error> {
+    let mut wrapper = TypstWrapper::new(feedback_typst(entry, title));
+    wrapper.add_file("img.png", image_png);
+    let document = typst::compile(&wrapper)
+        .output
+        .map_err(|errors| Error::Output(format!("could not typeset feedback: {:?}", errors)))?;
+    document_to_pdf(&document)
+}
+
+/// The feedback of a grading run. The images of the sheets are spooled as
+/// they come in, only the entries themselves are kept until the grades are
+/// known. Every student is typeset on their own.
+pub struct FeedbackPdfs {
+    pub mode: FeedbackMode,
+    images: Spool,
+    entries: Vec<(FeedbackEntry, Spooled)>,
+}
+
+impl FeedbackPdfs {
+    pub fn new(mode: FeedbackMode) -> Self {
+        Self {
+            mode,
+            images: Spool::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// `image_png` is the annotated sheet as a PNG file.
+    pub fn add(&mut self, entry: FeedbackEntry, image_png: &[u8]) -> Result<(), Error> {
+        let image = self.images.store(image_png)?;
+        self.entries.push((entry, image));
+        Ok(())
+    }
+
+    /// The grades of the entries in the order they were added.
+    pub fn set_grades(&mut self, grades: impl IntoIterator<Item = String>) {
+        for ((entry, _), grade) in self.entries.iter_mut().zip(grades) {
+            entry.grade = Some(grade);
+        }
+    }
+
+    /// Writes one PDF per student or a single `feedback.pdf` sorted by ID,
+    /// with a bookmark per student.
+    pub fn finish(mut self, title: &str, records: &mut dyn RecordSink) -> Result<(), Error> {
+        match self.mode {
+            FeedbackMode::PerStudent => {
+                for (entry, image) in &self.entries {
+                    let pdf = feedback_pdf(entry, self.images.load(image)?, title)?;
+                    records.add_file(&entry.filename(), &pdf)?;
+                }
+            }
+            FeedbackMode::Merged => {
+                self.entries.sort_by(|a, b| id_order(&a.0, &b.0));
+                let mut merger = PdfMerger::new(Spool::new())?;
+                for (entry, image) in &self.entries {
+                    let pdf = feedback_pdf(entry, self.images.load(image)?, title)?;
+                    let first = merger.add_pdf(pdf)?;
+                    merger.add_bookmark(&entry.bookmark(), first);
+                }
+                records.add_file_from("feedback.pdf", &mut merger.finish(title)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+
+    fn entry(sid: Option<&str>, identifier: &str) -> FeedbackEntry {
+        FeedbackEntry {
+            identifier: identifier.to_string(),
+            sid: sid.map(String::from),
+            name: None,
+            version: Some(0),
+            score: 3,
+            max_score: 4,
+            grade: Some("B".into()),
+            rows: vec![FeedbackRow {
+                question: "Q1".into(),
+                response: "A,B".into(),
+                correct: "A".into(),
+                points: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn sorted_by_id() {
+        let mut entries = vec![
+            entry(None, "page1"),
+            entry(Some("0042"), "page2"),
+            entry(Some("0007"), "page3"),
+        ];
+        sort_by_id(&mut entries);
+        let order: Vec<&str> = entries.iter().map(|e| e.identifier.as_str()).collect();
+        assert_eq!(order, vec!["page3", "page2", "page1"]);
+        assert_eq!(entries[2].filename(), "feedback-NOID-page1.pdf");
+    }
+
+    #[test]
+    fn readable_answers() {
+        let answers = ["B", "_", "(A{Maybe})", "{No}", "[2.5]", "[]", "[?]"];
+        let shown: Vec<String> = answers.iter().map(|a| readable(a)).collect();
+        assert_eq!(shown, vec!["B", "", "A,Maybe", "No", "2.5", "", "?"]);
+    }
+
+    #[test]
+    fn typst_data() {
+        let code = feedback_typst(&entry(Some("0042"), "page1"), "Quiz \"1\"");
+        assert!(code.starts_with("#let title = \"Quiz \\\"1\\\"\""));
+        assert!(code.contains("id: \"0042\""));
+        assert!(code.contains("percentage: \"75.0%\""));
+        assert!(code.contains("rows: ((\"Q1\", \"A,B\", \"A\", \"\"),)"));
+        assert!(code.contains("image: \"img.png\""));
+    }
+
+    #[test]
+    fn pdfs() {
+        let mut png = Vec::new();
+        image::RgbImage::new(10, 14)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let mut feedback = FeedbackPdfs::new(FeedbackMode::Merged);
+        let mut zoe = entry(None, "page1");
+        zoe.name = Some("Zoë Ђорђевић".into());
+        feedback.add(zoe, &png).unwrap();
+        let mut long = entry(Some("0042"), "page2");
+        long.rows = vec![long.rows[0].clone(); 120];
+        feedback.add(long, &png).unwrap();
+        feedback.set_grades(["A".to_string(), "C".to_string()]);
+
+        let mut sink = MemorySink::default();
+        feedback.finish("Quiz", &mut sink).unwrap();
+        assert_eq!(sink.files.len(), 1);
+        let data = sink.file("feedback.pdf").unwrap().to_vec();
+        // a bookmark per student, sorted by ID
+        let text = String::from_utf8_lossy(&data).to_string();
+        let bookmark = |title: &str| {
+            let hex: String = title.encode_utf16().map(|u| format!("{:04X}", u)).collect();
+            text.find(&format!("/Title <FEFF{}>", hex)).unwrap()
+        };
+        assert!(bookmark("0042 – page2") < bookmark("NOID – page1"));
+        // 120 answers need more than one page besides the sheet
+        let pdf = pdf::file::FileOptions::cached().load(data).unwrap();
+        assert!(pdf.num_pages() > 4);
+
+        let mut feedback = FeedbackPdfs::new(FeedbackMode::PerStudent);
+        feedback.add(entry(Some("7"), "page1"), &png).unwrap();
+        feedback.finish("Quiz", &mut sink).unwrap();
+        assert_eq!(sink.files[1].0, "feedback-7-page1.pdf");
+        assert!(sink.files[1].1.starts_with(b"%PDF"));
+    }
+}
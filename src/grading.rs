@@ -0,0 +1,232 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+
+/// How the thresholds of a grading scale are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GradingMode {
+    /// The minimal number of points for a grade.
+    Points,
+    /// The minimal percentage of the maximal score for a grade.
+    Percent,
+    /// Grading on a curve: a grade goes to the top students up to the given
+    /// (cumulative) percentage of all students.
+    Rank,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradeBand {
+    pub grade: String,
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradingScale {
+    pub mode: GradingMode,
+    pub bands: Vec<GradeBand>,
+}
+
+pub fn percentage(score: u32, max_score: u32) -> Option<f64> {
+    if max_score == 0 {
+        None
+    } else {
+        Some(100.0 * score as f64 / max_score as f64)
+    }
+}
+
+impl GradingScale {
+    /// Parses a scale like `A:90, B:80, C:70, D:60, F:0`. The order of the
+    /// bands does not matter.
+    pub fn parse(mode: GradingMode, spec: &str) -> Result<Self, Error> {
+        let mut bands = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (grade, threshold) = entry.rsplit_once(':').ok_or_else(|| {
+                Error::Settings(format!("'{}' is not of the form grade:threshold", entry))
+            })?;
+            let threshold: f64 = threshold
+                .trim()
+                .parse()
+                .map_err(|_| Error::Settings(format!("invalid threshold in '{}'", entry)))?;
+            if grade.trim().is_empty() {
+                return Err(Error::Settings(format!("missing grade in '{}'", entry)));
+            }
+            bands.push(GradeBand {
+                grade: grade.trim().to_string(),
+                threshold,
+            });
+        }
+        if bands.is_empty() {
+            return Err(Error::Settings("no grades given".to_string()));
+        }
+
+        // best grade first
+        match mode {
+            GradingMode::Points | GradingMode::Percent => {
+                bands.sort_by(|a, b| b.threshold.total_cmp(&a.threshold))
+            }
+            GradingMode::Rank => bands.sort_by(|a, b| a.threshold.total_cmp(&b.threshold)),
+        }
+        Ok(Self { mode, bands })
+    }
+
+    /// The grade for every score. Scores below the lowest threshold get an
+    /// empty grade.
+    pub fn grades(&self, scores: &[u32], max_score: u32) -> Vec<String> {
+        let band_for = |value: f64, matches: &dyn Fn(f64, f64) -> bool| {
+            self.bands
+                .iter()
+                .find(|band| matches(value, band.threshold))
+                .map(|band| band.grade.clone())
+                .unwrap_or_default()
+        };
+
+        match self.mode {
+            GradingMode::Points => scores
+                .iter()
+                .map(|&s| band_for(s as f64, &|v, t| v >= t))
+                .collect(),
+            GradingMode::Percent => scores
+                .iter()
+                .map(|&s| match percentage(s, max_score) {
+                    Some(p) => band_for(p, &|v, t| v >= t - 1e-9),
+                    None => String::new(),
+                })
+                .collect(),
+            GradingMode::Rank => {
+                // students with the same score share the better rank
+                let n = scores.len() as f64;
+                scores
+                    .iter()
+                    .map(|&s| {
+                        let better = scores.iter().filter(|&&other| other > s).count();
+                        band_for(100.0 * better as f64 / n, &|v, t| v < t)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The grade for a single score, which is only known in advance if we
+    /// do not grade on a curve.
+    pub fn grade_of(&self, score: u32, max_score: u32) -> Option<String> {
+        match self.mode {
+            GradingMode::Rank => None,
+            _ => self.grades(&[score], max_score).pop(),
+        }
+    }
+
+    /// The number of students in every band (best grade first).
+    pub fn band_counts(&self, grades: &[String]) -> Vec<(String, usize)> {
+        self.bands
+            .iter()
+            .map(|band| {
+                let count = grades.iter().filter(|g| **g == band.grade).count();
+                (band.grade.clone(), count)
+            })
+            .collect()
+    }
+
+    pub fn band_summary_csv(&self, grades: &[String]) -> Result<String, Error> {
+        let mut csv_writer = csv::Writer::from_writer(std::io::Cursor::new(Vec::new()));
+        csv_writer.write_record(["Grade", "Count", "Percent"])?;
+        for (grade, count) in self.band_counts(grades) {
+            let percent = if grades.is_empty() {
+                String::new()
+            } else {
+                format!("{:.1}", 100.0 * count as f64 / grades.len() as f64)
+            };
+            csv_writer.write_record([grade, count.to_string(), percent])?;
+        }
+        let csv_data = csv_writer.into_inner()?.into_inner();
+        Ok(String::from_utf8(csv_data)?)
+    }
+
+    /// Adds the columns "Percentage" and "Grade" after the total score to the
+    /// CSV header and records and returns the grades.
+    pub fn add_grade_columns(
+        &self,
+        header: &mut Vec<String>,
+        records: &mut [Vec<String>],
+        scores: &[u32],
+        max_score: u32,
+    ) -> Vec<String> {
+        let grades = self.grades(scores, max_score);
+        let idx = header
+            .iter()
+            .position(|h| h == "Total Score")
+            .map_or(header.len(), |i| i + 1);
+
+        header.insert(idx, "Percentage".into());
+        header.insert(idx + 1, "Grade".into());
+        for ((record, &score), grade) in records.iter_mut().zip(scores).zip(grades.iter()) {
+            let percent = percentage(score, max_score)
+                .map(|p| format!("{:.1}", p))
+                .unwrap_or_default();
+            record.insert(idx.min(record.len()), percent);
+            record.insert((idx + 1).min(record.len()), grade.clone());
+        }
+        grades
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_and_percentages() {
+        let scale = GradingScale::parse(GradingMode::Points, "F:0, A:18, B:15,C:10").unwrap();
+        assert_eq!(scale.bands[0].grade, "A");
+        assert_eq!(
+            scale.grades(&[20, 18, 17, 10, 9], 20),
+            vec!["A", "A", "B", "C", "F"]
+        );
+
+        let scale = GradingScale::parse(GradingMode::Percent, "1:90,2:75,3:50").unwrap();
+        assert_eq!(scale.grades(&[27, 30, 14, 15], 30), vec!["1", "1", "", "3"]);
+        assert_eq!(
+            scale.band_counts(&scale.grades(&[27, 30, 14, 15], 30)),
+            vec![("1".into(), 2), ("2".into(), 0), ("3".into(), 1)]
+        );
+
+        assert_eq!(scale.grade_of(15, 30), Some("3".into()));
+        assert!(GradingScale::parse(GradingMode::Points, "A90").is_err());
+        assert!(GradingScale::parse(GradingMode::Points, "A:x").is_err());
+        assert!(GradingScale::parse(GradingMode::Points, "").is_err());
+    }
+
+    #[test]
+    fn curve_by_rank() {
+        let scale = GradingScale::parse(GradingMode::Rank, "A:20,B:60,C:100").unwrap();
+        let scores = [10, 3, 7, 7, 9, 1, 5, 8, 2, 6];
+        assert_eq!(
+            scale.grades(&scores, 10),
+            vec!["A", "C", "B", "B", "A", "C", "C", "B", "C", "B"]
+        );
+
+        // ties get the better grade
+        assert_eq!(scale.grades(&[5, 5, 5, 5, 5], 10), vec!["A"; 5]);
+    }
+
+    #[test]
+    fn grade_columns() {
+        let scale = GradingScale::parse(GradingMode::Percent, "pass:50,fail:0").unwrap();
+        let mut header: Vec<String> = ["Filename", "ID", "Total Score", "Version", "Q001"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut records = vec![
+            vec!["a".into(), "1".into(), "1".into(), "1".into(), "1".into()],
+            vec!["b".into(), "2".into(), "0".into(), "1".into(), "0".into()],
+        ];
+        let grades = scale.add_grade_columns(&mut header, &mut records, &[1, 0], 1);
+        assert_eq!(grades, vec!["pass", "fail"]);
+        assert_eq!(header[3], "Percentage");
+        assert_eq!(header[4], "Grade");
+        assert_eq!(records[0][3..5], ["100.0", "pass"]);
+        assert_eq!(records[1][3..5], ["0.0", "fail"]);
+        assert!(scale
+            .band_summary_csv(&grades)
+            .unwrap()
+            .contains("pass,1,50.0"));
+    }
+}
@@ -1,18 +1,30 @@
-use crate::image_helpers::{binary_image_from_image, create_error_image, fax_to_grayimage};
-use image::{DynamicImage, GrayImage, ImageBuffer, ImageDecoder, Luma, Rgb, Rgba};
+use crate::ccitt::{decode_fax, FaxCoding, FaxOptions};
+use crate::error::Error;
+use crate::image_helpers::{
+    binary_image_from_image, erode_bilevel, samples_to_grayimage, SampleFormat,
+};
+use crate::pdf_raster::{decode_image, render_page, RENDER_DPI};
+use image::{DynamicImage, GenericImage, GrayImage, ImageBuffer, ImageDecoder, Luma, Rgb, Rgba};
 
 use pdf::any::AnySync;
+use pdf::backend::Backend;
 use pdf::file::NoLog;
 use pdf::file::SyncCache;
 use pdf::object::*;
 use std::sync::Arc;
 use tiff::decoder::DecodingResult;
+use tiff::tags::Tag;
+use tiff::ColorType;
 
-use std::iter;
+use std::cmp::Ordering;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
-pub struct PdfContainer {
+/// The pages of a PDF file, read from any `Backend`: the data of a file in
+/// memory, or a borrowed slice of it.
+pub struct PdfContainer<B: Backend = Vec<u8>> {
     pub pdf_file: pdf::file::File<
-        Vec<u8>,
+        B,
         Arc<SyncCache<PlainRef, Result<AnySync, Arc<pdf::PdfError>>>>,
         Arc<SyncCache<PlainRef, Result<Arc<[u8]>, Arc<pdf::PdfError>>>>,
         NoLog,
@@ -20,194 +32,1222 @@ pub struct PdfContainer {
 }
 
 pub struct TiffContainer<R: std::io::BufRead + std::io::Seek> {
-    pub decoder: tiff::decoder::Decoder<R>,
+    reader: R,
 }
 
 pub struct SingleImageContainer {
     pub image: DynamicImage,
 }
 
+/// The PDFs, TIFFs, images and ZIP files in a directory or matching a glob
+/// like `scans/*.pdf`, sorted naturally so that `scan2.jpg` comes before
+/// `scan10.jpg`. A file is only opened when its pages are needed.
+pub struct DirectoryContainer {
+    paths: Vec<PathBuf>,
+    pages: Vec<Option<usize>>,
+    // the file opened last, read page by page
+    open: Option<(usize, Box<dyn ImageContainer>)>,
+}
+
+/// Several containers graded in one run, e.g. all PDFs of an exam session.
+pub struct ChainedContainer<'a> {
+    containers: Vec<Box<dyn ImageContainer + 'a>>,
+    // the page count of each container, asked for once
+    counts: Option<Vec<usize>>,
+}
+
+/// How deep ZIP files may be nested in each other.
+pub const MAX_ZIP_DEPTH: usize = 4;
+
+/// How many bytes a ZIP file may decompress to, with the archives nested in it.
+pub const MAX_ZIP_SIZE: u64 = 2 << 30;
+
+/// The pages of all PDFs, TIFFs, images and nested archives in a ZIP file, in
+/// natural order of their names. An entry is only decompressed when its pages
+/// are needed.
+pub struct ZipContainer<R: Read + Seek = std::io::Cursor<Vec<u8>>> {
+    archive: zip::ZipArchive<R>,
+    entries: Vec<ZipEntry>,
+    depth: usize,
+    // the entry decompressed last, read page by page
+    open: Option<(usize, Box<dyn ImageContainer>)>,
+}
+
+struct ZipEntry {
+    name: String,
+    kind: ZipEntryKind,
+    // the declared size, more is never read
+    size: u64,
+    pages: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ZipEntryKind {
+    Pdf,
+    Tiff,
+    Image(image::ImageFormat),
+    // with what the nested archive decompresses to
+    Zip { unpacked: u64 },
+}
+
+// an image in a ZIP file, only decoded when needed
+struct EncodedImage {
+    data: Vec<u8>,
+    format: image::ImageFormat,
+}
+
 impl SingleImageContainer {
-    pub fn from_data_with_format(data: &[u8], format: image::ImageFormat) -> Self {
+    pub fn from_data_with_format(data: &[u8], format: image::ImageFormat) -> Result<Self, Error> {
         let reader = image::ImageReader::with_format(std::io::Cursor::new(data), format);
-        if let Ok(mut decoder) = reader.into_decoder() {
-            let orientation = decoder
-                .orientation()
-                .unwrap_or(image::metadata::Orientation::NoTransforms);
-
-            if let Ok(mut dynimage) = image::DynamicImage::from_decoder(decoder) {
-                dynimage.apply_orientation(orientation);
+        let mut decoder = reader.into_decoder()?;
+        let orientation = decoder
+            .orientation()
+            .unwrap_or(image::metadata::Orientation::NoTransforms);
 
-                return SingleImageContainer { image: dynimage };
-            }
-        }
+        let mut dynimage = image::DynamicImage::from_decoder(decoder)?;
+        dynimage.apply_orientation(orientation);
 
-        panic!("could not decode single image!");
+        Ok(SingleImageContainer { image: dynimage })
     }
 }
 
-pub trait ImageContainer {
-    fn get_page(&mut self, n: usize) -> Result<GrayImage, std::boxed::Box<dyn std::error::Error>>;
+pub(crate) fn out_of_range(n: usize) -> Error {
+    Error::Decode(format!("there is no page {}", n + 1))
+}
 
-    fn to_iter(&mut self) -> Box<dyn Iterator<Item = GrayImage> + '_> {
-        let mut page = 0;
+/// A page of a container, or why it could not be read.
+pub type PageResult = Result<GrayImage, Error>;
 
-        let iter = iter::from_fn(move || match self.get_page(page) {
-            Ok(image) => {
-                page += 1;
-                Some(image)
-            }
-            Err(_) => None,
-        });
+pub trait ImageContainer {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error>;
 
-        Box::new(iter)
+    /// The number of pages, including those that cannot be read.
+    fn page_count(&mut self) -> usize;
+
+    /// All pages in order. A page that cannot be read does not end the
+    /// iteration, it is an error of its own.
+    fn to_iter(&mut self) -> Box<dyn Iterator<Item = PageResult> + '_> {
+        let count = self.page_count();
+        Box::new((0..count).map(move |n| self.get_page(n)))
     }
 
+    /// All pages that can be read.
     fn to_vector(&mut self) -> Vec<GrayImage> {
-        self.to_iter().collect()
+        self.to_iter().flatten().collect()
     }
 }
 
 impl ImageContainer for SingleImageContainer {
-    fn get_page(&mut self, n: usize) -> Result<GrayImage, std::boxed::Box<dyn std::error::Error>> {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
         if n == 0 {
             Ok(binary_image_from_image(self.image.clone()))
         } else {
-            Err(std::boxed::Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Page out of range",
-            )))
+            Err(out_of_range(n))
         }
     }
+
+    fn page_count(&mut self) -> usize {
+        1
+    }
 }
 
-impl<R: std::io::BufRead + std::io::Seek> ImageContainer for TiffContainer<R> {
-    fn get_page(&mut self, _n: usize) -> Result<GrayImage, std::boxed::Box<dyn std::error::Error>> {
-        unimplemented!();
-    }
-    fn to_iter(&mut self) -> Box<dyn Iterator<Item = GrayImage> + '_> {
-        let decoder = &mut self.decoder;
-        let mut first_call = true; // Flag to check if it's the first image
-
-        let iter = std::iter::from_fn(move || {
-            // Attempt to read the next image
-            if !first_call && decoder.next_image().is_err() {
-                return None; // End iteration if no more images are available
-            }
-
-            first_call = false;
-            let from_tiff = match decoder.read_image() {
-                Ok(DecodingResult::U8(buffer)) => buffer,
-                Ok(DecodingResult::U16(buffer)) => buffer
-                    .iter()
-                    .flat_map(|&x| x.to_be_bytes().into_iter())
-                    .collect::<Vec<u8>>(),
-                Ok(_) => return None,  // Unsupported data type; end iteration
-                Err(_) => return None, // Error reading; end iteration
-            };
+/// Opens a PDF, TIFF or ZIP file, a single image, or the files of a directory
+/// or glob (see `DirectoryContainer::open`).
+pub fn open_container(path: &Path) -> Result<Box<dyn ImageContainer>, Error> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if path.is_dir() || name.contains(['*', '?']) {
+        return Ok(Box::new(DirectoryContainer::open(path)?));
+    }
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pdf" => Ok(Box::new(PdfContainer {
+            pdf_file: pdf::file::FileOptions::cached().open(path)?,
+        })),
+        "tif" | "tiff" => {
+            let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+            Ok(Box::new(TiffContainer::new(reader)?))
+        }
+        "jpg" | "jpeg" | "png" => Ok(Box::new(SingleImageContainer {
+            image: image::open(path)?,
+        })),
+        "zip" => Ok(Box::new(ZipContainer::from_data(std::fs::read(path)?)?)),
+        _ => Err(Error::Decode(format!(
+            "unsupported file type: {}",
+            path.display()
+        ))),
+    }
+}
+
+impl DirectoryContainer {
+    pub const EXTENSIONS: [&'static str; 7] = ["pdf", "tif", "tiff", "jpg", "jpeg", "png", "zip"];
 
-            // Get the dimensions of the image
-            let (width, height) = match decoder.dimensions() {
-                Ok(dimensions) => dimensions,
-                Err(_) => return None, // Error getting dimensions; end iteration
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        DirectoryContainer {
+            pages: vec![None; paths.len()],
+            paths,
+            open: None,
+        }
+    }
+
+    /// `path` is either a directory or a pattern in which the file name may
+    /// contain `*` and `?`.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let (dir, pattern) = if path.is_dir() {
+            (path, "*".to_string())
+        } else {
+            let dir = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
             };
+            let pattern = path.file_name().unwrap_or_default().to_string_lossy();
+            (dir, pattern.to_string())
+        };
+
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let extension = path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                path.is_file()
+                    && Self::EXTENSIONS.contains(&extension.as_str())
+                    && glob_match(&pattern, &name)
+            })
+            .collect();
+
+        if paths.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no scans found for {:?}", path),
+            )
+            .into());
+        }
+        paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        Ok(Self::new(paths))
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    // the container of file `index`, opened unless it is open already
+    fn file(&mut self, index: usize) -> Result<&mut Box<dyn ImageContainer>, Error> {
+        if !matches!(self.open, Some((open, _)) if open == index) {
+            self.open = None;
+            let path = &self.paths[index];
+            let container = open_container(path)
+                .map_err(|e| Error::Decode(format!("could not read {}: {}", path.display(), e)))?;
+            self.open = Some((index, container));
+        }
+        Ok(&mut self.open.as_mut().unwrap().1)
+    }
+
+    // a file that cannot be read is one page, which is the error
+    fn pages(&mut self, index: usize) -> usize {
+        if let Some(pages) = self.pages[index] {
+            return pages;
+        }
+        let pages = match self.file(index) {
+            Ok(container) => container.page_count(),
+            Err(e) => {
+                log::warn!("{}", e);
+                1
+            }
+        };
+        self.pages[index] = Some(pages);
+        pages
+    }
+}
+
+// `*` matches any number of characters and `?` exactly one
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // the last `*` and where in the name it stopped matching
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the `*` match one more character and try again
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
 
-            let color_type = match decoder.colortype() {
-                Ok(t) => t,
-                Err(_) => {
-                    return Some(create_error_image(
-                        "Could not determine color type. Consider scanning to grayscale",
-                    ))
+/// Compares strings like a human would, i.e. runs of digits by their value.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(d) = chars.next_if(|c| c.is_ascii_digit()) {
+                        digits.push(d);
+                    }
+                    digits
+                };
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then(x_trimmed.cmp(y_trimmed))
+                    .then(x.len().cmp(&y.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+impl ZipContainer {
+    pub fn from_data(data: Vec<u8>) -> Result<Self, Error> {
+        Self::from_reader(std::io::Cursor::new(data))
+    }
+}
+
+impl<R: Read + Seek> ZipContainer<R> {
+    /// Reads the archive without a copy of it, e.g. from a `Cursor<&[u8]>`.
+    pub fn from_reader(reader: R) -> Result<Self, Error> {
+        Self::open(reader, 0, MAX_ZIP_SIZE)
+    }
+
+    // fails if the archive is nested deeper than `MAX_ZIP_DEPTH` or would
+    // decompress to more than `budget` bytes
+    fn open(reader: R, depth: usize, budget: u64) -> Result<Self, Error> {
+        if depth > MAX_ZIP_DEPTH {
+            return Err(Error::Decode(format!(
+                "ZIP files nested more than {} deep",
+                MAX_ZIP_DEPTH
+            )));
+        }
+        let too_large = || {
+            Error::Decode(format!(
+                "the ZIP file decompresses to more than {} MB",
+                budget >> 20
+            ))
+        };
+
+        let mut archive = zip::ZipArchive::new(reader)?;
+        let mut names: Vec<String> = archive
+            .file_names()
+            .filter(|name| {
+                // skip folders and the metadata macOS adds to archives
+                !name.ends_with('/')
+                    && !name.starts_with("__MACOSX/")
+                    && !name.rsplit('/').next().unwrap_or_default().starts_with('.')
+            })
+            .map(String::from)
+            .collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+
+        let mut entries = Vec::new();
+        let mut total: u64 = 0;
+        for name in names {
+            let extension = Path::new(&name)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let kind = match extension.as_str() {
+                "pdf" => ZipEntryKind::Pdf,
+                "tif" | "tiff" => ZipEntryKind::Tiff,
+                "jpg" | "jpeg" => ZipEntryKind::Image(image::ImageFormat::Jpeg),
+                "png" => ZipEntryKind::Image(image::ImageFormat::Png),
+                "zip" => ZipEntryKind::Zip { unpacked: 0 },
+                _ => {
+                    log::warn!("skipping {} in ZIP file", name);
+                    continue;
                 }
             };
+            let size = archive.by_name(&name)?.size();
+            total = total.saturating_add(size);
+            if total > budget {
+                return Err(too_large());
+            }
+            entries.push(ZipEntry {
+                name,
+                kind,
+                size,
+                pages: None,
+            });
+        }
+
+        // nested archives are looked into once, to count what they hold
+        for entry in entries.iter_mut() {
+            if let ZipEntryKind::Zip { unpacked } = &mut entry.kind {
+                let data = read_entry(&mut archive, &entry.name, entry.size)?;
+                let nested =
+                    ZipContainer::open(std::io::Cursor::new(data), depth + 1, budget - total)?;
+                *unpacked = nested.unpacked();
+                total += *unpacked;
+            }
+        }
+
+        if entries.is_empty() {
+            return Err(Error::Decode(
+                "no PDF, TIFF or image files in the ZIP file".into(),
+            ));
+        }
+        Ok(Self {
+            archive,
+            entries,
+            depth,
+            open: None,
+        })
+    }
+
+    // the declared size of all entries, with those of nested archives
+    fn unpacked(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| match entry.kind {
+                ZipEntryKind::Zip { unpacked } => entry.size + unpacked,
+                _ => entry.size,
+            })
+            .sum()
+    }
 
-            let decoded = match color_type {
-                tiff::ColorType::Gray(_) => {
-                    ImageBuffer::<Luma<u8>, _>::from_raw(width, height, from_tiff)
-                        .map(DynamicImage::ImageLuma8)
+    fn decompress(&mut self, index: usize) -> Result<Box<dyn ImageContainer>, Error> {
+        let entry = &self.entries[index];
+        let data = read_entry(&mut self.archive, &entry.name, entry.size)?;
+        Ok(match entry.kind {
+            ZipEntryKind::Pdf => Box::new(PdfContainer {
+                pdf_file: pdf::file::FileOptions::cached().load(data)?,
+            }),
+            ZipEntryKind::Tiff => Box::new(TiffContainer::new(std::io::Cursor::new(data))?),
+            ZipEntryKind::Image(format) => Box::new(EncodedImage { data, format }),
+            ZipEntryKind::Zip { unpacked } => Box::new(ZipContainer::open(
+                std::io::Cursor::new(data),
+                self.depth + 1,
+                unpacked,
+            )?),
+        })
+    }
+
+    // the container of entry `index`, decompressed unless it is open already
+    fn entry(&mut self, index: usize) -> Result<&mut Box<dyn ImageContainer>, Error> {
+        if !matches!(self.open, Some((open, _)) if open == index) {
+            self.open = None;
+            self.open = Some((index, self.decompress(index)?));
+        }
+        Ok(&mut self.open.as_mut().unwrap().1)
+    }
+
+    // an entry that cannot be read is one page, which is the error
+    fn pages(&mut self, index: usize) -> usize {
+        if let Some(pages) = self.entries[index].pages {
+            return pages;
+        }
+        let pages = match self.entries[index].kind {
+            ZipEntryKind::Image(_) => 1,
+            _ => match self.entry(index) {
+                Ok(container) => container.page_count(),
+                Err(e) => {
+                    log::warn!("cannot read {}: {}", self.entries[index].name, e);
+                    1
                 }
-                tiff::ColorType::RGB(_) => {
-                    ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, from_tiff)
-                        .map(DynamicImage::ImageRgb8)
+            },
+        };
+        self.entries[index].pages = Some(pages);
+        pages
+    }
+}
+
+// at most `size` bytes, as the archive declares for the entry
+fn read_entry<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+    size: u64,
+) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    archive.by_name(name)?.take(size).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+impl<R: Read + Seek> ImageContainer for ZipContainer<R> {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+        let mut remaining = n;
+        for index in 0..self.entries.len() {
+            let count = self.pages(index);
+            if remaining < count {
+                return self.entry(index)?.get_page(remaining);
+            }
+            remaining -= count;
+        }
+        Err(out_of_range(n))
+    }
+
+    fn page_count(&mut self) -> usize {
+        (0..self.entries.len()).map(|index| self.pages(index)).sum()
+    }
+}
+
+impl ImageContainer for EncodedImage {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+        if n > 0 {
+            return Err(out_of_range(n));
+        }
+        let reader = image::ImageReader::with_format(std::io::Cursor::new(&self.data), self.format);
+        Ok(binary_image_from_image(reader.decode()?))
+    }
+
+    fn page_count(&mut self) -> usize {
+        1
+    }
+}
+
+impl ImageContainer for DirectoryContainer {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+        let mut remaining = n;
+        for index in 0..self.paths.len() {
+            let count = self.pages(index);
+            if remaining < count {
+                return self.file(index)?.get_page(remaining);
+            }
+            remaining -= count;
+        }
+        Err(out_of_range(n))
+    }
+
+    fn page_count(&mut self) -> usize {
+        (0..self.paths.len()).map(|index| self.pages(index)).sum()
+    }
+}
+
+impl<'a> ChainedContainer<'a> {
+    pub fn new(containers: Vec<Box<dyn ImageContainer + 'a>>) -> Self {
+        ChainedContainer {
+            containers,
+            counts: None,
+        }
+    }
+
+    fn counts(&mut self) -> &[usize] {
+        let containers = &mut self.containers;
+        self.counts
+            .get_or_insert_with(|| containers.iter_mut().map(|c| c.page_count()).collect())
+    }
+}
+
+impl ImageContainer for ChainedContainer<'_> {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+        let mut remaining = n;
+        for index in 0..self.containers.len() {
+            let count = self.counts()[index];
+            if remaining < count {
+                return self.containers[index].get_page(remaining);
+            }
+            remaining -= count;
+        }
+        Err(out_of_range(n))
+    }
+
+    fn page_count(&mut self) -> usize {
+        self.counts().iter().sum()
+    }
+
+    fn to_iter(&mut self) -> Box<dyn Iterator<Item = PageResult> + '_> {
+        Box::new(
+            self.containers
+                .iter_mut()
+                .flat_map(|container| container.to_iter()),
+        )
+    }
+}
+
+impl<R: std::io::BufRead + std::io::Seek> TiffContainer<R> {
+    /// Fails if `reader` does not hold a TIFF file.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        tiff::decoder::Decoder::new(&mut reader)?;
+        Ok(TiffContainer { reader })
+    }
+
+    // a decoder at the IFD of page `n`
+    fn decoder(&mut self, n: usize) -> Result<tiff::decoder::Decoder<&mut R>, Error> {
+        self.reader.rewind()?;
+        let mut decoder = tiff::decoder::Decoder::new(&mut self.reader)?;
+        decoder.seek_to_image(n)?;
+        Ok(decoder)
+    }
+
+    fn read_image(&mut self, n: usize) -> Result<GrayImage, Error> {
+        let mut decoder = self.decoder(n)?;
+        let compression = decoder
+            .find_tag_unsigned::<u16>(Tag::Compression)?
+            .unwrap_or(1);
+        let photometric = decoder.get_tag_unsigned::<u16>(Tag::PhotometricInterpretation)?;
+        let bits = decoder
+            .find_tag_unsigned_vec::<u16>(Tag::BitsPerSample)?
+            .and_then(|bits| bits.first().copied())
+            .unwrap_or(1);
+
+        // The tiff crate can neither decode fax images nor handle palettes
+        // and fewer than 8 bits per sample. We read those ourselves, but leave
+        // other compressions like LZW and Deflate to the tiff crate.
+        let unsupported = photometric == 3 || bits < 8;
+        if matches!(compression, 2..=4) || (unsupported && matches!(compression, 1 | 32773)) {
+            return self.read_strips(n, compression, photometric, bits);
+        }
+
+        let (width, height) = decoder.dimensions()?;
+        let decoded = match (decoder.colortype()?, decoder.read_image()?) {
+            (ColorType::Gray(_), DecodingResult::U8(buffer)) => {
+                ImageBuffer::<Luma<u8>, _>::from_raw(width, height, buffer)
+                    .map(DynamicImage::ImageLuma8)
+            }
+            (ColorType::Gray(_), DecodingResult::U16(buffer)) => {
+                ImageBuffer::<Luma<u16>, _>::from_raw(width, height, buffer)
+                    .map(DynamicImage::ImageLuma16)
+            }
+            (ColorType::RGB(_), DecodingResult::U8(buffer)) => {
+                ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, buffer)
+                    .map(DynamicImage::ImageRgb8)
+            }
+            (ColorType::RGB(_), DecodingResult::U16(buffer)) => {
+                ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, buffer)
+                    .map(DynamicImage::ImageRgb16)
+            }
+            (ColorType::RGBA(_), DecodingResult::U8(buffer)) => {
+                ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, buffer)
+                    .map(DynamicImage::ImageRgba8)
+            }
+            (ColorType::RGBA(_), DecodingResult::U16(buffer)) => {
+                ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, buffer)
+                    .map(DynamicImage::ImageRgba16)
+            }
+            (ColorType::CMYK(8), DecodingResult::U8(buffer)) => Some(DynamicImage::ImageLuma8(
+                samples_to_grayimage(&buffer, width, height, &SampleFormat::Cmyk { bits: 8 }, false)?,
+            )),
+            (colortype, _) => {
+                return Err(Error::Decode(format!(
+                    "cannot handle TIFF images of type {:?}, please convert to PDF or scan to grayscale",
+                    colortype
+                )))
+            }
+        };
+
+        let decoded = decoded
+            .ok_or_else(|| Error::Decode("image data does not match its dimensions".into()))?;
+        Ok(binary_image_from_image(decoded))
+    }
+
+    // decodes the strips of page `n` ourselves
+    fn read_strips(
+        &mut self,
+        n: usize,
+        compression: u16,
+        photometric: u16,
+        bits: u16,
+    ) -> Result<GrayImage, Error> {
+        let mut decoder = self.decoder(n)?;
+        let (width, height) = decoder.dimensions()?;
+        if width == 0 || height == 0 {
+            return Err(Error::Decode("TIFF image without pixels".into()));
+        }
+        if decoder.find_tag(Tag::TileOffsets)?.is_some() {
+            return Err(Error::Decode(
+                "tiled TIFF images of this kind are not supported".into(),
+            ));
+        }
+        let offsets = decoder.get_tag_u64_vec(Tag::StripOffsets)?;
+        let counts = decoder.get_tag_u64_vec(Tag::StripByteCounts)?;
+        let rows_per_strip = decoder
+            .find_tag_unsigned::<u32>(Tag::RowsPerStrip)?
+            .unwrap_or(height)
+            .clamp(1, height);
+        let lsb_first = decoder.find_tag_unsigned::<u16>(Tag::FillOrder)? == Some(2);
+        let samples = decoder
+            .find_tag_unsigned::<u16>(Tag::SamplesPerPixel)?
+            .unwrap_or(1);
+        // T4Options: bit 0 for 2D coding, bit 2 for byte aligned rows
+        let t4_options = decoder
+            .find_tag_unsigned::<u32>(Tag::Unknown(292))?
+            .unwrap_or(0);
+
+        let bits = bits as u8;
+        let format = match (photometric, samples) {
+            (0 | 1, 1) => SampleFormat::Gray { bits },
+            (2, 3) => SampleFormat::Rgb { bits },
+            (3, 1) => {
+                // all red values, then all green values, then all blue values
+                let map = decoder.get_tag_u16_vec(Tag::ColorMap)?;
+                let n = map.len() / 3;
+                let colours = (0..n)
+                    .map(|i| {
+                        Rgb([
+                            (map[i] >> 8) as u8,
+                            (map[n + i] >> 8) as u8,
+                            (map[2 * n + i] >> 8) as u8,
+                        ])
+                    })
+                    .collect();
+                SampleFormat::Palette { bits, colours }
+            }
+            (5, 4) => SampleFormat::Cmyk { bits },
+            _ => {
+                return Err(Error::Decode(format!(
+                    "cannot handle TIFF images with photometric interpretation {} and {} samples",
+                    photometric, samples
+                )))
+            }
+        };
+        let white_is_zero = photometric == 0;
+
+        drop(decoder);
+
+        let length = self.reader.seek(SeekFrom::End(0))?;
+        let mut image = GrayImage::new(width, height);
+        for (strip, (&offset, &count)) in offsets.iter().zip(&counts).enumerate() {
+            let top = strip as u32 * rows_per_strip;
+            if top >= height {
+                break;
+            }
+            let rows = rows_per_strip.min(height - top);
+
+            if !matches!(offset.checked_add(count), Some(end) if end <= length) {
+                return Err(Error::Decode(format!(
+                    "TIFF strip {} ends after the end of the file",
+                    strip
+                )));
+            }
+            self.reader.seek(SeekFrom::Start(offset))?;
+            let mut data = vec![0u8; count as usize];
+            self.reader.read_exact(&mut data)?;
+            if lsb_first {
+                data.iter_mut().for_each(|byte| *byte = byte.reverse_bits());
+            }
+
+            let part = match compression {
+                2..=4 => {
+                    let coding = match compression {
+                        2 => FaxCoding::ModifiedHuffman,
+                        3 => FaxCoding::Group3 {
+                            two_dimensional: t4_options & 1 != 0,
+                        },
+                        _ => FaxCoding::Group4,
+                    };
+                    let options = FaxOptions {
+                        coding,
+                        width,
+                        height: rows,
+                        byte_align: compression == 3 && t4_options & 4 != 0,
+                    };
+                    let mut part = decode_fax(&data, &options)?;
+                    // white runs are zeros, which are black with BlackIsZero
+                    if !white_is_zero {
+                        image::imageops::invert(&mut part);
+                    }
+                    part
                 }
-                tiff::ColorType::RGBA(_) => {
-                    ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, from_tiff)
-                        .map(DynamicImage::ImageRgba8)
+                1 => samples_to_grayimage(&data, width, rows, &format, white_is_zero)?,
+                32773 => {
+                    samples_to_grayimage(&unpack_bits(&data), width, rows, &format, white_is_zero)?
+                }
+                _ => {
+                    return Err(Error::Decode(format!(
+                        "cannot decode TIFF compression {} with {} bits per sample",
+                        compression, bits
+                    )))
                 }
-                _ => return Some(create_error_image(
-                    "Cannot handle this color type. Please convert to PDF or scan to grayscale.",
-                )),
             };
+            image.copy_from(&part, 0, top)?;
+        }
+
+        if bits == 1 {
+            erode_bilevel(&mut image);
+        }
+        Ok(binary_image_from_image(DynamicImage::ImageLuma8(image)))
+    }
+}
+
+// PackBits, the run length encoding of TIFF compression 32773
+fn unpack_bits(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let n = data[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let end = (i + n as usize + 1).min(data.len());
+            result.extend_from_slice(&data[i..end]);
+            i = end;
+        } else if n != -128 {
+            if let Some(&byte) = data.get(i) {
+                result.resize(result.len() + (1 - n as isize) as usize, byte);
+            }
+            i += 1;
+        }
+    }
+    result
+}
 
-            // Convert the buffer into a GrayImage and process it
-            let gray = decoded?.into_luma8().into();
-            // Return the processed image
-            Some(binary_image_from_image(gray))
-        });
+impl<R: std::io::BufRead + std::io::Seek> ImageContainer for TiffContainer<R> {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+        self.read_image(n)
+    }
 
-        Box::new(iter)
+    fn page_count(&mut self) -> usize {
+        if self.reader.rewind().is_err() {
+            return 0;
+        }
+        let Ok(mut decoder) = tiff::decoder::Decoder::new(&mut self.reader) else {
+            return 0;
+        };
+        // a page whose IFD cannot be parsed still counts, so that it shows up
+        // as unreadable instead of silently ending the batch
+        let mut count = 0;
+        loop {
+            match decoder.seek_to_image(count) {
+                Err(tiff::TiffError::FormatError(
+                    tiff::TiffFormatError::ImageFileDirectoryNotFound,
+                )) => return count,
+                _ => count += 1,
+            }
+        }
     }
 }
 
-impl ImageContainer for PdfContainer {
-    fn get_page(&mut self, n: usize) -> Result<GrayImage, std::boxed::Box<dyn std::error::Error>> {
+impl<B: Backend> ImageContainer for PdfContainer<B> {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
         let file = &self.pdf_file;
         let resolver = file.resolver();
         let page = file.get_page(n as u32)?;
 
-        let images: Vec<_> = page
-            .resources()?
-            .xobjects
+        // most scanners put exactly one image on every page, which we take
+        // at its own resolution; everything else, including turned pages and
+        // pages with annotations, is rendered
+        let image = match single_scan(&page, &resolver) {
+            Some(scan) => decode_image(&scan, &resolver)?,
+            None => render_page(&page, &resolver, RENDER_DPI)?,
+        };
+        Ok(binary_image_from_image(DynamicImage::ImageLuma8(image)))
+    }
+
+    fn page_count(&mut self) -> usize {
+        self.pdf_file.num_pages() as usize
+    }
+}
+
+// the image of a page that consists of nothing but one image
+fn single_scan(page: &Page, resolver: &impl Resolve) -> Option<ImageXObject> {
+    if page.rotate.rem_euclid(360) != 0 || page.other.get("Annots").is_some() {
+        return None;
+    }
+    let operations = page.contents.as_ref()?.operations(resolver).ok()?;
+    let painted: Vec<&pdf::content::Op> = operations
+        .iter()
+        .filter(|op| {
+            matches!(
+                op,
+                pdf::content::Op::XObject { .. }
+                    | pdf::content::Op::InlineImage { .. }
+                    | pdf::content::Op::Fill { .. }
+                    | pdf::content::Op::FillAndStroke { .. }
+                    | pdf::content::Op::Stroke
+                    | pdf::content::Op::TextDraw { .. }
+                    | pdf::content::Op::TextDrawAdjusted { .. }
+            )
+        })
+        .collect();
+
+    match painted[..] {
+        [pdf::content::Op::XObject { name }] => {
+            let reference = *page.resources().ok()?.xobjects.get(name)?;
+            match *resolver.get(reference).ok()? {
+                XObject::Image(ref image) => Some(image.clone()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "scan10.jpg",
+            "scan2.jpg",
+            "scan1.jpg",
+            "scan02.jpg",
+            "a.jpg",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec![
+                "a.jpg",
+                "scan1.jpg",
+                "scan2.jpg",
+                "scan02.jpg",
+                "scan10.jpg"
+            ]
+        );
+
+        assert!(glob_match("*.jpg", "scan1.jpg"));
+        assert!(glob_match("scan?.jpg", "scan1.jpg"));
+        assert!(!glob_match("scan?.jpg", "scan10.jpg"));
+        assert!(!glob_match("*.jpg", "scan1.png"));
+        assert!(glob_match("*a*a*a*b", "xaxaxaab"));
+        assert!(glob_match("s*?.*", "scan1.jpg"));
+        assert!(!glob_match("*a*a*a*b", &"a".repeat(1000)));
+    }
+
+    #[test]
+    fn directory() {
+        let container =
+            DirectoryContainer::open(Path::new("tests/assets/scanner-jpg-*.jpg")).unwrap();
+        assert_eq!(container.paths.len(), 4);
+        assert!(container.paths[0].ends_with("scanner-jpg-0001.jpg"));
+
+        let single = SingleImageContainer {
+            image: DynamicImage::new_luma8(10, 10),
+        };
+        let mut chained = ChainedContainer::new(vec![Box::new(single), Box::new(container)]);
+        assert_eq!(chained.page_count(), 5);
+        assert_eq!(chained.to_iter().count(), 5);
+        assert!(chained.get_page(4).is_ok());
+        assert!(chained.get_page(5).is_err());
+        assert!(DirectoryContainer::open(Path::new("tests/assets/*.gif")).is_err());
+
+        // a broken file is an error of its own and does not end the batch
+        let mut broken = DirectoryContainer::new(vec![
+            PathBuf::from("tests/assets/template.json"),
+            PathBuf::from("tests/assets/scanner-jpg-0001.jpg"),
+        ]);
+        let pages: Vec<PageResult> = broken.to_iter().collect();
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].is_err());
+        assert!(pages[1].is_ok());
+        assert_eq!(broken.to_vector().len(), 1);
+
+        // PDFs and TIFFs are opened like files given on their own
+        let mut scans = DirectoryContainer::open(Path::new("tests/assets/scanner-*")).unwrap();
+        assert_eq!(scans.paths().len(), 8);
+        let pages: usize = scans
+            .paths()
             .iter()
-            .filter_map(|(_name, &r)| {
-                resolver.get(r).ok().and_then(|o| match *o {
-                    XObject::Image(ref im) => Some(im.clone()),
-                    _ => None,
-                })
+            .map(|path| open_container(path).unwrap().page_count())
+            .sum();
+        assert_eq!(pages, 21);
+        assert_eq!(scans.page_count(), pages);
+        assert!(scans.get_page(pages - 1).is_ok());
+        assert!(scans.get_page(pages).is_err());
+    }
+
+    // counts how often it is asked for its pages
+    struct Counted<'a> {
+        pages: usize,
+        asked: &'a std::cell::Cell<usize>,
+    }
+
+    impl ImageContainer for Counted<'_> {
+        fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+            if n < self.pages {
+                Ok(GrayImage::new(1, 1))
+            } else {
+                Err(out_of_range(n))
+            }
+        }
+
+        fn page_count(&mut self) -> usize {
+            self.asked.set(self.asked.get() + 1);
+            self.pages
+        }
+    }
+
+    #[test]
+    fn chained_counts_once() {
+        let asked = std::cell::Cell::new(0);
+        let containers: Vec<Box<dyn ImageContainer>> = (0..10)
+            .map(|_| {
+                Box::new(Counted {
+                    pages: 3,
+                    asked: &asked,
+                }) as Box<dyn ImageContainer>
             })
             .collect();
-
-        if images.is_empty() {
-            return Ok(create_error_image(&format!("No image on page {}", n + 1)));
+        let mut chained = ChainedContainer::new(containers);
+        for n in 0..30 {
+            assert!(chained.get_page(n).is_ok());
         }
+        assert!(chained.get_page(30).is_err());
+        assert_eq!(chained.page_count(), 30);
+        assert_eq!(asked.get(), 10);
+    }
 
-        let largest_image = images
-            .into_iter()
-            .max_by_key(|img| img.width * img.height)
-            .unwrap();
+    #[test]
+    fn tiff_pages() {
+        let data = std::fs::read("tests/assets/scanner-multipagetiff.tif").unwrap();
+        let mut container = TiffContainer::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(container.page_count(), 5);
 
-        let (image_data, filter) = largest_image.raw_image_data(&resolver).unwrap();
-
-        match filter {
-            Some(pdf::enc::StreamFilter::DCTDecode(_)) => {
-                match image::load_from_memory_with_format(&image_data, image::ImageFormat::Jpeg) {
-                    Ok(img) => Ok(binary_image_from_image(img)),
-                    Err(e) => Ok(create_error_image(&format!(
-                        "Failed to decode JPEG on page {}: {}",
-                        n + 1,
-                        e
-                    ))),
-                }
+        // pages can be read in any order
+        let fourth = container.get_page(3).unwrap();
+        let first = container.get_page(0).unwrap();
+        assert_eq!(container.get_page(3).unwrap(), fourth);
+        assert_eq!(container.to_vector()[0], first);
+        assert!(container.get_page(5).is_err());
+    }
+
+    // a little endian TIFF with a single strip
+    fn tiff_file(width: u32, height: u32, tags: &[(u16, Vec<u16>)], data: &[u8]) -> Vec<u8> {
+        let long = |tag: u16, value: u32| (tag, 4u16, value.to_le_bytes().to_vec());
+        let mut entries = vec![
+            long(256, width),
+            long(257, height),
+            long(273, 8),
+            long(278, height),
+            long(279, data.len() as u32),
+        ];
+        for (tag, values) in tags {
+            let bytes = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            entries.push((*tag, 3, bytes));
+        }
+        entries.sort_by_key(|entry| entry.0);
+
+        let ifd_offset = 8 + data.len().div_ceil(2) * 2;
+        let mut extra_offset = ifd_offset + 2 + 12 * entries.len() + 4;
+        let mut file = b"II*\0".to_vec();
+        file.extend((ifd_offset as u32).to_le_bytes());
+        file.extend(data);
+        file.resize(ifd_offset, 0);
+        file.extend((entries.len() as u16).to_le_bytes());
+        let mut extra: Vec<u8> = Vec::new();
+        for (tag, kind, bytes) in &entries {
+            let size = if *kind == 3 { 2 } else { 4 };
+            file.extend(tag.to_le_bytes());
+            file.extend(kind.to_le_bytes());
+            file.extend(((bytes.len() / size) as u32).to_le_bytes());
+            if bytes.len() <= 4 {
+                let mut inline = bytes.clone();
+                inline.resize(4, 0);
+                file.extend(inline);
+            } else {
+                file.extend((extra_offset as u32).to_le_bytes());
+                extra_offset += bytes.len();
+                extra.extend(bytes);
             }
-            Some(pdf::enc::StreamFilter::FlateDecode(_)) => {
-                match image::load_from_memory_with_format(&image_data, image::ImageFormat::Png) {
-                    Ok(img) => Ok(binary_image_from_image(img)),
-                    Err(e) => Ok(create_error_image(&format!(
-                        "Failed to decode PNG on page {}: {}",
-                        n + 1,
-                        e
-                    ))),
+        }
+        file.extend(0u32.to_le_bytes());
+        file.extend(extra);
+        file
+    }
+
+    // the left quarter of a 64x32 image is black
+    fn read_tiff(tags: &[(u16, Vec<u16>)], data: &[u8]) -> GrayImage {
+        let file = tiff_file(64, 32, tags, data);
+        let mut container = TiffContainer::new(std::io::Cursor::new(file)).unwrap();
+        let image = container.get_page(0).unwrap();
+        assert_eq!(image.dimensions(), (64, 32));
+        assert_eq!(image.get_pixel(4, 10)[0], 0, "{:?}", tags);
+        assert_eq!(image.get_pixel(40, 10)[0], 255, "{:?}", tags);
+        image
+    }
+
+    #[test]
+    fn tiff_encodings() {
+        const PHOTOMETRIC: u16 = 262;
+        const BITS: u16 = 258;
+        const COMPRESSION: u16 = 259;
+        const SAMPLES: u16 = 277;
+
+        // bilevel, white is zero
+        let bilevel: Vec<u8> = (0..32)
+            .flat_map(|_| [0xff, 0xff, 0, 0, 0, 0, 0, 0])
+            .collect();
+        read_tiff(&[(PHOTOMETRIC, vec![0])], &bilevel);
+
+        // the same with PackBits: each row is two repeated runs
+        let packed: Vec<u8> = (0..32).flat_map(|_| [0xff, 0xff, 0xfb, 0]).collect();
+        read_tiff(
+            &[(PHOTOMETRIC, vec![0]), (COMPRESSION, vec![32773])],
+            &packed,
+        );
+
+        // CCITT Group 4
+        let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+        for _ in 0..32 {
+            let pels = (0..64).map(|x| match x < 16 {
+                true => fax::Color::Black,
+                false => fax::Color::White,
+            });
+            encoder.encode_line(pels, 64).unwrap();
+        }
+        let g4 = encoder.finish().unwrap().finish();
+        read_tiff(&[(PHOTOMETRIC, vec![0]), (COMPRESSION, vec![4])], &g4);
+
+        // 8 bit palette, index 1 is black
+        let indices: Vec<u8> = (0..32 * 64).map(|i| (i % 64 < 16) as u8).collect();
+        let mut colour_map = vec![0u16; 3 * 256];
+        colour_map[0] = 0xffff;
+        colour_map[256] = 0xffff;
+        colour_map[512] = 0xffff;
+        read_tiff(
+            &[(PHOTOMETRIC, vec![3]), (BITS, vec![8]), (0x140, colour_map)],
+            &indices,
+        );
+
+        // 16 bit grayscale
+        let gray: Vec<u8> = (0..32 * 64)
+            .flat_map(|i| if i % 64 < 16 { [0, 0] } else { [0xff, 0xff] })
+            .collect();
+        read_tiff(&[(PHOTOMETRIC, vec![1]), (BITS, vec![16])], &gray);
+
+        // CMYK
+        let cmyk: Vec<u8> = (0..32 * 64)
+            .flat_map(|i| {
+                if i % 64 < 16 {
+                    [0, 0, 0, 255]
+                } else {
+                    [0, 0, 0, 0]
                 }
-            }
-            Some(pdf::enc::StreamFilter::CCITTFaxDecode(_)) => Ok(fax_to_grayimage(
-                &image_data,
-                largest_image.width,
-                largest_image.height,
-            )),
-            _ => Ok(create_error_image(&format!(
-                "Could not decode image on page {}: Unsupported format",
-                n + 1
-            ))),
+            })
+            .collect();
+        read_tiff(
+            &[
+                (PHOTOMETRIC, vec![5]),
+                (BITS, vec![8, 8, 8, 8]),
+                (SAMPLES, vec![4]),
+            ],
+            &cmyk,
+        );
+
+        // broken files are errors instead of panics or huge allocations
+        let broken = |file: Vec<u8>| TiffContainer::new(std::io::Cursor::new(file))?.get_page(0);
+        let fax = [(PHOTOMETRIC, vec![0]), (COMPRESSION, vec![4])];
+        assert!(broken(tiff_file(0, 0, &fax, &g4)).is_err());
+        let mut too_long = tiff_file(64, 32, &fax, &g4);
+        // StripByteCounts is the last of the seven entries
+        let entry = 8 + g4.len().div_ceil(2) * 2 + 2 + 6 * 12;
+        too_long[entry + 8..entry + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(broken(too_long).is_err());
+    }
+
+    #[test]
+    fn zip_archive() {
+        use std::io::Write;
+        use zip::write::FileOptions;
+
+        let jpg = std::fs::read("tests/assets/scanner-jpg-0001.jpg").unwrap();
+        let tif = std::fs::read("tests/assets/scanner-multipagetiff.tif").unwrap();
+
+        let mut inner = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        inner
+            .start_file::<String, ()>("scan.png".into(), FileOptions::default())
+            .unwrap();
+        let mut png = Vec::new();
+        DynamicImage::new_luma8(10, 10)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        inner.write_all(&png).unwrap();
+        let inner = inner.finish().unwrap().into_inner();
+
+        let mut outer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, data) in [
+            ("scans/page10.JPG", &jpg),
+            ("scans/page2.tif", &tif),
+            ("__MACOSX/scans/._page2.tif", &tif),
+            ("notes.txt", &Vec::new()),
+            ("more.zip", &inner),
+        ] {
+            outer
+                .start_file::<String, ()>(name.into(), FileOptions::default())
+                .unwrap();
+            outer.write_all(data).unwrap();
+        }
+        let data = outer.finish().unwrap().into_inner();
+
+        // more.zip (1 page), then the TIFF (5 pages) before the JPEG
+        let mut container = ZipContainer::from_data(data).unwrap();
+        assert_eq!(container.page_count(), 7);
+        let pages = container.to_vector();
+        assert_eq!(pages.len(), 7);
+        assert_eq!(pages[0].dimensions(), (10, 10));
+        assert!(ZipContainer::from_data(inner[..10].to_vec()).is_err());
+
+        // the PNG is only decompressed for its page
+        let mut container = ZipContainer::from_data(inner.clone()).unwrap();
+        assert_eq!(container.page_count(), 1);
+        assert!(container.open.is_none());
+        assert!(container.get_page(0).is_ok());
+        assert!(container.get_page(1).is_err());
+    }
+
+    fn zip_of(name: &str, data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file::<String, ()>(name.into(), zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(data).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn zip_bombs() {
+        let mut png = Vec::new();
+        DynamicImage::new_luma8(10, 10)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let mut nested = zip_of("scan.png", &png);
+        for _ in 0..MAX_ZIP_DEPTH {
+            nested = zip_of("more.zip", &nested);
         }
+        let mut container = ZipContainer::from_data(nested.clone()).unwrap();
+        assert_eq!(container.to_vector().len(), 1);
+        assert!(ZipContainer::from_data(zip_of("more.zip", &nested)).is_err());
+
+        // nested archives count with what they hold
+        let unpacked = ZipContainer::from_data(nested.clone()).unwrap().unpacked();
+        assert!(unpacked > (png.len() * (MAX_ZIP_DEPTH + 1)) as u64);
+        let cursor = || std::io::Cursor::new(&nested[..]);
+        assert!(ZipContainer::open(cursor(), 0, unpacked).is_ok());
+        assert!(ZipContainer::open(cursor(), 0, unpacked - 1).is_err());
+
+        // zeros compress well, but not below the limit
+        let zeros = vec![0; 1 << 20];
+        let zip = zip_of("zeros.tif", &zeros);
+        assert!(zip.len() < 1 << 16);
+        assert!(ZipContainer::open(std::io::Cursor::new(zip), 0, 1 << 19).is_err());
     }
 }
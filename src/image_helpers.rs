@@ -1,11 +1,11 @@
+use crate::error::Error;
 use crate::point::Point;
-use fax::decoder;
-use fax::Color;
-use image::{DynamicImage, GrayImage, ImageReader, Luma, Rgb, RgbImage};
+use image::{DynamicImage, GrayImage, ImageReader, Luma, Pixel, Rgb, RgbImage};
 use imageproc::drawing;
 
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::OnceLock;
 /// Returns the [Kapur threshold level] of an 8bpp image. This threshold
 /// maximizes the entropy of the background and foreground.
 ///
@@ -74,23 +74,113 @@ pub fn kapur_level(img: &GrayImage) -> u8 {
     best_threshold as u8
 }
 
-pub fn fax_to_grayimage(data: &[u8], width: u32, height: u32) -> GrayImage {
-    let mut result = GrayImage::new(width, height);
-    let mut y = 0;
-    decoder::decode_g4(data.iter().cloned(), width as u16, None, |transitions| {
-        for (x, c) in decoder::pels(transitions, width as u16).enumerate() {
-            let pixel = match c {
-                Color::Black => Luma([0u8]),
-                Color::White => Luma([255u8]),
-            };
-            result.put_pixel(x as u32, y, pixel);
+/// We don't trust binary images and erode them first.
+pub fn erode_bilevel(image: &mut GrayImage) {
+    imageproc::morphology::erode_mut(image, imageproc::distance_transform::Norm::L1, 1);
+}
+
+/// The layout of uncompressed pixel data as found in TIFF files and PDF
+/// streams: samples of 1, 2, 4, 8 or 16 bits (big endian), every row starts
+/// on a byte boundary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SampleFormat {
+    /// 0 is black
+    Gray {
+        bits: u8,
+    },
+    Rgb {
+        bits: u8,
+    },
+    Cmyk {
+        bits: u8,
+    },
+    /// indices into a list of colours
+    Palette {
+        bits: u8,
+        colours: Vec<Rgb<u8>>,
+    },
+}
+
+impl SampleFormat {
+    fn layout(&self) -> (u8, usize) {
+        match self {
+            Self::Gray { bits } => (*bits, 1),
+            Self::Rgb { bits } => (*bits, 3),
+            Self::Cmyk { bits } => (*bits, 4),
+            Self::Palette { bits, .. } => (*bits, 1),
         }
-        y += 1;
-    });
+    }
+}
 
-    // we don't trust binary images and erode them first
-    imageproc::morphology::erode_mut(&mut result, imageproc::distance_transform::Norm::L1, 1);
-    result
+pub fn cmyk_to_rgb(c: u8, m: u8, y: u8, k: u8) -> Rgb<u8> {
+    let channel = |x: u8| ((255 - x as u32) * (255 - k as u32) / 255) as u8;
+    Rgb([channel(c), channel(m), channel(y)])
+}
+
+/// Converts raw samples into a grayscale image. `invert` swaps black and
+/// white, e.g. for a PDF decode array of `[1 0]`.
+pub fn samples_to_grayimage(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: &SampleFormat,
+    invert: bool,
+) -> Result<GrayImage, Error> {
+    let (bits, components) = format.layout();
+    if ![1, 2, 4, 8, 16].contains(&bits) {
+        return Err(Error::Decode(format!(
+            "cannot handle {} bits per sample",
+            bits
+        )));
+    }
+    let row_bytes = (width as usize * components * bits as usize).div_ceil(8);
+    if data.len() < row_bytes * height as usize {
+        return Err(Error::Decode(format!(
+            "expected {} bytes of image data, found {}",
+            row_bytes * height as usize,
+            data.len()
+        )));
+    }
+
+    let max = (1u32 << bits) - 1;
+    let sample = |row: &[u8], index: usize| -> u32 {
+        match bits {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]) as u32,
+            8 => row[index] as u32,
+            _ => {
+                let bit = index * bits as usize;
+                (row[bit / 8] as u32 >> (8 - bits as usize - bit % 8)) & max
+            }
+        }
+    };
+    // scaled to 0..=255
+    let value = |row: &[u8], index: usize| (sample(row, index) * 255 / max) as u8;
+
+    let mut image = GrayImage::new(width, height);
+    for (y, row) in data.chunks(row_bytes).take(height as usize).enumerate() {
+        for x in 0..width as usize {
+            let i = x * components;
+            let gray = match format {
+                SampleFormat::Gray { .. } => value(row, i),
+                SampleFormat::Rgb { .. } => {
+                    Rgb([value(row, i), value(row, i + 1), value(row, i + 2)]).to_luma()[0]
+                }
+                SampleFormat::Cmyk { .. } => cmyk_to_rgb(
+                    value(row, i),
+                    value(row, i + 1),
+                    value(row, i + 2),
+                    value(row, i + 3),
+                )
+                .to_luma()[0],
+                SampleFormat::Palette { colours, .. } => colours
+                    .get(sample(row, i) as usize)
+                    .map_or(0, |colour| colour.to_luma()[0]),
+            };
+            let gray = if invert { 255 - gray } else { gray };
+            image.put_pixel(x as u32, y as u32, Luma([gray]));
+        }
+    }
+    Ok(image)
 }
 pub fn binary_image_from_image(img: DynamicImage) -> GrayImage {
     let gray = img.into_luma8();
@@ -99,23 +189,19 @@ pub fn binary_image_from_image(img: DynamicImage) -> GrayImage {
     imageproc::contrast::threshold(&gray, threshold, imageproc::contrast::ThresholdType::Binary)
 }
 
-pub fn binary_image_from_file(path: &String) -> GrayImage {
+pub fn binary_image_from_file(path: &String) -> Result<GrayImage, Error> {
     let image_path = Path::new(path);
-    let img = ImageReader::open(image_path)
-        .expect("failed to open file")
-        .decode()
-        .expect("failed to decode image");
+    let img = ImageReader::open(image_path)?.decode()?;
 
-    binary_image_from_image(img)
+    Ok(binary_image_from_image(img))
 }
 pub fn gray_to_rgb(gray_image: &GrayImage) -> RgbImage {
     let (width, height) = gray_image.dimensions();
-    let gray_data = gray_image.as_raw();
-    let mut rgb_data = Vec::with_capacity(gray_data.len() * 3);
-
-    for &intensity in gray_data {
-        rgb_data.extend_from_slice(&[intensity, intensity, intensity]);
-    }
+    let rgb_data: Vec<u8> = gray_image
+        .as_raw()
+        .iter()
+        .flat_map(|&intensity| [intensity; 3])
+        .collect();
 
     image::ImageBuffer::from_raw(width, height, rgb_data).unwrap()
 }
@@ -150,10 +236,12 @@ pub fn replace_colours(
     let x_max = x_max.min(width - 1);
     let y_max = y_max.min(height - 1);
 
+    // there are only a few colours, comparing them is faster than hashing
+    let replacements: Vec<(Rgb<u8>, Rgb<u8>)> = replacements.into_iter().collect();
     for y in y_min..=y_max {
         for x in x_min..=x_max {
             let pixel = image.get_pixel_mut(x, y);
-            if let Some(&replacement) = replacements.get(pixel) {
+            if let Some(&(_, replacement)) = replacements.iter().find(|(from, _)| from == pixel) {
                 *pixel = replacement;
             }
         }
@@ -177,6 +265,114 @@ pub fn draw_circle_around_box(
     }
 }
 
+fn draw_thick_line(
+    img: &mut RgbImage,
+    from: (f32, f32),
+    to: (f32, f32),
+    thickness: u32,
+    color: image::Rgb<u8>,
+) {
+    let half = (thickness / 2) as i32;
+    for dx in -half..=half {
+        for dy in -half..=half {
+            drawing::draw_line_segment_mut(
+                img,
+                (from.0 + dx as f32, from.1 + dy as f32),
+                (to.0 + dx as f32, to.1 + dy as f32),
+                color,
+            );
+        }
+    }
+}
+
+/// A ring just outside the box.
+pub fn draw_ring_around_box(
+    img: &mut RgbImage,
+    topleft: Point,
+    botright: Point,
+    color: image::Rgb<u8>,
+) {
+    let width = botright.x - topleft.x;
+    let radius = (width / 2 + width / 6) as i32;
+    let center = (
+        ((topleft.x + botright.x) / 2) as i32,
+        ((topleft.y + botright.y) / 2) as i32,
+    );
+
+    for i in 0..(width as i32 / 12).max(1) {
+        drawing::draw_hollow_circle_mut(img, center, radius + i, color);
+    }
+}
+
+/// A cross from corner to corner of the box.
+pub fn draw_cross_over_box(
+    img: &mut RgbImage,
+    topleft: Point,
+    botright: Point,
+    color: image::Rgb<u8>,
+) {
+    let (a, b) = (
+        (topleft.x as f32, topleft.y as f32),
+        (botright.x as f32, botright.y as f32),
+    );
+    let thickness = (botright.x - topleft.x) / 10;
+    draw_thick_line(img, a, b, thickness, color);
+    draw_thick_line(img, (a.0, b.1), (b.0, a.1), thickness, color);
+}
+
+/// A tick mark of the given size with its top left corner at (x, y).
+pub fn draw_tick(img: &mut RgbImage, x: u32, y: u32, size: u32, color: image::Rgb<u8>) {
+    let (x, y, size) = (x as f32, y as f32, size as f32);
+    let thickness = (size / 8.0) as u32;
+    let bottom = (x + 0.35 * size, y + size);
+    draw_thick_line(img, (x, y + 0.6 * size), bottom, thickness, color);
+    draw_thick_line(img, bottom, (x + size, y), thickness, color);
+}
+
+/// Diagonal lines over all pixels of colour `on` within the rectangle.
+pub fn hatch(
+    image: &mut RgbImage,
+    topleft: Point,
+    botright: Point,
+    spacing: u32,
+    on: Rgb<u8>,
+    color: Rgb<u8>,
+) {
+    let x_max = botright.x.min(image.width() - 1);
+    let y_max = botright.y.min(image.height() - 1);
+    let spacing = spacing.max(2);
+
+    for y in topleft.y..=y_max {
+        for x in topleft.x..=x_max {
+            let pixel = image.get_pixel_mut(x, y);
+            if (x + y) % spacing < spacing / 4 + 1 && *pixel == on {
+                *pixel = color;
+            }
+        }
+    }
+}
+
+// the font of banners, footers and error images, parsed on first use
+fn font() -> Option<&'static ab_glyph::FontArc> {
+    static FONT: OnceLock<Option<ab_glyph::FontArc>> = OnceLock::new();
+    FONT.get_or_init(|| {
+        ab_glyph::FontArc::try_from_slice(crate::typst_helpers::BIOLINUM_BOLD)
+            .inspect_err(|e| log::error!("could not load the font: {}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+pub fn draw_text(image: &mut RgbImage, x: u32, y: u32, height: f32, colour: Rgb<u8>, text: &str) {
+    // without the font the text is left out rather than losing the page
+    let Some(font) = font() else {
+        return;
+    };
+    let scale = ab_glyph::PxScale::from(height);
+
+    drawing::draw_text_mut(image, colour, x as i32, y as i32, scale, font, text);
+}
+
 #[cfg(target_arch = "wasm32")]
 pub fn rgb_to_egui_color_image(image: &RgbImage) -> egui::ColorImage {
     let (width, height) = image.dimensions();
@@ -198,8 +394,9 @@ pub fn create_error_image(error_text: &str) -> GrayImage {
         *pixel = image::Luma([255u8]);
     }
 
-    let font_data = crate::typst_helpers::BIOLINUM_BOLD;
-    let font = ab_glyph::FontArc::try_from_slice(font_data).expect("Error loading font");
+    let Some(font) = font() else {
+        return image;
+    };
 
     let scale = ab_glyph::PxScale::from(30.0);
 
@@ -212,9 +409,31 @@ pub fn create_error_image(error_text: &str) -> GrayImage {
         x,
         y,
         scale,
-        &font,
+        font,
         error_text,
     );
 
     image
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples() {
+        // two rows of three 4 bit samples, padded to two bytes each
+        let data = [0x0f, 0x80, 0xf0, 0x00];
+        let gray = samples_to_grayimage(&data, 3, 2, &SampleFormat::Gray { bits: 4 }, false);
+        assert_eq!(gray.unwrap().into_raw(), vec![0, 255, 136, 255, 0, 0]);
+        let inverted = samples_to_grayimage(&data, 3, 2, &SampleFormat::Gray { bits: 4 }, true);
+        assert_eq!(inverted.unwrap().into_raw(), vec![255, 0, 119, 0, 255, 255]);
+
+        let rgb = [0xff, 0xff, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let image = samples_to_grayimage(&rgb, 2, 1, &SampleFormat::Rgb { bits: 16 }, false);
+        assert_eq!(image.unwrap().into_raw(), vec![54, 255]);
+
+        assert!(samples_to_grayimage(&data, 4, 2, &SampleFormat::Gray { bits: 8 }, false).is_err());
+        assert_eq!(cmyk_to_rgb(0, 255, 0, 0), Rgb([255, 0, 255]));
+    }
+}
@@ -0,0 +1,1480 @@
+// A decoder for the JBIG2 images (ITU-T T.88) that scanners put into PDFs.
+// It handles generic regions (arithmetic or MMR coded) and text regions with
+// arithmetic coded symbol dictionaries, which is what encoders like jbig2enc
+// produce. Huffman coding, refinement and halftone regions are not supported.
+use crate::ccitt::{decode_rows, FaxCoding, FaxOptions};
+use crate::error::Error;
+use image::GrayImage;
+use std::collections::HashMap;
+
+// (Qe, next index after an MPS, next index after an LPS, switch MPS)
+const QE_TABLE: [(u32, u8, u8, bool); 47] = [
+    (0x5601, 1, 1, true),
+    (0x3401, 2, 6, false),
+    (0x1801, 3, 9, false),
+    (0x0AC1, 4, 12, false),
+    (0x0521, 5, 29, false),
+    (0x0221, 38, 33, false),
+    (0x5601, 7, 6, true),
+    (0x5401, 8, 14, false),
+    (0x4801, 9, 14, false),
+    (0x3801, 10, 14, false),
+    (0x3001, 11, 17, false),
+    (0x2401, 12, 18, false),
+    (0x1C01, 13, 20, false),
+    (0x1601, 29, 21, false),
+    (0x5601, 15, 14, true),
+    (0x5401, 16, 14, false),
+    (0x5101, 17, 15, false),
+    (0x4801, 18, 16, false),
+    (0x3801, 19, 17, false),
+    (0x3401, 20, 18, false),
+    (0x3001, 21, 19, false),
+    (0x2801, 22, 19, false),
+    (0x2401, 23, 20, false),
+    (0x2201, 24, 21, false),
+    (0x1C01, 25, 22, false),
+    (0x1801, 26, 23, false),
+    (0x1601, 27, 24, false),
+    (0x1401, 28, 25, false),
+    (0x1201, 29, 26, false),
+    (0x1101, 30, 27, false),
+    (0x0AC1, 31, 28, false),
+    (0x09C1, 32, 29, false),
+    (0x08A1, 33, 30, false),
+    (0x0521, 34, 31, false),
+    (0x0441, 35, 32, false),
+    (0x02A1, 36, 33, false),
+    (0x0221, 37, 34, false),
+    (0x0141, 38, 35, false),
+    (0x0111, 39, 36, false),
+    (0x0085, 40, 37, false),
+    (0x0049, 41, 38, false),
+    (0x0025, 42, 39, false),
+    (0x0015, 43, 40, false),
+    (0x0009, 44, 41, false),
+    (0x0005, 45, 42, false),
+    (0x0001, 45, 43, false),
+    (0x5601, 46, 46, false),
+];
+
+// The MQ arithmetic decoder of Annex E. A context is stored as its index
+// into `QE_TABLE` shifted by one and its MPS in the lowest bit.
+struct MqDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    c_high: u32,
+    c_low: u32,
+    a: u32,
+    ct: u32,
+}
+
+impl<'a> MqDecoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        let mut decoder = MqDecoder {
+            data,
+            pos: 0,
+            c_high: *data.first().unwrap_or(&0xff) as u32,
+            c_low: 0,
+            a: 0x8000,
+            ct: 0,
+        };
+        decoder.byte_in();
+        decoder.c_high = ((decoder.c_high << 7) & 0xffff) | ((decoder.c_low >> 9) & 0x7f);
+        decoder.c_low = (decoder.c_low << 7) & 0xffff;
+        decoder.ct -= 7;
+        decoder
+    }
+
+    fn byte_in(&mut self) {
+        let byte = |pos: usize| *self.data.get(pos).unwrap_or(&0xff) as u32;
+        if byte(self.pos) == 0xff {
+            if byte(self.pos + 1) > 0x8f {
+                self.c_low += 0xff00;
+                self.ct = 8;
+            } else {
+                self.pos += 1;
+                self.c_low += byte(self.pos) << 9;
+                self.ct = 7;
+            }
+        } else {
+            self.pos += 1;
+            self.c_low += byte(self.pos) << 8;
+            self.ct = 8;
+        }
+        if self.c_low > 0xffff {
+            self.c_high += self.c_low >> 16;
+            self.c_low &= 0xffff;
+        }
+    }
+
+    fn decode(&mut self, contexts: &mut [u8], cx: usize) -> u32 {
+        let mut index = (contexts[cx] >> 1) as usize;
+        let mut mps = (contexts[cx] & 1) as u32;
+        let (qe, nmps, nlps, switch) = QE_TABLE[index];
+
+        let mut a = self.a - qe;
+        let bit;
+        if self.c_high < qe {
+            // the LPS and MPS intervals are exchanged if the LPS one is larger
+            if a < qe {
+                bit = mps;
+                index = nmps as usize;
+            } else {
+                bit = 1 - mps;
+                if switch {
+                    mps = bit;
+                }
+                index = nlps as usize;
+            }
+            a = qe;
+        } else {
+            self.c_high -= qe;
+            if a & 0x8000 != 0 {
+                self.a = a;
+                return mps;
+            }
+            if a < qe {
+                bit = 1 - mps;
+                if switch {
+                    mps = bit;
+                }
+                index = nlps as usize;
+            } else {
+                bit = mps;
+                index = nmps as usize;
+            }
+        }
+
+        while a & 0x8000 == 0 {
+            if self.ct == 0 {
+                self.byte_in();
+            }
+            a <<= 1;
+            self.c_high = ((self.c_high << 1) & 0xffff) | ((self.c_low >> 15) & 1);
+            self.c_low = (self.c_low << 1) & 0xffff;
+            self.ct -= 1;
+        }
+        self.a = a;
+        contexts[cx] = ((index as u8) << 1) | mps as u8;
+        bit
+    }
+}
+
+// The integer decoding procedure of Annex A.2, `None` is OOB.
+struct IntegerDecoder {
+    contexts: Vec<u8>,
+}
+
+impl IntegerDecoder {
+    fn new() -> Self {
+        IntegerDecoder {
+            contexts: vec![0; 512],
+        }
+    }
+
+    fn decode(&mut self, mq: &mut MqDecoder) -> Option<i64> {
+        let mut prev = 1;
+        let mut read = |bits: u32| -> i64 {
+            let mut value = 0;
+            for _ in 0..bits {
+                let bit = mq.decode(&mut self.contexts, prev) as usize;
+                prev = if prev < 256 {
+                    (prev << 1) | bit
+                } else {
+                    (((prev << 1) | bit) & 511) | 256
+                };
+                value = (value << 1) | bit as i64;
+            }
+            value
+        };
+
+        let negative = read(1) == 1;
+        let value = if read(1) == 0 {
+            read(2)
+        } else if read(1) == 0 {
+            read(4) + 4
+        } else if read(1) == 0 {
+            read(6) + 20
+        } else if read(1) == 0 {
+            read(8) + 84
+        } else if read(1) == 0 {
+            read(12) + 340
+        } else {
+            read(32) + 4436
+        };
+
+        match (negative, value) {
+            (true, 0) => None,
+            (true, value) => Some(-value),
+            (false, value) => Some(value),
+        }
+    }
+}
+
+// The symbol ID decoding procedure of Annex A.3.
+struct IdDecoder {
+    contexts: Vec<u8>,
+    code_length: u32,
+}
+
+impl IdDecoder {
+    fn new(code_length: u32) -> Self {
+        IdDecoder {
+            contexts: vec![0; 1 << (code_length + 1)],
+            code_length,
+        }
+    }
+
+    fn decode(&mut self, mq: &mut MqDecoder) -> usize {
+        let mut prev = 1;
+        for _ in 0..self.code_length {
+            prev = (prev << 1) | mq.decode(&mut self.contexts, prev) as usize;
+        }
+        prev - (1 << self.code_length)
+    }
+}
+
+// 1 is black
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Bitmap {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Bitmap {
+    fn new(width: u32, height: u32, value: u8) -> Self {
+        Bitmap {
+            width,
+            height,
+            pixels: vec![value; width as usize * height as usize],
+        }
+    }
+
+    fn get(&self, x: i64, y: i64) -> u8 {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            0
+        } else {
+            self.pixels[y as usize * self.width as usize + x as usize]
+        }
+    }
+
+    // draws `other` with its top left corner at (x, y)
+    fn combine(&mut self, other: &Bitmap, x: i64, y: i64, operator: u8) {
+        for other_y in 0..other.height as i64 {
+            let target_y = y + other_y;
+            if target_y < 0 || target_y >= self.height as i64 {
+                continue;
+            }
+            for other_x in 0..other.width as i64 {
+                let target_x = x + other_x;
+                if target_x < 0 || target_x >= self.width as i64 {
+                    continue;
+                }
+                let source = other.get(other_x, other_y);
+                let target = &mut self.pixels[(target_y * self.width as i64 + target_x) as usize];
+                *target = match operator {
+                    0 => *target | source,
+                    1 => *target & source,
+                    2 => *target ^ source,
+                    3 => 1 - (*target ^ source),
+                    _ => source,
+                };
+            }
+        }
+    }
+}
+
+// The pixels of the generic region templates from the most to the least
+// significant bit of the context, `None` stands for the next adaptive pixel.
+fn template_pixels(template: u8) -> Vec<Option<(i64, i64)>> {
+    let fixed = |positions: &[(i64, i64)]| positions.iter().map(|&p| Some(p)).collect::<Vec<_>>();
+    let mut pixels = Vec::new();
+    match template {
+        0 => {
+            pixels.push(None); // A4
+            pixels.extend(fixed(&[(-1, -2), (0, -2), (1, -2)]));
+            pixels.extend([None, None]); // A3, A2
+            pixels.extend(fixed(&[(-2, -1), (-1, -1), (0, -1), (1, -1), (2, -1)]));
+            pixels.push(None); // A1
+            pixels.extend(fixed(&[(-4, 0), (-3, 0), (-2, 0), (-1, 0)]));
+        }
+        1 => {
+            pixels.extend(fixed(&[(-1, -2), (0, -2), (1, -2), (2, -2)]));
+            pixels.extend(fixed(&[(-2, -1), (-1, -1), (0, -1), (1, -1), (2, -1)]));
+            pixels.push(None);
+            pixels.extend(fixed(&[(-3, 0), (-2, 0), (-1, 0)]));
+        }
+        2 => {
+            pixels.extend(fixed(&[(-1, -2), (0, -2), (1, -2)]));
+            pixels.extend(fixed(&[(-2, -1), (-1, -1), (0, -1), (1, -1)]));
+            pixels.push(None);
+            pixels.extend(fixed(&[(-2, 0), (-1, 0)]));
+        }
+        _ => {
+            pixels.extend(fixed(&[(-3, -1), (-2, -1), (-1, -1), (0, -1), (1, -1)]));
+            pixels.push(None);
+            pixels.extend(fixed(&[(-4, 0), (-3, 0), (-2, 0), (-1, 0)]));
+        }
+    }
+    pixels
+}
+
+// The arithmetic generic region decoding procedure of 6.2.5. The adaptive
+// pixels are given as A1, A2, ...
+fn decode_generic(
+    mq: &mut MqDecoder,
+    contexts: &mut [u8],
+    width: u32,
+    height: u32,
+    template: u8,
+    typical_prediction: bool,
+    adaptive: &[(i64, i64)],
+) -> Bitmap {
+    // the adaptive pixels are listed from A4 (or A1) down to A1
+    let mut remaining = adaptive.iter().rev();
+    let pixels: Vec<(i64, i64)> = template_pixels(template)
+        .into_iter()
+        .map(|p| p.unwrap_or_else(|| *remaining.next().unwrap_or(&(0, -1))))
+        .collect();
+    // the context of the "typical prediction" bit of each row
+    let sltp = [0x9B25, 0x0795, 0x00E5, 0x0195][template as usize & 3];
+
+    let mut bitmap = Bitmap::new(width, height, 0);
+    let mut typical = false;
+    for y in 0..height as i64 {
+        if typical_prediction {
+            typical ^= mq.decode(contexts, sltp) == 1;
+            if typical {
+                // the row is the same as the one above
+                if y > 0 {
+                    let (above, row) = bitmap.pixels.split_at_mut(y as usize * width as usize);
+                    row[..width as usize].copy_from_slice(&above[above.len() - width as usize..]);
+                }
+                continue;
+            }
+        }
+        for x in 0..width as i64 {
+            let context = pixels.iter().fold(0, |context, &(dx, dy)| {
+                (context << 1) | bitmap.get(x + dx, y + dy) as usize
+            });
+            if mq.decode(contexts, context) == 1 {
+                bitmap.pixels[(y * width as i64 + x) as usize] = 1;
+            }
+        }
+    }
+    bitmap
+}
+
+fn decode_mmr(data: &[u8], width: u32, height: u32) -> Result<Bitmap, String> {
+    let options = FaxOptions {
+        coding: FaxCoding::Group4,
+        width,
+        height,
+        byte_align: false,
+    };
+    let image = decode_rows(data, &options)?;
+    Ok(Bitmap {
+        width,
+        height,
+        pixels: image.pixels().map(|p| (p[0] == 0) as u8).collect(),
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or("JBIG2 data ends unexpectedly")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn adaptive_pixels(&mut self, count: usize) -> Result<Vec<(i64, i64)>, String> {
+        (0..count)
+            .map(|_| Ok((self.u8()? as i8 as i64, self.u8()? as i8 as i64)))
+            .collect()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos.min(self.data.len())..]
+    }
+}
+
+struct Segment<'a> {
+    number: u32,
+    kind: u8,
+    referred: Vec<u32>,
+    data: &'a [u8],
+    // the height of an immediate generic region of unknown length
+    row_count: Option<u32>,
+}
+
+// the segment header of 7.2
+fn read_segment<'a>(reader: &mut Reader<'a>) -> Result<Segment<'a>, String> {
+    let number = reader.u32()?;
+    let flags = reader.u8()?;
+    let kind = flags & 0x3f;
+
+    let first = reader.u8()?;
+    let count = if first >> 5 == 7 {
+        reader.pos -= 1;
+        let count = reader.u32()? & 0x1fff_ffff;
+        // the retention flags
+        reader.bytes((count as usize + 8) / 8)?;
+        count
+    } else {
+        (first >> 5) as u32
+    };
+    let referred = (0..count)
+        .map(|_| match number {
+            0..=256 => reader.u8().map(|n| n as u32),
+            257..=65536 => reader.u16().map(|n| n as u32),
+            _ => reader.u32(),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // the page association
+    if flags & 0x40 != 0 {
+        reader.u32()?;
+    } else {
+        reader.u8()?;
+    }
+
+    let length = reader.u32()?;
+    if length != u32::MAX {
+        let data = reader.bytes(length as usize)?;
+        return Ok(Segment {
+            number,
+            kind,
+            referred,
+            data,
+            row_count: None,
+        });
+    }
+
+    // Only immediate generic regions may leave out their length. Their data
+    // ends with a marker and the number of rows.
+    if kind != 38 && kind != 39 {
+        return Err(format!("JBIG2 segment {} has no length", number));
+    }
+    let rest = reader.rest();
+    let mmr = rest.get(17).ok_or("JBIG2 data ends unexpectedly")? & 1 == 1;
+    let marker: [u8; 2] = if mmr { [0, 0] } else { [0xff, 0xac] };
+    let end = (18..rest.len().saturating_sub(5))
+        .find(|&i| rest[i..i + 2] == marker)
+        .ok_or("JBIG2 generic region without end marker")?;
+    let data = reader.bytes(end + 6)?;
+    let row_count = u32::from_be_bytes(data[end + 2..end + 6].try_into().unwrap());
+    Ok(Segment {
+        number,
+        kind,
+        referred,
+        data: &data[..end + 2],
+        row_count: Some(row_count),
+    })
+}
+
+// the region segment information field of 7.4.1
+struct RegionInfo {
+    width: u32,
+    height: u32,
+    x: i64,
+    y: i64,
+    operator: u8,
+}
+
+fn read_region_info(reader: &mut Reader) -> Result<RegionInfo, String> {
+    Ok(RegionInfo {
+        width: reader.u32()?,
+        height: reader.u32()?,
+        x: reader.u32()? as i64,
+        y: reader.u32()? as i64,
+        operator: reader.u8()? & 7,
+    })
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), String> {
+    if width as u64 * height as u64 > 100_000_000 {
+        return Err(format!(
+            "JBIG2 bitmap of {}x{} pixels is too large",
+            width, height
+        ));
+    }
+    Ok(())
+}
+
+fn generic_region(segment: &Segment) -> Result<(RegionInfo, Bitmap), String> {
+    let mut reader = Reader {
+        data: segment.data,
+        pos: 0,
+    };
+    let mut info = read_region_info(&mut reader)?;
+    if let Some(rows) = segment.row_count {
+        info.height = rows;
+    }
+    check_size(info.width, info.height)?;
+    let flags = reader.u8()?;
+    let template = (flags >> 1) & 3;
+    if flags & 0x10 != 0 {
+        return Err("extended JBIG2 templates are not supported".into());
+    }
+
+    let bitmap = if flags & 1 == 1 {
+        decode_mmr(reader.rest(), info.width, info.height)?
+    } else {
+        let adaptive = reader.adaptive_pixels(if template == 0 { 4 } else { 1 })?;
+        let mut mq = MqDecoder::new(reader.rest());
+        let mut contexts = vec![0; 1 << 16];
+        decode_generic(
+            &mut mq,
+            &mut contexts,
+            info.width,
+            info.height,
+            template,
+            flags & 8 != 0,
+            &adaptive,
+        )
+    };
+    Ok((info, bitmap))
+}
+
+// the most symbols a dictionary may hold, including the ones it refers to
+const MAX_SYMBOLS: usize = 1 << 16;
+// the most pixels of all new symbols of a dictionary together, like the
+// limit of `check_size`
+const MAX_SYMBOL_AREA: u64 = 100_000_000;
+
+// the symbol dictionary decoding procedure of 6.5
+fn symbol_dictionary(segment: &Segment, input: Vec<Bitmap>) -> Result<Vec<Bitmap>, String> {
+    let mut reader = Reader {
+        data: segment.data,
+        pos: 0,
+    };
+    let flags = reader.u16()?;
+    if flags & 1 != 0 {
+        return Err("Huffman coded JBIG2 symbols are not supported".into());
+    }
+    if flags & 2 != 0 {
+        return Err("refined JBIG2 symbols are not supported".into());
+    }
+    if flags & 0x100 != 0 {
+        return Err("JBIG2 symbol dictionaries with shared contexts are not supported".into());
+    }
+    let template = ((flags >> 10) & 3) as u8;
+    let adaptive = reader.adaptive_pixels(if template == 0 { 4 } else { 1 })?;
+    let exported = reader.u32()? as usize;
+    let new = reader.u32()? as usize;
+    if new > MAX_SYMBOLS || input.len() + new > MAX_SYMBOLS {
+        return Err(format!("JBIG2 symbol dictionary with {} symbols", new));
+    }
+
+    let mut mq = MqDecoder::new(reader.rest());
+    let mut contexts = vec![0; 1 << 16];
+    let mut height_delta = IntegerDecoder::new();
+    let mut width_delta = IntegerDecoder::new();
+    let mut export_runs = IntegerDecoder::new();
+
+    let mut symbols = input;
+    let input_count = symbols.len();
+    let mut height: i64 = 0;
+    let mut area = 0;
+    while symbols.len() < input_count + new {
+        height += height_delta
+            .decode(&mut mq)
+            .ok_or("invalid JBIG2 symbol height")?;
+        let class_start = symbols.len();
+        let mut width: i64 = 0;
+        // all symbols of the same height, until OOB
+        while let Some(delta) = width_delta.decode(&mut mq) {
+            width += delta;
+            if width <= 0 || height <= 0 || symbols.len() >= input_count + new {
+                return Err("invalid JBIG2 symbol size".into());
+            }
+            area += width as u64 * height as u64;
+            if width > u32::MAX as i64 || height > u32::MAX as i64 || area > MAX_SYMBOL_AREA {
+                return Err("JBIG2 symbols are too large".into());
+            }
+            symbols.push(decode_generic(
+                &mut mq,
+                &mut contexts,
+                width as u32,
+                height as u32,
+                template,
+                false,
+                &adaptive,
+            ));
+        }
+        // every height class holds at least one symbol, otherwise garbage
+        // data could keep us here forever
+        if symbols.len() == class_start {
+            return Err("empty JBIG2 symbol height class".into());
+        }
+    }
+
+    // which of the input and new symbols are exported, in runs
+    let mut flags = Vec::with_capacity(symbols.len());
+    let mut export = false;
+    let mut runs = 0;
+    while flags.len() < symbols.len() {
+        let run = export_runs
+            .decode(&mut mq)
+            .ok_or("invalid JBIG2 export flags")?;
+        // only the first run may be empty
+        if run < 0 || (run == 0 && runs > 0) || flags.len() + run as usize > symbols.len() {
+            return Err("invalid JBIG2 export flags".into());
+        }
+        flags.resize(flags.len() + run as usize, export);
+        export = !export;
+        runs += 1;
+    }
+    let result: Vec<Bitmap> = symbols
+        .into_iter()
+        .zip(flags)
+        .filter_map(|(symbol, export)| export.then_some(symbol))
+        .collect();
+    if result.len() != exported {
+        return Err("wrong number of exported JBIG2 symbols".into());
+    }
+    Ok(result)
+}
+
+// the most symbols a text region may place
+const MAX_INSTANCES: u32 = 1 << 20;
+
+// the text region decoding procedure of 6.4
+fn text_region(segment: &Segment, symbols: &[Bitmap]) -> Result<(RegionInfo, Bitmap), String> {
+    let mut reader = Reader {
+        data: segment.data,
+        pos: 0,
+    };
+    let info = read_region_info(&mut reader)?;
+    check_size(info.width, info.height)?;
+    let flags = reader.u16()?;
+    if flags & 1 != 0 {
+        return Err("Huffman coded JBIG2 text regions are not supported".into());
+    }
+    if flags & 2 != 0 {
+        return Err("refined JBIG2 text regions are not supported".into());
+    }
+    let strip_size = 1i64 << ((flags >> 2) & 3);
+    let corner = (flags >> 4) & 3;
+    let transposed = flags & 0x40 != 0;
+    let operator = ((flags >> 7) & 3) as u8;
+    let default_pixel = ((flags >> 9) & 1) as u8;
+    let offset = {
+        let offset = ((flags >> 10) & 0x1f) as i64;
+        if offset > 15 {
+            offset - 32
+        } else {
+            offset
+        }
+    };
+    let instances = reader.u32()?;
+    if instances > MAX_INSTANCES || symbols.len() > MAX_SYMBOLS {
+        return Err("JBIG2 text region with too many symbols".into());
+    }
+
+    let code_length = (0..32)
+        .find(|&n| 1usize << n >= symbols.len())
+        .unwrap_or(32);
+    let mut mq = MqDecoder::new(reader.rest());
+    let mut strip_delta = IntegerDecoder::new();
+    let mut first_delta = IntegerDecoder::new();
+    let mut s_delta = IntegerDecoder::new();
+    let mut t_in_strip = IntegerDecoder::new();
+    let mut ids = IdDecoder::new(code_length);
+    let invalid = || "invalid JBIG2 text region".to_string();
+
+    let mut bitmap = Bitmap::new(info.width, info.height, default_pixel);
+    let mut strip_t = -strip_delta.decode(&mut mq).ok_or_else(invalid)? * strip_size;
+    let mut first_s = 0;
+    let mut decoded = 0;
+    while decoded < instances {
+        strip_t += strip_delta.decode(&mut mq).ok_or_else(invalid)? * strip_size;
+        first_s += first_delta.decode(&mut mq).ok_or_else(invalid)?;
+        let mut s = first_s;
+        loop {
+            let t = strip_t
+                + if strip_size > 1 {
+                    t_in_strip.decode(&mut mq).ok_or_else(invalid)?
+                } else {
+                    0
+                };
+            let symbol = symbols.get(ids.decode(&mut mq)).ok_or_else(invalid)?;
+            let (width, height) = (symbol.width as i64, symbol.height as i64);
+
+            // s runs along the strip and t across it, the reference corner
+            // is the one at (s, t)
+            let right = corner & 2 != 0;
+            let top = corner & 1 != 0;
+            if !transposed {
+                let y = if top { t } else { t - height + 1 };
+                bitmap.combine(symbol, s, y, operator);
+                s += width - 1;
+            } else {
+                let x = if right { t - width + 1 } else { t };
+                bitmap.combine(symbol, x, s, operator);
+                s += height - 1;
+            }
+
+            decoded += 1;
+            if decoded >= instances {
+                break;
+            }
+            match s_delta.decode(&mut mq) {
+                Some(delta) => s += delta + offset,
+                None => break,
+            }
+        }
+    }
+    Ok((info, bitmap))
+}
+
+struct PageBitmap {
+    bitmap: Bitmap,
+    // the height is only known at the end of the last stripe
+    growing: bool,
+    default_pixel: u8,
+}
+
+impl PageBitmap {
+    fn place(&mut self, info: &RegionInfo, region: &Bitmap) -> Result<(), String> {
+        if self.growing {
+            let bottom = u32::try_from(info.y + region.height as i64)
+                .map_err(|_| "JBIG2 region below the page")?;
+            self.grow(bottom)?;
+        }
+        self.bitmap.combine(region, info.x, info.y, info.operator);
+        Ok(())
+    }
+
+    // grows the page to at least `height` rows, within the size limit
+    fn grow(&mut self, height: u32) -> Result<(), String> {
+        if height > self.bitmap.height {
+            check_size(self.bitmap.width, height)?;
+            self.bitmap.pixels.resize(
+                self.bitmap.width as usize * height as usize,
+                self.default_pixel,
+            );
+            self.bitmap.height = height;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the JBIG2 image of a PDF stream (which leaves out the file header
+/// of a JBIG2 file) with the segments in `globals` shared between images.
+pub fn decode_jbig2(data: &[u8], globals: Option<&[u8]>) -> Result<GrayImage, Error> {
+    let bitmap = decode_page(data, globals).map_err(Error::Decode)?;
+    let pixels = bitmap.pixels.iter().map(|&p| 255 * (1 - p)).collect();
+    GrayImage::from_raw(bitmap.width, bitmap.height, pixels)
+        .ok_or_else(|| Error::Decode("invalid JBIG2 page".into()))
+}
+
+fn decode_page(data: &[u8], globals: Option<&[u8]>) -> Result<Bitmap, String> {
+    let mut dictionaries: HashMap<u32, Vec<Bitmap>> = HashMap::new();
+    let mut page: Option<PageBitmap> = None;
+
+    for stream in globals.into_iter().chain(std::iter::once(data)) {
+        let mut reader = Reader {
+            data: stream,
+            pos: 0,
+        };
+        while reader.pos < stream.len() {
+            let segment = read_segment(&mut reader)?;
+            let referred_symbols = || {
+                segment
+                    .referred
+                    .iter()
+                    .filter_map(|n| dictionaries.get(n))
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            match segment.kind {
+                0 => {
+                    let symbols = symbol_dictionary(&segment, referred_symbols())?;
+                    dictionaries.insert(segment.number, symbols);
+                }
+                6 | 7 | 38 | 39 => {
+                    let page = page
+                        .as_mut()
+                        .ok_or("JBIG2 region before the page information")?;
+                    let (info, region) = if segment.kind < 38 {
+                        text_region(&segment, &referred_symbols())?
+                    } else {
+                        generic_region(&segment)?
+                    };
+                    page.place(&info, &region)?;
+                }
+                48 => {
+                    let mut reader = Reader {
+                        data: segment.data,
+                        pos: 0,
+                    };
+                    let width = reader.u32()?;
+                    let height = reader.u32()?;
+                    reader.bytes(8)?; // the resolution
+                    let default_pixel = (reader.u8()? >> 2) & 1;
+                    let growing = height == u32::MAX;
+                    let height = if growing { 0 } else { height };
+                    check_size(width, height)?;
+                    page = Some(PageBitmap {
+                        bitmap: Bitmap::new(width, height, default_pixel),
+                        growing,
+                        default_pixel,
+                    });
+                }
+                // the end of a stripe tells us the height of the page so far
+                50 => {
+                    if let Some(page) = page.as_mut().filter(|page| page.growing) {
+                        let end = Reader {
+                            data: segment.data,
+                            pos: 0,
+                        }
+                        .u32()?;
+                        page.grow(end.checked_add(1).ok_or("invalid JBIG2 stripe end")?)?;
+                    }
+                }
+                4 | 16 | 20 | 22 | 23 | 36 | 40 | 42 | 43 => {
+                    return Err(format!(
+                        "JBIG2 segments of type {} are not supported",
+                        segment.kind
+                    ))
+                }
+                // end of page and file, profiles, tables and extensions
+                _ => {}
+            }
+        }
+    }
+
+    Ok(page.ok_or("JBIG2 image without page")?.bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The MQ encoder of Annex E, which is what encoders like jbig2enc use.
+    // The first byte of `out` stands for the byte before the data.
+    struct MqEncoder {
+        a: u32,
+        c: u32,
+        ct: u32,
+        out: Vec<u8>,
+    }
+
+    impl MqEncoder {
+        fn new() -> Self {
+            MqEncoder {
+                a: 0x8000,
+                c: 0,
+                ct: 12,
+                out: vec![0],
+            }
+        }
+
+        fn encode(&mut self, contexts: &mut [u8], cx: usize, bit: u32) {
+            let index = (contexts[cx] >> 1) as usize;
+            let mps = (contexts[cx] & 1) as u32;
+            let (qe, nmps, nlps, switch) = QE_TABLE[index];
+            self.a -= qe;
+            if bit == mps {
+                if self.a & 0x8000 != 0 {
+                    self.c += qe;
+                    return;
+                }
+                if self.a < qe {
+                    self.a = qe;
+                } else {
+                    self.c += qe;
+                }
+                contexts[cx] = (nmps << 1) | mps as u8;
+            } else {
+                if self.a < qe {
+                    self.c += qe;
+                } else {
+                    self.a = qe;
+                }
+                let mps = if switch { 1 - mps } else { mps };
+                contexts[cx] = (nlps << 1) | mps as u8;
+            }
+            loop {
+                self.a <<= 1;
+                self.c <<= 1;
+                self.ct -= 1;
+                if self.ct == 0 {
+                    self.byte_out();
+                }
+                if self.a & 0x8000 != 0 {
+                    break;
+                }
+            }
+        }
+
+        fn byte_out(&mut self) {
+            let last = self.out.last_mut().unwrap();
+            if *last != 0xff && self.c >= 0x800_0000 {
+                // the carry goes into the last byte
+                *last += 1;
+                self.c &= 0x7ff_ffff;
+            }
+            if *last == 0xff {
+                self.out.push((self.c >> 20) as u8);
+                self.c &= 0xf_ffff;
+                self.ct = 7;
+            } else {
+                self.out.push((self.c >> 19) as u8);
+                self.c &= 0x7_ffff;
+                self.ct = 8;
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            let top = self.c + self.a;
+            self.c |= 0xffff;
+            if self.c >= top {
+                self.c -= 0x8000;
+            }
+            self.c <<= self.ct;
+            self.byte_out();
+            self.c <<= self.ct;
+            self.byte_out();
+            if self.out.last() != Some(&0xff) {
+                self.out.push(0xff);
+            }
+            self.out.push(0xac);
+            self.out.remove(0);
+            self.out
+        }
+    }
+
+    // the inverse of `IntegerDecoder`
+    struct IntegerEncoder {
+        contexts: Vec<u8>,
+    }
+
+    impl IntegerEncoder {
+        fn new() -> Self {
+            IntegerEncoder {
+                contexts: vec![0; 512],
+            }
+        }
+
+        fn encode(&mut self, mq: &mut MqEncoder, value: Option<i64>) {
+            let mut prev = 1;
+            let mut write = |bits: u32, value: i64| {
+                for i in (0..bits).rev() {
+                    let bit = ((value >> i) & 1) as usize;
+                    mq.encode(&mut self.contexts, prev, bit as u32);
+                    prev = if prev < 256 {
+                        (prev << 1) | bit
+                    } else {
+                        (((prev << 1) | bit) & 511) | 256
+                    };
+                }
+            };
+            let (negative, value) = match value {
+                Some(value) => (value < 0, value.abs()),
+                None => (true, 0),
+            };
+            // (prefix, prefix length, value bits, offset)
+            let (prefix, length, bits, offset) = [
+                (0b0, 1, 2, 0),
+                (0b10, 2, 4, 4),
+                (0b110, 3, 6, 20),
+                (0b1110, 4, 8, 84),
+                (0b11110, 5, 12, 340),
+                (0b11111, 5, 32, 4436),
+            ]
+            .into_iter()
+            .find(|&(_, _, bits, offset)| value - offset < 1 << bits)
+            .unwrap();
+            write(1, negative as i64);
+            write(length, prefix);
+            write(bits, value - offset);
+        }
+    }
+
+    fn encode_id(mq: &mut MqEncoder, contexts: &mut [u8], code_length: u32, id: usize) {
+        let mut prev = 1;
+        for i in (0..code_length).rev() {
+            let bit = (id >> i) & 1;
+            mq.encode(contexts, prev, bit as u32);
+            prev = (prev << 1) | bit;
+        }
+    }
+
+    // the inverse of `decode_generic`
+    fn encode_generic(
+        mq: &mut MqEncoder,
+        contexts: &mut [u8],
+        bitmap: &Bitmap,
+        template: u8,
+        typical_prediction: bool,
+        adaptive: &[(i64, i64)],
+    ) {
+        let mut remaining = adaptive.iter().rev();
+        let pixels: Vec<(i64, i64)> = template_pixels(template)
+            .into_iter()
+            .map(|p| p.unwrap_or_else(|| *remaining.next().unwrap()))
+            .collect();
+        let sltp = [0x9B25, 0x0795, 0x00E5, 0x0195][template as usize];
+        let width = bitmap.width as usize;
+        let row = |y: usize| &bitmap.pixels[y * width..(y + 1) * width];
+
+        let mut typical = false;
+        for y in 0..bitmap.height as i64 {
+            if typical_prediction {
+                let same = if y == 0 {
+                    row(0).iter().all(|&p| p == 0)
+                } else {
+                    row(y as usize) == row(y as usize - 1)
+                };
+                mq.encode(contexts, sltp, (same != typical) as u32);
+                typical = same;
+                if same {
+                    continue;
+                }
+            }
+            for x in 0..bitmap.width as i64 {
+                let context = pixels.iter().fold(0, |context, &(dx, dy)| {
+                    (context << 1) | bitmap.get(x + dx, y + dy) as usize
+                });
+                mq.encode(contexts, context, bitmap.get(x, y) as u32);
+            }
+        }
+    }
+
+    // the nominal adaptive pixels of the templates
+    fn nominal_adaptive(template: u8) -> Vec<(i64, i64)> {
+        match template {
+            0 => vec![(3, -1), (-3, -1), (2, -2), (-2, -2)],
+            1 => vec![(3, -1)],
+            _ => vec![(2, -1)],
+        }
+    }
+
+    fn adaptive_bytes(adaptive: &[(i64, i64)]) -> Vec<u8> {
+        adaptive
+            .iter()
+            .flat_map(|&(x, y)| [x as i8 as u8, y as i8 as u8])
+            .collect()
+    }
+
+    // a ring with a bar through it, something like a filled in box
+    fn pattern(width: u32, height: u32) -> Bitmap {
+        let mut bitmap = Bitmap::new(width, height, 0);
+        let (cx, cy) = (width as i64 / 2, height as i64 / 2);
+        let radius = cx.min(cy) * cx.min(cy);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let distance = (x - cx) * (x - cx) + (y - cy) * (y - cy);
+                let ring = distance <= radius && 2 * distance >= radius;
+                let bar = (y - cy).abs() < 2 && x > cx / 2;
+                bitmap.pixels[(y * width as i64 + x) as usize] = (ring || bar) as u8;
+            }
+        }
+        bitmap
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    fn segment(number: u32, kind: u8, referred: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = number.to_be_bytes().to_vec();
+        bytes.extend([kind, (referred.len() as u8) << 5]);
+        bytes.extend(referred);
+        bytes.push(1);
+        bytes.extend((data.len() as u32).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn page_info(width: u32, height: u32) -> Vec<u8> {
+        let mut info = words(&[width, height, 0, 0]);
+        info.extend([0, 0, 0]);
+        info
+    }
+
+    // the data of an arithmetic generic region with `bitmap` at (x, y)
+    fn generic_region_data(bitmap: &Bitmap, x: u32, y: u32, template: u8, tpgdon: bool) -> Vec<u8> {
+        let adaptive = nominal_adaptive(template);
+        let mut mq = MqEncoder::new();
+        let mut contexts = vec![0; 1 << 16];
+        encode_generic(&mut mq, &mut contexts, bitmap, template, tpgdon, &adaptive);
+
+        let mut data = words(&[bitmap.width, bitmap.height, x, y]);
+        data.extend([0, (template << 1) | ((tpgdon as u8) << 3)]);
+        data.extend(adaptive_bytes(&adaptive));
+        data.extend(mq.finish());
+        data
+    }
+
+    // a symbol dictionary with `new` symbols and those of `input` whose
+    // exported flags are `export`
+    fn symbol_dictionary_data(input: usize, new: &[Bitmap], export: &[bool]) -> Vec<u8> {
+        let adaptive = nominal_adaptive(0);
+        let mut mq = MqEncoder::new();
+        let mut contexts = vec![0; 1 << 16];
+        let mut height_delta = IntegerEncoder::new();
+        let mut width_delta = IntegerEncoder::new();
+        let mut export_runs = IntegerEncoder::new();
+
+        // the symbols come in classes of the same height
+        let mut height = 0;
+        for class in new.chunk_by(|a, b| a.height == b.height) {
+            height_delta.encode(&mut mq, Some(class[0].height as i64 - height));
+            height = class[0].height as i64;
+            let mut width = 0;
+            for symbol in class {
+                width_delta.encode(&mut mq, Some(symbol.width as i64 - width));
+                width = symbol.width as i64;
+                encode_generic(&mut mq, &mut contexts, symbol, 0, false, &adaptive);
+            }
+            width_delta.encode(&mut mq, None);
+        }
+        assert_eq!(export.len(), input + new.len());
+        let mut exported = false;
+        let mut position = 0;
+        while position < export.len() {
+            let run = export[position..]
+                .iter()
+                .take_while(|&&e| e == exported)
+                .count();
+            export_runs.encode(&mut mq, Some(run as i64));
+            position += run;
+            exported = !exported;
+        }
+
+        let mut data = vec![0, 0];
+        data.extend(adaptive_bytes(&adaptive));
+        let exported = export.iter().filter(|&&e| e).count() as u32;
+        data.extend(words(&[exported, new.len() as u32]));
+        data.extend(mq.finish());
+        data
+    }
+
+    // A text region of `width`x`height` with the top left corners of the
+    // symbols at (s, t), in strips of the same t, from the symbols `symbols`.
+    fn text_region_data(
+        width: u32,
+        height: u32,
+        symbols: &[Bitmap],
+        instances: &[(usize, i64, i64)],
+    ) -> Vec<u8> {
+        let mut mq = MqEncoder::new();
+        let mut strip_delta = IntegerEncoder::new();
+        let mut first_delta = IntegerEncoder::new();
+        let mut s_delta = IntegerEncoder::new();
+        let mut id_contexts = vec![0; 1 << 8];
+        let code_length = (0..32).find(|&n| 1usize << n >= symbols.len()).unwrap();
+
+        strip_delta.encode(&mut mq, Some(0));
+        let (mut strip_t, mut first_s) = (0, 0);
+        for strip in instances.chunk_by(|a, b| a.2 == b.2) {
+            strip_delta.encode(&mut mq, Some(strip[0].2 - strip_t));
+            strip_t = strip[0].2;
+            first_delta.encode(&mut mq, Some(strip[0].1 - first_s));
+            first_s = strip[0].1;
+            let mut s = first_s;
+            for (i, &(id, symbol_s, _)) in strip.iter().enumerate() {
+                if i > 0 {
+                    s_delta.encode(&mut mq, Some(symbol_s - s));
+                    s = symbol_s;
+                }
+                encode_id(&mut mq, &mut id_contexts, code_length, id);
+                s += symbols[id].width as i64 - 1;
+            }
+            s_delta.encode(&mut mq, None);
+        }
+
+        let mut data = words(&[width, height, 0, 0]);
+        data.push(0);
+        // top left reference corner, one row strips, OR combination
+        data.extend(0x0010u16.to_be_bytes());
+        data.extend((instances.len() as u32).to_be_bytes());
+        data.extend(mq.finish());
+        data
+    }
+
+    fn glyphs() -> Vec<Bitmap> {
+        let glyph = |width: u32, height: u32, f: fn(u32, u32) -> bool| {
+            let mut bitmap = Bitmap::new(width, height, 0);
+            for y in 0..height {
+                for x in 0..width {
+                    bitmap.pixels[(y * width + x) as usize] = f(x, y) as u8;
+                }
+            }
+            bitmap
+        };
+        vec![
+            glyph(5, 7, |x, y| x == 0 || y == 0 || y == 3),
+            glyph(6, 7, |x, y| x == 5 || y == 6 || x + y == 6),
+            glyph(4, 9, |x, y| (x + y) % 3 == 0),
+        ]
+    }
+
+    fn mmr_fixture() -> Vec<Vec<u8>> {
+        // a black bar in the middle of a 40x20 generic region on a 50x30 page
+        let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+        for _ in 0..20 {
+            let pels = (0..40).map(|x| match (10..30).contains(&x) {
+                true => fax::Color::Black,
+                false => fax::Color::White,
+            });
+            encoder.encode_line(pels, 40).unwrap();
+        }
+        let mmr = encoder.finish().unwrap().finish();
+        let mut region = words(&[40, 20, 5, 5]);
+        region.extend([0, 1]);
+        region.extend(&mmr);
+        vec![
+            segment(0, 48, &[], &page_info(50, 30)),
+            segment(1, 38, &[], &region),
+            segment(2, 49, &[], &[]),
+        ]
+    }
+
+    fn generic_fixture(template: u8, tpgdon: bool) -> Vec<Vec<u8>> {
+        let region = generic_region_data(&pattern(37, 23), 5, 4, template, tpgdon);
+        vec![
+            segment(0, 48, &[], &page_info(50, 30)),
+            segment(1, 38, &[], &region),
+            segment(2, 49, &[], &[]),
+        ]
+    }
+
+    fn text_fixture() -> Vec<Vec<u8>> {
+        let glyphs = glyphs();
+        let instances = [
+            (0, 2, 1),
+            (1, 9, 1),
+            (0, 16, 1),
+            (2, 30, 1),
+            (1, 4, 12),
+            (2, 11, 12),
+        ];
+        vec![
+            segment(0, 0, &[], &symbol_dictionary_data(0, &glyphs, &[true; 3])),
+            segment(1, 48, &[], &page_info(40, 25)),
+            segment(2, 6, &[0], &text_region_data(40, 25, &glyphs, &instances)),
+            segment(3, 49, &[], &[]),
+        ]
+    }
+
+    #[test]
+    fn arithmetic_decoder() {
+        // the test sequence of H.2 in T.88, decoded with a single context
+        let encoded = [
+            0x84, 0xC7, 0x3B, 0xFC, 0xE1, 0xA1, 0x43, 0x04, 0x02, 0x20, 0x00, 0x00, 0x41, 0x0D,
+            0xBB, 0x86, 0xF4, 0x31, 0x7F, 0xFF, 0x88, 0xFF, 0x37, 0x47, 0x1A, 0xDB, 0x6A, 0xDF,
+            0xFF, 0xAC,
+        ];
+        let expected = [
+            0x00, 0x02, 0x00, 0x51, 0x00, 0x00, 0x00, 0xC0, 0x03, 0x52, 0x87, 0x2A, 0xAA, 0xAA,
+            0xAA, 0xAA, 0x82, 0xC0, 0x20, 0x00, 0xFC, 0xD7, 0x9E, 0xF6, 0xBF, 0x7F, 0xED, 0x90,
+            0x4F, 0x46, 0xA3, 0xBF,
+        ];
+        let mut mq = MqDecoder::new(&encoded);
+        let mut contexts = [0u8];
+        let decoded: Vec<u8> = (0..expected.len())
+            .map(|_| (0..8).fold(0, |byte, _| (byte << 1) | mq.decode(&mut contexts, 0) as u8))
+            .collect();
+        assert_eq!(decoded, expected);
+
+        // the encoder the other tests use turns it back into the same data
+        let mut mq = MqEncoder::new();
+        let mut contexts = [0u8];
+        for byte in expected {
+            for i in (0..8).rev() {
+                mq.encode(&mut contexts, 0, (byte >> i) as u32 & 1);
+            }
+        }
+        assert_eq!(mq.finish(), encoded);
+    }
+
+    #[test]
+    fn mmr_page() {
+        let image = decode_jbig2(&mmr_fixture().concat(), None).unwrap();
+        assert_eq!(image.dimensions(), (50, 30));
+        assert_eq!(image.get_pixel(20, 10)[0], 0);
+        assert_eq!(image.get_pixel(12, 10)[0], 255);
+        assert_eq!(image.get_pixel(20, 27)[0], 255);
+
+        // the region needs the page
+        assert!(decode_jbig2(&mmr_fixture()[1], None).is_err());
+    }
+
+    #[test]
+    fn generic_regions() {
+        for template in 0..4 {
+            for tpgdon in [false, true] {
+                let mut expected = Bitmap::new(50, 30, 0);
+                expected.combine(&pattern(37, 23), 5, 4, 0);
+                let page = decode_page(&generic_fixture(template, tpgdon).concat(), None);
+                assert_eq!(page, Ok(expected), "template {}", template);
+            }
+        }
+    }
+
+    #[test]
+    fn striped_page() {
+        // a page of unknown height, with an immediate generic region whose
+        // length is only given by its end
+        let top = pattern(30, 12);
+        let bottom = pattern(20, 9);
+        let mut info = words(&[30, u32::MAX, 0, 0]);
+        info.extend([0, 0x80, 0x10]);
+        let mut unknown_length = segment(1, 38, &[], &[]);
+        unknown_length.truncate(unknown_length.len() - 4);
+        unknown_length.extend(words(&[u32::MAX]));
+        unknown_length.extend(generic_region_data(&top, 0, 0, 0, true));
+        unknown_length.extend(words(&[12]));
+
+        let stripes = [
+            segment(0, 48, &[], &info),
+            unknown_length,
+            segment(2, 50, &[], &words(&[13])),
+            segment(3, 38, &[], &generic_region_data(&bottom, 4, 14, 2, false)),
+            segment(4, 50, &[], &words(&[27])),
+            segment(5, 49, &[], &[]),
+        ];
+        let mut expected = Bitmap::new(30, 28, 0);
+        expected.combine(&top, 0, 0, 0);
+        expected.combine(&bottom, 4, 14, 0);
+        assert_eq!(decode_page(&stripes.concat(), None), Ok(expected));
+
+        // the page may not grow beyond the size limit or wrap around
+        for end in [u32::MAX, 10_000_000] {
+            let mut data = stripes[..2].concat();
+            data.extend(segment(2, 50, &[], &words(&[end])));
+            assert!(decode_page(&data, None).is_err(), "end of stripe {}", end);
+        }
+        let far_down = generic_region_data(&bottom, 0, u32::MAX - 5, 0, false);
+        let mut data = stripes[0].clone();
+        data.extend(segment(1, 38, &[], &far_down));
+        assert!(decode_page(&data, None).is_err());
+    }
+
+    #[test]
+    fn text_region() {
+        let glyphs = glyphs();
+        let mut expected = Bitmap::new(40, 25, 0);
+        for (id, s, t) in [
+            (0, 2, 1),
+            (1, 9, 1),
+            (0, 16, 1),
+            (2, 30, 1),
+            (1, 4, 12),
+            (2, 11, 12),
+        ] {
+            expected.combine(&glyphs[id], s, t, 0);
+        }
+        assert_eq!(decode_page(&text_fixture().concat(), None), Ok(expected));
+    }
+
+    #[test]
+    fn symbol_dictionary_exports() {
+        // the second dictionary exports the second symbol of the first one
+        // and its own symbol, which the text region then refers to
+        let glyphs = glyphs();
+        let extra = pattern(8, 8);
+        let first = symbol_dictionary_data(0, &glyphs, &[true; 3]);
+        let second =
+            symbol_dictionary_data(3, std::slice::from_ref(&extra), &[false, true, false, true]);
+        let text = text_region_data(
+            20,
+            10,
+            &[glyphs[1].clone(), extra.clone()],
+            &[(1, 0, 0), (0, 10, 2)],
+        );
+        let data = [
+            segment(0, 0, &[], &first),
+            segment(1, 0, &[0], &second),
+            segment(2, 48, &[], &page_info(20, 10)),
+            segment(3, 6, &[1], &text),
+        ]
+        .concat();
+        let mut expected = Bitmap::new(20, 10, 0);
+        expected.combine(&extra, 0, 0, 0);
+        expected.combine(&glyphs[1], 10, 2, 0);
+        assert_eq!(decode_page(&data, None), Ok(expected));
+
+        // the dictionary says it exports a different number of symbols
+        let mut wrong = second.clone();
+        wrong[13] = 1;
+        let data = [segment(0, 0, &[], &first), segment(1, 0, &[0], &wrong)].concat();
+        assert!(decode_page(&data, None).is_err());
+    }
+
+    #[test]
+    fn broken_data() {
+        let fixtures = [
+            mmr_fixture(),
+            generic_fixture(0, true),
+            generic_fixture(3, false),
+            text_fixture(),
+        ];
+
+        // data cut off inside a segment
+        for segments in &fixtures {
+            let data = segments.concat();
+            let boundaries: Vec<usize> = segments
+                .iter()
+                .scan(0, |end, segment| {
+                    *end += segment.len();
+                    Some(*end)
+                })
+                .collect();
+            for length in 1..data.len() {
+                if !boundaries.contains(&length) {
+                    assert!(decode_jbig2(&data[..length], None).is_err(), "{}", length);
+                }
+            }
+        }
+
+        // garbage in place of some of the bytes, or all of them, may decode
+        // to anything but must neither panic nor hang
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move |below: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as usize
+        };
+        for _ in 0..400 {
+            let mut data = fixtures[random(fixtures.len())].concat();
+            for _ in 0..1 + random(4) {
+                let position = random(data.len());
+                data[position] = random(256) as u8;
+            }
+            let _ = decode_jbig2(&data, None);
+        }
+        for _ in 0..200 {
+            let data: Vec<u8> = (0..random(200)).map(|_| random(256) as u8).collect();
+            let _ = decode_jbig2(&data, None);
+        }
+    }
+}
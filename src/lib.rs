@@ -1,80 +1,151 @@
+pub mod ccitt;
+pub mod colours;
+pub mod error;
+pub mod feedback;
+pub mod grading;
 pub mod image_container;
 pub mod image_helpers;
+pub mod jbig2;
+pub mod naming;
+pub mod pdf_merge;
+pub mod pdf_raster;
+pub mod pipeline;
 pub mod point;
 pub mod report;
+pub mod report_pdf;
 pub mod scan;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod simulate;
+pub mod sink;
+pub mod student_id;
+pub mod survey;
 pub mod template;
 pub mod template_scan;
 pub mod typst_helpers;
 #[cfg(target_arch = "wasm32")]
 pub mod webapp;
 
+pub use crate::error::Error;
+
 use crate::image_container::SingleImageContainer;
 use crate::image_helpers::binary_image_from_image;
 use crate::scan::Scan;
 use crate::template::Template;
 use crate::template_scan::TemplateScan;
 
+/// Grades all pages of the container and hands the results to `records`:
+/// the CSV records, `issues.json`, the annotated images (or `report.pdf`) and
+/// whatever else the settings ask for.
+///
+/// `progress` is called after every sheet. Once `cancel` is cancelled the run
+/// stops with `Error::Cancelled` and nothing more is written.
 #[cfg(not(target_arch = "wasm32"))]
 pub fn generate_reports_for_image_container(
     container: &mut dyn crate::image_container::ImageContainer,
     template: &Template,
     key: &crate::template::ExamKey,
-    out_prefix: String,
-) -> Result<String, Box<dyn std::error::Error>> {
-    use itertools::Itertools;
-    use rayon::prelude::*;
-    use template::are_compatible;
+    settings: &crate::report::ReportSettings,
+    records: &mut dyn crate::sink::RecordSink,
+    progress: &mut dyn FnMut(crate::pipeline::Progress),
+    cancel: &crate::pipeline::CancellationToken,
+) -> Result<(), Error> {
+    use crate::pipeline::GradingRun;
+    use crate::sink::{PdfSink, PngSink};
 
-    if !are_compatible(template, key) {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Template and key are incompatible",
-        )));
+    match settings.combined_pdf {
+        Some(pdf) => {
+            let images = PdfSink::new(pdf, &settings.title);
+            let run = GradingRun::new(template, key, settings, images)?;
+            grade_all(container, run, records, progress, cancel)
+        }
+        None => {
+            let run = GradingRun::new(template, key, settings, PngSink)?;
+            grade_all(container, run, records, progress, cancel)
+        }
     }
+}
 
-    let iterator = container.to_iter();
-    let mut all_records = Vec::new();
-    let chunksize = 100;
-    for (turn, chunk) in iterator.chunks(chunksize).into_iter().enumerate() {
-        let images: Vec<image::GrayImage> = chunk.collect();
+// Pages are decoded here, graded on `settings.threads` workers and added in
+// page order.
+#[cfg(not(target_arch = "wasm32"))]
+fn grade_all<I: crate::sink::ImageSink>(
+    container: &mut dyn crate::image_container::ImageContainer,
+    run: crate::pipeline::GradingRun<I>,
+    records: &mut dyn crate::sink::RecordSink,
+    progress: &mut dyn FnMut(crate::pipeline::Progress),
+    cancel: &crate::pipeline::CancellationToken,
+) -> Result<(), Error> {
+    let total = Some(container.page_count());
+    crate::pipeline::for_each_page(
+        container,
+        run.settings.threads,
+        cancel,
+        |idx, page| run.encode(&run.grade(&format!("page{}", idx + 1), page)),
+        |_, sheet| {
+            run.add(sheet, records)?;
+            progress(run.progress(total));
+            Ok(())
+        },
+    )?;
 
-        // Process each chunk in parallel and collect the results
-        let chunk_records: Vec<_> = images
-            .into_par_iter()
-            .enumerate()
-            .map(|(idx, img)| {
-                let scan = Scan { image: img };
-                let template_scan = TemplateScan::new(template, scan);
-                let report = template_scan
-                    .generate_image_report(key, &format!("page{}", idx + turn * chunksize + 1));
-                report.save_to_file(&out_prefix);
-                report.to_serializable_vector()
-            })
-            .collect();
+    cancel.check()?;
+    run.finish(records)
+}
 
-        // Add this chunk's records to the main collection
-        all_records.extend(chunk_records);
-    }
+// Survey mode: there is no key, we just tally the responses of all scans and
+// save the aggregated results (and bar charts) with the given prefix.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate_survey_for_image_container(
+    container: &mut dyn crate::image_container::ImageContainer,
+    template: &Template,
+    record_ids: bool,
+    out_prefix: String,
+) -> Result<crate::survey::SurveyTally, Error> {
+    use crate::pipeline::{catch_panic, for_each_page, CancellationToken};
+    use crate::survey::{SurveyResponse, SurveyTally};
 
-    // Write all records to CSV
-    let mut csv_writer = csv::Writer::from_writer(std::io::Cursor::new(Vec::new()));
-    let header = template.to_csv_header();
-    csv_writer.write_record(&header)?;
-    for record in all_records {
-        csv_writer.serialize(record)?;
-    }
-    let csv_data = csv_writer.into_inner()?.into_inner();
-    Ok(String::from_utf8(csv_data)?)
+    let mut tally = SurveyTally::new(template, record_ids);
+    // there is nothing to tally for pages we cannot read or align, they are
+    // listed with the reason instead
+    for_each_page(
+        container,
+        0,
+        &CancellationToken::default(),
+        |idx, page| {
+            let identifier = format!("page{}", idx + 1);
+            page.and_then(|image| {
+                catch_panic(|| {
+                    let template_scan = TemplateScan::aligned(template, Scan::new(image))?;
+                    Ok(SurveyResponse::from_scan(
+                        &template_scan,
+                        &identifier,
+                        record_ids,
+                    ))
+                })
+            })
+            .map_err(|error| (identifier, error))
+        },
+        |_, response| {
+            match response {
+                Ok(response) => tally.add(response),
+                Err((identifier, error)) => {
+                    log::warn!("skipping {}: {}", identifier, error);
+                    tally.add_unreadable(&identifier, &error);
+                }
+            }
+            Ok(())
+        },
+    )?;
+
+    tally.save_to_files(&out_prefix)?;
+    Ok(tally)
 }
 
 pub fn debug_report(container: &SingleImageContainer, template: &Template) {
     use crate::point::Point;
-    let scan = Scan {
-        image: binary_image_from_image(container.image.clone()),
-    };
-    let h_scale = (template.height as f64) / (scan.image.height() as f64);
-    let w_scale = (template.width as f64) / (scan.image.width() as f64);
+    let scan = Scan::new(binary_image_from_image(container.image.clone()));
+    let h_scale = (template.height as f64) / (scan.image().height() as f64);
+    let w_scale = (template.width as f64) / (scan.image().width() as f64);
 
     let template_scan = TemplateScan::new(template, scan);
     let scale = (h_scale + w_scale) / 2.0;
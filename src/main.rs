@@ -1,14 +1,114 @@
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+    use autograder::colours::ColourScheme;
     use autograder::debug_report;
-    use autograder::generate_reports_for_image_container;
-    use autograder::image_container::{PdfContainer, SingleImageContainer, TiffContainer};
-    use autograder::template::{ExamKey, Template};
-    use autograder::typst_helpers::typst_frame_to_template;
-    use autograder::typst_helpers::*;
-    use clap::{value_parser, Arg, Command};
+    use autograder::feedback::FeedbackMode;
+    use autograder::grading::{GradingMode, GradingScale};
+    use autograder::image_container::{
+        open_container, ChainedContainer, ImageContainer, SingleImageContainer,
+    };
+    use autograder::naming::NamingScheme;
+    use autograder::pipeline::{CancellationToken, Progress};
+    use autograder::report::ReportSettings;
+    use autograder::report_pdf::{PageCompression, PageOrder, PdfSettings};
+    use autograder::simulate::{Noise, Simulation};
+    use autograder::sink::{records_to_csv, DirectorySink, RecordSink};
+    use autograder::student_id::CheckDigit;
+    use autograder::template::{key_from_json, Template};
+    use autograder::template_scan::VersionFallback;
+    use autograder::typst_helpers::{
+        document_to_pdf, generate_form_and_template, parse_option_labels, FormSettings,
+    };
+    use autograder::{generate_reports_for_image_container, generate_survey_for_image_container};
+    use clap::{value_parser, Arg, ArgAction, Command};
     use std::path::Path;
+
+    fn parse_check_digit(scheme: &str) -> Option<CheckDigit> {
+        match scheme {
+            "luhn" => Some(CheckDigit::Luhn),
+            "mod11" => Some(CheckDigit::Mod11),
+            _ => None,
+        }
+    }
+
+    // a single line on stderr that is redrawn after every sheet
+    fn print_progress(progress: Progress) {
+        use std::io::IsTerminal;
+        if !std::io::stderr().is_terminal() {
+            return;
+        }
+        let width = 40;
+        let (bar, total) = match progress.total {
+            Some(total) if total > 0 => {
+                let filled = width * progress.done.min(total) / total;
+                (
+                    format!("[{}{}] ", "#".repeat(filled), " ".repeat(width - filled)),
+                    format!("/{}", total),
+                )
+            }
+            _ => (String::new(), String::new()),
+        };
+        eprint!(
+            "\r{}{}{} sheets, {} to grade by hand",
+            bar, progress.done, total, progress.issues
+        );
+        if progress.total == Some(progress.done) {
+            eprintln!();
+        }
+    }
+
+    // The CSV records go to stdout, where scripts expect them, and with
+    // --csv-file into results.csv as well. All other files go into the
+    // output directory.
+    struct CliSink {
+        dir: DirectorySink,
+        csv_file: bool,
+    }
+
+    impl RecordSink for CliSink {
+        fn add_records(
+            &mut self,
+            header: &[String],
+            records: &[Vec<String>],
+        ) -> Result<(), autograder::error::Error> {
+            println!(
+                "{}",
+                String::from_utf8_lossy(&records_to_csv(header, records)?)
+            );
+            if self.csv_file {
+                self.dir.add_records(header, records)?;
+            }
+            Ok(())
+        }
+
+        fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), autograder::error::Error> {
+            self.dir.add_file(name, data)
+        }
+
+        fn add_file_from(
+            &mut self,
+            name: &str,
+            data: &mut dyn std::io::Read,
+        ) -> Result<(), autograder::error::Error> {
+            self.dir.add_file_from(name, data)
+        }
+    }
+
+    // all given files, directories and globs are graded as one batch
+    fn open_containers<'a>(
+        paths: impl Iterator<Item = &'a String>,
+    ) -> Result<Box<dyn ImageContainer>, Box<dyn std::error::Error>> {
+        let mut containers = paths
+            .map(|path| open_container(Path::new(path)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if containers.len() == 1 {
+            Ok(containers.remove(0))
+        } else {
+            Ok(Box::new(ChainedContainer::new(containers)))
+        }
+    }
+
     let matches = Command::new("autograder")
         .about("automatically grade MCQ exams using optical mark recognition")
         .subcommand(Command::new("test"))
@@ -34,8 +134,242 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
                 )
                 .arg(
                     Arg::new("images")
+                        .num_args(1..)
                         .default_value("tests/assets/scanner-multipagetiff.tif")
-                        .help("image container in PDF or multipage TIFF format"),
+                        .help(
+                            "PDF, TIFF or ZIP files, images, directories or globs like \"scans/*.jpg\"",
+                        ),
+                )
+                .arg(
+                    Arg::new("id-check")
+                        .long("id-check")
+                        .value_parser(["luhn", "mod11"])
+                        .help("Validate student IDs with a check digit (overrides the template)"),
+                )
+                .arg(
+                    Arg::new("grades")
+                        .long("grades")
+                        .value_name("SCALE")
+                        .help("Grading scale like \"A:90,B:80,C:70,D:60,F:0\""),
+                )
+                .arg(
+                    Arg::new("grading")
+                        .long("grading")
+                        .value_parser(["percent", "points", "rank"])
+                        .default_value("percent")
+                        .help("Thresholds are percentages, points or top shares of students"),
+                )
+                .arg(
+                    Arg::new("feedback")
+                        .long("feedback")
+                        .value_parser(["per-student", "merged"])
+                        .help("Write feedback PDFs, one per student or one sorted by ID"),
+                )
+                .arg(
+                    Arg::new("title")
+                        .long("title")
+                        .default_value("Exam report")
+                        .help("Title of the feedback and report PDFs"),
+                )
+                .arg(
+                    Arg::new("pdf")
+                        .long("pdf")
+                        .action(ArgAction::SetTrue)
+                        .help("Write all annotated sheets into report.pdf instead of PNG files"),
+                )
+                .arg(
+                    Arg::new("order")
+                        .long("order")
+                        .value_parser(["scan", "filename", "id"])
+                        .default_value("scan")
+                        .help("Order of the sheets in report.pdf"),
+                )
+                .arg(
+                    Arg::new("jpeg-quality")
+                        .long("jpeg-quality")
+                        .value_parser(value_parser!(u8).range(1..=100))
+                        .default_value("80")
+                        .help("JPEG quality of the sheets in report.pdf"),
+                )
+                .arg(
+                    Arg::new("lossless")
+                        .long("lossless")
+                        .action(ArgAction::SetTrue)
+                        .help("Embed the sheets in report.pdf as lossless PNG"),
+                )
+                .arg(
+                    Arg::new("naming")
+                        .long("naming")
+                        .value_name("PATTERN")
+                        .default_value(NamingScheme::DEFAULT_PATTERN)
+                        .help("Names of the annotated images, e.g. \"{status}/{id}-score{score}\""),
+                )
+                .arg(
+                    Arg::new("colours")
+                        .long("colours")
+                        .value_name("SCHEME")
+                        .default_value("standard")
+                        .help("standard, colourblind, print or a JSON file with a colour scheme"),
+                )
+                .arg(
+                    Arg::new("banner")
+                        .long("banner")
+                        .action(ArgAction::SetTrue)
+                        .help("Print ID, version, score and grade on top of every image"),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .value_parser(value_parser!(usize))
+                        .default_value("0")
+                        .help("Number of sheets graded at the same time, 0 for one per core"),
+                )
+                .arg(
+                    Arg::new("version-fallback")
+                        .long("version-fallback")
+                        .value_name("VERSION")
+                        .help("Grade sheets with an unclear version as \"best\" or 0, 1, ..."),
+                )
+                .arg(
+                    Arg::new("csv-file")
+                        .long("csv-file")
+                        .action(ArgAction::SetTrue)
+                        .help("Also write the CSV output to results.csv in the output directory"),
+                ),
+        )
+        .subcommand(
+            Command::new("survey")
+                .about("Tally the responses of a survey, no key needed")
+                .arg(
+                    Arg::new("outpath")
+                        .long("outpath")
+                        .value_name("OUT")
+                        .default_value("./")
+                        .help("Specify the output path"),
+                )
+                .arg(
+                    Arg::new("with-ids")
+                        .long("with-ids")
+                        .action(ArgAction::SetTrue)
+                        .help("Read the student IDs, otherwise the survey is anonymous"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .required(true)
+                        .help("template configuration"),
+                )
+                .arg(
+                    Arg::new("images").required(true).num_args(1..).help(
+                        "PDF, TIFF or ZIP files, images, directories or globs like \"scans/*.jpg\"",
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("simulate")
+                .about("Grade made up sheets with known answers to measure accuracy and speed")
+                .arg(
+                    Arg::new("template")
+                        .default_value("tests/assets/template.json")
+                        .help("template configuration"),
+                )
+                .arg(
+                    Arg::new("key")
+                        .default_value("tests/assets/key.json")
+                        .help("exam key"),
+                )
+                .arg(
+                    Arg::new("form")
+                        .default_value("tests/assets/template-a4.png")
+                        .help("the blank form at the size of the template, e.g. form.png"),
+                )
+                .arg(
+                    Arg::new("sheets")
+                        .long("sheets")
+                        .value_parser(value_parser!(u64))
+                        .default_value("100")
+                        .help("Number of sheets"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0")
+                        .help("The same seed gives the same sheets"),
+                )
+                .arg(
+                    Arg::new("rotation")
+                        .long("rotation")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Largest rotation of the pages in degrees"),
+                )
+                .arg(
+                    Arg::new("blur")
+                        .long("blur")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Standard deviation of the blur in pixels"),
+                )
+                .arg(
+                    Arg::new("grain")
+                        .long("grain")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Standard deviation of the grain of paper and scanner in grey levels"),
+                )
+                .arg(
+                    Arg::new("jpeg-quality")
+                        .long("jpeg-quality")
+                        .value_parser(value_parser!(u8).range(1..=100))
+                        .help("Save the scans as JPEG of this quality"),
+                )
+                .arg(
+                    Arg::new("faintness")
+                        .long("faintness")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Marks are a grey between black (0) and this share of white"),
+                )
+                .arg(
+                    Arg::new("stray-marks")
+                        .long("stray-marks")
+                        .value_parser(value_parser!(u32))
+                        .default_value("0")
+                        .help("Number of pencil strokes on every page"),
+                )
+                .arg(
+                    Arg::new("erasures")
+                        .long("erasures")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Chance that a question has another, erased mark"),
+                )
+                .arg(
+                    Arg::new("blanks")
+                        .long("blanks")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Chance that a question, a column of the name or a field is left blank"),
+                )
+                .arg(
+                    Arg::new("multiple-marks")
+                        .long("multiple-marks")
+                        .value_parser(value_parser!(f32))
+                        .default_value("0")
+                        .help("Chance that a question or a field of the examiner has two marks"),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .value_parser(value_parser!(usize))
+                        .default_value("0")
+                        .help("Number of sheets graded at the same time, 0 for one per core"),
+                )
+                .arg(
+                    Arg::new("outpath")
+                        .long("outpath")
+                        .value_name("OUT")
+                        .help("Save the sheets that were misread without an issue here"),
                 ),
         )
         .subcommand(
@@ -79,6 +413,65 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
                         .value_parser(value_parser!(u32))
                         .help("Number of choices in each MCQ"),
                 )
+                .arg(
+                    Arg::new("title")
+                        .long("title")
+                        .default_value("")
+                        .help("Title printed at the top of the form"),
+                )
+                .arg(
+                    Arg::new("name-cols")
+                        .long("name-cols")
+                        .default_value("0")
+                        .value_parser(value_parser!(u32))
+                        .help("Number of columns in the name grid (0 for no name grid)"),
+                )
+                .arg(
+                    Arg::new("name-digits")
+                        .long("name-digits")
+                        .action(ArgAction::SetTrue)
+                        .help("Allow digits in the name grid"),
+                )
+                .arg(
+                    Arg::new("numeric-qs")
+                        .long("numeric-qs")
+                        .default_value("0")
+                        .value_parser(value_parser!(u32))
+                        .help("Number of numeric (gridded-in) questions"),
+                )
+                .arg(
+                    Arg::new("numeric-cols")
+                        .long("numeric-cols")
+                        .default_value("4")
+                        .value_parser(value_parser!(u32))
+                        .help("Number of columns in each numeric question"),
+                )
+                .arg(
+                    Arg::new("teacher-qs")
+                        .long("teacher-qs")
+                        .default_value("0")
+                        .value_parser(value_parser!(u32))
+                        .help("Number of fields scored by hand in the examiner area"),
+                )
+                .arg(
+                    Arg::new("teacher-max-points")
+                        .long("teacher-max-points")
+                        .default_value("10")
+                        .value_parser(value_parser!(u32))
+                        .help("Highest number of points in each examiner field"),
+                )
+                .arg(
+                    Arg::new("labels")
+                        .long("labels")
+                        .value_name("SPEC")
+                        .help("Option labels like \"T,F\" or \"1-10=T,F; 11=Yes,No\""),
+                )
+                .arg(
+                    Arg::new("id-check")
+                        .long("id-check")
+                        .value_parser(["luhn", "mod11"])
+                        .help("Validate student IDs with a check digit"),
+                )
                 .arg(
                     Arg::new("outprefix")
                         .long("outprefix")
@@ -101,53 +494,135 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
                 .to_string();
 
             let keypath = sub_matches.get_one::<String>("key").unwrap().to_string();
-            let imagespath = sub_matches.get_one::<String>("images").unwrap().to_string();
-
-            let t: Template = serde_json::from_reader(
-                std::fs::File::open(templatepath).expect("could not open template"),
-            )?;
-            let k: ExamKey =
-                serde_json::from_reader(std::fs::File::open(keypath).expect("could not open key"))?;
 
-            let imagefile = Path::new(&imagespath);
-
-            let mut csv_report = String::new();
-
-            match imagefile.extension().and_then(|ext| ext.to_str()) {
-                Some("pdf") => {
-                    let file = pdf::file::FileOptions::cached().open(imagefile).unwrap();
-                    let mut container = PdfContainer { pdf_file: file };
-
-                    csv_report =
-                        generate_reports_for_image_container(&mut container, &t, &k, outpath)
-                            .expect("error generating report");
+            let mut t = Template::from_json(std::fs::File::open(templatepath)?)?;
+            if let Some(scheme) = sub_matches.get_one::<String>("id-check") {
+                t.id_check_digit = parse_check_digit(scheme);
+            }
+            let k = key_from_json(std::fs::File::open(keypath)?)?;
+            let grading = match sub_matches.get_one::<String>("grades") {
+                Some(spec) => {
+                    let mode = match sub_matches.get_one::<String>("grading").map(|s| s.as_str()) {
+                        Some("points") => GradingMode::Points,
+                        Some("rank") => GradingMode::Rank,
+                        _ => GradingMode::Percent,
+                    };
+                    Some(GradingScale::parse(mode, spec)?)
                 }
-                Some("tif") | Some("tiff") => {
-                    let buffer = std::io::BufReader::new(
-                        std::fs::File::open(imagefile).expect("could not open tif container"),
-                    );
-
-                    let tiff = tiff::decoder::Decoder::new(buffer)?;
+                None => None,
+            };
+            let feedback =
+                sub_matches
+                    .get_one::<String>("feedback")
+                    .map(|mode| match mode.as_str() {
+                        "merged" => FeedbackMode::Merged,
+                        _ => FeedbackMode::PerStudent,
+                    });
+            let combined_pdf = sub_matches.get_flag("pdf").then(|| PdfSettings {
+                order: match sub_matches.get_one::<String>("order").map(|s| s.as_str()) {
+                    Some("filename") => PageOrder::Filename,
+                    Some("id") => PageOrder::Id,
+                    _ => PageOrder::Scan,
+                },
+                compression: if sub_matches.get_flag("lossless") {
+                    PageCompression::Flate
+                } else {
+                    PageCompression::Jpeg {
+                        quality: *sub_matches.get_one::<u8>("jpeg-quality").unwrap(),
+                    }
+                },
+            });
+            let colours = sub_matches.get_one::<String>("colours").unwrap();
+            let colours = match ColourScheme::preset(colours) {
+                Some(scheme) => scheme,
+                None => serde_json::from_reader(std::fs::File::open(colours)?)?,
+            };
+            let settings = ReportSettings {
+                title: sub_matches.get_one::<String>("title").unwrap().to_string(),
+                grading,
+                feedback,
+                combined_pdf,
+                naming: NamingScheme::parse(sub_matches.get_one::<String>("naming").unwrap())?,
+                colours,
+                banner: sub_matches.get_flag("banner"),
+                version_fallback: match sub_matches.get_one::<String>("version-fallback") {
+                    Some(v) if v == "best" => VersionFallback::BestScore,
+                    Some(v) => VersionFallback::Version(v.parse()?),
+                    None => VersionFallback::Ungraded,
+                },
+                threads: *sub_matches.get_one::<usize>("threads").unwrap(),
+            };
+            if let VersionFallback::Version(v) = settings.version_fallback {
+                if v as usize >= k.len() {
+                    return Err(format!("the key has no version {}", v).into());
+                }
+            }
 
-                    let mut container = TiffContainer { decoder: tiff };
+            let mut container = open_containers(sub_matches.get_many::<String>("images").unwrap())?;
+            generate_reports_for_image_container(
+                container.as_mut(),
+                &t,
+                &k,
+                &settings,
+                &mut CliSink {
+                    dir: DirectorySink::new(&outpath),
+                    csv_file: sub_matches.get_flag("csv-file"),
+                },
+                &mut print_progress,
+                &CancellationToken::default(),
+            )?;
+        }
+        Some(("survey", sub_matches)) => {
+            let outpath = sub_matches
+                .get_one::<String>("outpath")
+                .unwrap()
+                .to_string();
+            let templatepath = sub_matches.get_one::<String>("template").unwrap();
+            let t = Template::from_json(std::fs::File::open(templatepath)?)?;
+            let mut container = open_containers(sub_matches.get_many::<String>("images").unwrap())?;
+            let tally = generate_survey_for_image_container(
+                container.as_mut(),
+                &t,
+                sub_matches.get_flag("with-ids"),
+                outpath,
+            )?;
 
-                    csv_report =
-                        generate_reports_for_image_container(&mut container, &t, &k, outpath)
-                            .expect("error while generating report");
-                }
-                Some("jpg") | Some("jpeg") | Some("png") => {
-                    let image = image::open(imagefile).expect("could not open single image");
+            println!("{}", tally.summary_csv()?);
+        }
+        Some(("simulate", sub_matches)) => {
+            let templatepath = sub_matches.get_one::<String>("template").unwrap();
+            let keypath = sub_matches.get_one::<String>("key").unwrap();
+            let formpath = sub_matches.get_one::<String>("form").unwrap();
+            let t = Template::from_json(std::fs::File::open(templatepath)?)?;
+            let k = key_from_json(std::fs::File::open(keypath)?)?;
+            let form = image::open(formpath)?;
 
-                    let mut container = SingleImageContainer { image };
+            let noise = Noise {
+                rotation: *sub_matches.get_one::<f32>("rotation").unwrap(),
+                blur: *sub_matches.get_one::<f32>("blur").unwrap(),
+                grain: *sub_matches.get_one::<f32>("grain").unwrap(),
+                jpeg_quality: sub_matches.get_one::<u8>("jpeg-quality").copied(),
+                faintness: *sub_matches.get_one::<f32>("faintness").unwrap(),
+                stray_marks: *sub_matches.get_one::<u32>("stray-marks").unwrap(),
+                erasures: *sub_matches.get_one::<f32>("erasures").unwrap(),
+                blanks: *sub_matches.get_one::<f32>("blanks").unwrap(),
+                multiple_marks: *sub_matches.get_one::<f32>("multiple-marks").unwrap(),
+            };
+            let seed = *sub_matches.get_one::<u64>("seed").unwrap();
+            let simulation = Simulation::new(&t, &k, &form, noise, seed)?;
+            let accuracy = simulation.run(
+                *sub_matches.get_one::<u64>("sheets").unwrap(),
+                *sub_matches.get_one::<usize>("threads").unwrap(),
+                &mut print_progress,
+            )?;
+            println!("{}", accuracy);
 
-                    let _ = generate_reports_for_image_container(&mut container, &t, &k, outpath)
-                        .expect("error while generating report");
+            if let Some(outpath) = sub_matches.get_one::<String>("outpath") {
+                std::fs::create_dir_all(outpath)?;
+                for &index in &accuracy.misread {
+                    let path = Path::new(outpath).join(format!("sheet{}.png", index));
+                    simulation.sheet(index).image.save(path)?;
                 }
-                _ => println!("Unsupported file type: {:?}", imagefile),
-            }
-
-            if !csv_report.is_empty() {
-                println!("{}", csv_report);
             }
         }
         Some(("debug", sub_matches)) => {
@@ -158,7 +633,7 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
 
             let imagepath = sub_matches.get_one::<String>("image").unwrap().to_string();
 
-            let t: Template = serde_json::from_reader(std::fs::File::open(templatepath)?)?;
+            let t = Template::from_json(std::fs::File::open(templatepath)?)?;
 
             let imagefile = Path::new(&imagepath);
             let image = image::open(imagefile)?;
@@ -181,34 +656,52 @@ fn main() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
             let outprefix = sub_matches
                 .get_one::<String>("outprefix")
                 .expect("defaulted by clap");
-            let input = std::fs::read_to_string("assets/formtemplate.typ")?;
-
-            let code = format!(
-                r#"
-#let num_qs = {}
-#let num_idqs = {}
-#let num_answers = {}
-#let num_versions = {}
-{}
-"#,
-                qs, idqs, choices, versions, input
-            );
-
-            let wrapper = TypstWrapper::new(code);
+            let title = sub_matches
+                .get_one::<String>("title")
+                .expect("defaulted by clap");
+            let name_cols = sub_matches
+                .get_one::<u32>("name-cols")
+                .expect("defaulted by clap");
+            let option_labels = match sub_matches.get_one::<String>("labels") {
+                Some(spec) => parse_option_labels(spec, *qs)?,
+                None => Vec::new(),
+            };
 
-            let document = typst::compile(&wrapper)
-                .output
-                .expect("Error from Typst. This really should not happen. So sorry.");
+            let settings = FormSettings {
+                title: title.to_string(),
+                num_qs: *qs,
+                num_id_qs: *idqs,
+                num_versions: *versions,
+                num_answers: *choices,
+                num_name_cols: *name_cols,
+                name_digits: sub_matches.get_flag("name-digits"),
+                num_numeric_qs: *sub_matches
+                    .get_one::<u32>("numeric-qs")
+                    .expect("defaulted by clap"),
+                numeric_cols: *sub_matches
+                    .get_one::<u32>("numeric-cols")
+                    .expect("defaulted by clap"),
+                num_teacher_qs: *sub_matches
+                    .get_one::<u32>("teacher-qs")
+                    .expect("defaulted by clap"),
+                teacher_max_points: *sub_matches
+                    .get_one::<u32>("teacher-max-points")
+                    .expect("defaulted by clap"),
+                option_labels,
+            };
 
             let scale = 3.0;
-            let template = typst_frame_to_template(&document.pages[0].frame, scale);
+            let (document, mut template) = generate_form_and_template(&settings, scale)?;
+            template.id_check_digit = sub_matches
+                .get_one::<String>("id-check")
+                .and_then(|scheme| parse_check_digit(scheme));
 
             let _ = serde_json::to_writer_pretty(
                 &std::fs::File::create(outprefix.to_owned() + ".json").unwrap(),
                 &template,
             );
 
-            let pdf = typst_pdf::pdf(&document, &typst_pdf::PdfOptions::default()).expect("bla");
+            let pdf = document_to_pdf(&document)?;
             let _ = std::fs::write(format!("{}.pdf", outprefix), pdf);
 
             let _ = typst_render::render(&document.pages[0], scale as f32)
@@ -0,0 +1,246 @@
+use crate::error::Error;
+use crate::report::ImageReport;
+
+/// The fields that can be used in a naming pattern. There is none for the
+/// section or course, since a sheet does not record it; a pattern can start
+/// with a fixed directory for it instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameField {
+    /// the student ID or `NOID`
+    Id,
+    /// the name of the student (if the template has a name grid)
+    Name,
+    /// `v0`, `v1`, ... or `NOVERSION`
+    Version,
+    Score,
+    /// the identifier of the scan, e.g. `page3`
+    Page,
+    /// `GRADE_BY_HAND-` if the sheet needs to be graded by hand
+    Issue,
+    /// `issues` or `ok`, meant for subdirectories
+    Status,
+}
+
+impl NameField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(Self::Id),
+            "name" => Some(Self::Name),
+            "version" => Some(Self::Version),
+            "score" => Some(Self::Score),
+            "page" => Some(Self::Page),
+            "issue" => Some(Self::Issue),
+            "status" => Some(Self::Status),
+            _ => None,
+        }
+    }
+
+    fn value(&self, report: &ImageReport) -> String {
+        let value = match self {
+            Self::Id => report.sid.clone().unwrap_or("NOID".to_string()),
+            Self::Name => report.name.clone().unwrap_or_default().replace(' ', "_"),
+            Self::Version => match report.version {
+                Some(version) => format!("v{}", version),
+                None => "NOVERSION".to_string(),
+            },
+            Self::Score => report.score().to_string(),
+            Self::Page => report.identifier.clone(),
+            Self::Issue if report.has_issues() => "GRADE_BY_HAND-".to_string(),
+            Self::Issue => String::new(),
+            Self::Status if report.has_issues() => "issues".to_string(),
+            Self::Status => "ok".to_string(),
+        };
+        // values must never introduce directories of their own, nor characters
+        // that file systems or zip tools reject
+        value.replace(|c: char| INVALID.contains(&c) || c.is_control(), "_")
+    }
+}
+
+const INVALID: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(NameField),
+}
+
+/// How the annotated images are named, e.g. `{status}/{id}-score{score}`.
+/// Every `/` in the pattern starts a subdirectory and the file extension is
+/// added by the caller. A pattern without `{page}` gets `-{page}` appended,
+/// since no two sheets may end up with the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamingScheme {
+    segments: Vec<Segment>,
+}
+
+impl NamingScheme {
+    /// The names we have always used, like
+    /// `GRADE_BY_HAND-20120-v1-score5-page2`.
+    pub const DEFAULT_PATTERN: &'static str = "{issue}{id}-{version}-score{score}-{page}";
+
+    pub fn parse(pattern: &str) -> Result<Self, Error> {
+        let mut segments = Vec::new();
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(0) if rest.starts_with('{') => {
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| Error::Settings(format!("missing '}}' in '{}'", pattern)))?;
+                    let name = &rest[1..end];
+                    let field = NameField::parse(name.trim())
+                        .ok_or_else(|| Error::Settings(format!("unknown field '{{{}}}'", name)))?;
+                    segments.push(Segment::Field(field));
+                    rest = &rest[end + 1..];
+                }
+                Some(0) => {
+                    return Err(Error::Settings(format!("unexpected '}}' in '{}'", pattern)))
+                }
+                Some(start) => {
+                    segments.push(Segment::Literal(rest[..start].to_string()));
+                    rest = &rest[start..];
+                }
+                None => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+
+        if segments.is_empty() {
+            return Err(Error::Settings("the pattern is empty".to_string()));
+        }
+        if let Some(c) = pattern
+            .chars()
+            .find(|c| INVALID[2..].contains(c) || c.is_control())
+        {
+            return Err(Error::Settings(format!(
+                "invalid character '{}' in '{}'",
+                c, pattern
+            )));
+        }
+        if pattern.starts_with('/') || pattern.ends_with('/') || pattern.contains('\\') {
+            return Err(Error::Settings(format!(
+                "'{}' is not a relative file name",
+                pattern
+            )));
+        }
+        if pattern
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(Error::Settings(format!(
+                "invalid directory in '{}'",
+                pattern
+            )));
+        }
+        if !segments.contains(&Segment::Field(NameField::Page)) {
+            segments.push(Segment::Literal("-".to_string()));
+            segments.push(Segment::Field(NameField::Page));
+        }
+        Ok(Self { segments })
+    }
+
+    /// The relative path (without extension) of the image of this report.
+    pub fn apply(&self, report: &ImageReport) -> String {
+        let path: String = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Field(field) => field.value(report),
+            })
+            .collect();
+        // an empty field leaves no empty directory behind
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .map(|part| match part {
+                "." | ".." => "_",
+                part => part,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+impl Default for NamingScheme {
+    fn default() -> Self {
+        Self::parse(Self::DEFAULT_PATTERN).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Issue;
+
+    fn report(sid: Option<&str>, version: Option<u32>, issue: bool) -> ImageReport {
+        ImageReport {
+            image: image::RgbImage::new(1, 1),
+            sid: sid.map(String::from),
+            name: Some("JANE DOE".into()),
+            version,
+            answers: Vec::new(),
+            issues: if issue {
+                vec![Issue::MultipleMarks { question: 1 }]
+            } else {
+                Vec::new()
+            },
+            scores: vec![Some(1), Some(0), Some(4)],
+            identifier: "scan-page2".into(),
+        }
+    }
+
+    #[test]
+    fn default_names() {
+        let naming = NamingScheme::default();
+        assert_eq!(
+            naming.apply(&report(Some("20120"), Some(1), true)),
+            "GRADE_BY_HAND-20120-v1-score5-scan-page2"
+        );
+        assert_eq!(
+            naming.apply(&report(None, None, false)),
+            "NOID-NOVERSION-score5-scan-page2"
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        let naming = NamingScheme::parse("{status}/{version}/{ name }_{id}_{page}").unwrap();
+        assert_eq!(
+            naming.apply(&report(Some("42"), Some(0), false)),
+            "ok/v0/JANE_DOE_42_scan-page2"
+        );
+        assert_eq!(
+            naming.apply(&report(Some("42"), None, true)),
+            "issues/NOVERSION/JANE_DOE_42_scan-page2"
+        );
+
+        // every sheet gets a name of its own
+        let naming = NamingScheme::parse("{status}/{name}/{id}").unwrap();
+        let mut sheet = report(Some("42"), Some(0), false);
+        assert_eq!(naming.apply(&sheet), "ok/JANE_DOE/42-scan-page2");
+        sheet.name = Some("O'NEIL? J:R".into());
+        assert_eq!(naming.apply(&sheet), "ok/O'NEIL__J_R/42-scan-page2");
+        sheet.name = None;
+        assert_eq!(naming.apply(&sheet), "ok/42-scan-page2");
+        sheet.name = Some("..".into());
+        assert_eq!(naming.apply(&sheet), "ok/_/42-scan-page2");
+        let naming = NamingScheme::parse("{name}/{id}-{page}").unwrap();
+        sheet.name = Some(String::new());
+        assert_eq!(naming.apply(&sheet), "42-scan-page2");
+        // a section graded on its own goes into a directory of its own
+        let naming = NamingScheme::parse("MATH101-A/{status}/{id}").unwrap();
+        assert_eq!(naming.apply(&sheet), "MATH101-A/ok/42-scan-page2");
+
+        assert!(NamingScheme::parse("{grade}").is_err());
+        assert!(NamingScheme::parse("{id}?").is_err());
+        assert!(NamingScheme::parse("a:{page}").is_err());
+        assert!(NamingScheme::parse("{id").is_err());
+        assert!(NamingScheme::parse("id}").is_err());
+        assert!(NamingScheme::parse("").is_err());
+        assert!(NamingScheme::parse("/tmp/{id}").is_err());
+        assert!(NamingScheme::parse("../{id}").is_err());
+        assert!(NamingScheme::parse("a//{id}").is_err());
+    }
+}
@@ -0,0 +1,542 @@
+// Merges the PDFs that Typst writes for parts of the combined report and for
+// the feedback sheets into one file. The pages of each part are copied as soon
+// as it is added, so that a report of thousands of sheets is typeset a few
+// sheets at a time and never kept in memory as a whole.
+
+use crate::error::Error;
+use pdf::object::{PlainRef, Resolve};
+use pdf::primitive::{Dictionary, Primitive};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+
+// the object of our page tree
+const PAGES: usize = 1;
+
+// attributes a page inherits from the nodes of the page tree above it
+const INHERITED: [&str; 4] = ["Resources", "MediaBox", "CropBox", "Rotate"];
+
+/// A temporary file that is removed when dropped. On the web the browser
+/// keeps it; it lives in memory if the file cannot be created.
+pub struct Spool {
+    inner: SpoolInner,
+}
+
+enum SpoolInner {
+    Memory(Cursor<Vec<u8>>),
+    #[cfg(not(target_arch = "wasm32"))]
+    File(std::fs::File, std::path::PathBuf),
+    #[cfg(target_arch = "wasm32")]
+    Blob(crate::webapp::blob_buffer::BlobBuffer),
+}
+
+/// Where data was put by `Spool::store`.
+#[derive(Debug, Clone, Copy)]
+pub struct Spooled {
+    offset: u64,
+    len: usize,
+}
+
+impl Spool {
+    pub fn new() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "autograder-{}-{}.tmp",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            match std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => {
+                    return Self {
+                        inner: SpoolInner::File(file, path),
+                    }
+                }
+                Err(e) => log::warn!("keeping {} in memory: {}", path.display(), e),
+            }
+            Self::memory()
+        }
+        #[cfg(target_arch = "wasm32")]
+        Self {
+            inner: SpoolInner::Blob(Default::default()),
+        }
+    }
+
+    /// A spool that never touches the disk, for small files.
+    pub fn memory() -> Self {
+        Self {
+            inner: SpoolInner::Memory(Cursor::new(Vec::new())),
+        }
+    }
+
+    /// Appends `data`, e.g. an encoded image.
+    pub fn store(&mut self, data: &[u8]) -> Result<Spooled, Error> {
+        let offset = self.seek(SeekFrom::End(0))?;
+        self.write_all(data)?;
+        Ok(Spooled {
+            offset,
+            len: data.len(),
+        })
+    }
+
+    /// Reads back what `store` wrote.
+    pub fn load(&mut self, spooled: &Spooled) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; spooled.len];
+        self.seek(SeekFrom::Start(spooled.offset))?;
+        self.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// The whole content, for spools that fit into memory.
+    pub fn into_bytes(mut self) -> Result<Vec<u8>, Error> {
+        if let SpoolInner::Memory(cursor) = &mut self.inner {
+            return Ok(std::mem::take(cursor.get_mut()));
+        }
+        let mut data = Vec::new();
+        self.rewind()?;
+        self.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let SpoolInner::File(_, path) = &self.inner {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Read for Spool {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.inner {
+            SpoolInner::Memory(cursor) => cursor.read(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            SpoolInner::File(file, _) => file.read(buf),
+            #[cfg(target_arch = "wasm32")]
+            SpoolInner::Blob(buffer) => buffer.read(buf),
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.inner {
+            SpoolInner::Memory(cursor) => cursor.write(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            SpoolInner::File(file, _) => file.write(buf),
+            #[cfg(target_arch = "wasm32")]
+            SpoolInner::Blob(buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.inner {
+            SpoolInner::Memory(cursor) => cursor.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
+            SpoolInner::File(file, _) => file.flush(),
+            #[cfg(target_arch = "wasm32")]
+            SpoolInner::Blob(buffer) => buffer.flush(),
+        }
+    }
+}
+
+impl Seek for Spool {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match &mut self.inner {
+            SpoolInner::Memory(cursor) => cursor.seek(pos),
+            #[cfg(not(target_arch = "wasm32"))]
+            SpoolInner::File(file, _) => file.seek(pos),
+            #[cfg(target_arch = "wasm32")]
+            SpoolInner::Blob(buffer) => buffer.seek(pos),
+        }
+    }
+}
+
+// bookmarks and the document title may use any character
+fn pdf_text_string(text: &str) -> String {
+    let mut result = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        result += &format!("{:04X}", unit);
+    }
+    result + ">"
+}
+
+/// Writes the pages of several PDFs into one, in a `Spool`. The page tree and
+/// the bookmarks follow at the end.
+pub struct PdfMerger {
+    out: BufWriter<Spool>,
+    position: u64,
+    // by object number, starting at 1
+    offsets: Vec<u64>,
+    pages: Vec<usize>,
+    bookmarks: Vec<(String, usize)>,
+}
+
+impl PdfMerger {
+    pub fn new(spool: Spool) -> Result<Self, Error> {
+        let mut merger = Self {
+            out: BufWriter::new(spool),
+            position: 0,
+            offsets: vec![0; PAGES],
+            pages: Vec::new(),
+            bookmarks: Vec::new(),
+        };
+        merger.write(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n")?;
+        Ok(merger)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.out.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    fn reserve(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn object(&mut self, id: usize, body: &[u8]) -> Result<(), Error> {
+        self.offsets[id - 1] = self.position;
+        self.write(format!("{} 0 obj\n", id).as_bytes())?;
+        self.write(body)?;
+        self.write(b"\nendobj\n")
+    }
+
+    /// Copies the pages of `pdf` with everything they use and returns the
+    /// number of the first, starting at 0.
+    pub fn add_pdf(&mut self, pdf: Vec<u8>) -> Result<usize, Error> {
+        let file = pdf::file::FileOptions::cached().load(pdf)?;
+        let resolver = file.resolver();
+        let root = resolver.resolve(file.trailer.root.get_ref().get_inner())?;
+        let tree = match dictionary(&root)?.get("Pages") {
+            Some(Primitive::Reference(r)) => *r,
+            _ => return Err(Error::Decode("PDF without pages".into())),
+        };
+
+        // the pages with what they inherit; references to the nodes of the
+        // page tree become references to ours
+        let mut pages = Vec::new();
+        let mut ids: HashMap<PlainRef, usize> = HashMap::new();
+        collect_pages(&resolver, tree, &Dictionary::new(), &mut pages, &mut ids, 0)?;
+
+        let first = self.pages.len();
+        let mut pending = VecDeque::new();
+        for (page, _) in &pages {
+            let id = self.reserve();
+            ids.insert(*page, id);
+            self.pages.push(id);
+            pending.push_back(*page);
+        }
+        let mut inherited: HashMap<PlainRef, Dictionary> = pages.into_iter().collect();
+
+        while let Some(r) = pending.pop_front() {
+            let mut object = resolver.resolve(r)?;
+            if let (Some(attributes), Primitive::Dictionary(page)) =
+                (inherited.remove(&r), &mut object)
+            {
+                for (key, value) in attributes.iter() {
+                    if page.get(key).is_none() {
+                        page.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+
+            let mut body = Vec::new();
+            let offsets = &mut self.offsets;
+            let mut new_id = |r: PlainRef| {
+                *ids.entry(r).or_insert_with(|| {
+                    pending.push_back(r);
+                    offsets.push(0);
+                    offsets.len()
+                })
+            };
+            match object {
+                Primitive::Stream(stream) => {
+                    let data = stream.raw_data(&resolver)?;
+                    let mut info = stream.info.clone();
+                    info.insert("Length", Primitive::Integer(data.len() as i32));
+                    serialize(&Primitive::Dictionary(info), &mut body, &mut new_id)?;
+                    body.extend_from_slice(b"\nstream\n");
+                    body.extend_from_slice(&data);
+                    body.extend_from_slice(b"\nendstream");
+                }
+                object => serialize(&object, &mut body, &mut new_id)?,
+            }
+            let id = ids[&r];
+            self.object(id, &body)?;
+        }
+        Ok(first)
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// A bookmark to the given page, in the order they are added.
+    pub fn add_bookmark(&mut self, title: &str, page: usize) {
+        self.bookmarks.push((title.to_string(), page));
+    }
+
+    /// Writes the page tree, the bookmarks and the cross-reference table and
+    /// returns the finished PDF, rewound.
+    pub fn finish(mut self, title: &str) -> Result<Spool, Error> {
+        if self.pages.is_empty() {
+            let page = self.reserve();
+            self.object(
+                page,
+                format!(
+                    "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 595.28 841.89] >>",
+                    PAGES
+                )
+                .as_bytes(),
+            )?;
+            self.pages.push(page);
+        }
+
+        let mut catalog = format!("/Type /Catalog /Pages {} 0 R", PAGES);
+        if !self.bookmarks.is_empty() {
+            let outline = self.reserve();
+            let first = self.offsets.len() + 1;
+            let last = first + self.bookmarks.len() - 1;
+            for (i, (text, page)) in std::mem::take(&mut self.bookmarks).into_iter().enumerate() {
+                let id = self.reserve();
+                let mut item = format!(
+                    "<< /Title {} /Parent {} 0 R /Dest [{} 0 R /Fit]",
+                    pdf_text_string(&text),
+                    outline,
+                    self.pages[page]
+                );
+                if id > first {
+                    item += &format!(" /Prev {} 0 R", id - 1);
+                }
+                if id < last {
+                    item += &format!(" /Next {} 0 R", id + 1);
+                }
+                self.object(id, (item + " >>").as_bytes())?;
+                // ids are handed out in order
+                debug_assert_eq!(id, first + i);
+            }
+            self.object(
+                outline,
+                format!(
+                    "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+                    first,
+                    last,
+                    last - first + 1
+                )
+                .as_bytes(),
+            )?;
+            catalog += &format!(" /Outlines {} 0 R /PageMode /UseOutlines", outline);
+        }
+
+        let kids: Vec<String> = self.pages.iter().map(|id| format!("{} 0 R", id)).collect();
+        self.object(
+            PAGES,
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                kids.len()
+            )
+            .as_bytes(),
+        )?;
+        let info = self.reserve();
+        self.object(
+            info,
+            format!(
+                "<< /Title {} /Producer (autograder) >>",
+                pdf_text_string(title)
+            )
+            .as_bytes(),
+        )?;
+        let root = self.reserve();
+        self.object(root, format!("<< {} >>", catalog).as_bytes())?;
+
+        let xref = self.position;
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            table += &format!("{:010} 00000 n \n", offset);
+        }
+        table += &format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            root,
+            info,
+            xref
+        );
+        self.write(table.as_bytes())?;
+
+        let mut spool = self.out.into_inner().map_err(|e| e.into_error())?;
+        spool.rewind()?;
+        Ok(spool)
+    }
+}
+
+fn dictionary(primitive: &Primitive) -> Result<&Dictionary, Error> {
+    match primitive {
+        Primitive::Dictionary(dictionary) => Ok(dictionary),
+        _ => Err(Error::Decode("broken page tree".into())),
+    }
+}
+
+// the pages below the node `r` in order, with the attributes they inherit
+fn collect_pages(
+    resolver: &impl Resolve,
+    r: PlainRef,
+    inherited: &Dictionary,
+    pages: &mut Vec<(PlainRef, Dictionary)>,
+    ids: &mut HashMap<PlainRef, usize>,
+    depth: usize,
+) -> Result<(), Error> {
+    if depth > 32 {
+        return Err(Error::Decode("page tree too deep".into()));
+    }
+    let node = resolver.resolve(r)?;
+    let node = dictionary(&node)?;
+    let mut attributes = inherited.clone();
+    for key in INHERITED {
+        if let Some(value) = node.get(key) {
+            attributes.insert(key, value.clone());
+        }
+    }
+
+    match node.get("Kids") {
+        Some(Primitive::Array(kids)) => {
+            ids.insert(r, PAGES);
+            for kid in kids {
+                if let Primitive::Reference(kid) = kid {
+                    collect_pages(resolver, *kid, &attributes, pages, ids, depth + 1)?;
+                }
+            }
+        }
+        _ => pages.push((r, attributes)),
+    }
+    Ok(())
+}
+
+// writes `primitive`, with the references that `new_id` gives for the old ones
+fn serialize(
+    primitive: &Primitive,
+    out: &mut Vec<u8>,
+    new_id: &mut impl FnMut(PlainRef) -> usize,
+) -> Result<(), Error> {
+    match primitive {
+        Primitive::Null => out.extend_from_slice(b"null"),
+        Primitive::Integer(i) => write!(out, "{}", i)?,
+        Primitive::Number(n) => write!(out, "{}", n)?,
+        Primitive::Boolean(b) => write!(out, "{}", b)?,
+        Primitive::String(s) => {
+            out.push(b'<');
+            for byte in s.as_bytes() {
+                write!(out, "{:02X}", byte)?;
+            }
+            out.push(b'>');
+        }
+        Primitive::Name(name) => serialize_name(name, out),
+        Primitive::Reference(r) => write!(out, "{} 0 R", new_id(*r))?,
+        Primitive::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b' ');
+                }
+                serialize(item, out, new_id)?;
+            }
+            out.push(b']');
+        }
+        Primitive::Dictionary(dictionary) => {
+            out.extend_from_slice(b"<<");
+            for (key, value) in dictionary.iter() {
+                serialize_name(key, out);
+                out.push(b' ');
+                serialize(value, out, new_id)?;
+            }
+            out.extend_from_slice(b">>");
+        }
+        Primitive::Stream(_) => {
+            return Err(Error::Decode("stream inside an object".into()));
+        }
+    }
+    Ok(())
+}
+
+// `/` and the name, with every byte that is not a regular character as `#xx`
+fn serialize_name(name: &str, out: &mut Vec<u8>) {
+    out.push(b'/');
+    for &byte in name.as_bytes() {
+        if byte.is_ascii_graphic() && !b"()<>[]{}/%#".contains(&byte) {
+            out.push(byte);
+        } else {
+            out.extend_from_slice(format!("#{:02X}", byte).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typst_helpers::{document_to_pdf, TypstWrapper};
+
+    fn typeset(code: &str) -> Vec<u8> {
+        let document = typst::compile(&TypstWrapper::new(code.to_string()))
+            .output
+            .unwrap();
+        document_to_pdf(&document).unwrap()
+    }
+
+    #[test]
+    fn merged() {
+        let mut merger = PdfMerger::new(Spool::new()).unwrap();
+        let first = merger
+            .add_pdf(typeset("Grüße, Zoë\n#pagebreak()\nПривет"))
+            .unwrap();
+        let second = merger
+            .add_pdf(typeset("#set page(flipped: true)\nΚαλημέρα"))
+            .unwrap();
+        assert_eq!((first, second, merger.page_count()), (0, 2, 3));
+        merger.add_bookmark("Zoë", first);
+        merger.add_bookmark("Καλημέρα", second);
+
+        let data = merger.finish("Exam").unwrap().into_bytes().unwrap();
+        assert!(data.ends_with(b"%%EOF\n"));
+        let file = pdf::file::FileOptions::cached().load(data).unwrap();
+        assert_eq!(file.num_pages(), 3);
+        // every page keeps its size and its fonts
+        let widths: Vec<f32> = file
+            .pages()
+            .map(|page| {
+                let page = page.unwrap();
+                assert!(!page.resources().unwrap().fonts.is_empty());
+                page.media_box().unwrap().right
+            })
+            .collect();
+        assert!(widths[0] < widths[2]);
+        let outlines = file.get_root().outlines.as_ref().unwrap();
+        assert_eq!(outlines.count, 2);
+    }
+
+    #[test]
+    fn spooled() {
+        let mut spool = Spool::new();
+        let a = spool.store(b"first").unwrap();
+        let b = spool.store(b"second").unwrap();
+        assert_eq!(spool.load(&b).unwrap(), b"second");
+        assert_eq!(spool.load(&a).unwrap(), b"first");
+        assert_eq!(spool.into_bytes().unwrap(), b"firstsecond");
+    }
+}
@@ -0,0 +1,839 @@
+use crate::ccitt::{decode_fax, FaxCoding, FaxOptions};
+use crate::error::Error;
+use crate::image_helpers::{cmyk_to_rgb, erode_bilevel, samples_to_grayimage, SampleFormat};
+use crate::jbig2::decode_jbig2;
+use image::{GrayImage, Luma, Rgb};
+use pdf::content::{Color, FormXObject, Matrix, Op, Winding};
+use pdf::enc::StreamFilter;
+use pdf::object::{ColorSpace, ImageXObject, Object, Page, Rect, Resolve, Resources, XObject};
+use pdf::primitive::Primitive;
+
+/// The resolution at which we render PDF pages that are not a single scan.
+pub const RENDER_DPI: f32 = 200.0;
+
+// An affine map x' = a x + c y + e, y' = b x + d y + f, just like a PDF matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transform {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+    f: f32,
+}
+
+impl Transform {
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.e,
+            self.b * x + self.d * y + self.f,
+        )
+    }
+
+    // first `self`, then `other`
+    fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            a: self.a * other.a + self.b * other.c,
+            b: self.a * other.b + self.b * other.d,
+            c: self.c * other.a + self.d * other.c,
+            d: self.c * other.b + self.d * other.d,
+            e: self.e * other.a + self.f * other.c + other.e,
+            f: self.e * other.b + self.f * other.d + other.f,
+        }
+    }
+
+    fn invert(&self) -> Option<Transform> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        Some(Transform {
+            a: self.d / det,
+            b: -self.b / det,
+            c: -self.c / det,
+            d: self.a / det,
+            e: (self.c * self.f - self.d * self.e) / det,
+            f: (self.b * self.e - self.a * self.f) / det,
+        })
+    }
+
+    // how much lengths grow on average
+    fn scale(&self) -> f32 {
+        (self.a * self.d - self.b * self.c).abs().sqrt()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GraphicsState {
+    ctm: Transform,
+    fill: u8,
+    stroke: u8,
+    line_width: f32,
+}
+
+type SubPath = Vec<(f32, f32)>;
+
+/// A white page on which we paint in device pixels.
+struct Canvas {
+    image: GrayImage,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            image: GrayImage::from_pixel(width, height, Luma([255u8])),
+        }
+    }
+
+    /// Fills the (implicitly closed) subpaths with a scanline at the centre
+    /// of every pixel row.
+    fn fill(&mut self, subpaths: &[SubPath], even_odd: bool, gray: u8) {
+        let edges: Vec<((f32, f32), (f32, f32))> = subpaths
+            .iter()
+            .filter(|path| path.len() > 1)
+            .flat_map(|path| {
+                path.iter()
+                    .zip(path.iter().cycle().skip(1))
+                    .map(|(p, q)| (*p, *q))
+            })
+            .filter(|(p, q)| p.1 != q.1)
+            .collect();
+        if edges.is_empty() {
+            return;
+        }
+
+        let (width, height) = self.image.dimensions();
+        let rows = pixel_range(edges.iter().flat_map(|(p, q)| [p.1, q.1]), height);
+
+        let mut crossings: Vec<(f32, i32)> = Vec::new();
+        for row in rows {
+            let y = row as f32 + 0.5;
+            crossings.clear();
+            for (p, q) in &edges {
+                let (top, bottom, direction) = if p.1 < q.1 { (p, q, 1) } else { (q, p, -1) };
+                if top.1 <= y && y < bottom.1 {
+                    let t = (y - top.1) / (bottom.1 - top.1);
+                    crossings.push((top.0 + t * (bottom.0 - top.0), direction));
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = if even_odd {
+                    winding % 2 != 0
+                } else {
+                    winding != 0
+                };
+                if inside {
+                    // pixels whose centre lies in between
+                    let from = (pair[0].0 - 0.5).ceil().max(0.0) as u32;
+                    let to = ((pair[1].0 - 0.5).ceil().max(0.0) as u32).min(width);
+                    for x in from..to {
+                        self.image.put_pixel(x, row, Luma([gray]));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Strokes the subpaths with (at least) one pixel wide lines.
+    fn stroke(&mut self, subpaths: &[SubPath], width: f32, gray: u8) {
+        let half = width.max(1.0) / 2.0;
+        for path in subpaths {
+            for (p, q) in path.iter().zip(path.iter().skip(1)) {
+                let (dx, dy) = (q.0 - p.0, q.1 - p.1);
+                let length = (dx * dx + dy * dy).sqrt();
+                if length > 0.0 {
+                    let (nx, ny) = (-dy / length * half, dx / length * half);
+                    let quad = vec![
+                        (p.0 + nx, p.1 + ny),
+                        (q.0 + nx, q.1 + ny),
+                        (q.0 - nx, q.1 - ny),
+                        (p.0 - nx, p.1 - ny),
+                    ];
+                    self.fill(&[quad], false, gray);
+                }
+                // a square at every corner instead of proper line joins
+                let square = vec![
+                    (q.0 - half, q.1 - half),
+                    (q.0 + half, q.1 - half),
+                    (q.0 + half, q.1 + half),
+                    (q.0 - half, q.1 + half),
+                ];
+                self.fill(&[square], false, gray);
+            }
+        }
+    }
+
+    /// Paints an image that covers the unit square of `transform`.
+    fn draw_image(&mut self, source: &GrayImage, transform: &Transform) {
+        let (source_width, source_height) = source.dimensions();
+        let Some(inverse) = transform.invert() else {
+            return;
+        };
+        if source_width == 0 || source_height == 0 {
+            return;
+        }
+        let corners =
+            [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)].map(|(u, v)| transform.apply(u, v));
+        let (width, height) = self.image.dimensions();
+        let columns = pixel_range(corners.iter().map(|p| p.0), width);
+        let rows = pixel_range(corners.iter().map(|p| p.1), height);
+
+        for y in rows {
+            for x in columns.clone() {
+                let (u, v) = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+                if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                    continue;
+                }
+                // the first row of an image is at the top of the unit square
+                let sx = ((u * source_width as f32) as u32).min(source_width - 1);
+                let sy = (((1.0 - v) * source_height as f32) as u32).min(source_height - 1);
+                self.image.put_pixel(x, y, *source.get_pixel(sx, sy));
+            }
+        }
+    }
+}
+
+// the pixels between the smallest and largest coordinate, within the canvas
+fn pixel_range(coordinates: impl Iterator<Item = f32>, limit: u32) -> std::ops::Range<u32> {
+    let (min, max) = coordinates.fold((f32::MAX, f32::MIN), |(min, max), c| {
+        (min.min(c), max.max(c))
+    });
+    let start = min.floor().clamp(0.0, limit as f32) as u32;
+    let end = max.ceil().clamp(0.0, limit as f32) as u32;
+    start..end.max(start)
+}
+
+fn gray_of(color: &Color) -> u8 {
+    let luma = match *color {
+        Color::Gray(g) => g,
+        Color::Rgb(rgb) => 0.299 * rgb.red + 0.587 * rgb.green + 0.114 * rgb.blue,
+        Color::Cmyk(cmyk) => luma_of_cmyk(cmyk.cyan, cmyk.magenta, cmyk.yellow, cmyk.key),
+        // `sc` and `scn` give the components of the current colour space,
+        // e.g. the ICC based gray of typst; patterns and separations are ink
+        Color::Other(ref values) => {
+            let values: Vec<f32> = values.iter().filter_map(|v| v.as_number().ok()).collect();
+            match values[..] {
+                [g] => g,
+                [r, g, b] => 0.299 * r + 0.587 * g + 0.114 * b,
+                [c, m, y, k] => luma_of_cmyk(c, m, y, k),
+                _ => 0.0,
+            }
+        }
+    };
+    (luma.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn luma_of_cmyk(c: f32, m: f32, y: f32, k: f32) -> f32 {
+    (0.299 * (1.0 - c) + 0.587 * (1.0 - m) + 0.114 * (1.0 - y)) * (1.0 - k)
+}
+
+// cubic Bézier curves become polylines with segments of about two pixels
+fn flatten_curve(path: &mut SubPath, c1: (f32, f32), c2: (f32, f32), p: (f32, f32)) {
+    let Some(&start) = path.last() else {
+        return;
+    };
+    let distance =
+        |a: (f32, f32), b: (f32, f32)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+    let length = distance(start, c1) + distance(c1, c2) + distance(c2, p);
+    let steps = ((length / 2.0).ceil() as usize).clamp(1, 100);
+    for i in 1..=steps {
+        let t = i as f32 / steps as f32;
+        let s = 1.0 - t;
+        let point = |a: f32, b: f32, c: f32, d: f32| {
+            s * s * s * a + 3.0 * s * s * t * b + 3.0 * s * t * t * c + t * t * t * d
+        };
+        path.push((
+            point(start.0, c1.0, c2.0, p.0),
+            point(start.1, c1.1, c2.1, p.1),
+        ));
+    }
+}
+
+// how the samples of `FlateDecode` and unfiltered images are laid out
+fn sample_format(color_space: Option<&ColorSpace>, bits: u8) -> Result<SampleFormat, Error> {
+    Ok(match color_space {
+        // image masks have no colour space
+        None | Some(ColorSpace::DeviceGray) | Some(ColorSpace::CalGray(_)) => {
+            SampleFormat::Gray { bits }
+        }
+        Some(ColorSpace::DeviceRGB) | Some(ColorSpace::CalRGB(_)) => SampleFormat::Rgb { bits },
+        Some(ColorSpace::DeviceCMYK) | Some(ColorSpace::CalCMYK(_)) => SampleFormat::Cmyk { bits },
+        Some(ColorSpace::Icc(profile)) => match profile.components {
+            1 => SampleFormat::Gray { bits },
+            3 => SampleFormat::Rgb { bits },
+            4 => SampleFormat::Cmyk { bits },
+            n => {
+                return Err(Error::Decode(format!(
+                    "unsupported ICC profile with {} components",
+                    n
+                )))
+            }
+        },
+        Some(ColorSpace::Indexed(base, _, lookup)) => {
+            let base = sample_format(Some(base), 8)?;
+            let colours = match base {
+                SampleFormat::Gray { .. } => lookup.iter().map(|&g| Rgb([g, g, g])).collect(),
+                SampleFormat::Rgb { .. } => lookup
+                    .chunks_exact(3)
+                    .map(|c| Rgb([c[0], c[1], c[2]]))
+                    .collect(),
+                SampleFormat::Cmyk { .. } => lookup
+                    .chunks_exact(4)
+                    .map(|c| cmyk_to_rgb(c[0], c[1], c[2], c[3]))
+                    .collect(),
+                SampleFormat::Palette { .. } => {
+                    return Err(Error::Decode(
+                        "nested indexed colour spaces are not allowed".into(),
+                    ))
+                }
+            };
+            SampleFormat::Palette { bits, colours }
+        }
+        Some(other) => {
+            return Err(Error::Decode(format!(
+                "unsupported colour space {:?}",
+                other
+            )))
+        }
+    })
+}
+
+fn invert(image: &mut GrayImage) {
+    for pixel in image.pixels_mut() {
+        pixel[0] = 255 - pixel[0];
+    }
+}
+
+/// Decodes an image XObject into a grayscale image.
+pub fn decode_image(image: &ImageXObject, resolver: &impl Resolve) -> Result<GrayImage, Error> {
+    let (image_data, filter) = image.raw_image_data(resolver)?;
+    // a decode array of [1 0] swaps black and white
+    let inverted = matches!(image.decode.as_deref(), Some([from, to, ..]) if from > to);
+
+    let mut result = match filter {
+        Some(StreamFilter::DCTDecode(_)) => {
+            return Ok(
+                image::load_from_memory_with_format(&image_data, image::ImageFormat::Jpeg)?
+                    .into_luma8(),
+            );
+        }
+        Some(StreamFilter::CCITTFaxDecode(params)) => {
+            let coding = match params.k {
+                k if k < 0 => FaxCoding::Group4,
+                0 => FaxCoding::Group3 {
+                    two_dimensional: false,
+                },
+                _ => FaxCoding::Group3 {
+                    two_dimensional: true,
+                },
+            };
+            let options = FaxOptions {
+                coding,
+                width: image.width,
+                height: image.height,
+                byte_align: params.encoded_byte_align,
+            };
+            let mut result = decode_fax(&image_data, &options)?;
+            if params.black_is_1 != inverted {
+                invert(&mut result);
+            }
+            result
+        }
+        Some(StreamFilter::JBIG2Decode(params)) => {
+            let globals = match &params.globals {
+                Some(globals) => Some(globals.data(resolver)?),
+                None => None,
+            };
+            let mut result = decode_jbig2(&image_data, globals.as_deref())?;
+            if inverted {
+                invert(&mut result);
+            }
+            result
+        }
+        None | Some(StreamFilter::FlateDecode(_)) => {
+            let samples = image.image_data(resolver)?;
+            let bits = match image.image_mask {
+                true => 1,
+                false => image.bits_per_component.unwrap_or(8) as u8,
+            };
+            let format = sample_format(image.color_space.as_ref(), bits)?;
+            let result =
+                samples_to_grayimage(&samples, image.width, image.height, &format, inverted)?;
+            // only bilevel images are eroded
+            if bits > 1 {
+                return Ok(result);
+            }
+            result
+        }
+        Some(other) => {
+            return Err(Error::Decode(format!(
+                "unsupported image filter {:?}",
+                other
+            )))
+        }
+    };
+
+    erode_bilevel(&mut result);
+    Ok(result)
+}
+
+// forms drawn by forms drawn by ... are left out from here on, which also
+// ends forms that draw themselves
+const MAX_FORM_DEPTH: usize = 16;
+
+impl From<Matrix> for Transform {
+    fn from(m: Matrix) -> Self {
+        Transform {
+            a: m.a,
+            b: m.b,
+            c: m.c,
+            d: m.d,
+            e: m.e,
+            f: m.f,
+        }
+    }
+}
+
+// Maps the media box to pixels at `scale` pixels per point, turned clockwise
+// by `rotate` degrees as a viewer shows the page, and returns the size of the
+// image.
+fn page_transform(media_box: &Rect, rotate: i32, scale: f32) -> (Transform, f32, f32) {
+    let (left, right) = (
+        media_box.left.min(media_box.right),
+        media_box.left.max(media_box.right),
+    );
+    let (bottom, top) = (
+        media_box.bottom.min(media_box.top),
+        media_box.bottom.max(media_box.top),
+    );
+    let (w, h) = (right - left, top - bottom);
+    let origin = Transform {
+        a: 1.0,
+        b: 0.0,
+        c: 0.0,
+        d: 1.0,
+        e: -left,
+        f: -bottom,
+    };
+    // rotate = 90 shows the left edge at the top
+    let (turn, width, height) = match rotate.rem_euclid(360) {
+        90 => ((0.0, -1.0, 1.0, 0.0, 0.0, w), h, w),
+        180 => ((-1.0, 0.0, 0.0, -1.0, w, h), w, h),
+        270 => ((0.0, 1.0, -1.0, 0.0, h, 0.0), h, w),
+        _ => ((1.0, 0.0, 0.0, 1.0, 0.0, 0.0), w, h),
+    };
+    let turn = Transform {
+        a: turn.0,
+        b: turn.1,
+        c: turn.2,
+        d: turn.3,
+        e: turn.4,
+        f: turn.5,
+    };
+    // PDF coordinates start at the bottom left, pixels at the top left
+    let flip = Transform {
+        a: scale,
+        b: 0.0,
+        c: 0.0,
+        d: -scale,
+        e: 0.0,
+        f: height * scale,
+    };
+    (
+        origin.then(&turn).then(&flip),
+        width * scale,
+        height * scale,
+    )
+}
+
+// The map that fits the bounding box of a form, as its matrix moves it, into
+// the rectangle of an annotation (section 12.5.5 of the PDF standard).
+fn fit_to_rect(bbox: &Rect, matrix: &Transform, rect: &Rect) -> Option<Transform> {
+    let corners = [
+        (bbox.left, bbox.bottom),
+        (bbox.right, bbox.bottom),
+        (bbox.left, bbox.top),
+        (bbox.right, bbox.top),
+    ]
+    .map(|(x, y)| matrix.apply(x, y));
+    let (x_min, x_max) = corners.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+        (min.min(p.0), max.max(p.0))
+    });
+    let (y_min, y_max) = corners.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+        (min.min(p.1), max.max(p.1))
+    });
+    if x_max <= x_min || y_max <= y_min {
+        return None;
+    }
+    let a = (rect.right - rect.left).abs() / (x_max - x_min);
+    let d = (rect.top - rect.bottom).abs() / (y_max - y_min);
+    Some(Transform {
+        a,
+        b: 0.0,
+        c: 0.0,
+        d,
+        e: rect.left.min(rect.right) - x_min * a,
+        f: rect.bottom.min(rect.top) - y_min * d,
+    })
+}
+
+// The normal appearance of an annotation, which is how viewers show the
+// fields of a filled-in form, and the rectangle it goes in. Hidden
+// annotations and those without an appearance give `None`.
+fn appearance(
+    annotation: Primitive,
+    resolver: &impl Resolve,
+) -> Result<Option<(Rect, FormXObject)>, Error> {
+    let mut annotation = annotation.resolve(resolver)?.into_dictionary()?;
+    let flags = match annotation.get("F") {
+        Some(flags) => flags.as_integer()?,
+        None => 0,
+    };
+    // the flags Hidden and NoView
+    if flags & (2 | 32) != 0 {
+        return Ok(None);
+    }
+    let (Some(appearances), Some(rect)) = (annotation.remove("AP"), annotation.remove("Rect"))
+    else {
+        return Ok(None);
+    };
+    let Some(normal) = appearances
+        .resolve(resolver)?
+        .into_dictionary()?
+        .remove("N")
+    else {
+        return Ok(None);
+    };
+    let stream = match normal.resolve(resolver)? {
+        // one appearance for every state, e.g. /Yes and /Off of a check box
+        Primitive::Dictionary(mut states) => {
+            let state = match annotation.get("AS") {
+                Some(state) => state.as_name()?.to_string(),
+                None => return Ok(None),
+            };
+            match states.remove(state.as_str()) {
+                Some(stream) => stream.resolve(resolver)?,
+                None => return Ok(None),
+            }
+        }
+        stream => stream,
+    };
+    Ok(Some((
+        Rect::from_primitive(rect, resolver)?,
+        FormXObject::from_primitive(stream, resolver)?,
+    )))
+}
+
+/// Paints the operations of a content stream onto the canvas.
+struct Painter<'a, R: Resolve> {
+    canvas: Canvas,
+    resolver: &'a R,
+}
+
+impl<R: Resolve> Painter<'_, R> {
+    fn paint(
+        &mut self,
+        operations: &[Op],
+        resources: Option<&Resources>,
+        mut state: GraphicsState,
+        depth: usize,
+    ) -> Result<(), Error> {
+        let mut stack = Vec::new();
+        let mut path: Vec<SubPath> = Vec::new();
+
+        for op in operations {
+            match op {
+                Op::Save => stack.push(state),
+                Op::Restore => state = stack.pop().unwrap_or(state),
+                Op::Transform { matrix } => {
+                    state.ctm = Transform::from(*matrix).then(&state.ctm);
+                }
+                Op::LineWidth { width } => state.line_width = *width,
+                Op::FillColor { color } => state.fill = gray_of(color),
+                Op::StrokeColor { color } => state.stroke = gray_of(color),
+                Op::MoveTo { p } => path.push(vec![state.ctm.apply(p.x, p.y)]),
+                Op::LineTo { p } => {
+                    if let Some(current) = path.last_mut() {
+                        current.push(state.ctm.apply(p.x, p.y));
+                    }
+                }
+                Op::CurveTo { c1, c2, p } => {
+                    if let Some(current) = path.last_mut() {
+                        flatten_curve(
+                            current,
+                            state.ctm.apply(c1.x, c1.y),
+                            state.ctm.apply(c2.x, c2.y),
+                            state.ctm.apply(p.x, p.y),
+                        );
+                    }
+                }
+                Op::Rect { rect } => {
+                    let corners = [
+                        (rect.x, rect.y),
+                        (rect.x + rect.width, rect.y),
+                        (rect.x + rect.width, rect.y + rect.height),
+                        (rect.x, rect.y + rect.height),
+                        (rect.x, rect.y),
+                    ];
+                    path.push(corners.map(|(x, y)| state.ctm.apply(x, y)).to_vec());
+                }
+                Op::Close => {
+                    if let Some(current) = path.last_mut() {
+                        if let Some(&start) = current.first() {
+                            current.push(start);
+                        }
+                    }
+                }
+                Op::Fill { winding } => {
+                    self.canvas
+                        .fill(&path, matches!(winding, Winding::EvenOdd), state.fill);
+                    path.clear();
+                }
+                Op::FillAndStroke { winding } => {
+                    self.canvas
+                        .fill(&path, matches!(winding, Winding::EvenOdd), state.fill);
+                    self.canvas
+                        .stroke(&path, state.line_width * state.ctm.scale(), state.stroke);
+                    path.clear();
+                }
+                Op::Stroke => {
+                    self.canvas
+                        .stroke(&path, state.line_width * state.ctm.scale(), state.stroke);
+                    path.clear();
+                }
+                // clipping is not needed for the pages we care about
+                Op::EndPath | Op::Clip { .. } => path.clear(),
+                Op::XObject { name } => {
+                    let Some(&reference) = resources.and_then(|r| r.xobjects.get(name)) else {
+                        continue;
+                    };
+                    let xobject = self.resolver.get(reference)?;
+                    match *xobject {
+                        XObject::Image(ref image) => match decode_image(image, self.resolver) {
+                            Ok(decoded) => self.canvas.draw_image(&decoded, &state.ctm),
+                            Err(e) => log::warn!("skipping image: {}", e),
+                        },
+                        XObject::Form(ref form) => {
+                            if let Err(e) = self.paint_form(form, resources, state, depth + 1) {
+                                log::warn!("skipping form: {}", e);
+                            }
+                        }
+                        XObject::Postscript(_) => {}
+                    }
+                }
+                Op::InlineImage { image } => {
+                    if let Ok(decoded) = decode_image(image, self.resolver) {
+                        self.canvas.draw_image(&decoded, &state.ctm);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // A form is drawn like a content stream of its own, moved by its matrix
+    // and with its own resources, if it has any.
+    fn paint_form(
+        &mut self,
+        form: &FormXObject,
+        resources: Option<&Resources>,
+        state: GraphicsState,
+        depth: usize,
+    ) -> Result<(), Error> {
+        if depth > MAX_FORM_DEPTH {
+            return Err(Error::Decode("forms are nested too deeply".into()));
+        }
+        let dict = form.dict();
+        let matrix = form_matrix(form, self.resolver)?;
+        let operations = form.operations(self.resolver)?;
+        let state = GraphicsState {
+            ctm: matrix.then(&state.ctm),
+            ..state
+        };
+        self.paint(
+            &operations,
+            dict.resources.as_deref().or(resources),
+            state,
+            depth,
+        )
+    }
+}
+
+fn form_matrix(form: &FormXObject, resolver: &impl Resolve) -> Result<Transform, Error> {
+    Ok(match &form.dict().matrix {
+        Some(matrix) => Matrix::from_primitive(matrix.clone(), resolver)?.into(),
+        None => Transform::from(Matrix::default()),
+    })
+}
+
+/// Renders the images and paths of a page, of the forms it draws and of the
+/// appearances of its annotations, turned as the page asks for; text is left
+/// out since bubble sheets only need their circles and boxes.
+pub fn render_page(page: &Page, resolver: &impl Resolve, dpi: f32) -> Result<GrayImage, Error> {
+    let media_box = page.media_box()?;
+    let (ctm, width, height) = page_transform(&media_box, page.rotate, dpi / 72.0);
+    let (width, height) = (width.round() as u32, height.round() as u32);
+    if width == 0 || height == 0 || width > 20_000 || height > 20_000 {
+        return Err(Error::Decode(format!(
+            "cannot render a page of {}x{} pixels",
+            width, height
+        )));
+    }
+
+    let operations = match &page.contents {
+        Some(contents) => contents.operations(resolver)?,
+        None => Vec::new(),
+    };
+    let resources = page.resources()?;
+
+    let mut painter = Painter {
+        canvas: Canvas::new(width, height),
+        resolver,
+    };
+    let state = GraphicsState {
+        ctm,
+        fill: 0,
+        stroke: 0,
+        line_width: 1.0,
+    };
+    painter.paint(&operations, Some(resources), state, 0)?;
+
+    let annotations = match page.other.get("Annots") {
+        Some(annotations) => annotations.clone().resolve(resolver)?.into_array()?,
+        None => Vec::new(),
+    };
+    for annotation in annotations {
+        let (rect, form) = match appearance(annotation, resolver) {
+            Ok(Some(appearance)) => appearance,
+            Ok(None) => continue,
+            Err(e) => {
+                log::warn!("skipping annotation: {}", e);
+                continue;
+            }
+        };
+        let result = form_matrix(&form, resolver).and_then(|matrix| {
+            let Some(fit) = fit_to_rect(&form.dict().bbox, &matrix, &rect) else {
+                return Ok(());
+            };
+            let state = GraphicsState {
+                ctm: fit.then(&ctm),
+                ..state
+            };
+            painter.paint_form(&form, Some(resources), state, 1)
+        });
+        if let Err(e) = result {
+            log::warn!("skipping annotation: {}", e);
+        }
+    }
+
+    Ok(painter.canvas.image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms() {
+        let scale = Transform {
+            a: 2.0,
+            b: 0.0,
+            c: 0.0,
+            d: -2.0,
+            e: 0.0,
+            f: 100.0,
+        };
+        let shift = Transform {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 1.0,
+            e: 5.0,
+            f: 10.0,
+        };
+        let both = shift.then(&scale);
+        assert_eq!(both.apply(1.0, 1.0), (12.0, 78.0));
+        assert_eq!(both.invert().unwrap().apply(12.0, 78.0), (1.0, 1.0));
+        assert_eq!(both.scale(), 2.0);
+    }
+
+    #[test]
+    fn filling_and_stroking() {
+        let mut canvas = Canvas::new(40, 40);
+        let square = |x: f32, y: f32, size: f32| {
+            vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+        };
+        // a square with a hole, once with each winding rule
+        let ring = [square(0.0, 0.0, 20.0), square(5.0, 5.0, 10.0)];
+        canvas.fill(&ring, true, 0);
+        assert_eq!(canvas.image.get_pixel(2, 2).0, [0]);
+        assert_eq!(canvas.image.get_pixel(10, 10).0, [255]);
+        assert_eq!(canvas.image.get_pixel(20, 20).0, [255]);
+        canvas.fill(&ring, false, 100);
+        assert_eq!(canvas.image.get_pixel(10, 10).0, [100]);
+
+        let mut circle = vec![(30.0, 20.0)];
+        flatten_curve(&mut circle, (30.0, 30.0), (40.0, 30.0), (40.0, 20.0));
+        assert_eq!(*circle.last().unwrap(), (40.0, 20.0));
+        canvas.stroke(&[vec![(20.0, 35.5), (39.0, 35.5)]], 0.2, 0);
+        assert_eq!(canvas.image.get_pixel(30, 35).0, [0]);
+        assert_eq!(canvas.image.get_pixel(30, 37).0, [255]);
+
+        let mut target = Canvas::new(10, 10);
+        let image = GrayImage::from_fn(2, 2, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 200 }]));
+        let transform = Transform {
+            a: 10.0,
+            b: 0.0,
+            c: 0.0,
+            d: -10.0,
+            e: 0.0,
+            f: 10.0,
+        };
+        target.draw_image(&image, &transform);
+        assert_eq!(target.image.get_pixel(2, 2).0, [0]);
+        assert_eq!(target.image.get_pixel(7, 2).0, [200]);
+        assert_eq!(target.image.get_pixel(7, 7).0, [0]);
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(gray_of(&Color::Gray(1.0)), 255);
+        let rgb = pdf::content::Rgb {
+            red: 1.0,
+            green: 0.0,
+            blue: 0.0,
+        };
+        assert_eq!(gray_of(&Color::Rgb(rgb)), 76);
+        let cmyk = pdf::content::Cmyk {
+            cyan: 0.0,
+            magenta: 0.0,
+            yellow: 0.0,
+            key: 1.0,
+        };
+        assert_eq!(gray_of(&Color::Cmyk(cmyk)), 0);
+        // `1 scn` in an ICC based gray, as typst paints white
+        assert_eq!(gray_of(&Color::Other(vec![Primitive::Integer(1)])), 255);
+        let pattern = Color::Other(vec![Primitive::Name("P0".into())]);
+        assert_eq!(gray_of(&pattern), 0);
+
+        let lookup: std::sync::Arc<[u8]> = vec![255, 0, 0, 0, 0, 0].into();
+        let palette = ColorSpace::Indexed(Box::new(ColorSpace::DeviceRGB), 1, lookup);
+        assert_eq!(
+            sample_format(Some(&palette), 1).unwrap(),
+            SampleFormat::Palette {
+                bits: 1,
+                colours: vec![Rgb([255, 0, 0]), Rgb([0, 0, 0])]
+            }
+        );
+        assert_eq!(
+            sample_format(None, 1).unwrap(),
+            SampleFormat::Gray { bits: 1 }
+        );
+        assert!(sample_format(Some(&ColorSpace::Pattern), 8).is_err());
+    }
+}
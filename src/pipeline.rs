@@ -0,0 +1,411 @@
+use crate::error::Error;
+use crate::feedback::{FeedbackEntry, FeedbackPdfs};
+use crate::image_container::{ImageContainer, PageResult};
+use crate::report::{ImageReport, IssueSummary, ReportSettings};
+use crate::scan::Scan;
+use crate::sink::{ImageSink, RecordSink};
+use crate::template::{check_compatible, ExamKey, Template};
+use crate::template_scan::TemplateScan;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How far a grading run has come, reported after every sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub done: usize,
+    /// The number of pages, if the caller knows it.
+    pub total: Option<usize>,
+    /// The sheets so far that have to be graded by hand.
+    pub issues: usize,
+}
+
+/// Stops a grading run from another thread or the UI. Clones share the flag;
+/// the run ends with `Error::Cancelled` before the next page.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// `Err(Error::Cancelled)` once `cancel` has been called.
+    pub fn check(&self) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// A sheet after `GradingRun::encode`, ready to be added to the run.
+pub struct GradedSheet<S> {
+    pub score: u32,
+    pub record: Vec<String>,
+    pub issues: Option<IssueSummary>,
+    pub feedback: Option<(FeedbackEntry, Vec<u8>)>,
+    pub sheet: S,
+}
+
+/// Grades the pages of a batch and writes the results once all pages are
+/// done. The caller feeds the pages:
+///
+/// 1. `grade` every page and `encode` the report, both may run in parallel,
+/// 2. `add` the graded sheets in page order, while other pages are graded,
+/// 3. `finish` to write the CSV records, grades, issues and feedback.
+///
+/// Only the CSV records (and the feedback tables, if asked for) are kept
+/// until the end: the grades depend on all scores. The images of the feedback
+/// sheets wait in a temporary file.
+pub struct GradingRun<'a, I: ImageSink> {
+    pub template: &'a Template,
+    pub key: &'a ExamKey,
+    pub settings: &'a ReportSettings,
+    images: I,
+    state: Mutex<RunState>,
+}
+
+struct RunState {
+    records: Vec<Vec<String>>,
+    scores: Vec<u32>,
+    issues: Vec<IssueSummary>,
+    feedback: Option<FeedbackPdfs>,
+}
+
+impl<'a, I: ImageSink> GradingRun<'a, I> {
+    pub fn new(
+        template: &'a Template,
+        key: &'a ExamKey,
+        settings: &'a ReportSettings,
+        images: I,
+    ) -> Result<Self, Error> {
+        check_compatible(template, key)?;
+        Ok(Self {
+            template,
+            key,
+            settings,
+            images,
+            state: Mutex::new(RunState {
+                records: Vec::new(),
+                scores: Vec::new(),
+                issues: Vec::new(),
+                feedback: settings.feedback.map(FeedbackPdfs::new),
+            }),
+        })
+    }
+
+    /// The annotated report of a page, with a banner if the settings ask for
+    /// it. A page that cannot be read or aligned, or that grading panics on,
+    /// gets an error image.
+    pub fn grade(&self, identifier: &str, page: PageResult) -> ImageReport {
+        let graded = page.and_then(|image| {
+            catch_panic(|| {
+                let template_scan = TemplateScan::aligned(self.template, Scan::new(image))?;
+                Ok(template_scan.generate_image_report_with(
+                    self.key,
+                    &identifier.to_string(),
+                    &self.settings.colours,
+                    self.settings.version_fallback,
+                ))
+            })
+        });
+        let mut report = match graded {
+            Ok(report) => report,
+            Err(error) => ImageReport::unreadable(self.template, identifier, &error),
+        };
+        if self.settings.banner {
+            let max_score = self.template.max_score();
+            let grade = self
+                .settings
+                .grading
+                .as_ref()
+                .and_then(|scale| scale.grade_of(report.score(), max_score));
+            report.add_banner(max_score, grade.as_deref());
+        }
+        report
+    }
+
+    pub fn encode(&self, report: &ImageReport) -> GradedSheet<I::Sheet> {
+        let naming = &self.settings.naming;
+        GradedSheet {
+            score: report.score(),
+            record: report.to_serializable_vector(naming),
+            issues: report.has_issues().then(|| report.issue_summary(naming)),
+            feedback: self.settings.feedback.map(|_| {
+                let mut image = Vec::new();
+                report.write_to_buffer(&mut image);
+                (FeedbackEntry::new(report, self.template, self.key), image)
+            }),
+            sheet: self.images.encode(report, naming),
+        }
+    }
+
+    pub fn add(
+        &self,
+        sheet: GradedSheet<I::Sheet>,
+        records: &mut dyn RecordSink,
+    ) -> Result<(), Error> {
+        self.images.add(sheet.sheet, records)?;
+        let mut state = self.state.lock().unwrap();
+        state.scores.push(sheet.score);
+        state.records.push(sheet.record);
+        state.issues.extend(sheet.issues);
+        if let (Some(feedback), Some((entry, image))) = (&mut state.feedback, sheet.feedback) {
+            feedback.add(entry, &image)?;
+        }
+        Ok(())
+    }
+
+    pub fn progress(&self, total: Option<usize>) -> Progress {
+        let state = self.state.lock().unwrap();
+        Progress {
+            done: state.records.len(),
+            total,
+            issues: state.issues.len(),
+        }
+    }
+
+    pub fn finish(self, records: &mut dyn RecordSink) -> Result<(), Error> {
+        let settings = self.settings;
+        let mut state = self.state.into_inner().unwrap();
+        records.add_file(
+            "issues.json",
+            serde_json::to_string_pretty(&state.issues)?.as_bytes(),
+        )?;
+
+        // grades can only be computed once we know all scores
+        let mut header = self.template.to_csv_header();
+        if let Some(scale) = &settings.grading {
+            let grades = scale.add_grade_columns(
+                &mut header,
+                &mut state.records,
+                &state.scores,
+                self.template.max_score(),
+            );
+            records.add_file(
+                "grade-summary.csv",
+                scale.band_summary_csv(&grades)?.as_bytes(),
+            )?;
+            if let Some(feedback) = &mut state.feedback {
+                feedback.set_grades(grades);
+            }
+        }
+
+        if let Some(feedback) = state.feedback {
+            feedback.finish(&settings.title, records)?;
+        }
+
+        self.images.finish(records)?;
+        records.add_records(&header, &state.records)
+    }
+}
+
+/// Runs `f` and turns a panic into an error, so that a page we fail on
+/// becomes an issue of its own instead of ending the batch.
+pub fn catch_panic<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "unknown error".to_string(),
+        };
+        Err(Error::Decode(format!("grading failed: {}", message)))
+    })
+}
+
+/// Runs `work` on every page of the container on `threads` workers (`0` for
+/// one per core) and hands the results to `add` in page order, together with
+/// the index of the page. At most `2 * threads` pages are decoded, in work or
+/// waiting for an earlier page at any time, so memory does not grow with the
+/// container. Once `cancel` is cancelled no more pages are decoded.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn for_each_page<T: Send>(
+    container: &mut dyn ImageContainer,
+    threads: usize,
+    cancel: &CancellationToken,
+    work: impl Fn(usize, PageResult) -> T + Sync,
+    mut add: impl FnMut(usize, T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{channel, sync_channel};
+
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let window = 2 * threads;
+
+    let (page_tx, page_rx) = sync_channel(window);
+    let page_rx = Mutex::new(page_rx);
+    let (result_tx, result_rx) = channel();
+    std::thread::scope(|scope| {
+        // dropped when we return, which stops the workers
+        let (page_tx, result_rx) = (page_tx, result_rx);
+        for _ in 0..threads {
+            let (page_rx, result_tx, work) = (&page_rx, result_tx.clone(), &work);
+            scope.spawn(move || loop {
+                // the lock is released before the page is worked on
+                let next = page_rx.lock().unwrap().recv();
+                let Ok((idx, page)) = next else { break };
+                // a panic is passed on to the caller instead of losing the page
+                let result =
+                    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| work(idx, page)));
+                if result_tx.send((idx, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+
+        let mut pages = container.to_iter().enumerate();
+        let mut waiting = BTreeMap::new();
+        let mut in_flight = 0;
+        let mut next = 0;
+        loop {
+            while in_flight < window {
+                let Some(page) = pages.next() else { break };
+                cancel.check()?;
+                // the workers only stop when we drop `page_tx`
+                let _ = page_tx.send(page);
+                in_flight += 1;
+            }
+            if in_flight == 0 {
+                break;
+            }
+            let Ok((idx, result)) = result_rx.recv() else {
+                break;
+            };
+            waiting.insert(idx, result);
+            while let Some(result) = waiting.remove(&next) {
+                let result = result.unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                add(next, result)?;
+                next += 1;
+                in_flight -= 1;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Issue;
+    use crate::sink::PngSink;
+    use crate::template::key_from_json;
+    use image::{GrayImage, Luma};
+    use std::sync::atomic::AtomicUsize;
+
+    // counts the pages that were decoded
+    struct CountingContainer<'a> {
+        pages: usize,
+        decoded: &'a AtomicUsize,
+    }
+
+    impl ImageContainer for CountingContainer<'_> {
+        fn get_page(&mut self, _n: usize) -> Result<GrayImage, Error> {
+            self.decoded.fetch_add(1, Ordering::SeqCst);
+            Ok(GrayImage::new(1, 1))
+        }
+
+        fn page_count(&mut self) -> usize {
+            self.pages
+        }
+    }
+
+    #[test]
+    fn bounded_pages() {
+        for threads in [1, 3] {
+            let decoded = AtomicUsize::new(0);
+            let mut container = CountingContainer {
+                pages: 60,
+                decoded: &decoded,
+            };
+            let mut added = 0;
+            for_each_page(
+                &mut container,
+                threads,
+                &CancellationToken::default(),
+                |idx, _| {
+                    // a slow page makes the later ones wait
+                    if idx % 10 == 0 {
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                    }
+                    idx
+                },
+                |idx, result| {
+                    assert_eq!((idx, result), (added, added));
+                    let unconsumed = decoded.load(Ordering::SeqCst) - added;
+                    assert!(unconsumed <= 2 * threads, "{} pages", unconsumed);
+                    added += 1;
+                    Ok(())
+                },
+            )
+            .unwrap();
+            assert_eq!(added, 60);
+        }
+    }
+
+    #[test]
+    fn panics_as_errors() {
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+        assert!(matches!(
+            catch_panic::<()>(|| Err(Error::Alignment)),
+            Err(Error::Alignment)
+        ));
+        let error = catch_panic::<()>(|| panic!("page {}", 3)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "could not decode image: grading failed: page 3"
+        );
+    }
+
+    #[test]
+    fn unaligned_page() {
+        let template =
+            Template::from_json(std::fs::File::open("tests/assets/template.json").unwrap())
+                .unwrap();
+        let key = key_from_json(std::fs::File::open("tests/assets/key.json").unwrap()).unwrap();
+        let settings = ReportSettings::default();
+        let run = GradingRun::new(&template, &key, &settings, PngSink).unwrap();
+
+        // a blank page has no circles to align the template with
+        let blank = GrayImage::from_pixel(template.width, template.height, Luma([255]));
+        let report = run.grade("page1", Ok(blank));
+        assert_eq!(
+            report.issues,
+            vec![Issue::UnreadablePage {
+                message: Error::Alignment.to_string()
+            }]
+        );
+        assert_eq!(report.score(), 0);
+    }
+
+    #[test]
+    fn cancelled() {
+        let decoded = AtomicUsize::new(0);
+        let mut container = CountingContainer {
+            pages: 60,
+            decoded: &decoded,
+        };
+        let cancel = CancellationToken::default();
+        let result = for_each_page(
+            &mut container,
+            2,
+            &cancel,
+            |idx, _| idx,
+            |idx, _| {
+                if idx == 5 {
+                    cancel.cancel();
+                }
+                Ok(())
+            },
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(decoded.load(Ordering::SeqCst) < 20);
+    }
+}
@@ -1,86 +1,428 @@
-use image::RgbImage;
-use std::io::Write;
-use zip::write::FileOptions;
-use zip::ZipWriter;
+use crate::colours::ColourScheme;
+use crate::error::Error;
+use crate::feedback::FeedbackMode;
+use crate::grading::percentage;
+use crate::grading::GradingScale;
+use crate::image_helpers::{create_error_image, draw_text, gray_to_rgb};
+use crate::naming::NamingScheme;
+use crate::report_pdf::PdfSettings;
+use crate::template::{option_letter, Template};
+use crate::template_scan::VersionFallback;
+use image::{Rgb, RgbImage};
+use imageproc::drawing;
+use serde::Serialize;
+
+const WHITE: Rgb<u8> = Rgb([255u8, 255u8, 255u8]);
+const BLACK: Rgb<u8> = Rgb([0u8, 0u8, 0u8]);
+const ISSUE_RED: Rgb<u8> = Rgb([200u8, 0u8, 0u8]);
+
+/// What we produce besides the CSV file when grading a batch of scans.
+#[derive(Debug, Clone)]
+pub struct ReportSettings {
+    // printed on top of the feedback and report PDFs, e.g. the name of the exam
+    pub title: String,
+    pub grading: Option<GradingScale>,
+    pub feedback: Option<FeedbackMode>,
+    // all annotated sheets in a single PDF instead of one PNG per sheet
+    pub combined_pdf: Option<PdfSettings>,
+    pub naming: NamingScheme,
+    pub colours: ColourScheme,
+    // a header with ID, version, score and grade on every annotated image
+    pub banner: bool,
+    pub version_fallback: VersionFallback,
+    // worker threads for grading, 0 for one per core
+    pub threads: usize,
+}
+
+impl Default for ReportSettings {
+    fn default() -> Self {
+        Self {
+            title: "Exam report".to_string(),
+            grading: None,
+            feedback: None,
+            combined_pdf: None,
+            naming: NamingScheme::default(),
+            colours: ColourScheme::default(),
+            banner: false,
+            version_fallback: VersionFallback::default(),
+            threads: 0,
+        }
+    }
+}
+
+/// Why a sheet has to be graded by hand. Questions and columns are counted
+/// from 1, versions from 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// `version_scores` holds the score for every version and `assumed` the
+    /// version we graded the sheet as, if any
+    MissingVersion {
+        version_scores: Vec<u32>,
+        assumed: Option<u32>,
+    },
+    MultipleVersions {
+        version_scores: Vec<u32>,
+        assumed: Option<u32>,
+    },
+    IdGap {
+        column: usize,
+    },
+    IdMultipleMarks {
+        column: usize,
+    },
+    IdCheckDigit,
+    MultipleMarks {
+        question: usize,
+    },
+    InvalidNumeric {
+        question: usize,
+    },
+    TeacherPoints {
+        field: usize,
+    },
+    AlignmentFailure,
+    UnreadablePage {
+        message: String,
+    },
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::MissingVersion {
+                version_scores,
+                assumed,
+            } => {
+                write!(f, "no version marked")?;
+                write_version_scores(f, version_scores, *assumed)
+            }
+            Self::MultipleVersions {
+                version_scores,
+                assumed,
+            } => {
+                write!(f, "several versions marked")?;
+                write_version_scores(f, version_scores, *assumed)
+            }
+            Self::IdGap { column } => write!(f, "blank ID column {}", column),
+            Self::IdMultipleMarks { column } => write!(f, "several marks in ID column {}", column),
+            Self::IdCheckDigit => write!(f, "ID fails the check digit"),
+            Self::MultipleMarks { question } => write!(f, "several marks in Q{}", question),
+            Self::InvalidNumeric { question } => write!(f, "unreadable answer to N{}", question),
+            Self::TeacherPoints { field } => write!(f, "no clear points in T{}", field),
+            Self::AlignmentFailure => write!(f, "could not align the scan"),
+            Self::UnreadablePage { message } => write!(f, "unreadable page: {}", message),
+        }
+    }
+}
+
+// e.g. ", graded as v1 (v0: 12, v1: 15)"
+fn write_version_scores(
+    f: &mut std::fmt::Formatter,
+    version_scores: &[u32],
+    assumed: Option<u32>,
+) -> std::fmt::Result {
+    if let Some(v) = assumed {
+        write!(f, ", graded as v{}", v)?;
+    }
+    if !version_scores.is_empty() {
+        let scores: Vec<String> = version_scores
+            .iter()
+            .enumerate()
+            .map(|(v, score)| format!("v{}: {}", v, score))
+            .collect();
+        write!(f, " ({})", scores.join(", "))?;
+    }
+    Ok(())
+}
+
+/// The issues of one sheet as exported to `issues.json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssueSummary {
+    pub filename: String,
+    pub identifier: String,
+    pub sid: Option<String>,
+    pub issues: Vec<Issue>,
+}
+
+// "Grade by hand:" and the issues, as many on a line as fit; an issue too
+// long for a line of its own is broken between words
+fn issue_lines(issues: &[Issue], chars_per_line: usize) -> Vec<String> {
+    let mut lines = vec![String::from("Grade by hand:")];
+    for (i, issue) in issues.iter().map(|issue| issue.to_string()).enumerate() {
+        let separator = if i == 0 { " " } else { ", " };
+        let last = lines.last_mut().unwrap();
+        if last.chars().count() + separator.len() + issue.chars().count() <= chars_per_line {
+            *last += separator;
+            *last += &issue;
+            continue;
+        }
+        lines.push(String::new());
+        for word in issue.split(' ') {
+            let last = lines.last_mut().unwrap();
+            if last.is_empty() {
+                *last += word;
+            } else if last.chars().count() + 1 + word.chars().count() <= chars_per_line {
+                *last += " ";
+                *last += word;
+            } else {
+                lines.push(word.to_string());
+            }
+        }
+    }
+    lines
+}
+
+// the number of characters that fit in the width at the given text height
+fn chars_per_line(width: u32, line_height: u32) -> usize {
+    (2 * width / line_height).max(20) as usize - 4
+}
+
+/// Appends the list of issues below the image.
+pub fn add_issue_footer(image: RgbImage, issues: &[Issue]) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let line_height = (width / 50).max(12);
+    let lines = issue_lines(issues, chars_per_line(width, line_height));
+
+    // white rows below the pixels we have, which saves copying them
+    let footer_height = line_height * (3 * lines.len() as u32 + 2) / 2;
+    let mut data = image.into_raw();
+    data.resize(3 * width as usize * (height + footer_height) as usize, 255);
+    let mut result = RgbImage::from_raw(width, height + footer_height, data).unwrap();
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(
+            &mut result,
+            line_height,
+            height + line_height / 2 + i as u32 * 3 * line_height / 2,
+            line_height as f32,
+            ISSUE_RED,
+            line,
+        );
+    }
+    result
+}
 
 pub struct ImageReport {
     pub image: RgbImage,
-    pub sid: Option<u32>,
+    pub sid: Option<String>,
+    pub name: Option<String>,
     pub version: Option<u32>,
-    pub issue: bool,
+    // what was marked in every MCQ and numeric question, see
+    // `TemplateScan::answers`
+    pub answers: Vec<String>,
+    // empty if the sheet could be graded automatically
+    pub issues: Vec<Issue>,
     pub scores: Vec<Option<u32>>,
     pub identifier: String,
 }
 
 impl ImageReport {
+    /// A report for a page that could not be read at all, with the error
+    /// as its image. Its CSV record has the same columns as those of the
+    /// other sheets of the template.
+    pub fn unreadable(template: &Template, identifier: &str, error: &Error) -> Self {
+        let message = error.to_string();
+        let image = gray_to_rgb(&create_error_image(&message));
+        let issues = vec![Issue::UnreadablePage { message }];
+        let num_scores = template.questions.len()
+            + template.numeric_questions.len()
+            + template.teacher_questions.len();
+        Self {
+            image: add_issue_footer(image, &issues),
+            sid: None,
+            name: (!template.name_questions.is_empty()).then(String::new),
+            version: None,
+            answers: Vec::new(),
+            issues,
+            scores: vec![None; num_scores],
+            identifier: identifier.to_string(),
+        }
+    }
+
     pub fn score(&self) -> u32 {
         self.scores.clone().into_iter().flatten().sum()
     }
-    pub fn save_filename(&self, prefix: &String) -> String {
-        let mut filename: String = "".to_string();
 
-        if self.issue {
-            filename += "GRADE_BY_HAND-";
+    pub fn has_issues(&self) -> bool {
+        !self.issues.is_empty()
+    }
+
+    pub fn issue_summary(&self, naming: &NamingScheme) -> IssueSummary {
+        IssueSummary {
+            filename: self.save_filename(naming),
+            identifier: self.identifier.clone(),
+            sid: self.sid.clone(),
+            issues: self.issues.clone(),
         }
-        if let Some(id) = self.sid {
-            filename += &format!("{}-", id);
-        } else {
-            filename += "NOID-";
+    }
+    /// The relative path of the annotated image, see `NamingScheme`.
+    pub fn save_filename(&self, naming: &NamingScheme) -> String {
+        naming.apply(self) + ".png"
+    }
+    /// The line printed in the banner: ID, name, version, score and grade.
+    pub fn banner_text(&self, max_score: u32, grade: Option<&str>) -> String {
+        let mut parts = vec![format!(
+            "ID {}",
+            self.sid.clone().unwrap_or("unknown".to_string())
+        )];
+        if let Some(name) = self.name.as_ref().filter(|name| !name.is_empty()) {
+            parts.push(name.clone());
         }
+        parts.push(match self.version {
+            Some(version) => format!("Version {}", option_letter(version)),
+            None => "Version unknown".to_string(),
+        });
+        let mut score = format!("Score {}/{}", self.score(), max_score);
+        if let Some(percent) = percentage(self.score(), max_score) {
+            score += &format!(" ({:.1}%)", percent);
+        }
+        parts.push(score);
+        if let Some(grade) = grade.filter(|grade| !grade.is_empty()) {
+            parts.push(format!("Grade {}", grade));
+        }
+        parts.join("   ·   ")
+    }
 
-        if let Some(version) = self.version {
-            filename += &format!("v{}-", version);
+    /// Adds a header above the annotated image that summarises the sheet, so
+    /// the result can be read off the image without looking at the filename.
+    pub fn add_banner(&mut self, max_score: u32, grade: Option<&str>) {
+        let width = self.image.width();
+        let line_height = (width / 40).max(12);
+        let issue_lines = if self.has_issues() {
+            issue_lines(&self.issues, chars_per_line(width, line_height))
         } else {
-            filename += "NOVERSION-";
-        }
+            Vec::new()
+        };
+        let lines = 1 + issue_lines.len() as u32;
+        let banner_height = line_height * (3 * lines + 2) / 2;
 
-        filename += &format!("score{}-{}.png", self.score(), self.identifier);
+        let mut image = RgbImage::from_pixel(width, self.image.height() + banner_height, WHITE);
+        image::imageops::replace(&mut image, &self.image, 0, banner_height as i64);
 
-        prefix.to_string() + &filename
-    }
-    pub fn save_to_file(&self, prefix: &String) {
-        let path = self.save_filename(prefix);
-        let _ = self.image.save_with_format(&path, image::ImageFormat::Png);
+        let margin = line_height;
+        draw_text(
+            &mut image,
+            margin,
+            line_height / 2,
+            line_height as f32,
+            BLACK,
+            &self.banner_text(max_score, grade),
+        );
+        for (i, line) in issue_lines.iter().enumerate() {
+            draw_text(
+                &mut image,
+                margin,
+                line_height / 2 + (i as u32 + 1) * 3 * line_height / 2,
+                line_height as f32,
+                ISSUE_RED,
+                line,
+            );
+        }
+        drawing::draw_filled_rect_mut(
+            &mut image,
+            imageproc::rect::Rect::at(0, banner_height as i32 - line_height as i32 / 4)
+                .of_size(width, (line_height / 8).max(1)),
+            BLACK,
+        );
+        self.image = image;
     }
+
     pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
         let _ = self
             .image
             .write_to(&mut std::io::Cursor::new(buffer), image::ImageFormat::Png);
     }
 
-    pub fn to_serializable_vector(&self) -> Vec<String> {
-        std::iter::once(self.save_filename(&"".to_string()))
-            .chain(
-                std::iter::once(self.sid)
-                    .chain(std::iter::once(Some(self.score())))
-                    .chain(std::iter::once(self.version))
-                    .chain(self.scores.iter().copied())
-                    .map(|opt| opt.map(|v| v.to_string()).unwrap_or_default()),
-            )
-            .collect()
-    }
-
-    pub fn add_to_zip<W: Write + std::io::Seek>(
-        &self,
-        zip_writer: &mut ZipWriter<W>,
-        csv_writer: &mut csv::Writer<std::io::Cursor<Vec<u8>>>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Encode the image as PNG into a separate buffer
-        let mut image_buffer = Vec::new();
-        self.write_to_buffer(&mut image_buffer);
-
-        // Define a filename for each image within the zip
-        let file_name = self.save_filename(&"".to_string());
-
-        let record = self.to_serializable_vector();
-        csv_writer.serialize(record)?;
-        // Add the encoded image to the zip archive
-        zip_writer.start_file::<String, ()>(
-            file_name,
-            FileOptions::default().compression_method(zip::CompressionMethod::Deflated),
-        )?;
-        zip_writer.write_all(&image_buffer)?;
-
-        Ok(())
+    pub fn to_serializable_vector(&self, naming: &NamingScheme) -> Vec<String> {
+        let mut record = vec![
+            self.save_filename(naming),
+            self.sid.clone().unwrap_or_default(),
+        ];
+        if let Some(name) = &self.name {
+            record.push(name.clone());
+        }
+        record.push(self.score().to_string());
+        record.push(self.version.map(|v| v.to_string()).unwrap_or_default());
+        record.extend(
+            self.scores
+                .iter()
+                .map(|opt| opt.map(|v| v.to_string()).unwrap_or_default()),
+        );
+        record.push(self.answers.concat());
+        record.push(
+            self.issues
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+        );
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banner() {
+        let mut report = ImageReport {
+            image: RgbImage::from_pixel(800, 1000, WHITE),
+            sid: Some("12345".into()),
+            name: Some("JANE DOE".into()),
+            version: Some(1),
+            answers: Vec::new(),
+            issues: vec![Issue::MultipleMarks { question: 3 }],
+            scores: vec![Some(1), Some(0), None, Some(1)],
+            identifier: "page1".into(),
+        };
+        assert_eq!(
+            report.banner_text(4, Some("B")),
+            "ID 12345   ·   JANE DOE   ·   Version B   ·   Score 2/4 (50.0%)   ·   Grade B"
+        );
+
+        report.add_banner(4, None);
+        assert!(report.image.height() > 1000);
+        let banner_height = report.image.height() - 1000;
+        assert!((0..banner_height)
+            .any(|y| (0..800).any(|x| *report.image.get_pixel(x, y) == ISSUE_RED)));
+        assert!((banner_height..report.image.height())
+            .all(|y| *report.image.get_pixel(400, y) == WHITE));
+    }
+
+    #[test]
+    fn issues() {
+        let issues = vec![
+            Issue::IdGap { column: 2 },
+            Issue::MultipleMarks { question: 7 },
+        ];
+        assert_eq!(issues[1].to_string(), "several marks in Q7");
+        assert_eq!(
+            serde_json::to_string(&issues).unwrap(),
+            r#"[{"kind":"id_gap","column":2},{"kind":"multiple_marks","question":7}]"#
+        );
+
+        let image = RgbImage::from_pixel(600, 800, WHITE);
+        assert_eq!(
+            issue_lines(&issues, 40),
+            vec!["Grade by hand: blank ID column 2", "several marks in Q7"]
+        );
+        let unreadable = [Issue::UnreadablePage {
+            message: "there is no page 3 in the file".into(),
+        }];
+        assert_eq!(
+            issue_lines(&unreadable, 20),
+            vec![
+                "Grade by hand:",
+                "unreadable page:",
+                "there is no page 3",
+                "in the file"
+            ]
+        );
+        let footer = add_issue_footer(image.clone(), &issues);
+        assert!(footer.height() > 800);
+        assert_eq!(footer.get_pixel(10, 10), image.get_pixel(10, 10));
+        assert_eq!(*footer.get_pixel(0, footer.height() - 1), WHITE);
+        assert!(footer.pixels().any(|p| *p == ISSUE_RED));
     }
 }
@@ -0,0 +1,302 @@
+use crate::error::Error;
+use crate::naming::NamingScheme;
+use crate::pdf_merge::{PdfMerger, Spool, Spooled};
+use crate::report::ImageReport;
+use crate::typst_helpers::{document_to_pdf, typst_string, TypstWrapper};
+use image::codecs::jpeg::JpegEncoder;
+use std::cmp::Ordering;
+
+/// The order of the sheets in the combined PDF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageOrder {
+    Scan,
+    Filename,
+    Id,
+}
+
+/// How the annotated images are embedded: JPEG keeps the PDF small, Flate
+/// (i.e. PNG) is lossless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageCompression {
+    Jpeg { quality: u8 },
+    Flate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdfSettings {
+    pub order: PageOrder,
+    pub compression: PageCompression,
+}
+
+impl Default for PdfSettings {
+    fn default() -> Self {
+        Self {
+            order: PageOrder::Scan,
+            compression: PageCompression::Jpeg { quality: 80 },
+        }
+    }
+}
+
+/// One annotated sheet of the combined PDF, without its image.
+#[derive(Debug, Clone)]
+pub struct ReportPage {
+    pub filename: String,
+    pub identifier: String,
+    pub sid: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<u32>,
+    pub score: u32,
+    pub issue: bool,
+}
+
+impl ReportPage {
+    pub fn new(report: &ImageReport, naming: &NamingScheme) -> Self {
+        Self {
+            filename: report.save_filename(naming),
+            identifier: report.identifier.clone(),
+            sid: report.sid.clone(),
+            name: report.name.clone(),
+            version: report.version,
+            score: report.score(),
+            issue: report.has_issues(),
+        }
+    }
+
+    fn heading(&self) -> String {
+        let mut heading = self.sid.clone().unwrap_or("NOID".to_string());
+        if let Some(name) = &self.name {
+            heading += &format!(" {}", name);
+        }
+        heading + &format!(" – {}", self.identifier)
+    }
+}
+
+impl PageOrder {
+    fn compare(self, a: &ReportPage, b: &ReportPage) -> Ordering {
+        match self {
+            PageOrder::Scan => Ordering::Equal,
+            PageOrder::Filename => a.filename.cmp(&b.filename),
+            PageOrder::Id => (a.sid.is_none(), &a.sid, &a.identifier).cmp(&(
+                b.sid.is_none(),
+                &b.sid,
+                &b.identifier,
+            )),
+        }
+    }
+}
+
+/// The annotated image of a sheet, compressed for the combined PDF.
+pub fn page_image(report: &ImageReport, compression: PageCompression) -> Vec<u8> {
+    let mut image_data = Vec::new();
+    match compression {
+        PageCompression::Jpeg { quality } => {
+            let encoder = JpegEncoder::new_with_quality(&mut image_data, quality);
+            let _ = report.image.write_with_encoder(encoder);
+        }
+        PageCompression::Flate => report.write_to_buffer(&mut image_data),
+    }
+    image_data
+}
+
+pub fn sort_pages(pages: &mut [ReportPage], order: PageOrder) {
+    pages.sort_by(|a, b| order.compare(a, b));
+}
+
+// how many sheets are typeset at once
+const SHEETS_PER_PART: usize = 16;
+
+/// The typst code of the cover page.
+pub fn cover_typst(pages: &[&ReportPage], title: &str) -> String {
+    let count = |f: &dyn Fn(&ReportPage) -> bool| {
+        typst_string(&pages.iter().filter(|p| f(p)).count().to_string())
+    };
+    let summary = format!(
+        "(scans: {}, issues: {}, noid: {}, noversion: {})",
+        typst_string(&pages.len().to_string()),
+        count(&|p| p.issue),
+        count(&|p| p.sid.is_none()),
+        count(&|p| p.version.is_none()),
+    );
+    let flagged: String = pages
+        .iter()
+        .filter(|page| page.issue)
+        .map(|page| {
+            format!(
+                "({}, {}, {}),",
+                typst_string(&page.identifier),
+                typst_string(page.sid.as_deref().unwrap_or("")),
+                typst_string(&page.score.to_string()),
+            )
+        })
+        .collect();
+    report_typst(title, &summary, &flagged, "")
+}
+
+/// The typst code of some sheets, each with the path of its image.
+pub fn sheets_typst(sheets: &[(&ReportPage, String)]) -> String {
+    let entries: String = sheets
+        .iter()
+        .map(|(page, image)| {
+            format!(
+                "(heading: {}, image: {}),",
+                typst_string(&page.heading()),
+                typst_string(image),
+            )
+        })
+        .collect();
+    report_typst("", "none", "", &entries)
+}
+
+fn report_typst(title: &str, summary: &str, flagged: &str, pages: &str) -> String {
+    let tmpl = include_str!("../assets/reporttemplate.typ");
+    format!(
+        "#let title = {}\n#let summary = {}\n#let flagged = ({})\n#let pages = ({})\n{}",
+        typst_string(title),
+        summary,
+        flagged,
+        pages,
+        tmpl
+    )
+}
+
+fn typeset(wrapper: &TypstWrapper) -> Result<Vec<u8>, Error> {
+    let document = typst::compile(wrapper)
+        .output
+        .map_err(|errors| Error::Output(format!("could not typeset report: {:?}", errors)))?;
+    document_to_pdf(&document)
+}
+
+/// All sheets in one PDF with a cover page listing the sheets that need to be
+/// graded by hand and a bookmark for every sheet. The images are spooled as
+/// they are added. Once the order is known, the sheets are typeset a few at a
+/// time and merged.
+pub struct ReportPdf {
+    compression: PageCompression,
+    images: Spool,
+    pages: Vec<(ReportPage, Spooled)>,
+}
+
+impl ReportPdf {
+    pub fn new(compression: PageCompression) -> Self {
+        Self {
+            compression,
+            images: Spool::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    /// `image` as `page_image` compresses it.
+    pub fn add(&mut self, page: ReportPage, image: &[u8]) -> Result<(), Error> {
+        let image = self.images.store(image)?;
+        self.pages.push((page, image));
+        Ok(())
+    }
+
+    // typst picks the image format by the extension
+    fn image_path(&self, index: usize) -> String {
+        match self.compression {
+            PageCompression::Jpeg { .. } => format!("page-{}.jpg", index),
+            PageCompression::Flate => format!("page-{}.png", index),
+        }
+    }
+
+    /// The finished PDF, rewound.
+    pub fn finish(mut self, order: PageOrder, title: &str) -> Result<Spool, Error> {
+        self.pages.sort_by(|a, b| order.compare(&a.0, &b.0));
+        let mut merger = PdfMerger::new(Spool::new())?;
+        let pages: Vec<&ReportPage> = self.pages.iter().map(|(page, _)| page).collect();
+        merger.add_pdf(typeset(&TypstWrapper::new(cover_typst(&pages, title)))?)?;
+
+        for part in self.pages.chunks(SHEETS_PER_PART) {
+            let sheets: Vec<(&ReportPage, String)> = (0..part.len())
+                .map(|i| (&part[i].0, self.image_path(i)))
+                .collect();
+            let mut wrapper = TypstWrapper::new(sheets_typst(&sheets));
+            for (i, (_, image)) in part.iter().enumerate() {
+                wrapper.add_file(&self.image_path(i), self.images.load(image)?);
+            }
+            let first = merger.add_pdf(typeset(&wrapper)?)?;
+            for (i, (page, _)) in part.iter().enumerate() {
+                merger.add_bookmark(&page.heading(), first + i);
+            }
+        }
+        merger.finish(title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(filename: &str, sid: Option<&str>, issue: bool) -> ReportPage {
+        ReportPage {
+            filename: filename.to_string(),
+            identifier: filename.to_string(),
+            sid: sid.map(String::from),
+            name: None,
+            version: Some(0),
+            score: 5,
+            issue,
+        }
+    }
+
+    #[test]
+    fn order_and_summary() {
+        let mut pages = vec![
+            page("c", Some("2"), false),
+            page("a", None, true),
+            page("b", Some("1"), false),
+        ];
+        sort_pages(&mut pages, PageOrder::Filename);
+        assert_eq!(pages[0].filename, "a");
+        sort_pages(&mut pages, PageOrder::Id);
+        let order: Vec<&str> = pages.iter().map(|p| p.filename.as_str()).collect();
+        assert_eq!(order, vec!["b", "c", "a"]);
+        assert_eq!(pages[2].heading(), "NOID – a");
+
+        let refs: Vec<&ReportPage> = pages.iter().collect();
+        let code = cover_typst(&refs, "Exam");
+        assert!(code.contains("(scans: \"3\", issues: \"1\", noid: \"1\", noversion: \"0\")"));
+        assert!(code.contains("#let flagged = ((\"a\", \"\", \"5\"),)"));
+        assert!(code.contains("#let pages = ()"));
+        let code = sheets_typst(&[(&pages[2], "page-0.png".into())]);
+        assert!(code.contains("#let summary = none"));
+        assert!(code.contains("(heading: \"NOID – a\", image: \"page-0.png\"),"));
+    }
+
+    #[test]
+    fn typeset_in_parts() {
+        let report = ImageReport {
+            image: image::RgbImage::from_pixel(20, 30, image::Rgb([255, 0, 0])),
+            sid: Some("42".into()),
+            name: None,
+            version: Some(0),
+            answers: Vec::new(),
+            issues: Vec::new(),
+            scores: Vec::new(),
+            identifier: "page1".into(),
+        };
+        let compression = PageCompression::Jpeg { quality: 50 };
+        let image = page_image(&report, compression);
+        assert!(image.starts_with(&[0xFF, 0xD8]));
+
+        let mut pdf = ReportPdf::new(compression);
+        for i in 0..SHEETS_PER_PART + 2 {
+            let mut page = page(&format!("page{}", i), None, i % 2 == 0);
+            page.name = Some("Łukasz Żółć".into());
+            pdf.add(page, &image).unwrap();
+        }
+        let data = pdf
+            .finish(PageOrder::Scan, "Exam")
+            .unwrap()
+            .into_bytes()
+            .unwrap();
+        // a cover page and one page per sheet, bookmarked in order
+        let pdf = pdf::file::FileOptions::cached().load(data.clone()).unwrap();
+        assert_eq!(pdf.num_pages() as usize, SHEETS_PER_PART + 3);
+        let outlines = pdf.get_root().outlines.as_ref().unwrap();
+        assert_eq!(outlines.count as usize, SHEETS_PER_PART + 2);
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.find("/Title <FEFF004E004F00490044").is_some());
+    }
+}
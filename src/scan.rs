@@ -1,10 +1,27 @@
 use crate::point::{find_circle, Point};
 use image::{GrayImage, Luma};
+use std::cell::RefCell;
 use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct Scan {
-    pub image: GrayImage,
+    image: GrayImage,
+}
+
+// the runs of pixels of every row of an ellipse, relative to its box
+type EllipseMask = Rc<[Option<(u32, u32)>]>;
+
+// how many masks each thread keeps; the bubbles of a template come in a few
+// sizes only
+const CACHED_MASKS: usize = 8;
+// the rows of taller boxes are worked out on every call instead
+const MAX_MASK_HEIGHT: u32 = 512;
+
+thread_local! {
+    static MASKS: RefCell<VecDeque<((u32, u32), EllipseMask)>> =
+        RefCell::new(VecDeque::with_capacity(CACHED_MASKS));
 }
 
 fn find_inner_boundary_points(
@@ -71,7 +88,96 @@ fn find_inner_boundary_points(
 fn is_dark(pixel: &Luma<u8>) -> bool {
     pixel[0] == 0
 }
+
+// The pixels `from..to` of row `y` that lie in the ellipse inscribed in the
+// box `x_min..x_max` by `y_min..y_max`. With the centre (h, k) and the radii
+// a, b of the box, a pixel is inside if ((x-h)²/a²) + ((y-k)²/b²) ≤ 1, which
+// in whole numbers reads (2x-2h)²·(2b)² + (2y-2k)²·(2a)² ≤ (2a)²·(2b)².
+fn ellipse_row(x_min: u32, x_max: u32, y_min: u32, y_max: u32, y: u32) -> Option<(u32, u32)> {
+    let width = (x_max - x_min) as u64;
+    let height = (y_max - y_min) as u64;
+    let dy = (2 * y as u64).abs_diff(y_min as u64 + y_max as u64);
+    if dy > height {
+        return None;
+    }
+
+    // the largest dx = |2x-2h| with dx²·height² ≤ rest, where the products
+    // take up to 128 bits for boxes as large as an u32 allows
+    let rest = (width * width) as u128 * (height * height - dy * dy) as u128;
+    let fits = |dx: u64| (dx as u128 * dx as u128) * (height * height) as u128 <= rest;
+    let mut dx =
+        (width as f64 * ((height * height - dy * dy) as f64).sqrt() / height as f64) as u64;
+    while dx > 0 && !fits(dx) {
+        dx -= 1;
+    }
+    while fits(dx + 1) {
+        dx += 1;
+    }
+    // 2x-2h is odd for boxes of odd width and even otherwise
+    if (dx + width) % 2 == 1 {
+        dx = dx.checked_sub(1)?;
+    }
+
+    let centre = x_min as u64 + x_max as u64;
+    let from = ((centre - dx) / 2) as u32;
+    let to = ((centre + dx) / 2) as u32;
+    Some((from, min(to + 1, x_max)))
+}
+
+// The rows of the ellipse inscribed in a box of the given size. The runs of
+// `ellipse_row` only depend on the position of the box by an offset, so the
+// masks of the last few sizes are kept for the next boxes.
+fn ellipse_mask(width: u32, height: u32) -> EllipseMask {
+    MASKS.with(|masks| {
+        let mut masks = masks.borrow_mut();
+        if let Some((_, mask)) = masks.iter().find(|(size, _)| *size == (width, height)) {
+            return mask.clone();
+        }
+        let mask: EllipseMask = (0..height)
+            .map(|y| ellipse_row(0, width, 0, height, y))
+            .collect();
+        if masks.len() == CACHED_MASKS {
+            masks.pop_back();
+        }
+        masks.push_front(((width, height), mask.clone()));
+        mask
+    })
+}
+
 impl Scan {
+    pub fn new(image: GrayImage) -> Self {
+        Self { image }
+    }
+
+    /// The binary image.
+    pub fn image(&self) -> &GrayImage {
+        &self.image
+    }
+
+    /// Erodes the dark parts of the image by a pixel, which removes white
+    /// noise.
+    pub fn erode(&mut self) {
+        imageproc::morphology::erode_mut(
+            &mut self.image,
+            imageproc::distance_transform::Norm::L1,
+            1,
+        );
+    }
+
+    // the dark pixels among the pixels `from..to` of row `y` within the image,
+    // and the number of those pixels
+    fn dark_in_row(&self, y: u32, from: u32, to: u32) -> (u64, u64) {
+        let w = self.image.width();
+        let to = min(to, w);
+        if from >= to {
+            return (0, 0);
+        }
+        let start = y as usize * w as usize;
+        let row = &self.image.as_raw()[start + from as usize..start + to as usize];
+        let dark = row.iter().filter(|&&p| p == 0).count();
+        (dark as u64, (to - from) as u64)
+    }
+
     fn blackness_around(&self, p: Point, r: u32) -> f64 {
         self.blackness(
             Point {
@@ -84,39 +190,36 @@ impl Scan {
             },
         )
     }
+    /// The share of dark pixels in the ellipse inscribed in the box spanned by
+    /// `p1` and `p2`; pixels outside the image are left out.
     pub fn blackness(&self, p1: Point, p2: Point) -> f64 {
-        let mut dark_pixels = 0;
-        let mut total_pixels = 0;
-
         let x_min = min(p1.x, p2.x);
         let x_max = max(p1.x, p2.x);
         let y_min = min(p1.y, p2.y);
         let y_max = max(p1.y, p2.y);
+        if x_min == x_max || y_min == y_max {
+            return 0.0;
+        }
 
-        // Calculate ellipse center and radii
-        let center_x = (x_min + x_max) as f64 / 2.0;
-        let center_y = (y_min + y_max) as f64 / 2.0;
-        let a = (x_max - x_min) as f64 / 2.0; // semi-major axis
-        let b = (y_max - y_min) as f64 / 2.0; // semi-minor axis
-
-        let (w, h) = self.image.dimensions();
-
-        for x in x_min..x_max {
-            for y in y_min..y_max {
-                // Check if point (x,y) lies within the ellipse using the equation:
-                // ((x-h)²/a²) + ((y-k)²/b²) ≤ 1
-                // where (h,k) is the center point
-                let normalized_x = (x as f64 - center_x) / a;
-                let normalized_y = (y as f64 - center_y) / b;
-                let in_ellipse = (normalized_x * normalized_x + normalized_y * normalized_y) <= 1.0;
-
-                if in_ellipse && x < w && y < h {
-                    let pixel = self.image.get_pixel(x, y);
-                    if is_dark(pixel) {
-                        dark_pixels += 1;
-                    }
-                    total_pixels += 1;
+        let h = self.image.height();
+        let mut dark_pixels = 0;
+        let mut total_pixels = 0;
+
+        // the ellipse covers a single run of pixels in every row, so we only
+        // need its ends and count the dark pixels in between
+        let mask =
+            (y_max - y_min <= MAX_MASK_HEIGHT).then(|| ellipse_mask(x_max - x_min, y_max - y_min));
+        for y in y_min..min(y_max, h) {
+            let run = match &mask {
+                Some(mask) => {
+                    mask[(y - y_min) as usize].map(|(from, to)| (x_min + from, x_min + to))
                 }
+                None => ellipse_row(x_min, x_max, y_min, y_max, y),
+            };
+            if let Some((from, to)) = run {
+                let (dark, total) = self.dark_in_row(y, from, to);
+                dark_pixels += dark;
+                total_pixels += total;
             }
         }
 
@@ -205,12 +308,11 @@ impl Scan {
         let real_radii: Vec<f64> = real_centers
             .iter()
             .map(|c| {
-                let boundary_points =
-                    find_inner_boundary_points(*c, max_radius, &self.image, 3).unwrap();
+                let boundary_points = find_inner_boundary_points(*c, max_radius, &self.image, 3)?;
                 let distances = boundary_points.map(|p| c.distance(p) as f64);
-                distances.iter().sum::<f64>() / 3.0
+                Some(distances.iter().sum::<f64>() / 3.0)
             })
-            .collect();
+            .collect::<Option<Vec<f64>>>()?;
 
         let average_radius = real_radii.iter().sum::<f64>() / real_radii.len() as f64;
 
@@ -249,8 +351,8 @@ impl Scan {
 
     pub fn real_center(&self, approx_center: Point, max_radius: u32) -> Option<Point> {
         let a = Point {
-            x: approx_center.x - max_radius / 4,
-            y: approx_center.y - max_radius / 4,
+            x: approx_center.x.saturating_sub(max_radius / 4),
+            y: approx_center.y.saturating_sub(max_radius / 4),
         };
         let b = Point {
             x: approx_center.x + max_radius / 4,
@@ -296,8 +398,8 @@ mod tests {
 
     #[test]
     fn image_circle_center_easy() {
-        let image = binary_image_from_file(&"tests/assets/c-47-47.png".to_string());
-        let scan = Scan { image };
+        let image = binary_image_from_file(&"tests/assets/c-47-47.png".to_string()).unwrap();
+        let scan = Scan::new(image);
 
         let real_center = Point { x: 47, y: 47 };
         let test_center = Point { x: 40, y: 60 };
@@ -310,10 +412,83 @@ mod tests {
         assert!(real_center.distance(res) < 2);
     }
 
+    // checks every pixel of the box, with the float arithmetic that
+    // `blackness` used to have if `exact` is false
+    fn blackness_per_pixel(scan: &Scan, p1: Point, p2: Point, exact: bool) -> f64 {
+        let (x_min, x_max) = (min(p1.x, p2.x), max(p1.x, p2.x));
+        let (y_min, y_max) = (min(p1.y, p2.y), max(p1.y, p2.y));
+        let (a, b) = ((x_max - x_min) as i64, (y_max - y_min) as i64);
+        let (w, h) = scan.image().dimensions();
+
+        let (mut dark_pixels, mut total_pixels) = (0, 0);
+        for x in x_min..min(x_max, w) {
+            for y in y_min..min(y_max, h) {
+                let dx = 2 * x as i64 - (x_min + x_max) as i64;
+                let dy = 2 * y as i64 - (y_min + y_max) as i64;
+                let in_ellipse = match exact {
+                    true => dx * dx * b * b + dy * dy * a * a <= a * a * b * b,
+                    false => (dx as f64 / a as f64).powi(2) + (dy as f64 / b as f64).powi(2) <= 1.0,
+                };
+                if in_ellipse {
+                    if is_dark(scan.image().get_pixel(x, y)) {
+                        dark_pixels += 1;
+                    }
+                    total_pixels += 1;
+                }
+            }
+        }
+        match total_pixels {
+            0 => 0.0,
+            _ => dark_pixels as f64 / total_pixels as f64,
+        }
+    }
+
+    #[test]
+    fn blackness_matches_per_pixel() {
+        let image = binary_image_from_file(&"tests/assets/example-ahmed.png".to_string()).unwrap();
+        let scan = Scan::new(image);
+        let (w, h) = scan.image().dimensions();
+
+        // boxes of all shapes, including flat ones and some beyond the edges
+        let corners = [(0, 0), (171, 200), (1460, 190), (w - 20, h - 7)];
+        for (x, y) in corners {
+            for (dx, dy) in (0..48).flat_map(|dx| (0..48).map(move |dy| (dx, dy))) {
+                let p1 = Point { x, y };
+                let p2 = Point {
+                    x: x + dx,
+                    y: y + dy,
+                };
+                let blackness = scan.blackness(p1, p2);
+                assert_eq!(blackness, blackness_per_pixel(&scan, p1, p2, true));
+                // floats miss some of the pixels right on the ellipse
+                assert!((blackness - blackness_per_pixel(&scan, p1, p2, false)).abs() < 0.01);
+                assert_eq!(scan.blackness(p2, p1), scan.blackness(p1, p2));
+            }
+        }
+
+        // boxes too tall for the cached masks
+        let (p1, p2) = (Point { x: 100, y: 150 }, Point { x: 700, y: 1300 });
+        assert_eq!(
+            scan.blackness(p1, p2),
+            blackness_per_pixel(&scan, p1, p2, true)
+        );
+    }
+
+    #[test]
+    fn ellipse_row_of_huge_boxes() {
+        let max = u32::MAX;
+        assert_eq!(
+            ellipse_row(0, max - 1, 0, max - 1, max / 2),
+            Some((0, max - 1))
+        );
+        assert_eq!(ellipse_row(0, max, 0, max, max / 2), Some((1, max)));
+        assert_eq!(ellipse_row(0, max, 0, max, 0), None);
+    }
+
     #[test]
     fn circles_in_sample_bubblesheet() {
-        let image = binary_image_from_file(&"tests/assets/example-ahmed.png".to_string());
-        let scan = Scan { image };
+        let image = binary_image_from_file(&"tests/assets/example-ahmed.png".to_string()).unwrap();
+        let scan = Scan::new(image);
 
         let real_centers = [
             Point { x: 173, y: 203 },
@@ -0,0 +1,831 @@
+//! Filled out sheets made up from a blank form, with known answers and the
+//! usual flaws of scans, to see how many of them are read correctly and how
+//! fast.
+
+use crate::error::Error;
+use crate::image_container::{out_of_range, ImageContainer};
+use crate::image_helpers::binary_image_from_image;
+use crate::pipeline::{for_each_page, CancellationToken, GradingRun, Progress};
+use crate::report::{ImageReport, Issue, ReportSettings};
+use crate::sink::PngSink;
+use crate::template::{
+    check_compatible, format_choices, format_numeric, name_symbol, name_symbol_value,
+    parse_numeric_response, Box, CorrectAnswer, ExamKey, NumericResponse, Question, Template,
+    NUMERIC_DOT, NUMERIC_MINUS,
+};
+use image::{DynamicImage, GrayImage, Luma, Rgba};
+use imageproc::drawing::{draw_filled_circle_mut, Canvas};
+use imageproc::geometric_transformations::{rotate_about_center, Interpolation};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What goes wrong between the student's pencil and the scanner. Nothing, by
+/// default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Noise {
+    /// the largest rotation of the page in degrees, either way
+    pub rotation: f32,
+    /// the standard deviation of a gaussian blur in pixels, 0 for none
+    pub blur: f32,
+    /// the standard deviation of the grain of paper and sensor in grey
+    /// levels. Without it the only greys are those of the marks, which the
+    /// threshold of `binary_image_from_image` then counts as black.
+    pub grain: f32,
+    /// the scan is saved as a JPEG of this quality
+    pub jpeg_quality: Option<u8>,
+    /// marks are a random grey between black (0) and this share of white
+    pub faintness: f32,
+    /// the number of pencil strokes across the page that are no marks
+    pub stray_marks: u32,
+    /// the chance that another option of a question was marked and erased
+    pub erasures: f32,
+    /// the chance that an MCQ, a numeric question, a column of the name or a
+    /// field of the examiner is left blank
+    pub blanks: f32,
+    /// the chance that an MCQ or a field of the examiner has two marks
+    pub multiple_marks: f32,
+}
+
+/// The marks on a sheet: what the student filled in and the points of the
+/// examiner. Values without a box on the template are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SheetAnswers {
+    /// the digits of the ID from the left, as many as were filled in
+    pub id: String,
+    pub version: u32,
+    /// the marked options of every MCQ: none if it was left blank, several
+    /// if the student could not decide
+    pub choices: Vec<Vec<u32>>,
+    /// the name from the left, a space for a blank column
+    pub name: String,
+    /// the answer to every numeric question as written in its columns, a
+    /// space for a blank column, e.g. " -1.5"
+    pub numeric: Vec<String>,
+    /// the points marked in every field of the examiner
+    pub teacher: Vec<Vec<u32>>,
+}
+
+impl SheetAnswers {
+    /// A sheet with the given ID and version and one marked option in every
+    /// MCQ.
+    pub fn new(id: &str, version: u32, choices: &[u32]) -> Self {
+        Self {
+            id: id.to_string(),
+            version,
+            choices: choices.iter().map(|&c| vec![c]).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The boxes that are marked on the template.
+    pub fn boxes<'t>(&self, template: &'t Template) -> Vec<&'t Box> {
+        let t = template;
+        let mut boxes = Vec::new();
+        if let Some(vq) = &t.version {
+            boxes.extend(box_of(vq, self.version));
+        }
+        for (q, digit) in t.id_questions.iter().zip(self.id.chars()) {
+            boxes.extend(digit.to_digit(10).and_then(|d| box_of(q, d)));
+        }
+        for (q, symbol) in t.name_questions.iter().zip(self.name.chars()) {
+            boxes.extend(name_symbol_value(symbol).and_then(|v| box_of(q, v)));
+        }
+        let marked = t
+            .questions
+            .iter()
+            .zip(&self.choices)
+            .chain(t.teacher_questions.iter().zip(&self.teacher));
+        for (q, values) in marked {
+            boxes.extend(values.iter().filter_map(|&v| box_of(q, v)));
+        }
+        for (nq, text) in t.numeric_questions.iter().zip(&self.numeric) {
+            for (column, values) in nq.columns.iter().zip(numeric_columns(text)) {
+                boxes.extend(values.into_iter().filter_map(|v| box_of(column, v)));
+            }
+        }
+        boxes
+    }
+
+    fn numeric_responses(&self) -> impl Iterator<Item = NumericResponse> + '_ {
+        self.numeric
+            .iter()
+            .map(|text| parse_numeric_response(&numeric_columns(text)))
+    }
+
+    /// The answers as they appear in the report, see `TemplateScan::answers`.
+    pub fn answers(&self, template: &Template) -> Vec<String> {
+        let mut answers: Vec<String> = self
+            .choices
+            .iter()
+            .enumerate()
+            .map(|(i, choices)| format_choices(choices, &template.option_labels(i)))
+            .collect();
+        answers.extend(self.numeric_responses().map(format_numeric));
+        answers
+    }
+
+    /// The score according to the key. Questions with several marks and
+    /// fields of the examiner without clear points do not count.
+    pub fn score(&self, template: &Template, key: &ExamKey) -> u32 {
+        let Some(key) = key.get(self.version as usize) else {
+            return 0;
+        };
+        let mcqs = self
+            .choices
+            .iter()
+            .zip(key)
+            .filter(|(choices, correct)| matches!(choices[..], [one] if correct.correct(one)))
+            .count() as u32;
+        let numeric = self
+            .numeric_responses()
+            .zip(key.iter().skip(template.questions.len()))
+            .filter(|(response, correct)| {
+                matches!(response, NumericResponse::Value(v) if correct.correct_value(*v))
+            })
+            .count() as u32;
+        let points: u32 = self
+            .teacher
+            .iter()
+            .filter_map(|points| match points[..] {
+                [one] => Some(one),
+                _ => None,
+            })
+            .sum();
+        mcqs + numeric + points
+    }
+
+    /// Whether the sheet has to be graded by hand: an MCQ with several marks,
+    /// a numeric answer that is no number, a field of the examiner without
+    /// clear points or an ID that fails the check digit.
+    pub fn needs_hand_grading(&self, template: &Template) -> bool {
+        self.choices.iter().any(|choices| choices.len() > 1)
+            || self
+                .numeric_responses()
+                .any(|response| response == NumericResponse::Invalid)
+            || self.teacher.iter().any(|points| points.len() != 1)
+            || template
+                .id_check_digit
+                .is_some_and(|check| !check.validate(&self.id))
+    }
+}
+
+// the box of the given value, e.g. of a digit in a column of the ID
+fn box_of(question: &Question, value: u32) -> Option<&Box> {
+    question.boxes.iter().find(|b| b.value == value)
+}
+
+// the value marked in each column of a numeric answer
+fn numeric_columns(text: &str) -> Vec<Vec<u32>> {
+    text.chars()
+        .map(|symbol| match symbol {
+            '-' => vec![NUMERIC_MINUS],
+            '.' => vec![NUMERIC_DOT],
+            d => d.to_digit(10).into_iter().collect(),
+        })
+        .collect()
+}
+
+/// Marks the answers on a copy of `form` with solid black circles, as a
+/// careful student with a black pen would.
+pub fn fill_out(form: &DynamicImage, template: &Template, answers: &SheetAnswers) -> DynamicImage {
+    let mut result = form.clone();
+    for thebox in answers.boxes(template) {
+        draw_mark(&mut result, thebox, (0.0, 0.0), 1.0, Rgba([0, 0, 0, 255]));
+    }
+    result
+}
+
+pub struct SimulatedSheet {
+    pub answers: SheetAnswers,
+    pub image: DynamicImage,
+}
+
+/// Makes up sheets from a blank form and grades them. Every sheet only
+/// depends on the seed and its index, so a misread sheet can be looked at
+/// again later.
+pub struct Simulation<'a> {
+    pub template: &'a Template,
+    pub key: &'a ExamKey,
+    form: GrayImage,
+    pub noise: Noise,
+    pub seed: u64,
+}
+
+impl<'a> Simulation<'a> {
+    /// `form` is the blank form at the size of the template, e.g. the PNG
+    /// written along with the template by `autograder form`.
+    pub fn new(
+        template: &'a Template,
+        key: &'a ExamKey,
+        form: &DynamicImage,
+        noise: Noise,
+        seed: u64,
+    ) -> Result<Self, Error> {
+        check_compatible(template, key)?;
+        // the same rounding as in `generate_form_and_template`
+        if template.width.abs_diff(form.width()) > 1 || template.height.abs_diff(form.height()) > 1
+        {
+            return Err(Error::Template(format!(
+                "the form is {}x{} pixels, the template {}x{}",
+                form.width(),
+                form.height(),
+                template.width,
+                template.height
+            )));
+        }
+        Ok(Self {
+            template,
+            key,
+            form: form.to_luma8(),
+            noise,
+            seed,
+        })
+    }
+
+    /// What is marked on sheet `index`, without drawing it.
+    pub fn answers(&self, index: u64) -> SheetAnswers {
+        self.make_up_answers(&mut Rng::new(self.seed, index))
+    }
+
+    fn make_up_answers(&self, rng: &mut Rng) -> SheetAnswers {
+        let t = self.template;
+        let noise = &self.noise;
+        // any option, by value
+        let any = |rng: &mut Rng, q: &Question| -> Option<u32> {
+            let n = q.boxes.len() as u32;
+            (n > 0).then(|| q.boxes[rng.below(n) as usize].value)
+        };
+        // one mark, none or two
+        let marks = |rng: &mut Rng, q: &Question| -> Vec<u32> {
+            let n = q.boxes.len() as u32;
+            if n == 0 || rng.chance(noise.blanks) {
+                return Vec::new();
+            }
+            let first = rng.below(n);
+            let mut marks = vec![q.boxes[first as usize].value];
+            if n > 1 && rng.chance(noise.multiple_marks) {
+                let second = (first + 1 + rng.below(n - 1)) % n;
+                marks.push(q.boxes[second as usize].value);
+                marks.sort();
+            }
+            marks
+        };
+
+        let version = t.version.as_ref().and_then(|vq| any(rng, vq)).unwrap_or(0);
+        let id = t
+            .id_questions
+            .iter()
+            .filter_map(|q| any(rng, q).map(|d| d.to_string()))
+            .collect();
+        let length = match t.name_questions.len() as u32 {
+            0 => 0,
+            n => 1 + rng.below(n) as usize,
+        };
+        let name = t.name_questions[..length]
+            .iter()
+            .map(|q| match any(rng, q) {
+                Some(value) if !rng.chance(noise.blanks) => name_symbol(value),
+                _ => ' ',
+            })
+            .collect();
+        let choices = t.questions.iter().map(|q| marks(rng, q)).collect();
+        let numeric = t
+            .numeric_questions
+            .iter()
+            .enumerate()
+            .map(|(i, nq)| {
+                let correct = self
+                    .key
+                    .get(version as usize)
+                    .and_then(|k| k.get(t.questions.len() + i));
+                self.make_up_number(rng, nq.columns.len(), correct)
+            })
+            .collect();
+        let teacher = t.teacher_questions.iter().map(|q| marks(rng, q)).collect();
+
+        SheetAnswers {
+            id,
+            version,
+            choices,
+            name,
+            numeric,
+            teacher,
+        }
+    }
+
+    // half of the answers are correct, if the correct one fits the columns
+    fn make_up_number(
+        &self,
+        rng: &mut Rng,
+        columns: usize,
+        correct: Option<&CorrectAnswer>,
+    ) -> String {
+        if columns == 0 || rng.chance(self.noise.blanks) {
+            return " ".repeat(columns);
+        }
+        let correct = match correct {
+            Some(CorrectAnswer::Value { value, .. }) => Some(value.to_string()),
+            Some(CorrectAnswer::Range { min, max }) => Some(((min + max) / 2.0).to_string()),
+            _ => None,
+        };
+        let fits = |text: &String| {
+            text.len() <= columns
+                && text
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '-' || c == '.')
+        };
+        // from the left, as only the first column has a minus
+        if let Some(text) = correct.filter(fits) {
+            if rng.chance(0.5) {
+                return format!("{:<columns$}", text);
+            }
+        }
+        // a minus only in front, a point only between digits
+        let length = 1 + rng.below(columns as u32) as usize;
+        let mut text = String::new();
+        for position in 0..length {
+            let symbol = if position == 0 && length > 1 && rng.chance(0.2) {
+                '-'
+            } else if position > 0
+                && position + 1 < length
+                && !text.contains('.')
+                && rng.chance(0.2)
+            {
+                '.'
+            } else {
+                char::from_digit(rng.below(10), 10).unwrap_or('0')
+            };
+            text.push(symbol);
+        }
+        format!("{:<columns$}", text)
+    }
+
+    pub fn sheet(&self, index: u64) -> SimulatedSheet {
+        let t = self.template;
+        let noise = &self.noise;
+        let mut rng = Rng::new(self.seed, index);
+        let answers = self.make_up_answers(&mut rng);
+        let mut image = self.form.clone();
+
+        // a filled circle a little off the centre of the box and not quite
+        // as large
+        let pencil = |image: &mut GrayImage, rng: &mut Rng, thebox: &Box, shade: f32| {
+            let offset = (rng.uniform(-0.2, 0.2), rng.uniform(-0.2, 0.2));
+            let size = rng.uniform(0.7, 1.0);
+            draw_mark(image, thebox, offset, size, Luma([(shade * 255.0) as u8]));
+        };
+
+        // what is left of a mark after erasing it
+        for (q, choices) in t.questions.iter().zip(&answers.choices) {
+            let unmarked: Vec<&Box> = q
+                .boxes
+                .iter()
+                .filter(|b| !choices.contains(&b.value))
+                .collect();
+            if !unmarked.is_empty() && rng.chance(noise.erasures) {
+                let erased = unmarked[rng.below(unmarked.len() as u32) as usize];
+                let shade = rng.uniform(0.85, 0.95);
+                pencil(&mut image, &mut rng, erased, shade);
+            }
+        }
+        for thebox in answers.boxes(t) {
+            let shade = rng.uniform(0.0, noise.faintness);
+            pencil(&mut image, &mut rng, thebox, shade);
+        }
+
+        let (width, height) = image.dimensions();
+        let size = t.circle_radius as f32;
+        for _ in 0..noise.stray_marks {
+            let (x, y) = (
+                rng.uniform(0.0, width as f32),
+                rng.uniform(0.0, height as f32),
+            );
+            let angle = rng.uniform(0.0, std::f32::consts::TAU);
+            let length = rng.uniform(1.0, 6.0) * size;
+            let shade = Luma([(rng.uniform(0.0, 0.4) * 255.0) as u8]);
+            let thickness = (size / 8.0).max(1.0) as i32;
+            for step in 0..length as u32 {
+                let (dx, dy) = (step as f32 * angle.cos(), step as f32 * angle.sin());
+                let centre = ((x + dx) as i32, (y + dy) as i32);
+                draw_filled_circle_mut(&mut image, centre, thickness, shade);
+            }
+        }
+
+        if noise.rotation > 0.0 {
+            let degrees = rng.uniform(-noise.rotation, noise.rotation);
+            image = rotate_about_center(
+                &image,
+                degrees.to_radians(),
+                Interpolation::Bilinear,
+                Luma([255]),
+            );
+        }
+        if noise.blur > 0.0 {
+            image = imageproc::filter::gaussian_blur_f32(&image, noise.blur);
+        }
+        if noise.grain > 0.0 {
+            imageproc::noise::gaussian_noise_mut(&mut image, 0.0, noise.grain as f64, rng.next());
+        }
+        let mut image = DynamicImage::ImageLuma8(image);
+        if let Some(quality) = noise.jpeg_quality {
+            image = jpeg_artefacts(&image, quality);
+        }
+
+        SimulatedSheet { answers, image }
+    }
+
+    /// Makes up and grades the sheets `0..count` on `threads` workers (`0`
+    /// for one per core) with the default report settings, just like the
+    /// pages of a container. `progress` is called after every sheet.
+    pub fn run(
+        &self,
+        count: u64,
+        threads: usize,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Accuracy, Error> {
+        let settings = ReportSettings {
+            threads,
+            ..Default::default()
+        };
+        let run = GradingRun::new(self.template, self.key, &settings, PngSink)?;
+        let mut accuracy = Accuracy::default();
+        let mut sheets = SimulatedSheets {
+            simulation: self,
+            count,
+            generating: Duration::ZERO,
+        };
+
+        let start = Instant::now();
+        for_each_page(
+            &mut sheets,
+            threads,
+            &CancellationToken::default(),
+            |idx, page| {
+                let start = Instant::now();
+                let report = run.grade(&format!("sheet{}", idx), page);
+                (report, start.elapsed())
+            },
+            |idx, (report, grading)| {
+                let index = idx as u64;
+                accuracy.grading += grading;
+                accuracy.add(
+                    self.template,
+                    self.key,
+                    index,
+                    &self.answers(index),
+                    &report,
+                );
+                progress(Progress {
+                    done: accuracy.sheets,
+                    total: Some(count as usize),
+                    issues: accuracy.flagged,
+                });
+                Ok(())
+            },
+        )?;
+        accuracy.elapsed = start.elapsed();
+        accuracy.generating = sheets.generating;
+        Ok(accuracy)
+    }
+}
+
+// The sheets of a simulation as the pages of a container: they are made up
+// and read where a real container decodes its pages.
+struct SimulatedSheets<'s, 'a> {
+    simulation: &'s Simulation<'a>,
+    count: u64,
+    generating: Duration,
+}
+
+impl ImageContainer for SimulatedSheets<'_, '_> {
+    fn get_page(&mut self, n: usize) -> Result<GrayImage, Error> {
+        if n as u64 >= self.count {
+            return Err(out_of_range(n));
+        }
+        let start = Instant::now();
+        let sheet = self.simulation.sheet(n as u64);
+        self.generating += start.elapsed();
+        Ok(binary_image_from_image(sheet.image))
+    }
+
+    fn page_count(&mut self) -> usize {
+        self.count as usize
+    }
+}
+
+/// How many of the simulated sheets were read correctly.
+#[derive(Debug, Clone, Default)]
+pub struct Accuracy {
+    pub sheets: usize,
+    /// sheets whose circles were found
+    pub aligned: usize,
+    pub ids: usize,
+    pub versions: usize,
+    pub scores: usize,
+    /// sheets with a name grid, and those whose name was read correctly
+    pub named: usize,
+    pub names: usize,
+    /// MCQs and numeric questions
+    pub questions: usize,
+    /// questions read as marked, see `SheetAnswers::responses`
+    pub answers: usize,
+    /// sheets to be graded by hand
+    pub flagged: usize,
+    /// sheets that had to be graded by hand because of what was marked on
+    /// them, see `SheetAnswers::needs_hand_grading`
+    pub to_flag: usize,
+    /// the indices of the sheets that were read wrong without being flagged,
+    /// the worst thing that can happen
+    pub misread: Vec<u64>,
+    pub generating: Duration,
+    /// the time spent on grading the sheets, added up over all workers
+    pub grading: Duration,
+    /// the time from the first sheet to the last
+    pub elapsed: Duration,
+}
+
+impl Accuracy {
+    /// Compares the report of sheet `index` with what was marked on it.
+    pub fn add(
+        &mut self,
+        template: &Template,
+        key: &ExamKey,
+        index: u64,
+        answers: &SheetAnswers,
+        report: &ImageReport,
+    ) {
+        let id_ok = report.sid.as_deref().unwrap_or_default() == answers.id;
+        let version_ok = report.version == Some(answers.version);
+        let score_ok = report.score() == answers.score(template, key);
+        let name_ok = report.name.as_deref().unwrap_or_default() == answers.name.trim();
+        let expected = answers.answers(template);
+        let answers_ok = expected
+            .iter()
+            .zip(&report.answers)
+            .filter(|(expected, answer)| expected == answer)
+            .count();
+        let needs_hand_grading = answers.needs_hand_grading(template);
+
+        self.sheets += 1;
+        let unaligned = Issue::UnreadablePage {
+            message: Error::Alignment.to_string(),
+        };
+        self.aligned += !report.issues.contains(&unaligned) as usize;
+        self.ids += id_ok as usize;
+        self.versions += version_ok as usize;
+        self.scores += score_ok as usize;
+        if !template.name_questions.is_empty() {
+            self.named += 1;
+            self.names += name_ok as usize;
+        }
+        self.questions += expected.len();
+        self.answers += answers_ok;
+        self.to_flag += needs_hand_grading as usize;
+        let read_ok = id_ok && version_ok && score_ok && name_ok && answers_ok == expected.len();
+        if report.has_issues() {
+            self.flagged += 1;
+        } else if needs_hand_grading || !read_ok {
+            self.misread.push(index);
+        }
+    }
+}
+
+impl fmt::Display for Accuracy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = |count: usize, total: usize| {
+            format!(
+                "{}/{} ({:.2}%)",
+                count,
+                total,
+                100.0 * count as f64 / total.max(1) as f64
+            )
+        };
+        let per_sheet = |time: Duration| 1000.0 * time.as_secs_f64() / self.sheets.max(1) as f64;
+        writeln!(
+            f,
+            "aligned:            {}",
+            share(self.aligned, self.sheets)
+        )?;
+        writeln!(f, "student IDs:        {}", share(self.ids, self.sheets))?;
+        if self.named > 0 {
+            writeln!(f, "names:              {}", share(self.names, self.named))?;
+        }
+        writeln!(
+            f,
+            "versions:           {}",
+            share(self.versions, self.sheets)
+        )?;
+        writeln!(
+            f,
+            "answers:            {}",
+            share(self.answers, self.questions)
+        )?;
+        writeln!(f, "scores:             {}", share(self.scores, self.sheets))?;
+        writeln!(
+            f,
+            "graded by hand:     {}, {} had to be",
+            share(self.flagged, self.sheets),
+            self.to_flag
+        )?;
+        writeln!(
+            f,
+            "misread, unnoticed: {}",
+            share(self.misread.len(), self.sheets)
+        )?;
+        writeln!(
+            f,
+            "making up a sheet:  {:.1} ms",
+            per_sheet(self.generating)
+        )?;
+        write!(
+            f,
+            "grading a sheet:    {:.1} ms ({:.1} sheets/s on all workers)",
+            per_sheet(self.grading),
+            self.sheets as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )
+    }
+}
+
+// a filled circle in the box, `offset` away from its centre and `size` times
+// as large, both relative to the radius of the box
+fn draw_mark<C: Canvas>(
+    image: &mut C,
+    thebox: &Box,
+    offset: (f32, f32),
+    size: f32,
+    colour: C::Pixel,
+) {
+    let radius = (thebox.b.x - thebox.a.x) as f32 / 2.0;
+    let centre = (
+        ((thebox.a.x + thebox.b.x) as f32 / 2.0 + offset.0 * radius) as i32,
+        ((thebox.a.y + thebox.b.y) as f32 / 2.0 + offset.1 * radius) as i32,
+    );
+    draw_filled_circle_mut(image, centre, (radius * size) as i32, colour);
+}
+
+fn jpeg_artefacts(image: &DynamicImage, quality: u8) -> DynamicImage {
+    let mut data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality)
+        .encode_image(image)
+        .expect("writing to a Vec cannot fail");
+    image::load_from_memory(&data).expect("a JPEG we just wrote")
+}
+
+// SplitMix64: reproducible, fast, and good enough to make up noise
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64, index: u64) -> Self {
+        let mut rng = Rng(seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        rng.next();
+        rng
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // 0 if there is nothing to choose from
+    fn below(&mut self, n: u32) -> u32 {
+        match n {
+            0 => 0,
+            n => (self.next() % n as u64) as u32,
+        }
+    }
+
+    fn uniform(&mut self, low: f32, high: f32) -> f32 {
+        let unit = (self.next() >> 40) as f32 / (1u64 << 24) as f32;
+        low + (high - low) * unit
+    }
+
+    fn chance(&mut self, p: f32) -> bool {
+        self.uniform(0.0, 1.0) < p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::key_from_json;
+
+    fn assets() -> (Template, ExamKey, DynamicImage) {
+        let template =
+            Template::from_json(std::fs::File::open("tests/assets/template.json").unwrap())
+                .unwrap();
+        let key = key_from_json(std::fs::File::open("tests/assets/key.json").unwrap()).unwrap();
+        let form = image::open("tests/assets/template-a4.png").unwrap();
+        (template, key, form)
+    }
+
+    #[test]
+    fn sheets_are_reproducible() {
+        let (template, key, form) = assets();
+        let simulation = Simulation::new(&template, &key, &form, Noise::default(), 7).unwrap();
+
+        let sheet = simulation.sheet(3);
+        assert_eq!(sheet.answers.choices.len(), template.questions.len());
+        assert_eq!(sheet.answers.id.len(), template.id_questions.len());
+        let again = simulation.sheet(3);
+        assert_eq!(sheet.answers, again.answers);
+        assert_eq!(sheet.image, again.image);
+        assert_ne!(sheet.answers, simulation.sheet(4).answers);
+
+        let small = form.thumbnail(100, 100);
+        assert!(Simulation::new(&template, &key, &small, Noise::default(), 7).is_err());
+    }
+
+    #[test]
+    fn clean_sheets_are_read() {
+        let (template, key, form) = assets();
+        let simulation = Simulation::new(&template, &key, &form, Noise::default(), 0).unwrap();
+
+        let mut done = Vec::new();
+        let accuracy = simulation.run(2, 2, &mut |p| done.push(p.done)).unwrap();
+        assert_eq!(done, [1, 2]);
+        assert_eq!(accuracy.sheets, 2);
+        assert_eq!(accuracy.aligned, 2);
+        assert_eq!(accuracy.ids, 2);
+        assert_eq!(accuracy.versions, 2);
+        assert_eq!(accuracy.scores, 2);
+        assert_eq!(accuracy.answers, accuracy.questions);
+        assert_eq!(accuracy.flagged, 0);
+        assert!(accuracy.misread.is_empty());
+
+        // a sheet read as something else than what was marked on it
+        let sheet = simulation.sheet(5);
+        let settings = ReportSettings::default();
+        let run = GradingRun::new(&template, &key, &settings, PngSink).unwrap();
+        let report = run.grade("sheet5", Ok(binary_image_from_image(sheet.image)));
+        let mut answers = sheet.answers;
+        answers.choices[0] = vec![(answers.choices[0][0] + 1) % 5];
+        let mut accuracy = Accuracy::default();
+        accuracy.add(&template, &key, 5, &answers, &report);
+        assert_eq!(accuracy.misread, [5]);
+        assert_eq!(accuracy.answers, accuracy.questions - 1);
+    }
+
+    #[test]
+    fn mistakes_of_students_are_flagged() {
+        let (template, key, form) = assets();
+        let noise = Noise {
+            blanks: 0.2,
+            multiple_marks: 0.1,
+            ..Default::default()
+        };
+        let simulation = Simulation::new(&template, &key, &form, noise, 1).unwrap();
+
+        let accuracy = simulation.run(4, 0, &mut |_| {}).unwrap();
+        assert!(accuracy.to_flag > 0);
+        assert_eq!(accuracy.flagged, accuracy.to_flag);
+        assert_eq!(accuracy.answers, accuracy.questions);
+        assert!(accuracy.misread.is_empty());
+    }
+
+    #[test]
+    fn questions_with_one_box_or_none() {
+        let (mut template, key, form) = assets();
+        template.questions[0].boxes.truncate(1);
+        template.questions[1].boxes.clear();
+        let noise = Noise {
+            erasures: 1.0,
+            multiple_marks: 1.0,
+            ..Default::default()
+        };
+        let simulation = Simulation::new(&template, &key, &form, noise, 0).unwrap();
+
+        let answers = simulation.sheet(0).answers;
+        assert_eq!(
+            answers.choices[0],
+            vec![template.questions[0].boxes[0].value]
+        );
+        assert!(answers.choices[1].is_empty());
+        assert!(answers.choices[2..]
+            .iter()
+            .all(|choices| choices.len() == 2));
+    }
+
+    #[test]
+    fn noisy_sheet_is_read() {
+        let (template, key, form) = assets();
+        let noise = Noise {
+            rotation: 1.0,
+            blur: 1.0,
+            grain: 8.0,
+            jpeg_quality: Some(70),
+            faintness: 0.4,
+            stray_marks: 2,
+            erasures: 0.0,
+            blanks: 0.0,
+            multiple_marks: 0.0,
+        };
+        let simulation = Simulation::new(&template, &key, &form, noise, 0).unwrap();
+
+        let accuracy = simulation.run(1, 1, &mut |_| {}).unwrap();
+        assert_eq!(accuracy.aligned, 1);
+        assert!(accuracy.misread.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::error::Error;
+use crate::naming::NamingScheme;
+use crate::report::ImageReport;
+use crate::report_pdf::{page_image, PdfSettings, ReportPage, ReportPdf};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Where the results of a grading run end up: the CSV records and every other
+/// file, like `issues.json`, the annotated images or the feedback PDFs.
+pub trait RecordSink {
+    /// The rows of the CSV file. They are only known once all sheets are
+    /// graded, since grades on a curve depend on every score.
+    fn add_records(&mut self, header: &[String], records: &[Vec<String>]) -> Result<(), Error>;
+    /// A file given by its relative path; a `/` in the name is a subdirectory.
+    fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error>;
+    /// Like `add_file`, for files too large to keep in memory.
+    fn add_file_from(&mut self, name: &str, data: &mut dyn Read) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        data.read_to_end(&mut buffer)?;
+        self.add_file(name, &buffer)
+    }
+}
+
+/// What becomes of the annotated sheets. `encode` does the expensive part and
+/// runs on the worker threads, `add` gets the encoded sheets in page order
+/// while the workers go on with the next pages.
+pub trait ImageSink: Sync {
+    type Sheet: Send;
+
+    fn encode(&self, report: &ImageReport, naming: &NamingScheme) -> Self::Sheet;
+    fn add(&self, sheet: Self::Sheet, records: &mut dyn RecordSink) -> Result<(), Error>;
+    /// Called once after the last sheet.
+    fn finish(self, records: &mut dyn RecordSink) -> Result<(), Error>;
+}
+
+pub fn records_to_csv(header: &[String], records: &[Vec<String>]) -> Result<Vec<u8>, Error> {
+    let mut csv_writer = csv::Writer::from_writer(Vec::new());
+    csv_writer.write_record(header)?;
+    for record in records {
+        csv_writer.serialize(record)?;
+    }
+    Ok(csv_writer.into_inner()?)
+}
+
+/// Writes everything into a directory, the records as `results.csv`.
+pub struct DirectorySink {
+    pub dir: PathBuf,
+}
+
+impl DirectorySink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl RecordSink for DirectorySink {
+    fn add_records(&mut self, header: &[String], records: &[Vec<String>]) -> Result<(), Error> {
+        self.add_file("results.csv", &records_to_csv(header, records)?)
+    }
+
+    fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(std::fs::write(path, data)?)
+    }
+
+    fn add_file_from(&mut self, name: &str, data: &mut dyn Read) -> Result<(), Error> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(data, &mut std::fs::File::create(path)?)?;
+        Ok(())
+    }
+}
+
+/// Writes everything into a zip file, the records as `csv_name`.
+pub struct ZipSink<W: Write + Seek> {
+    pub zip: ZipWriter<W>,
+    pub csv_name: String,
+}
+
+impl<W: Write + Seek> ZipSink<W> {
+    pub fn new(writer: W, csv_name: &str) -> Self {
+        Self {
+            zip: ZipWriter::new(writer),
+            csv_name: csv_name.to_string(),
+        }
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.zip.finish()?)
+    }
+}
+
+impl<W: Write + Seek> RecordSink for ZipSink<W> {
+    fn add_records(&mut self, header: &[String], records: &[Vec<String>]) -> Result<(), Error> {
+        let name = self.csv_name.clone();
+        self.add_file(&name, &records_to_csv(header, records)?)
+    }
+
+    fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        self.start_file(name)?;
+        Ok(self.zip.write_all(data)?)
+    }
+
+    fn add_file_from(&mut self, name: &str, data: &mut dyn Read) -> Result<(), Error> {
+        self.start_file(name)?;
+        std::io::copy(data, &mut self.zip)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> ZipSink<W> {
+    fn start_file(&mut self, name: &str) -> Result<(), Error> {
+        // PDFs are compressed already
+        let method = if name.ends_with(".pdf") {
+            zip::CompressionMethod::Stored
+        } else {
+            zip::CompressionMethod::Deflated
+        };
+        self.zip
+            .start_file::<&str, ()>(name, FileOptions::default().compression_method(method))?;
+        Ok(())
+    }
+}
+
+/// Keeps everything, e.g. to look at the results before writing them.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub header: Vec<String>,
+    pub records: Vec<Vec<String>>,
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl MemorySink {
+    pub fn file(&self, name: &str) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(file, _)| file == name)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+impl RecordSink for MemorySink {
+    fn add_records(&mut self, header: &[String], records: &[Vec<String>]) -> Result<(), Error> {
+        self.header = header.to_vec();
+        self.records = records.to_vec();
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        self.files.push((name.to_string(), data.to_vec()));
+        Ok(())
+    }
+}
+
+/// One PNG file per sheet, named by the `NamingScheme`.
+pub struct PngSink;
+
+impl ImageSink for PngSink {
+    type Sheet = (String, Vec<u8>);
+
+    fn encode(&self, report: &ImageReport, naming: &NamingScheme) -> Self::Sheet {
+        let mut data = Vec::new();
+        report.write_to_buffer(&mut data);
+        (report.save_filename(naming), data)
+    }
+
+    fn add(&self, sheet: Self::Sheet, records: &mut dyn RecordSink) -> Result<(), Error> {
+        records.add_file(&sheet.0, &sheet.1)
+    }
+
+    fn finish(self, _records: &mut dyn RecordSink) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// All sheets in a single `report.pdf`. The compressed images are spooled as
+/// they come and typeset a few at a time at the end.
+pub struct PdfSink {
+    pub settings: PdfSettings,
+    pub title: String,
+    pdf: Mutex<ReportPdf>,
+}
+
+impl PdfSink {
+    pub fn new(settings: PdfSettings, title: &str) -> Self {
+        Self {
+            settings,
+            title: title.to_string(),
+            pdf: Mutex::new(ReportPdf::new(settings.compression)),
+        }
+    }
+}
+
+impl ImageSink for PdfSink {
+    type Sheet = (ReportPage, Vec<u8>);
+
+    fn encode(&self, report: &ImageReport, naming: &NamingScheme) -> Self::Sheet {
+        (
+            ReportPage::new(report, naming),
+            page_image(report, self.settings.compression),
+        )
+    }
+
+    fn add(&self, sheet: Self::Sheet, _records: &mut dyn RecordSink) -> Result<(), Error> {
+        self.pdf.lock().unwrap().add(sheet.0, &sheet.1)
+    }
+
+    fn finish(self, records: &mut dyn RecordSink) -> Result<(), Error> {
+        let pdf = self.pdf.into_inner().unwrap();
+        let mut data = pdf.finish(self.settings.order, &self.title)?;
+        records.add_file_from("report.pdf", &mut data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_and_zip() {
+        let header = vec!["Filename".to_string(), "Score".to_string()];
+        let records = vec![vec!["page1.png".to_string(), "17".to_string()]];
+
+        let dir = std::env::temp_dir().join(format!("autograder-sink-{}", std::process::id()));
+        let mut sink = DirectorySink::new(&dir);
+        sink.add_records(&header, &records).unwrap();
+        sink.add_file("ok/page1.png", b"png").unwrap();
+        sink.add_file_from("report.pdf", &mut &b"pdf"[..]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("results.csv")).unwrap(),
+            "Filename,Score\npage1.png,17\n"
+        );
+        assert_eq!(std::fs::read(dir.join("ok/page1.png")).unwrap(), b"png");
+        assert_eq!(std::fs::read(dir.join("report.pdf")).unwrap(), b"pdf");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut sink = ZipSink::new(std::io::Cursor::new(Vec::new()), "exam.csv");
+        sink.add_records(&header, &records).unwrap();
+        sink.add_file_from("report.pdf", &mut &b"pdf"[..]).unwrap();
+        let data = sink.finish().unwrap().into_inner();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"exam.csv") && names.contains(&"report.pdf"));
+        let mut pdf = archive.by_name("report.pdf").unwrap();
+        assert_eq!(pdf.compression(), zip::CompressionMethod::Stored);
+        let mut data = Vec::new();
+        pdf.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"pdf");
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+
+/// What we read from a single column of the ID grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdColumn {
+    Blank,
+    Digit(u32),
+    Multiple,
+}
+
+/// Check digit schemes for student IDs. In all schemes the check digit is the
+/// last digit of the ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckDigit {
+    /// The Luhn algorithm as used for credit card numbers.
+    Luhn,
+    /// Weighted sum with weights 1, 2, 3, ... from the right (like ISBN-10),
+    /// which has to be divisible by 11. The ID grid has no `X`, so IDs whose
+    /// check value is 10 are not supported: they never validate and their
+    /// sheets are always flagged.
+    Mod11,
+}
+
+impl CheckDigit {
+    pub fn validate(&self, digits: &str) -> bool {
+        let digits: Option<Vec<u32>> = digits.chars().map(|c| c.to_digit(10)).collect();
+        let digits = match digits {
+            Some(d) if d.len() > 1 => d,
+            _ => return false,
+        };
+
+        match self {
+            Self::Luhn => {
+                let sum: u32 = digits
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, &d)| {
+                        if i % 2 == 1 {
+                            let doubled = 2 * d;
+                            if doubled > 9 {
+                                doubled - 9
+                            } else {
+                                doubled
+                            }
+                        } else {
+                            d
+                        }
+                    })
+                    .sum();
+                sum.is_multiple_of(10)
+            }
+            Self::Mod11 => {
+                let sum: u32 = digits
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, &d)| (i as u32 + 1) * d)
+                    .sum();
+                sum.is_multiple_of(11)
+            }
+        }
+    }
+}
+
+/// A student ID as read from the ID grid, one entry per column. Blank columns
+/// before and after the ID are fine (students with shorter IDs), blank columns
+/// in between are not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StudentId {
+    pub columns: Vec<IdColumn>,
+}
+
+impl StudentId {
+    // the columns between the first and the last non-blank column
+    fn used_range(&self) -> std::ops::Range<usize> {
+        let first = self.columns.iter().position(|c| *c != IdColumn::Blank);
+        let last = self.columns.iter().rposition(|c| *c != IdColumn::Blank);
+        match (first, last) {
+            (Some(first), Some(last)) => first..(last + 1),
+            _ => 0..0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used_range().is_empty()
+    }
+
+    /// Indices of blank columns in the middle of the ID.
+    pub fn gaps(&self) -> Vec<usize> {
+        self.used_range()
+            .filter(|&i| self.columns[i] == IdColumn::Blank)
+            .collect()
+    }
+
+    /// Indices of columns with more than one mark.
+    pub fn multiple_marks(&self) -> Vec<usize> {
+        (0..self.columns.len())
+            .filter(|&i| self.columns[i] == IdColumn::Multiple)
+            .collect()
+    }
+
+    /// The ID as a string of digits (keeping leading zeros), if it could be
+    /// read without any ambiguity.
+    pub fn digits(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        self.columns[self.used_range()]
+            .iter()
+            .map(|c| match c {
+                IdColumn::Digit(d) => char::from_digit(*d, 10),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The full width ID with `_` for blank and `?` for multiply marked
+    /// columns, e.g. `0012?4__`.
+    pub fn pattern(&self) -> String {
+        self.columns
+            .iter()
+            .map(|c| match c {
+                IdColumn::Blank => '_',
+                IdColumn::Digit(d) => char::from_digit(*d, 10).unwrap_or('?'),
+                IdColumn::Multiple => '?',
+            })
+            .collect()
+    }
+
+    /// Whether the ID is readable and passes the check digit test (if any).
+    pub fn is_valid(&self, check: Option<CheckDigit>) -> bool {
+        match (self.digits(), check) {
+            (Some(digits), Some(scheme)) => scheme.validate(&digits),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id_from(pattern: &str) -> StudentId {
+        StudentId {
+            columns: pattern
+                .chars()
+                .map(|c| match c {
+                    '_' => IdColumn::Blank,
+                    '?' => IdColumn::Multiple,
+                    d => IdColumn::Digit(d.to_digit(10).unwrap()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn leading_zeros_and_padding() {
+        let id = id_from("_00123___");
+        assert_eq!(id.digits(), Some("00123".to_string()));
+        assert!(id.gaps().is_empty());
+        assert_eq!(id.pattern(), "_00123___");
+
+        assert!(id_from("_____").is_empty());
+        assert_eq!(id_from("_____").digits(), None);
+    }
+
+    #[test]
+    fn gaps_and_multiple_marks() {
+        let id = id_from("12_45?7");
+        assert_eq!(id.gaps(), vec![2]);
+        assert_eq!(id.multiple_marks(), vec![5]);
+        assert_eq!(id.digits(), None);
+        assert!(!id.is_valid(None));
+    }
+
+    #[test]
+    fn long_ids() {
+        let id = id_from("123456789012345");
+        assert_eq!(id.digits(), Some("123456789012345".to_string()));
+    }
+
+    #[test]
+    fn check_digits() {
+        assert!(CheckDigit::Luhn.validate("79927398713"));
+        assert!(!CheckDigit::Luhn.validate("79927398710"));
+        assert!(CheckDigit::Mod11.validate("0306406152"));
+        assert!(!CheckDigit::Mod11.validate("0306406153"));
+        assert!(id_from("_79927398713").is_valid(Some(CheckDigit::Luhn)));
+        assert!(!id_from("_79927398713").is_valid(Some(CheckDigit::Mod11)));
+    }
+
+    #[test]
+    fn mod11_check_value_ten() {
+        // 097522980X is a valid ISBN-10, but no digit can take the place of X
+        assert!(!CheckDigit::Mod11.validate("097522980X"));
+        for d in 0..10 {
+            assert!(!CheckDigit::Mod11.validate(&format!("097522980{}", d)));
+        }
+    }
+}
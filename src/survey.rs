@@ -0,0 +1,416 @@
+use crate::error::Error;
+use crate::image_helpers::draw_text;
+use crate::template::Template;
+use crate::template_scan::TemplateScan;
+use image::{Rgb, RgbImage};
+use imageproc::drawing;
+use imageproc::rect::Rect;
+use serde::Serialize;
+use std::io::Write;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+const BAR_COLOUR: Rgb<u8> = Rgb([70, 110, 180]);
+const BLANK_COLOUR: Rgb<u8> = Rgb([170, 170, 170]);
+const TEXT_COLOUR: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// What we read from a single sheet in survey mode. There is no key, so we
+/// just keep the marks.
+pub struct SurveyResponse {
+    pub identifier: String,
+    // only read if the survey is not anonymous
+    pub sid: Option<String>,
+    pub choices: Vec<Vec<u32>>,
+    pub answers: String,
+}
+
+impl SurveyResponse {
+    pub fn from_scan(template_scan: &TemplateScan, identifier: &str, record_ids: bool) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            sid: if record_ids {
+                template_scan.id().digits()
+            } else {
+                None
+            },
+            choices: template_scan
+                .template
+                .questions
+                .iter()
+                .map(|q| q.choices(template_scan))
+                .collect(),
+            answers: template_scan.answers().concat(),
+        }
+    }
+}
+
+/// The responses to a single question.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuestionTally {
+    pub question: String,
+    pub labels: Vec<String>,
+    pub counts: Vec<u32>,
+    pub blank: u32,
+    pub multiple: u32,
+    pub mean: Option<f64>,
+}
+
+impl QuestionTally {
+    pub fn answered(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+}
+
+/// A sheet that could not be read or aligned, so none of its marks count.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnreadableSheet {
+    pub identifier: String,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+struct SurveySummary<'a> {
+    sheets: usize,
+    questions: &'a [QuestionTally],
+    unreadable: &'a [UnreadableSheet],
+}
+
+/// Tallies the responses to all MCQs of a template, for course evaluations
+/// and other surveys without correct answers.
+pub struct SurveyTally {
+    pub record_ids: bool,
+    pub questions: Vec<QuestionTally>,
+    pub responses: Vec<SurveyResponse>,
+    pub unreadable: Vec<UnreadableSheet>,
+}
+
+// Options count as 1, 2, 3, ... for the mean, unless all labels are numbers
+// (like a Likert scale 0-4), in which case we take these.
+fn option_values(labels: &[String]) -> Vec<f64> {
+    let parsed: Option<Vec<f64>> = labels.iter().map(|l| l.trim().parse().ok()).collect();
+    parsed.unwrap_or_else(|| (1..=labels.len()).map(|i| i as f64).collect())
+}
+
+impl SurveyTally {
+    pub fn new(template: &Template, record_ids: bool) -> Self {
+        let questions = (0..template.questions.len())
+            .map(|i| {
+                let labels = template.option_labels(i);
+                QuestionTally {
+                    question: format!("Q{:03}", i + 1),
+                    counts: vec![0; labels.len()],
+                    labels,
+                    blank: 0,
+                    multiple: 0,
+                    mean: None,
+                }
+            })
+            .collect();
+        Self {
+            record_ids,
+            questions,
+            responses: Vec::new(),
+            unreadable: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, response: SurveyResponse) {
+        for (tally, choices) in self.questions.iter_mut().zip(response.choices.iter()) {
+            match choices.as_slice() {
+                [] => tally.blank += 1,
+                [one] => {
+                    if let Some(count) = tally.counts.get_mut(*one as usize) {
+                        *count += 1;
+                    }
+                }
+                _ => tally.multiple += 1,
+            }
+            let answered = tally.answered();
+            tally.mean = if answered > 0 {
+                let values = option_values(&tally.labels);
+                let sum: f64 = tally
+                    .counts
+                    .iter()
+                    .zip(values)
+                    .map(|(&c, v)| c as f64 * v)
+                    .sum();
+                Some(sum / answered as f64)
+            } else {
+                None
+            };
+        }
+        self.responses.push(response);
+    }
+
+    /// Records a sheet that could not be tallied, so that it shows up in the
+    /// results instead of silently lowering the number of sheets.
+    pub fn add_unreadable(&mut self, identifier: &str, error: &Error) {
+        self.unreadable.push(UnreadableSheet {
+            identifier: identifier.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /// One row per question with the number of answers, blanks, multiple marks
+    /// and the mean.
+    pub fn summary_csv(&self) -> Result<String, Error> {
+        let mut csv_writer = csv::Writer::from_writer(std::io::Cursor::new(Vec::new()));
+        csv_writer.write_record(["Question", "Answered", "Blank", "Multiple", "Mean"])?;
+        for q in &self.questions {
+            csv_writer.write_record([
+                q.question.clone(),
+                q.answered().to_string(),
+                q.blank.to_string(),
+                q.multiple.to_string(),
+                q.mean.map(|m| format!("{:.2}", m)).unwrap_or_default(),
+            ])?;
+        }
+        let csv_data = csv_writer.into_inner()?.into_inner();
+        Ok(String::from_utf8(csv_data)?)
+    }
+
+    /// One row per question and option with the count and the percentage of
+    /// all answers to that question.
+    pub fn distribution_csv(&self) -> Result<String, Error> {
+        let mut csv_writer = csv::Writer::from_writer(std::io::Cursor::new(Vec::new()));
+        csv_writer.write_record(["Question", "Option", "Count", "Percent"])?;
+        for q in &self.questions {
+            let answered = q.answered();
+            for (label, count) in q.labels.iter().zip(q.counts.iter()) {
+                let percent = if answered > 0 {
+                    format!("{:.1}", 100.0 * *count as f64 / answered as f64)
+                } else {
+                    String::new()
+                };
+                csv_writer.write_record([
+                    q.question.clone(),
+                    label.clone(),
+                    count.to_string(),
+                    percent,
+                ])?;
+            }
+        }
+        let csv_data = csv_writer.into_inner()?.into_inner();
+        Ok(String::from_utf8(csv_data)?)
+    }
+
+    /// The marks on every sheet, with the student ID if the survey is not
+    /// anonymous, followed by the sheets that could not be read and why.
+    pub fn responses_csv(&self) -> Result<String, Error> {
+        let mut csv_writer = csv::Writer::from_writer(std::io::Cursor::new(Vec::new()));
+        let mut header = vec!["Filename"];
+        if self.record_ids {
+            header.push("ID");
+        }
+        header.extend(["Answers", "Error"]);
+        csv_writer.write_record(&header)?;
+        for r in &self.responses {
+            let mut record = vec![r.identifier.clone()];
+            if self.record_ids {
+                record.push(r.sid.clone().unwrap_or_default());
+            }
+            record.extend([r.answers.clone(), String::new()]);
+            csv_writer.write_record(&record)?;
+        }
+        for u in &self.unreadable {
+            let mut record = vec![u.identifier.clone()];
+            if self.record_ids {
+                record.push(String::new());
+            }
+            record.extend([String::new(), u.error.clone()]);
+            csv_writer.write_record(&record)?;
+        }
+        let csv_data = csv_writer.into_inner()?.into_inner();
+        Ok(String::from_utf8(csv_data)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&SurveySummary {
+            sheets: self.responses.len(),
+            questions: &self.questions,
+            unreadable: &self.unreadable,
+        })
+    }
+
+    /// A horizontal bar chart of the answers to the question with the given
+    /// index, including the blank answers.
+    pub fn bar_chart(&self, question: usize) -> RgbImage {
+        let q = &self.questions[question];
+        let row_height = 40;
+        let label_width = 200;
+        let max_bar_width = 480;
+        let width = 800;
+        let height = 80 + row_height * (q.labels.len() as u32 + 1) + 20;
+
+        let mut image = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+        let mut title = format!("{} (n = {})", q.question, q.answered());
+        if let Some(mean) = q.mean {
+            title += &format!(", mean {:.2}", mean);
+        }
+        draw_text(&mut image, 20, 20, 32.0, TEXT_COLOUR, &title);
+
+        let rows = q
+            .labels
+            .iter()
+            .zip(q.counts.iter())
+            .map(|(label, &count)| (label.as_str(), count, BAR_COLOUR))
+            .chain(std::iter::once(("(blank)", q.blank, BLANK_COLOUR)));
+        let max_count = q.counts.iter().copied().chain([q.blank]).max().unwrap_or(0);
+
+        for (idx, (label, count, colour)) in rows.enumerate() {
+            let y = 80 + row_height * idx as u32;
+            draw_text(&mut image, 20, y + 8, 24.0, TEXT_COLOUR, label);
+            let bar_width = (max_bar_width * count).checked_div(max_count).unwrap_or(0);
+            if bar_width > 0 {
+                drawing::draw_filled_rect_mut(
+                    &mut image,
+                    Rect::at(label_width as i32, y as i32 + 5).of_size(bar_width, row_height - 10),
+                    colour,
+                );
+            }
+            draw_text(
+                &mut image,
+                label_width + bar_width + 10,
+                y + 8,
+                24.0,
+                TEXT_COLOUR,
+                &count.to_string(),
+            );
+        }
+
+        image
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_to_files(&self, prefix: &str) -> Result<(), Error> {
+        std::fs::write(format!("{}survey.csv", prefix), self.summary_csv()?)?;
+        std::fs::write(
+            format!("{}survey-distribution.csv", prefix),
+            self.distribution_csv()?,
+        )?;
+        std::fs::write(
+            format!("{}survey-responses.csv", prefix),
+            self.responses_csv()?,
+        )?;
+        std::fs::write(format!("{}survey.json", prefix), self.to_json()?)?;
+        for (idx, q) in self.questions.iter().enumerate() {
+            self.bar_chart(idx).save_with_format(
+                format!("{}survey-{}.png", prefix, q.question),
+                image::ImageFormat::Png,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn add_to_zip<W: Write + std::io::Seek>(
+        &self,
+        zip_writer: &mut ZipWriter<W>,
+        prefix: &str,
+    ) -> Result<(), Error> {
+        let options =
+            FileOptions::<()>::default().compression_method(zip::CompressionMethod::Deflated);
+        let files = [
+            (format!("{}survey.csv", prefix), self.summary_csv()?),
+            (
+                format!("{}survey-distribution.csv", prefix),
+                self.distribution_csv()?,
+            ),
+            (
+                format!("{}survey-responses.csv", prefix),
+                self.responses_csv()?,
+            ),
+            (format!("{}survey.json", prefix), self.to_json()?),
+        ];
+        for (name, content) in files {
+            zip_writer.start_file(name, options)?;
+            zip_writer.write_all(content.as_bytes())?;
+        }
+        for (idx, q) in self.questions.iter().enumerate() {
+            let mut buffer = Vec::new();
+            self.bar_chart(idx).write_to(
+                &mut std::io::Cursor::new(&mut buffer),
+                image::ImageFormat::Png,
+            )?;
+            zip_writer.start_file(format!("{}survey-{}.png", prefix, q.question), options)?;
+            zip_writer.write_all(&buffer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tally(labels: Vec<&str>, responses: Vec<Vec<u32>>) -> SurveyTally {
+        let mut tally = SurveyTally {
+            record_ids: false,
+            questions: vec![QuestionTally {
+                question: "Q001".into(),
+                counts: vec![0; labels.len()],
+                labels: labels.into_iter().map(String::from).collect(),
+                blank: 0,
+                multiple: 0,
+                mean: None,
+            }],
+            responses: Vec::new(),
+            unreadable: Vec::new(),
+        };
+        for (idx, choices) in responses.into_iter().enumerate() {
+            tally.add(SurveyResponse {
+                identifier: format!("page{}", idx + 1),
+                sid: None,
+                choices: vec![choices],
+                answers: String::new(),
+            });
+        }
+        tally
+    }
+
+    #[test]
+    fn likert_distribution() {
+        let t = tally(
+            vec!["1", "2", "3", "4", "5"],
+            vec![vec![4], vec![3], vec![4], vec![], vec![0, 1], vec![1]],
+        );
+        let q = &t.questions[0];
+        assert_eq!(q.counts, vec![0, 1, 0, 1, 2]);
+        assert_eq!(q.blank, 1);
+        assert_eq!(q.multiple, 1);
+        assert_eq!(q.mean, Some(4.0));
+        assert!(t.distribution_csv().unwrap().contains("Q001,5,2,50.0"));
+        assert!(t.summary_csv().unwrap().contains("Q001,4,1,1,4.00"));
+    }
+
+    #[test]
+    fn means_of_non_numeric_labels() {
+        let t = tally(vec!["Yes", "No"], vec![vec![0], vec![1], vec![1]]);
+        let mean = t.questions[0].mean.unwrap();
+        assert!((mean - 5.0 / 3.0).abs() < 1e-9);
+
+        let t = tally(vec!["Yes", "No"], vec![vec![]]);
+        assert_eq!(t.questions[0].mean, None);
+        assert!(t.to_json().unwrap().contains("\"sheets\": 1"));
+    }
+
+    #[test]
+    fn anonymous_responses() {
+        let t = tally(vec!["A", "B"], vec![vec![0]]);
+        let csv = t.responses_csv().unwrap();
+        assert!(csv.starts_with("Filename,Answers,Error"));
+    }
+
+    #[test]
+    fn unreadable_sheets() {
+        let mut t = tally(vec!["A", "B"], vec![vec![0]]);
+        t.add_unreadable("page2", &Error::Alignment);
+        assert_eq!(t.questions[0].answered(), 1);
+        let csv = t.responses_csv().unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        let unreadable = format!("page2,,{}", Error::Alignment);
+        assert_eq!(rows, vec!["Filename,Answers,Error", "page1,,", &unreadable]);
+        let json = t.to_json().unwrap();
+        assert!(json.contains("\"sheets\": 1"));
+        assert!(json.contains("\"identifier\": \"page2\""));
+    }
+}
@@ -1,4 +1,6 @@
+use crate::error::Error;
 use crate::point::Point;
+use crate::student_id::CheckDigit;
 use crate::template_scan::TemplateScan;
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +11,16 @@ const THRESHOLD: f64 = 0.30;
 pub enum CorrectAnswer {
     Exactly(u32),
     OneOf(Vec<u32>),
+    // answers to numeric questions
+    Value {
+        value: f64,
+        #[serde(default)]
+        tolerance: f64,
+    },
+    Range {
+        min: f64,
+        max: f64,
+    },
 }
 
 impl CorrectAnswer {
@@ -16,13 +28,41 @@ impl CorrectAnswer {
         match self {
             Self::Exactly(this) => *this == answer,
             Self::OneOf(these) => these.contains(&answer),
+            _ => false,
         }
     }
 
+    pub fn correct_value(&self, answer: f64) -> bool {
+        match self {
+            Self::Value { value, tolerance } => (answer - value).abs() <= tolerance + 1e-9,
+            Self::Range { min, max } => *min <= answer && answer <= *max,
+            _ => false,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Self::Value { .. } | Self::Range { .. })
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = u32> + use<'_> {
         match self {
             Self::Exactly(one) => std::slice::from_ref(one).iter().copied(),
             Self::OneOf(many) => many.as_slice().iter().copied(),
+            _ => [].iter().copied(),
+        }
+    }
+}
+
+impl std::fmt::Display for CorrectAnswer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Exactly(n) => write!(f, "{}", n),
+            Self::OneOf(options) => write!(f, "{:?}", options),
+            Self::Value { value, tolerance } if *tolerance > 0.0 => {
+                write!(f, "{} ± {}", value, tolerance)
+            }
+            Self::Value { value, .. } => write!(f, "{}", value),
+            Self::Range { min, max } => write!(f, "{} – {}", min, max),
         }
     }
 }
@@ -30,12 +70,25 @@ impl CorrectAnswer {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Template {
     pub id_questions: Vec<Question>,
+    #[serde(default)]
+    pub name_questions: Vec<Question>,
     pub version: Option<Question>,
     pub questions: Vec<Question>,
+    #[serde(default)]
+    pub numeric_questions: Vec<NumericQuestion>,
+    // fields in the examiner area, where the box value is the number of points
+    #[serde(default)]
+    pub teacher_questions: Vec<Question>,
+    // labels of the options of each MCQ; questions without an entry (or with
+    // an empty one) are labelled A, B, C, ...
+    #[serde(default)]
+    pub option_labels: Vec<Vec<String>>,
     pub circle_centers: [Point; 3],
     pub circle_radius: u32,
     pub height: u32,
     pub width: u32,
+    #[serde(default)]
+    pub id_check_digit: Option<CheckDigit>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -43,6 +96,25 @@ pub struct Question {
     pub boxes: Vec<Box>,
 }
 
+/// A gridded-in numeric answer. Every column has boxes for the digits (with
+/// their value), the decimal point (`NUMERIC_DOT`) and in the first column the
+/// minus sign (`NUMERIC_MINUS`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NumericQuestion {
+    pub columns: Vec<Question>,
+}
+
+pub const NUMERIC_DOT: u32 = 10;
+pub const NUMERIC_MINUS: u32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumericResponse {
+    Blank,
+    Value(f64),
+    /// Several marks in a column, gaps or something that is not a number.
+    Invalid,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Box {
     pub a: Point,
@@ -62,18 +134,86 @@ impl Box {
 }
 
 impl Template {
+    /// Reads a template from its JSON form and checks that it can be used.
+    pub fn from_json(reader: impl std::io::Read) -> Result<Self, Error> {
+        let template: Template =
+            serde_json::from_reader(reader).map_err(|e| Error::Template(e.to_string()))?;
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// A template needs a size, its circles and boxes for every question.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.width == 0 || self.height == 0 || self.circle_radius == 0 {
+            return Err(Error::Template(
+                "the size or the circle radius is missing".into(),
+            ));
+        }
+        let mut questions = self
+            .id_questions
+            .iter()
+            .chain(&self.name_questions)
+            .chain(&self.version)
+            .chain(&self.questions)
+            .chain(&self.teacher_questions)
+            .chain(self.numeric_questions.iter().flat_map(|q| &q.columns));
+        if questions.any(|q| q.boxes.is_empty()) {
+            return Err(Error::Template("a question has no boxes".into()));
+        }
+        Ok(())
+    }
+
     pub fn to_csv_header(&self) -> Vec<String> {
-        let mut header: Vec<String> = vec![
-            "Filename".into(),
-            "ID".into(),
-            "Total Score".into(),
-            "Version".into(),
-        ];
+        let mut header: Vec<String> = vec!["Filename".into(), "ID".into()];
+        if !self.name_questions.is_empty() {
+            header.push("Name".into());
+        }
+        header.push("Total Score".into());
+        header.push("Version".into());
         for i in 1..=self.questions.len() {
             header.push(format!("Q{:03}", i));
         }
+        for i in 1..=self.numeric_questions.len() {
+            header.push(format!("N{:03}", i));
+        }
+        for i in 1..=self.teacher_questions.len() {
+            header.push(format!("T{:03}", i));
+        }
+        // after the columns of earlier versions, which scripts may rely on
+        header.push("Answers".into());
+        header.push("Issues".into());
         header
     }
+
+    /// The highest possible total score: a point for every MCQ and numeric
+    /// question plus the maximal points of the fields scored by the examiner.
+    pub fn max_score(&self) -> u32 {
+        let teacher_points: u32 = self
+            .teacher_questions
+            .iter()
+            .map(|q| q.boxes.iter().map(|b| b.value).max().unwrap_or(0))
+            .sum();
+        (self.questions.len() + self.numeric_questions.len()) as u32 + teacher_points
+    }
+
+    /// The labels of the options of the MCQ with the given index.
+    pub fn option_labels(&self, question: usize) -> Vec<String> {
+        match self.option_labels.get(question) {
+            Some(labels) if !labels.is_empty() => labels.clone(),
+            _ => {
+                let num_options = self.questions.get(question).map_or(0, |q| q.boxes.len());
+                (0..num_options as u32).map(option_letter).collect()
+            }
+        }
+    }
+
+    /// The labels of all MCQs, which is what `parse_key` and `key_to_string`
+    /// expect.
+    pub fn all_option_labels(&self) -> Vec<Vec<String>> {
+        (0..self.questions.len())
+            .map(|i| self.option_labels(i))
+            .collect()
+    }
 }
 
 impl Question {
@@ -128,24 +268,453 @@ impl Question {
     }
 }
 
+// The columns of a name grid contain the letters A-Z (values 0-25) and
+// optionally the digits 0-9 (values 26-35).
+pub fn name_symbol_value(symbol: char) -> Option<u32> {
+    match symbol {
+        'A'..='Z' => Some(symbol as u32 - 'A' as u32),
+        '0'..='9' => Some(symbol as u32 - '0' as u32 + 26),
+        _ => None,
+    }
+}
+
+pub fn name_symbol(value: u32) -> char {
+    match value {
+        0..=25 => char::from_u32('A' as u32 + value).unwrap_or('?'),
+        26..=35 => char::from_digit(value - 26, 10).unwrap_or('?'),
+        _ => '?',
+    }
+}
+
+impl NumericQuestion {
+    pub fn response(&self, template_scan: &TemplateScan) -> NumericResponse {
+        let choices: Vec<Vec<u32>> = self
+            .columns
+            .iter()
+            .map(|q| q.choices(template_scan))
+            .collect();
+        parse_numeric_response(&choices)
+    }
+
+    /// Top left and bottom right corner of all boxes.
+    pub fn bounds(&self) -> (Point, Point) {
+        let boxes = self.columns.iter().flat_map(|q| q.boxes.iter());
+        let a = Point {
+            x: boxes.clone().map(|b| b.a.x).min().unwrap_or(0),
+            y: boxes.clone().map(|b| b.a.y).min().unwrap_or(0),
+        };
+        let b = Point {
+            x: boxes.clone().map(|b| b.b.x).max().unwrap_or(0),
+            y: boxes.map(|b| b.b.y).max().unwrap_or(0),
+        };
+        (a, b)
+    }
+}
+
+// Turns the choices in each column into a number. Blank columns before and
+// after the number are fine.
+pub fn parse_numeric_response(columns: &[Vec<u32>]) -> NumericResponse {
+    let first = columns.iter().position(|c| !c.is_empty());
+    let last = columns.iter().rposition(|c| !c.is_empty());
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return NumericResponse::Blank,
+    };
+
+    let mut number = String::new();
+    for (idx, column) in columns[first..=last].iter().enumerate() {
+        if column.len() != 1 {
+            return NumericResponse::Invalid;
+        }
+        match column[0] {
+            NUMERIC_MINUS if idx == 0 => number.push('-'),
+            NUMERIC_DOT => number.push('.'),
+            d if d < 10 => number.push(char::from_digit(d, 10).unwrap()),
+            _ => return NumericResponse::Invalid,
+        }
+    }
+
+    if !number.chars().any(|c| c.is_ascii_digit()) {
+        return NumericResponse::Invalid;
+    }
+    match number.parse::<f64>() {
+        Ok(value) => NumericResponse::Value(value),
+        Err(_) => NumericResponse::Invalid,
+    }
+}
+
+// Options are labelled A-Z, then AA, AB, ... like spreadsheet columns.
+pub fn option_letter(index: u32) -> String {
+    let mut letters = Vec::new();
+    let mut index = index + 1;
+    while index > 0 {
+        index -= 1;
+        letters.push(char::from_u32('A' as u32 + index % 26).unwrap());
+        index /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+pub fn option_letter_value(letters: &str) -> Option<u32> {
+    if letters.is_empty() {
+        return None;
+    }
+    letters
+        .chars()
+        .try_fold(0u32, |acc, c| {
+            let c = c.to_ascii_uppercase();
+            if c.is_ascii_uppercase() {
+                Some(26 * acc + (c as u32 - 'A' as u32) + 1)
+            } else {
+                None
+            }
+        })
+        .map(|value| value - 1)
+}
+
+// the index of `token` among the labels of a question, where the default
+// labels A, B, C, ... are used if `labels` is empty
+fn option_value(token: &str, labels: &[String]) -> Option<u32> {
+    if labels.is_empty() {
+        option_letter_value(token)
+    } else {
+        labels
+            .iter()
+            .position(|l| l.eq_ignore_ascii_case(token))
+            .map(|i| i as u32)
+    }
+}
+
+fn option_token(value: u32, labels: &[String]) -> String {
+    let label = match labels.get(value as usize) {
+        Some(label) => label.clone(),
+        None => option_letter(value),
+    };
+    if label.chars().count() == 1 {
+        label
+    } else {
+        format!("{{{}}}", label)
+    }
+}
+
+// numeric answers are written as [2.5], [3.14+-0.01] or [1..2]
+fn parse_numeric_answer(input: &str) -> Option<CorrectAnswer> {
+    let input = input.trim();
+    if let Some((min, max)) = input.split_once("..") {
+        return Some(CorrectAnswer::Range {
+            min: min.trim().parse().ok()?,
+            max: max.trim().parse().ok()?,
+        });
+    }
+    if let Some((value, tolerance)) = input.split_once("+-") {
+        return Some(CorrectAnswer::Value {
+            value: value.trim().parse().ok()?,
+            tolerance: tolerance.trim().parse().ok()?,
+        });
+    }
+    Some(CorrectAnswer::Value {
+        value: input.parse().ok()?,
+        tolerance: 0.0,
+    })
+}
+
+/// Parses one version of a key like `AB(CD)E[2.5]`. Every MCQ is matched
+/// against its own labels (`labels[i]`, letters if missing or empty), labels
+/// longer than a single character are written in braces like `{Yes}`.
+pub fn parse_key(input: &str, labels: &[Vec<String>]) -> Vec<CorrectAnswer> {
+    let mut result = Vec::new();
+    let mut chars = input.chars();
+    let no_labels: Vec<String> = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let question_labels = labels.get(result.len()).unwrap_or(&no_labels);
+        match c {
+            '(' => {
+                // Collect multiple options within parentheses
+                let group: String = chars.by_ref().take_while(|&next| next != ')').collect();
+                let mut group = group.chars();
+                let mut options = Vec::new();
+                while let Some(g) = group.next() {
+                    let token: String = if g == '{' {
+                        group.by_ref().take_while(|&next| next != '}').collect()
+                    } else {
+                        g.to_string()
+                    };
+                    if let Some(value) = option_value(token.trim(), question_labels) {
+                        options.push(value);
+                    }
+                }
+                result.push(CorrectAnswer::OneOf(options));
+            }
+            '[' => {
+                let numeric: String = chars.by_ref().take_while(|&next| next != ']').collect();
+                if let Some(answer) = parse_numeric_answer(&numeric) {
+                    result.push(answer);
+                }
+            }
+            '{' => {
+                let token: String = chars.by_ref().take_while(|&next| next != '}').collect();
+                if let Some(value) = option_value(token.trim(), question_labels) {
+                    result.push(CorrectAnswer::Exactly(value));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                if let Some(value) = option_value(&c.to_string(), question_labels) {
+                    result.push(CorrectAnswer::Exactly(value));
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// A numeric response written like a numeric answer of a key: `[2.5]`, `[]`
+/// if the columns are blank and `[?]` if they are no number.
+pub fn format_numeric(response: NumericResponse) -> String {
+    match response {
+        NumericResponse::Blank => "[]".to_string(),
+        NumericResponse::Value(value) => format!("[{}]", value),
+        NumericResponse::Invalid => "[?]".to_string(),
+    }
+}
+
+/// The inverse of `parse_key`.
+pub fn key_to_string(answers: &[CorrectAnswer], labels: &[Vec<String>]) -> String {
+    let no_labels: Vec<String> = Vec::new();
+    answers
+        .iter()
+        .enumerate()
+        .map(|(i, answer)| {
+            let question_labels = labels.get(i).unwrap_or(&no_labels);
+            match answer {
+                CorrectAnswer::Exactly(n) => option_token(*n, question_labels),
+                CorrectAnswer::OneOf(options) => {
+                    let tokens: String = options
+                        .iter()
+                        .map(|&n| option_token(n, question_labels))
+                        .collect();
+                    format!("({})", tokens)
+                }
+                CorrectAnswer::Value { value, tolerance } if *tolerance > 0.0 => {
+                    format!("[{}+-{}]", value, tolerance)
+                }
+                CorrectAnswer::Value { value, .. } => format!("[{}]", value),
+                CorrectAnswer::Range { min, max } => format!("[{}..{}]", min, max),
+            }
+        })
+        .collect()
+}
+
+/// What a student marked in the MCQ with the given labels, written like a key:
+/// `_` for no mark and all marks in parentheses if there are several.
+pub fn format_choices(choices: &[u32], labels: &[String]) -> String {
+    match choices {
+        [] => "_".to_string(),
+        [one] => option_token(*one, labels),
+        many => format!(
+            "({})",
+            many.iter()
+                .map(|&n| option_token(n, labels))
+                .collect::<String>()
+        ),
+    }
+}
+
 pub type ExamKey = Vec<Vec<CorrectAnswer>>;
 
+/// Reads a key from its JSON form.
+pub fn key_from_json(reader: impl std::io::Read) -> Result<ExamKey, Error> {
+    serde_json::from_reader(reader).map_err(|e| Error::Key(e.to_string()))
+}
+
 // check whether template and key are compatible: the number of versions needs
-// to match and every version needs to have answers for all questions.
-pub fn are_compatible(t: &Template, k: &ExamKey) -> bool {
+// to match and every version needs to have answers for all questions. The
+// answers to the numeric questions come after the answers to the MCQs.
+pub fn check_compatible(t: &Template, k: &ExamKey) -> Result<(), Error> {
     let num_versions = if let Some(vq) = &t.version {
         vq.boxes.len()
     } else {
         1
     };
 
+    if k.is_empty() {
+        return Err(Error::Key("the key is empty".into()));
+    }
     if k.len() != num_versions {
-        return false;
+        return Err(Error::Key(format!(
+            "the key has {} versions, the template {}",
+            k.len(),
+            num_versions
+        )));
     }
 
-    if let Some(first_len) = k.first().map(|v| v.len()) {
-        k.iter().all(|v| v.len() == first_len) && first_len == t.questions.len()
-    } else {
-        false
+    let num_mcqs = t.questions.len();
+    let num_questions = num_mcqs + t.numeric_questions.len();
+    for (version, answers) in k.iter().enumerate() {
+        let version = option_letter(version as u32);
+        if answers.len() != num_questions {
+            return Err(Error::Key(format!(
+                "version {} has {} answers instead of {}",
+                version,
+                answers.len(),
+                num_questions
+            )));
+        }
+        let mismatch = answers
+            .iter()
+            .enumerate()
+            .position(|(i, answer)| answer.is_numeric() != (i >= num_mcqs));
+        if let Some(i) = mismatch {
+            return Err(Error::Key(format!(
+                "answer {} of version {} does not match the type of the question",
+                i + 1,
+                version
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn are_compatible(t: &Template, k: &ExamKey) -> bool {
+    check_compatible(t, k).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_and_key_errors() {
+        let json = |questions: &str| {
+            format!(
+                r#"{{"id_questions": [], "version": null, "questions": {},
+                    "circle_centers": [{{"x": 0, "y": 0}}, {{"x": 90, "y": 0}}, {{"x": 0, "y": 90}}],
+                    "circle_radius": 5, "height": 100, "width": 100}}"#,
+                questions
+            )
+        };
+        let one_box =
+            r#"[{"boxes": [{"a": {"x": 10, "y": 10}, "b": {"x": 20, "y": 20}, "value": 0}]}]"#;
+        let template = Template::from_json(json(one_box).as_bytes()).unwrap();
+        assert!(matches!(
+            Template::from_json(json(r#"[{"boxes": []}]"#).as_bytes()),
+            Err(Error::Template(_))
+        ));
+        assert!(matches!(
+            Template::from_json("{}".as_bytes()),
+            Err(Error::Template(_))
+        ));
+
+        let key = key_from_json("[[0]]".as_bytes()).unwrap();
+        assert!(check_compatible(&template, &key).is_ok());
+        assert!(matches!(
+            key_from_json("[[x]]".as_bytes()),
+            Err(Error::Key(_))
+        ));
+        let error = check_compatible(&template, &vec![vec![]]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid key: version A has 0 answers instead of 1"
+        );
+        assert!(!are_compatible(&template, &vec![]));
+        assert!(!are_compatible(
+            &template,
+            &key_from_json(r#"[[{"value": 1.5}]]"#.as_bytes()).unwrap()
+        ));
+    }
+
+    #[test]
+    fn option_letters() {
+        assert_eq!(option_letter(0), "A");
+        assert_eq!(option_letter(9), "J");
+        assert_eq!(option_letter(25), "Z");
+        assert_eq!(option_letter(26), "AA");
+        assert_eq!(option_letter(27), "AB");
+        assert_eq!(option_letter(52), "BA");
+        for i in 0..1000 {
+            assert_eq!(option_letter_value(&option_letter(i)), Some(i));
+        }
+        assert_eq!(option_letter_value("k"), Some(10));
+        assert_eq!(option_letter_value("1"), None);
+        assert_eq!(option_letter_value(""), None);
+    }
+
+    #[test]
+    fn keys_with_labels() {
+        let letters: Vec<String> = Vec::new();
+        let tf: Vec<String> = vec!["T".into(), "F".into()];
+        let yes_no: Vec<String> = vec!["Yes".into(), "No".into()];
+        let likert: Vec<String> = (1..=5).map(|i| i.to_string()).collect();
+        let labels = vec![letters, tf, yes_no, likert];
+
+        let key = parse_key("K f {no} (24) [1..2]", &labels);
+        assert!(matches!(key[0], CorrectAnswer::Exactly(10)));
+        assert!(matches!(key[1], CorrectAnswer::Exactly(1)));
+        assert!(matches!(key[2], CorrectAnswer::Exactly(1)));
+        assert!(matches!(&key[3], CorrectAnswer::OneOf(v) if v == &vec![1, 3]));
+        assert!(matches!(key[4], CorrectAnswer::Range { .. }));
+        assert_eq!(key_to_string(&key, &labels), "KF{No}(24)[1..2]");
+
+        // without labels, everything is a letter
+        let key = parse_key("AB(CD){AB}", &[]);
+        assert_eq!(key.len(), 4);
+        assert!(matches!(key[3], CorrectAnswer::Exactly(27)));
+        assert_eq!(key_to_string(&key, &[]), "AB(CD){AB}");
+
+        assert_eq!(format_choices(&[], &labels[2]), "_");
+        assert_eq!(format_choices(&[0, 1], &labels[2]), "({Yes}{No})");
+        assert_eq!(format_numeric(NumericResponse::Value(-0.5)), "[-0.5]");
+        assert_eq!(format_numeric(NumericResponse::Blank), "[]");
+    }
+
+    #[test]
+    fn numeric_responses() {
+        let blank: Vec<u32> = vec![];
+        let parse = |columns: Vec<Vec<u32>>| parse_numeric_response(&columns);
+
+        assert_eq!(parse(vec![blank.clone(); 4]), NumericResponse::Blank);
+        assert_eq!(
+            parse(vec![blank.clone(), vec![1], vec![2], blank.clone()]),
+            NumericResponse::Value(12.0)
+        );
+        assert_eq!(
+            parse(vec![
+                vec![NUMERIC_MINUS],
+                vec![0],
+                vec![NUMERIC_DOT],
+                vec![5]
+            ]),
+            NumericResponse::Value(-0.5)
+        );
+        assert_eq!(
+            parse(vec![vec![3], blank.clone(), vec![4]]),
+            NumericResponse::Invalid
+        );
+        assert_eq!(parse(vec![vec![3], vec![4, 5]]), NumericResponse::Invalid);
+        assert_eq!(
+            parse(vec![vec![1], vec![NUMERIC_DOT], vec![NUMERIC_DOT]]),
+            NumericResponse::Invalid
+        );
+        assert_eq!(
+            parse(vec![vec![1], vec![NUMERIC_MINUS]]),
+            NumericResponse::Invalid
+        );
+    }
+
+    #[test]
+    fn numeric_key_entries() {
+        let key: Vec<CorrectAnswer> =
+            serde_json::from_str(r#"[1, [0, 2], {"value": 2.5}, {"value": 3.14, "tolerance": 0.01}, {"min": -1, "max": 1}]"#)
+                .unwrap();
+        assert!(key[1].correct(2));
+        assert!(key[2].correct_value(2.5));
+        assert!(!key[2].correct_value(2.51));
+        assert!(key[3].correct_value(3.15));
+        assert!(!key[3].correct_value(3.16));
+        assert!(key[4].correct_value(-0.5));
+        assert!(!key[4].correct_value(1.5));
+        assert!(!key[1].is_numeric() && key[4].is_numeric());
     }
 }
@@ -1,11 +1,20 @@
-use crate::image_helpers::{draw_circle_around_box, gray_to_rgb, replace_colour, replace_colours};
+use crate::colours::ColourScheme;
+use crate::error::Error;
+use crate::image_helpers::{
+    draw_circle_around_box, draw_cross_over_box, draw_ring_around_box, draw_text, draw_tick,
+    gray_to_rgb, hatch, replace_colour, replace_colours,
+};
 use crate::point::affine_transformation;
 use crate::point::Point;
 use crate::point::Transformation;
-use crate::report::ImageReport;
+use crate::report::{add_issue_footer, ImageReport, Issue};
 use crate::scan::Scan;
+use crate::student_id::{IdColumn, StudentId};
 use crate::template::ExamKey;
 use crate::template::Template;
+use crate::template::{
+    format_choices, format_numeric, name_symbol, parse_numeric_response, NumericResponse,
+};
 
 use imageproc::drawing;
 
@@ -14,24 +23,20 @@ const GREEN: image::Rgb<u8> = image::Rgb([0u8, 255u8, 0u8]);
 const BLACK: image::Rgb<u8> = image::Rgb([0u8, 0u8, 0u8]);
 const WHITE: image::Rgb<u8> = image::Rgb([255u8, 255u8, 255u8]);
 
-pub struct ColourScheme {
-    pub correct_foreground: image::Rgb<u8>,
-    pub correct_background: image::Rgb<u8>,
-    pub wrong_foreground: image::Rgb<u8>,
-    pub wrong_background: image::Rgb<u8>,
-    pub highlight_background: image::Rgb<u8>,
-    pub highlight_foreground: image::Rgb<u8>,
+/// How to grade a sheet on which the version is not marked or marked several
+/// times. The sheet is flagged in any case, and a single marked version is
+/// always the one that is graded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VersionFallback {
+    /// only the teacher points are counted
+    #[default]
+    Ungraded,
+    /// grade it as this version, unless other versions are marked
+    Version(u32),
+    /// grade it as the version with the highest score
+    BestScore,
 }
 
-const STD_COLOUR_SCHEME: ColourScheme = ColourScheme {
-    correct_foreground: image::Rgb([30u8, 220u8, 30u8]),
-    correct_background: image::Rgb([220u8, 255u8, 220u8]),
-    wrong_foreground: image::Rgb([255u8, 50u8, 50u8]),
-    wrong_background: image::Rgb([255u8, 220u8, 220u8]),
-    highlight_background: image::Rgb([255u8, 215u8, 0u8]),
-    highlight_foreground: image::Rgb([255u8, 110u8, 0u8]),
-};
-
 pub struct TemplateScan<'a> {
     pub template: &'a Template,
     pub scan: Scan,
@@ -48,6 +53,15 @@ impl<'a> TemplateScan<'a> {
         ts.set_transformation();
         ts
     }
+
+    /// Like `new`, but a scan whose circles cannot be found is an error.
+    pub fn aligned(template: &'a Template, scan: Scan) -> Result<Self, Error> {
+        let ts = Self::new(template, scan);
+        match ts.transformation {
+            Some(_) => Ok(ts),
+            None => Err(Error::Alignment),
+        }
+    }
     pub fn transform(&self, p: Point) -> Point {
         if let Some(trafo) = self.transformation {
             trafo.apply(p)
@@ -55,25 +69,50 @@ impl<'a> TemplateScan<'a> {
             p
         }
     }
-    pub fn id(&self) -> Option<u32> {
+    pub fn id(&self) -> StudentId {
+        let columns = self
+            .template
+            .id_questions
+            .iter()
+            .map(|q| {
+                let choices = q.choices(self);
+                match choices.len() {
+                    0 => IdColumn::Blank,
+                    1 => IdColumn::Digit(choices[0]),
+                    _ => IdColumn::Multiple,
+                }
+            })
+            .collect();
+
+        StudentId { columns }
+    }
+    /// The name as read from the name grid with blank columns as spaces and
+    /// `?` for columns with more than one mark. `None` if the template has no
+    /// name grid.
+    pub fn name(&self) -> Option<String> {
         let t = &self.template;
-        let choices: Vec<Option<u32>> = t.id_questions.iter().map(|q| q.choice(self)).collect();
+        if t.name_questions.is_empty() {
+            return None;
+        }
 
-        let id: String = choices
+        let name: String = t
+            .name_questions
             .iter()
-            .filter_map(|&opt| opt.map(|num| num.to_string()))
+            .map(|q| {
+                let choices = q.choices(self);
+                match choices.len() {
+                    0 => ' ',
+                    1 => name_symbol(choices[0]),
+                    _ => '?',
+                }
+            })
             .collect();
 
-        // If the resulting string is empty, all entries were None, so return None
-        if id.is_empty() {
-            None
-        } else {
-            id.parse::<u32>().ok()
-        }
+        Some(name.trim().to_string())
     }
     pub fn circle_everything(&self) -> image::RgbImage {
         let t = &self.template;
-        let mut image = gray_to_rgb(&self.scan.image);
+        let mut image = gray_to_rgb(self.scan.image());
 
         let trafo = |p| self.transform(p);
         for c in t.circle_centers {
@@ -94,6 +133,11 @@ impl<'a> TemplateScan<'a> {
             all_questions.push(vq.clone());
         }
         all_questions.extend(t.id_questions.clone());
+        all_questions.extend(t.name_questions.clone());
+        all_questions.extend(t.teacher_questions.clone());
+        for nq in &t.numeric_questions {
+            all_questions.extend(nq.columns.clone());
+        }
 
         for q in all_questions {
             for b in q.boxes {
@@ -123,6 +167,14 @@ impl<'a> TemplateScan<'a> {
             println!("ID{}: {:?} -> {:?}", idx + 1, blacknesses, q.choice(self));
         }
 
+        if !t.name_questions.is_empty() {
+            println!("\nName Questions:");
+            for (idx, q) in t.name_questions.clone().into_iter().enumerate() {
+                let blacknesses: Vec<u32> = q.blacknesses_rounded(self);
+                println!("N{}: {:?} -> {:?}", idx + 1, blacknesses, q.choice(self));
+            }
+        }
+
         println!("\nMCQ:");
 
         for (idx, q) in t.questions.clone().into_iter().enumerate() {
@@ -134,7 +186,45 @@ impl<'a> TemplateScan<'a> {
                 q.choices(self)
             );
         }
+
+        if !t.teacher_questions.is_empty() {
+            println!("\nExaminer:");
+            for (idx, q) in t.teacher_questions.clone().into_iter().enumerate() {
+                let blacknesses: Vec<u32> = q.blacknesses_rounded(self);
+                println!(
+                    "T{:0>2}: {:?} -> {:?}",
+                    idx + 1,
+                    blacknesses,
+                    q.choice(self)
+                );
+            }
+        }
+
+        if !t.numeric_questions.is_empty() {
+            println!("\nNumeric:");
+            for (idx, nq) in t.numeric_questions.iter().enumerate() {
+                println!("N{:0>2}: {:?}", idx + 1, nq.response(self));
+            }
+        }
+    }
+    /// Everything the student marked, written like a key: for every MCQ the
+    /// labels of the chosen options, then every numeric answer in brackets.
+    pub fn answers(&self) -> Vec<String> {
+        let t = &self.template;
+        let mut answers: Vec<String> = t
+            .questions
+            .iter()
+            .enumerate()
+            .map(|(i, q)| format_choices(&q.choices(self), &t.option_labels(i)))
+            .collect();
+        answers.extend(
+            t.numeric_questions
+                .iter()
+                .map(|nq| format_numeric(nq.response(self))),
+        );
+        answers
     }
+
     fn mark_issue_with_highlight_box(
         &self,
         image: &mut image::RgbImage,
@@ -154,14 +244,104 @@ impl<'a> TemplateScan<'a> {
             WHITE,
             scheme.highlight_background,
         );
+        Self::hatch_if_cues(image, a, b, scheme);
+    }
+
+    fn hatch_if_cues(image: &mut image::RgbImage, a: Point, b: Point, scheme: &ColourScheme) {
+        if scheme.cues {
+            let spacing = (b.y - a.y).max(8) / 2;
+            hatch(
+                image,
+                a,
+                b,
+                spacing,
+                scheme.highlight_background,
+                scheme.highlight_foreground,
+            );
+        }
+    }
+
+    /// The points of the sheet (without teacher points) for every version of
+    /// the key.
+    pub fn version_scores(&self, k: &ExamKey) -> Vec<u32> {
+        let t = &self.template;
+        let choices: Vec<Vec<u32>> = t.questions.iter().map(|q| q.choices(self)).collect();
+        let numeric: Vec<NumericResponse> = t
+            .numeric_questions
+            .iter()
+            .map(|nq| nq.response(self))
+            .collect();
+
+        k.iter()
+            .map(|key| {
+                let mcq_points = choices
+                    .iter()
+                    .zip(key)
+                    .filter(|(choices, answer)| choices.len() == 1 && answer.correct(choices[0]))
+                    .count();
+                let numeric_points = numeric
+                    .iter()
+                    .zip(&key[t.questions.len()..])
+                    .filter(|(response, answer)| match response {
+                        NumericResponse::Value(value) => answer.correct_value(*value),
+                        _ => false,
+                    })
+                    .count();
+                (mcq_points + numeric_points) as u32
+            })
+            .collect()
+    }
+
+    // the version we grade a sheet with an unclear version bubble as; among
+    // several marked versions we only consider the marked ones
+    fn fallback_version(
+        fallback: VersionFallback,
+        marked: &[u32],
+        version_scores: &[u32],
+    ) -> Option<u32> {
+        let candidates: Vec<u32> = if marked.is_empty() {
+            (0..version_scores.len() as u32).collect()
+        } else {
+            marked.to_vec()
+        };
+        match fallback {
+            VersionFallback::Ungraded => None,
+            VersionFallback::Version(v) => {
+                ((v as usize) < version_scores.len() && candidates.contains(&v)).then_some(v)
+            }
+            // max_by_key picks the last maximum, so ties go to the lowest version
+            VersionFallback::BestScore => candidates
+                .into_iter()
+                .filter(|v| (*v as usize) < version_scores.len())
+                .rev()
+                .max_by_key(|v| version_scores[*v as usize]),
+        }
     }
 
     pub fn generate_image_report(&self, k: &ExamKey, identifier: &String) -> ImageReport {
+        self.generate_image_report_with(
+            k,
+            identifier,
+            &ColourScheme::default(),
+            VersionFallback::default(),
+        )
+    }
+
+    pub fn generate_image_report_with(
+        &self,
+        k: &ExamKey,
+        identifier: &String,
+        scheme: &ColourScheme,
+        fallback: VersionFallback,
+    ) -> ImageReport {
         let t = &self.template;
-        let mut image = gray_to_rgb(&self.scan.image);
-        let mut scores: Vec<Option<u32>> = vec![None; t.questions.len()];
-        let mut issue = false;
-        let scheme = STD_COLOUR_SCHEME;
+        let mut image = gray_to_rgb(self.scan.image());
+        let mut scores: Vec<Option<u32>> =
+            vec![None; t.questions.len() + t.numeric_questions.len()];
+        let mut issues = Vec::new();
+        if self.transformation.is_none() {
+            issues.push(Issue::AlignmentFailure);
+        }
 
         let trafo = |p| self.transform(p);
 
@@ -177,7 +357,26 @@ impl<'a> TemplateScan<'a> {
         }
 
         let v = if let Some(vq) = &t.version {
-            vq.choice(self)
+            let choices = vq.choices(self);
+            if choices.len() == 1 {
+                Some(choices[0])
+            } else {
+                let version_scores = self.version_scores(k);
+                let assumed = Self::fallback_version(fallback, &choices, &version_scores);
+                issues.push(if choices.is_empty() {
+                    Issue::MissingVersion {
+                        version_scores,
+                        assumed,
+                    }
+                } else {
+                    Issue::MultipleVersions {
+                        version_scores,
+                        assumed,
+                    }
+                });
+                self.mark_issue_with_highlight_box(&mut image, vq, trafo, scheme);
+                assumed
+            }
         } else {
             Some(0)
         };
@@ -204,10 +403,13 @@ impl<'a> TemplateScan<'a> {
                 0
             };
 
-            let pad_h = {
-                let b = trafo(t.questions[0].boxes[0].b);
-                let a = trafo(t.questions[0].boxes[1].a);
-                (a.x - b.x) / 2
+            let pad_h = match t.questions.first() {
+                Some(q) if q.boxes.len() > 1 => {
+                    let b = trafo(q.boxes[0].b);
+                    let a = trafo(q.boxes[1].a);
+                    (a.x - b.x) / 2
+                }
+                _ => 0,
             };
 
             for i in 0..t.questions.len() {
@@ -250,7 +452,8 @@ impl<'a> TemplateScan<'a> {
                         WHITE,
                         scheme.highlight_background,
                     );
-                    issue = true;
+                    Self::hatch_if_cues(&mut image, a, b, scheme);
+                    issues.push(Issue::MultipleMarks { question: i + 1 });
 
                     for choice in choices {
                         let thebox = q.boxes[choice as usize];
@@ -296,43 +499,172 @@ impl<'a> TemplateScan<'a> {
                         b.y + pad_v,
                         replacements,
                     );
+
+                    // cues for black and white printouts: the correct answer
+                    // is circled and wrong marks are crossed out
+                    if scheme.cues {
+                        if correct_answer.correct(idx as u32) {
+                            draw_ring_around_box(&mut image, a, b, scheme.correct_foreground);
+                        } else if choices.contains(&(idx as u32)) {
+                            draw_cross_over_box(&mut image, a, b, WHITE);
+                        }
+                    }
                 }
             }
-        }
 
-        let mut last_valid_id_pos = None;
-        for i in 0..t.id_questions.len() {
-            let q = &t.id_questions[i];
-            let choices = q.choices(self);
+            for (j, nq) in t.numeric_questions.iter().enumerate() {
+                let i = t.questions.len() + j;
+                let correct_answer = &k[v as usize][i];
+                let choices: Vec<Vec<u32>> = nq.columns.iter().map(|q| q.choices(self)).collect();
 
-            if !choices.is_empty() {
-                // If we found a previous valid position and there's a gap
-                if let Some(last_pos) = last_valid_id_pos {
-                    if i - last_pos > 1 {
-                        issue = true;
-                        let prev_question = &t.id_questions[i - 1];
-                        let a = trafo(prev_question.boxes[0].a);
-                        let b = trafo(prev_question.boxes.last().unwrap().b);
+                let response = parse_numeric_response(&choices);
+                let (background, foreground) = match response {
+                    NumericResponse::Value(value) if correct_answer.correct_value(value) => {
+                        scores[i] = Some(1);
+                        (scheme.correct_background, scheme.correct_foreground)
+                    }
+                    NumericResponse::Value(_) => {
+                        scores[i] = Some(0);
+                        (scheme.wrong_background, scheme.wrong_foreground)
+                    }
+                    NumericResponse::Blank => (scheme.wrong_background, scheme.wrong_foreground),
+                    NumericResponse::Invalid => {
+                        issues.push(Issue::InvalidNumeric { question: j + 1 });
+                        (scheme.highlight_background, scheme.highlight_foreground)
+                    }
+                };
 
-                        replace_colour(
+                let (a, b) = nq.bounds();
+                let (a, b) = (trafo(a), trafo(b));
+                replace_colour(&mut image, a.x, a.y, b.x, b.y, WHITE, background);
+                if response == NumericResponse::Invalid {
+                    Self::hatch_if_cues(&mut image, a, b, scheme);
+                }
+
+                for (column, column_choices) in nq.columns.iter().zip(choices.iter()) {
+                    for thebox in column.boxes.iter() {
+                        if column_choices.contains(&thebox.value) {
+                            let tl = trafo(thebox.a);
+                            let br = trafo(thebox.b);
+                            replace_colour(&mut image, tl.x, tl.y, br.x, br.y, BLACK, foreground);
+                        }
+                    }
+                }
+
+                // unlike for MCQs we cannot highlight the correct answer, so
+                // we write it below the grid
+                let box_height = match nq.columns.first().and_then(|q| q.boxes.first()) {
+                    Some(thebox) => trafo(thebox.b).y - trafo(thebox.a).y,
+                    None => 20,
+                };
+                let mut text_x = a.x;
+                if scheme.cues {
+                    let (x, y) = (a.x, b.y + box_height / 2);
+                    if scores[i] == Some(1) {
+                        draw_tick(&mut image, x, y, box_height, scheme.correct_foreground);
+                    } else {
+                        let br = Point {
+                            x: x + box_height,
+                            y: y + box_height,
+                        };
+                        draw_cross_over_box(
                             &mut image,
-                            a.x,
-                            a.y,
-                            b.x,
-                            b.y,
-                            WHITE,
-                            scheme.highlight_background,
+                            Point { x, y },
+                            br,
+                            scheme.wrong_foreground,
                         );
                     }
+                    text_x += 3 * box_height / 2;
+                }
+                draw_text(
+                    &mut image,
+                    text_x,
+                    b.y + box_height / 2,
+                    box_height as f32,
+                    BLACK,
+                    &correct_answer.to_string(),
+                );
+            }
+        }
+
+        // points given by hand do not depend on the version
+        for (j, q) in t.teacher_questions.iter().enumerate() {
+            let choices = q.choices(self);
+            if choices.len() == 1 {
+                let thebox = q.boxes.iter().find(|b| b.value == choices[0]).unwrap();
+                let tl = trafo(thebox.a);
+                let br = trafo(thebox.b);
+                replace_colour(
+                    &mut image,
+                    tl.x,
+                    tl.y,
+                    br.x,
+                    br.y,
+                    BLACK,
+                    scheme.highlight_foreground,
+                );
+                scores.push(Some(choices[0]));
+            } else {
+                // the examiner either forgot to grade or changed their mind
+                self.mark_issue_with_highlight_box(&mut image, q, trafo, scheme);
+                issues.push(Issue::TeacherPoints { field: j + 1 });
+                scores.push(None);
+            }
+        }
+
+        let student_id = self.id();
+
+        // blank columns in the middle of the ID
+        for i in student_id.gaps() {
+            issues.push(Issue::IdGap { column: i + 1 });
+            self.mark_issue_with_highlight_box(&mut image, &t.id_questions[i], trafo, scheme);
+        }
+
+        for (i, (q, column)) in t
+            .id_questions
+            .iter()
+            .zip(student_id.columns.iter())
+            .enumerate()
+        {
+            match column {
+                IdColumn::Digit(idx) => {
+                    let tl = trafo(q.boxes[*idx as usize].a);
+                    let br = trafo(q.boxes[*idx as usize].b);
+                    replace_colour(
+                        &mut image,
+                        tl.x,
+                        tl.y,
+                        br.x,
+                        br.y,
+                        BLACK,
+                        scheme.highlight_foreground,
+                    );
                 }
-                last_valid_id_pos = Some(i);
+                IdColumn::Multiple => {
+                    self.mark_issue_with_highlight_box(&mut image, q, trafo, scheme);
+                    issues.push(Issue::IdMultipleMarks { column: i + 1 });
+                }
+                IdColumn::Blank => {}
             }
+        }
 
+        // an ID that can be read but fails the check digit test
+        if student_id.digits().is_some() && !student_id.is_valid(t.id_check_digit) {
+            issues.push(Issue::IdCheckDigit);
+            for q in &t.id_questions {
+                self.mark_issue_with_highlight_box(&mut image, q, trafo, scheme);
+            }
+        }
+
+        // the name grid is only there to help identify students, so unclear
+        // columns are highlighted but not flagged
+        for q in &t.name_questions {
+            let choices = q.choices(self);
             match choices.len() {
                 1 => {
-                    let idx = choices[0];
-                    let tl = trafo(q.boxes[idx as usize].a);
-                    let br = trafo(q.boxes[idx as usize].b);
+                    let thebox = q.boxes.iter().find(|b| b.value == choices[0]).unwrap();
+                    let tl = trafo(thebox.a);
+                    let br = trafo(thebox.b);
                     replace_colour(
                         &mut image,
                         tl.x,
@@ -344,19 +676,24 @@ impl<'a> TemplateScan<'a> {
                     );
                 }
                 n if n > 1 => {
-                    self.mark_issue_with_highlight_box(&mut image, q, trafo, &scheme);
-                    issue = true;
+                    self.mark_issue_with_highlight_box(&mut image, q, trafo, scheme);
                 }
                 _ => {}
             }
         }
 
+        if !issues.is_empty() {
+            image = add_issue_footer(image, &issues);
+        }
+
         ImageReport {
             image,
-            sid: self.id(),
+            sid: student_id.digits(),
+            name: self.name(),
             version: v,
+            answers: self.answers(),
             scores,
-            issue,
+            issues,
             identifier: identifier.to_string(),
         }
     }
@@ -371,19 +708,15 @@ impl<'a> TemplateScan<'a> {
 
         // if this did not work, then in all cases known to us the image had too
         // much white noise, so we erode it
-        imageproc::morphology::erode_mut(
-            &mut self.scan.image,
-            imageproc::distance_transform::Norm::L1,
-            1,
-        );
+        self.scan.erode();
 
         self.transformation = self.find_transformation();
     }
 
     fn find_transformation(&self) -> Option<Transformation> {
         let t = &self.template;
-        let h_scale = (t.height as f64) / (self.scan.image.height() as f64);
-        let w_scale = (t.width as f64) / (self.scan.image.width() as f64);
+        let h_scale = (t.height as f64) / (self.scan.image().height() as f64);
+        let w_scale = (t.width as f64) / (self.scan.image().width() as f64);
 
         let scale = (h_scale + w_scale) / 2.0;
 
@@ -9,8 +9,12 @@ use typst::text::{Font, FontBook};
 use typst::utils::LazyHash;
 use typst::Library;
 
+use crate::error::Error;
 use crate::point::Point;
-use crate::template::{Box, Question, Template};
+use crate::template::{
+    name_symbol_value, option_letter_value, Box, NumericQuestion, Question, Template, NUMERIC_DOT,
+    NUMERIC_MINUS,
+};
 
 // hardcoding these for easy wasm support
 pub const BIOLINUM: &[u8] = include_bytes!("../assets/linux-biolinum.regular.ttf");
@@ -18,9 +22,30 @@ pub const BIOLINUM_BOLD: &[u8] = include_bytes!("../assets/linux-biolinum.bold.t
 
 #[derive(Debug)]
 enum BubbleType {
-    Mcq { question: i32, option: String },
-    Version { option: String },
-    ID { question: i32, digit: i32 },
+    Mcq {
+        question: i32,
+        option: u32,
+    },
+    Version {
+        option: String,
+    },
+    ID {
+        question: i32,
+        digit: i32,
+    },
+    Name {
+        column: i32,
+        symbol: String,
+    },
+    Numeric {
+        question: i32,
+        column: i32,
+        symbol: String,
+    },
+    Teacher {
+        question: i32,
+        points: i32,
+    },
 }
 
 #[derive(Debug)]
@@ -32,13 +57,87 @@ struct BubbleInfo {
     bubble_type: BubbleType,
 }
 
-pub fn typst_template(
-    title: impl std::fmt::Display,
-    num_qs: u32,
-    num_id_qs: u32,
-    num_versions: u32,
-    num_answers: u32,
-) -> String {
+/// Everything that determines the layout of a generated form.
+#[derive(Debug, Clone)]
+pub struct FormSettings {
+    pub title: String,
+    pub num_qs: u32,
+    pub num_id_qs: u32,
+    pub num_versions: u32,
+    pub num_answers: u32,
+    /// Number of columns in the name grid, 0 for no name grid.
+    pub num_name_cols: u32,
+    /// Whether the name grid also offers the digits 0-9.
+    pub name_digits: bool,
+    pub num_numeric_qs: u32,
+    /// Number of columns in each numeric question.
+    pub numeric_cols: u32,
+    /// Number of fields in the examiner area, 0 for no examiner area.
+    pub num_teacher_qs: u32,
+    /// Highest number of points an examiner can give in each field.
+    pub teacher_max_points: u32,
+    /// Option labels for each question; questions without an entry (or with
+    /// an empty one) get `num_answers` options labelled A, B, C, ...
+    pub option_labels: Vec<Vec<String>>,
+}
+
+impl Default for FormSettings {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            num_qs: 20,
+            num_id_qs: 9,
+            num_versions: 4,
+            num_answers: 5,
+            num_name_cols: 0,
+            name_digits: false,
+            num_numeric_qs: 0,
+            numeric_cols: 4,
+            num_teacher_qs: 0,
+            teacher_max_points: 10,
+            option_labels: Vec::new(),
+        }
+    }
+}
+
+fn invalid_number(entry: &str) -> Error {
+    Error::Settings(format!("invalid question number in '{}'", entry))
+}
+
+/// Parses option labels like `T,F` (for all questions) or
+/// `1-10=T,F; 11=Yes,No,Maybe` into one list of labels per question, where
+/// questions without labels get an empty list.
+pub fn parse_option_labels(spec: &str, num_qs: u32) -> Result<Vec<Vec<String>>, Error> {
+    let mut result = vec![Vec::new(); num_qs as usize];
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (range, labels) = match entry.split_once('=') {
+            Some((range, labels)) => {
+                let range = range.trim();
+                let (first, last) = range.split_once('-').unwrap_or((range, range));
+                let first: u32 = first.trim().parse().map_err(|_| invalid_number(entry))?;
+                let last: u32 = last.trim().parse().map_err(|_| invalid_number(entry))?;
+                if first == 0 || first > last || last > num_qs {
+                    return Err(Error::Settings(format!(
+                        "questions {} are out of range",
+                        range
+                    )));
+                }
+                (first..=last, labels)
+            }
+            None => (1..=num_qs, entry),
+        };
+        let labels: Vec<String> = labels.split(',').map(|l| l.trim().to_string()).collect();
+        if labels.iter().any(|l| l.is_empty()) {
+            return Err(Error::Settings(format!("empty label in '{}'", entry)));
+        }
+        for q in range {
+            result[q as usize - 1] = labels.clone();
+        }
+    }
+    Ok(result)
+}
+
+pub fn typst_template(settings: &FormSettings) -> String {
     let tmpl = include_str!("../assets/formtemplate.typ");
     format!(
         r#"
@@ -47,27 +146,68 @@ pub fn typst_template(
 #let num_idqs = {}
 #let num_answers = {}
 #let num_versions = {}
+#let num_name_cols = {}
+#let name_digits = {}
+#let num_numeric_qs = {}
+#let numeric_cols = {}
+#let num_teacher_qs = {}
+#let teacher_max_points = {}
+#let option_labels = ({})
 {}
 "#,
-        title, num_qs, num_id_qs, num_answers, num_versions, tmpl
+        settings.title,
+        settings.num_qs,
+        settings.num_id_qs,
+        settings.num_answers,
+        settings.num_versions,
+        settings.num_name_cols,
+        settings.name_digits,
+        settings.num_numeric_qs,
+        settings.numeric_cols,
+        settings.num_teacher_qs,
+        settings.teacher_max_points,
+        settings
+            .option_labels
+            .iter()
+            .map(|labels| {
+                let labels: String = labels.iter().map(|l| typst_string(l) + ",").collect();
+                format!("({}),", labels)
+            })
+            .collect::<String>(),
+        tmpl
     )
 }
 
+// a string literal in typst code
+pub(crate) fn typst_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 pub fn generate_form_and_template(
-    title: impl std::fmt::Display,
-    num_qs: u32,
-    num_id_qs: u32,
-    num_versions: u32,
-    num_answers: u32,
+    settings: &FormSettings,
     scale: f64,
-) -> (typst::model::Document, Template) {
-    let code = typst_template(title, num_qs, num_id_qs, num_versions, num_answers);
+) -> Result<(typst::model::Document, Template), Error> {
+    let code = typst_template(settings);
     let wrapper = TypstWrapper::new(code);
-    let document = typst::compile(&wrapper).output.unwrap();
+    let document = typst::compile(&wrapper)
+        .output
+        .map_err(|errors| Error::Output(format!("could not typeset form: {:?}", errors)))?;
     let frame = &document.pages[0].frame;
-    let template = typst_frame_to_template(frame, scale);
+    let mut template = typst_frame_to_template(frame, scale);
+    if settings
+        .option_labels
+        .iter()
+        .any(|labels| !labels.is_empty())
+    {
+        template.option_labels = settings.option_labels.clone();
+    }
+
+    Ok((document, template))
+}
 
-    (document, template)
+pub fn document_to_pdf(document: &typst::model::Document) -> Result<Vec<u8>, Error> {
+    typst_pdf::pdf(document, &typst_pdf::PdfOptions::default())
+        .map_err(|errors| Error::Output(format!("could not write PDF: {:?}", errors)))
 }
 
 fn extract_bubbles(frame: &Frame) -> Vec<BubbleInfo> {
@@ -150,20 +290,49 @@ fn parse_bubble_type(id: &str) -> Option<BubbleType> {
     let parts: Vec<&str> = id.split('-').collect();
     match *(parts.first()?) {
         "mcq" => Some(BubbleType::Mcq {
-            question: parts[1].parse().ok()?,
-            option: parts[2].to_string(),
+            question: parts.get(1)?.parse().ok()?,
+            option: parts.get(2)?.parse().ok()?,
         }),
         "version" => Some(BubbleType::Version {
-            option: parts[1].to_string(),
+            option: parts.get(1)?.to_string(),
         }),
         "id" => Some(BubbleType::ID {
-            question: parts[1].parse().ok()?,
-            digit: parts[2].parse().ok()?,
+            question: parts.get(1)?.parse().ok()?,
+            digit: parts.get(2)?.parse().ok()?,
+        }),
+        "name" => Some(BubbleType::Name {
+            column: parts.get(1)?.parse().ok()?,
+            symbol: parts.get(2)?.to_string(),
+        }),
+        "num" => Some(BubbleType::Numeric {
+            question: parts.get(1)?.parse().ok()?,
+            column: parts.get(2)?.parse().ok()?,
+            symbol: parts.get(3)?.to_string(),
+        }),
+        "teacher" => Some(BubbleType::Teacher {
+            question: parts.get(1)?.parse().ok()?,
+            points: parts.get(2)?.parse().ok()?,
         }),
         _ => None,
     }
 }
 
+// the value of the box a bubble stands for, if its symbol is one we know
+fn bubble_value(bubble_type: &BubbleType) -> Option<u32> {
+    match bubble_type {
+        BubbleType::Mcq { option, .. } => Some(*option),
+        BubbleType::Version { option } => option_letter_value(option),
+        BubbleType::ID { digit, .. } => u32::try_from(*digit).ok(),
+        BubbleType::Name { symbol, .. } => name_symbol_value(symbol.chars().next()?),
+        BubbleType::Numeric { symbol, .. } => match symbol.as_str() {
+            "dot" => Some(NUMERIC_DOT),
+            "minus" => Some(NUMERIC_MINUS),
+            digit => digit.parse().ok(),
+        },
+        BubbleType::Teacher { points, .. } => u32::try_from(*points).ok(),
+    }
+}
+
 pub fn typst_frame_to_template(frame: &typst::layout::Frame, scale: f64) -> Template {
     use std::collections::HashMap;
     let page_width = 595;
@@ -198,9 +367,15 @@ pub fn typst_frame_to_template(frame: &typst::layout::Frame, scale: f64) -> Temp
     }
     let mut mcq_questions: HashMap<i32, Question> = HashMap::new();
     let mut id_questions: HashMap<i32, Question> = HashMap::new();
+    let mut name_questions: HashMap<i32, Question> = HashMap::new();
+    let mut numeric_questions: HashMap<i32, HashMap<i32, Question>> = HashMap::new();
+    let mut teacher_questions: HashMap<i32, Question> = HashMap::new();
     let mut version = Question { boxes: Vec::new() };
 
     for bubble in bubbles {
+        let Some(value) = bubble_value(&bubble.bubble_type) else {
+            continue;
+        };
         let box_data = Box {
             a: Point {
                 x: (bubble.top_left_x * scale).round() as u32,
@@ -210,15 +385,7 @@ pub fn typst_frame_to_template(frame: &typst::layout::Frame, scale: f64) -> Temp
                 x: (bubble.bottom_right_x * scale).round() as u32,
                 y: (bubble.bottom_right_y * scale).round() as u32,
             },
-            value: match &bubble.bubble_type {
-                BubbleType::Mcq { option, .. } => {
-                    option.chars().next().unwrap() as u32 - 'A' as u32
-                }
-                BubbleType::Version { option } => {
-                    option.chars().next().unwrap() as u32 - 'A' as u32
-                }
-                BubbleType::ID { digit, .. } => *digit as u32,
-            },
+            value,
         };
 
         match bubble.bubble_type {
@@ -239,6 +406,31 @@ pub fn typst_frame_to_template(frame: &typst::layout::Frame, scale: f64) -> Temp
                     .boxes
                     .push(box_data);
             }
+            BubbleType::Name { column, .. } => {
+                name_questions
+                    .entry(column)
+                    .or_insert_with(|| Question { boxes: Vec::new() })
+                    .boxes
+                    .push(box_data);
+            }
+            BubbleType::Numeric {
+                question, column, ..
+            } => {
+                numeric_questions
+                    .entry(question)
+                    .or_default()
+                    .entry(column)
+                    .or_insert_with(|| Question { boxes: Vec::new() })
+                    .boxes
+                    .push(box_data);
+            }
+            BubbleType::Teacher { question, .. } => {
+                teacher_questions
+                    .entry(question)
+                    .or_insert_with(|| Question { boxes: Vec::new() })
+                    .boxes
+                    .push(box_data);
+            }
         }
     }
 
@@ -248,6 +440,25 @@ pub fn typst_frame_to_template(frame: &typst::layout::Frame, scale: f64) -> Temp
     let mut id_questions: Vec<(i32, Question)> = id_questions.into_iter().collect();
     id_questions.sort_by_key(|(key, _)| *key);
     let id_questions: Vec<Question> = id_questions.into_iter().map(|(_, q)| q).collect();
+    let mut name_questions: Vec<(i32, Question)> = name_questions.into_iter().collect();
+    name_questions.sort_by_key(|(key, _)| *key);
+    let name_questions: Vec<Question> = name_questions.into_iter().map(|(_, q)| q).collect();
+    let mut numeric_questions: Vec<(i32, HashMap<i32, Question>)> =
+        numeric_questions.into_iter().collect();
+    numeric_questions.sort_by_key(|(key, _)| *key);
+    let numeric_questions: Vec<NumericQuestion> = numeric_questions
+        .into_iter()
+        .map(|(_, columns)| {
+            let mut columns: Vec<(i32, Question)> = columns.into_iter().collect();
+            columns.sort_by_key(|(key, _)| *key);
+            NumericQuestion {
+                columns: columns.into_iter().map(|(_, q)| q).collect(),
+            }
+        })
+        .collect();
+    let mut teacher_questions: Vec<(i32, Question)> = teacher_questions.into_iter().collect();
+    teacher_questions.sort_by_key(|(key, _)| *key);
+    let teacher_questions: Vec<Question> = teacher_questions.into_iter().map(|(_, q)| q).collect();
 
     let actual_version = if version.boxes.len() == 0 {
         None
@@ -257,12 +468,17 @@ pub fn typst_frame_to_template(frame: &typst::layout::Frame, scale: f64) -> Temp
 
     Template {
         id_questions,
+        name_questions,
         version: actual_version,
         questions: mcq_questions,
+        numeric_questions,
+        teacher_questions,
+        option_labels: Vec::new(),
         circle_centers,
         circle_radius,
         height: (page_height as f64 * scale).round() as u32,
         width: (page_width as f64 * scale).round() as u32,
+        id_check_digit: None,
     }
 }
 
@@ -271,6 +487,8 @@ pub struct TypstWrapper {
     book: LazyHash<FontBook>,
     fonts: Vec<Font>,
     document: String,
+    // files that the document can read, like images
+    files: std::collections::HashMap<String, Bytes>,
 }
 
 impl TypstWrapper {
@@ -291,8 +509,14 @@ impl TypstWrapper {
             book: LazyHash::new(FontBook::from_fonts(&fonts)),
             fonts,
             document: doc,
+            files: std::collections::HashMap::new(),
         }
     }
+
+    /// Makes `data` available to the document under the (relative) `path`.
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        self.files.insert(path.to_string(), Bytes::from(data));
+    }
 }
 
 impl typst::World for TypstWrapper {
@@ -312,10 +536,12 @@ impl typst::World for TypstWrapper {
         Ok(Source::detached(self.document.clone()))
     }
 
-    fn file(&self, _id: FileId) -> FileResult<Bytes> {
-        Err(FileError::Other(Some(
-            "This should never have been called".into(),
-        )))
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        let path = id.vpath().as_rootless_path();
+        path.to_str()
+            .and_then(|p| self.files.get(p))
+            .cloned()
+            .ok_or_else(|| FileError::NotFound(path.to_path_buf()))
     }
 
     fn font(&self, id: usize) -> Option<Font> {
@@ -0,0 +1,119 @@
+use crate::error::Error;
+use js_sys::{Array, Uint8Array};
+use std::io::{Read, Seek, SeekFrom, Write};
+use web_sys::{Blob, BlobPropertyBag};
+
+// the size of the pieces handed over to the browser
+const CHUNK: usize = 8 << 20;
+
+/// A file that the browser keeps for us: every 8 MB written are moved out of
+/// the memory of the app, which cannot grow beyond 4 GB, into a JavaScript
+/// array. It can still be read, sought and overwritten anywhere, as a
+/// `ZipWriter` needs.
+#[derive(Default)]
+pub struct BlobBuffer {
+    chunks: Vec<Uint8Array>,
+    // the last chunk, not yet full
+    tail: Vec<u8>,
+    position: u64,
+}
+
+impl BlobBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn len(&self) -> u64 {
+        (self.chunks.len() * CHUNK + self.tail.len()) as u64
+    }
+
+    // the chunk and the offset in it of the current position
+    fn locate(&self) -> (usize, usize) {
+        (
+            (self.position / CHUNK as u64) as usize,
+            (self.position % CHUNK as u64) as usize,
+        )
+    }
+
+    /// The content, e.g. to download it.
+    pub fn into_blob(self, mime_type: &str) -> Result<Blob, Error> {
+        let parts = Array::new();
+        for chunk in &self.chunks {
+            parts.push(chunk);
+        }
+        parts.push(&Uint8Array::from(self.tail.as_slice()));
+        let options = BlobPropertyBag::new();
+        options.set_type(mime_type);
+        Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+            .map_err(|e| Error::Output(format!("{:?}", e)))
+    }
+}
+
+impl Write for BlobBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let (index, offset) = self.locate();
+        let n = buf.len().min(CHUNK - offset);
+        if index < self.chunks.len() {
+            self.chunks[index]
+                .subarray(offset as u32, (offset + n) as u32)
+                .copy_from(&buf[..n]);
+        } else {
+            // overwrite what is there and append the rest
+            let end = (offset + n).min(self.tail.len());
+            self.tail[offset..end].copy_from_slice(&buf[..end - offset]);
+            self.tail.extend_from_slice(&buf[end - offset..n]);
+            if self.tail.len() == CHUNK {
+                self.chunks.push(Uint8Array::from(self.tail.as_slice()));
+                self.tail.clear();
+            }
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for BlobBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.len() {
+            return Ok(0);
+        }
+        let (index, offset) = self.locate();
+        let n = if index < self.chunks.len() {
+            let n = buf.len().min(CHUNK - offset);
+            self.chunks[index]
+                .subarray(offset as u32, (offset + n) as u32)
+                .copy_to(&mut buf[..n]);
+            n
+        } else {
+            let n = buf.len().min(self.tail.len() - offset);
+            buf[..n].copy_from_slice(&self.tail[offset..offset + n]);
+            n
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for BlobBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        match target {
+            Some(position) if position <= self.len() => {
+                self.position = position;
+                Ok(position)
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek outside of the buffer",
+            )),
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use crate::image_helpers::binary_image_from_image;
 use crate::scan::Scan;
+use crate::student_id::CheckDigit;
 use crate::template::Template;
 use crate::template_scan::TemplateScan;
-use crate::typst_helpers::generate_form_and_template;
+use crate::typst_helpers::{
+    document_to_pdf, generate_form_and_template, parse_option_labels, FormSettings,
+};
 use crate::webapp::utils::{download_button, QuestionSettings};
 use crate::webapp::webapp::StateView;
 use eframe::egui::{Context, ScrollArea};
@@ -15,6 +18,15 @@ pub struct CreateForm {
     pub template: Option<Template>,
     pub png: Option<Vec<u8>>,
     pub title: String,
+    pub id_check_digit: Option<CheckDigit>,
+    pub num_name_cols: u32,
+    pub name_digits: bool,
+    pub num_numeric_qs: u32,
+    pub numeric_cols: u32,
+    pub num_teacher_qs: u32,
+    pub teacher_max_points: u32,
+    pub option_labels: String,
+    pub error: Option<String>,
 }
 
 impl Default for CreateForm {
@@ -26,6 +38,15 @@ impl Default for CreateForm {
             template: None,
             png: None,
             title: String::new(),
+            id_check_digit: None,
+            num_name_cols: 0,
+            name_digits: false,
+            num_numeric_qs: 0,
+            numeric_cols: 4,
+            num_teacher_qs: 0,
+            teacher_max_points: 10,
+            option_labels: String::new(),
+            error: None,
         }
     }
 }
@@ -66,63 +87,143 @@ impl StateView for CreateForm {
                         .text("Answers per Question"),
                 );
 
-                ui.add_space(20.0);
+                ui.add(
+                    egui::Slider::new(&mut self.num_name_cols, 0..=18).text("Name Grid Columns"),
+                );
+                if self.num_name_cols > 0 {
+                    ui.checkbox(&mut self.name_digits, "Allow digits in names");
+                }
 
-                if ui.button("Generate").clicked() {
-                    let scale = 3.0;
-                    let (document, template) = generate_form_and_template(
-                        self.title.clone(),
-                        self.question_settings.num_qs,
-                        self.question_settings.num_id_qs,
-                        self.question_settings.num_versions,
-                        self.question_settings.num_answers,
-                        scale,
+                ui.add(
+                    egui::Slider::new(&mut self.num_numeric_qs, 0..=10).text("Numeric Questions"),
+                );
+                if self.num_numeric_qs > 0 {
+                    ui.add(
+                        egui::Slider::new(&mut self.numeric_cols, 2..=8)
+                            .text("Columns per Numeric Question"),
                     );
+                }
 
-                    self.template = Some(template.clone());
-                    let pdf =
-                        typst_pdf::pdf(&document, &typst_pdf::PdfOptions::default()).expect("bla");
+                ui.add(
+                    egui::Slider::new(&mut self.num_teacher_qs, 0..=5)
+                        .text("Fields Scored by the Examiner"),
+                );
+                if self.num_teacher_qs > 0 {
+                    ui.add(
+                        egui::Slider::new(&mut self.teacher_max_points, 1..=15)
+                            .text("Maximal Points per Field"),
+                    );
+                }
 
-                    self.pdf = Some(pdf.clone());
+                egui::ComboBox::from_label("ID check digit")
+                    .selected_text(match self.id_check_digit {
+                        None => "None",
+                        Some(CheckDigit::Luhn) => "Luhn",
+                        Some(CheckDigit::Mod11) => "Mod 11",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.id_check_digit, None, "None");
+                        ui.selectable_value(
+                            &mut self.id_check_digit,
+                            Some(CheckDigit::Luhn),
+                            "Luhn",
+                        );
+                        ui.selectable_value(
+                            &mut self.id_check_digit,
+                            Some(CheckDigit::Mod11),
+                            "Mod 11",
+                        );
+                    });
 
-                    let png = typst_render::render(&document.pages[0], scale as f32)
-                        .encode_png()
-                        .unwrap();
+                ui.horizontal(|ui| {
+                    ui.add(egui::Label::new("Option labels:"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.option_labels)
+                            .hint_text("e.g. 1-10=T,F; 11=Yes,No"),
+                    );
+                });
+                let option_labels =
+                    parse_option_labels(&self.option_labels, self.question_settings.num_qs);
+                if let Err(e) = &option_labels {
+                    ui.colored_label(egui::Color32::RED, e.to_string());
+                }
 
-                    self.png = Some(png.clone());
+                ui.add_space(20.0);
 
-                    let dynimage = image::load_from_memory(&png).unwrap();
-                    let scan = Scan {
-                        image: binary_image_from_image(dynimage),
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if ui
+                    .add_enabled(option_labels.is_ok(), egui::Button::new("Generate"))
+                    .clicked()
+                {
+                    let scale = 3.0;
+                    let settings = FormSettings {
+                        title: self.title.clone(),
+                        num_qs: self.question_settings.num_qs,
+                        num_id_qs: self.question_settings.num_id_qs,
+                        num_versions: self.question_settings.num_versions,
+                        num_answers: self.question_settings.num_answers,
+                        num_name_cols: self.num_name_cols,
+                        name_digits: self.name_digits,
+                        num_numeric_qs: self.num_numeric_qs,
+                        numeric_cols: self.numeric_cols,
+                        num_teacher_qs: self.num_teacher_qs,
+                        teacher_max_points: self.teacher_max_points,
+                        option_labels: option_labels.unwrap_or_default(),
                     };
+                    let form = generate_form_and_template(&settings, scale).and_then(
+                        |(document, template)| {
+                            Ok((document_to_pdf(&document)?, document, template))
+                        },
+                    );
+                    match form {
+                        Err(e) => self.error = Some(e.to_string()),
+                        Ok((pdf, document, mut template)) => {
+                            self.error = None;
+                            template.id_check_digit = self.id_check_digit;
 
-                    let template_scan = TemplateScan {
-                        template: &template,
-                        scan,
-                        transformation: None,
-                    };
+                            self.template = Some(template.clone());
+                            self.pdf = Some(pdf.clone());
 
-                    let circled = template_scan.circle_everything();
-                    let dynamic_image = image::DynamicImage::ImageRgb8(circled);
+                            let png = typst_render::render(&document.pages[0], scale as f32)
+                                .encode_png()
+                                .unwrap();
 
-                    let size = [dynamic_image.width() as _, dynamic_image.height() as _];
-                    let image_buffer = dynamic_image.to_rgba8();
-                    let pixels = image_buffer.as_flat_samples();
+                            self.png = Some(png.clone());
 
-                    let texture = ctx.load_texture(
-                        "preview_image",
-                        egui::ColorImage::from_rgba_unmultiplied(size, pixels.as_slice()),
-                        egui::TextureOptions::default(),
-                    );
-                    self.preview = Some(texture);
+                            let dynimage = image::load_from_memory(&png).unwrap();
+                            let scan = Scan::new(binary_image_from_image(dynimage));
 
-                    download_button(
-                        ui,
-                        "💾 Save Template as json",
-                        "template.json",
-                        serde_json::to_vec(&template).unwrap(),
-                    );
-                    download_button(ui, "💾 Save form as PNG", "form.png", png);
+                            let template_scan = TemplateScan {
+                                template: &template,
+                                scan,
+                                transformation: None,
+                            };
+
+                            let circled = template_scan.circle_everything();
+                            let dynamic_image = image::DynamicImage::ImageRgb8(circled);
+
+                            let size = [dynamic_image.width() as _, dynamic_image.height() as _];
+                            let image_buffer = dynamic_image.to_rgba8();
+                            let pixels = image_buffer.as_flat_samples();
+
+                            let texture = ctx.load_texture(
+                                "preview_image",
+                                egui::ColorImage::from_rgba_unmultiplied(size, pixels.as_slice()),
+                                egui::TextureOptions::default(),
+                            );
+                            self.preview = Some(texture);
+
+                            download_button(
+                                ui,
+                                "💾 Save Template as json",
+                                "template.json",
+                                serde_json::to_vec(&template).unwrap(),
+                            );
+                            download_button(ui, "💾 Save form as PNG", "form.png", png);
+                        }
+                    }
                 }
 
                 if let Some(template) = &self.template {
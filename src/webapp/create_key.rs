@@ -1,4 +1,4 @@
-use crate::template::{CorrectAnswer, ExamKey};
+use crate::template::{key_to_string, parse_key, ExamKey, Template};
 use crate::webapp::utils::download_button;
 use crate::webapp::webapp::StateView;
 use eframe::egui::{Context, Slider, TextEdit};
@@ -8,6 +8,8 @@ pub struct CreateKey {
     number_of_versions: usize,
     inputs: Vec<String>,
     key: ExamKey,
+    // option labels of the current template, if any
+    labels: Vec<Vec<String>>,
 }
 
 impl Default for CreateKey {
@@ -16,56 +18,11 @@ impl Default for CreateKey {
             number_of_versions: 4,
             inputs: vec![String::new(); 4], // Start with 4 empty strings
             key: Vec::new(),
+            labels: Vec::new(),
         }
     }
 }
 
-fn convert_to_vector(input: &str) -> Vec<CorrectAnswer> {
-    let mut result = Vec::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        let c = c.to_ascii_uppercase();
-        if c == '(' {
-            // Collect multiple options within parentheses
-            let mut options = Vec::new();
-            while let Some(&next) = chars.peek() {
-                let next = next.to_ascii_uppercase();
-                if next == ')' {
-                    chars.next(); // consume the closing parenthesis
-                    break;
-                }
-                if ('A'..='Z').contains(&next) {
-                    options.push((next as u32 - 'A' as u32) as u32);
-                }
-                chars.next();
-            }
-            result.push(CorrectAnswer::OneOf(options));
-        } else if ('A'..='Z').contains(&c) {
-            // Single letter is Exactly
-            result.push(CorrectAnswer::Exactly(c as u32 - 'A' as u32));
-        }
-    }
-
-    result
-}
-
-fn convert_to_string(input: &Vec<CorrectAnswer>) -> String {
-    input
-        .iter()
-        .map(|key| match key {
-            CorrectAnswer::Exactly(n) => char::from(b'A' + (*n as u8)).to_string(),
-            CorrectAnswer::OneOf(options) => {
-                let option_chars: String = options
-                    .iter()
-                    .map(|&n| char::from(b'A' + (n as u8)))
-                    .collect();
-                format!("({})", option_chars)
-            }
-        })
-        .collect()
-}
-
 impl StateView for CreateKey {
     fn get_key(&self) -> Option<&ExamKey> {
         if self.key.len() > 0 {
@@ -78,12 +35,26 @@ impl StateView for CreateKey {
     fn set_key(&mut self, key: Option<ExamKey>) {
         if let Some(vec) = key {
             self.number_of_versions = vec.len();
-            self.inputs = vec.iter().map(&convert_to_string).collect();
+            self.inputs = vec
+                .iter()
+                .map(|answers| key_to_string(answers, &self.labels))
+                .collect();
             self.key = vec;
         } else {
             self.key = vec![];
         }
     }
+
+    fn set_template(&mut self, template: Option<Template>) {
+        if let Some(template) = template {
+            self.labels = template.all_option_labels();
+            self.inputs = self
+                .key
+                .iter()
+                .map(|answers| key_to_string(answers, &self.labels))
+                .collect();
+        }
+    }
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         eframe::egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Create Key");
@@ -104,17 +75,19 @@ impl StateView for CreateKey {
                         TextEdit::singleline(&mut self.inputs[i])
                             .hint_text("Enter sequence like ABCDE"),
                     );
-                    let answer_count = convert_to_vector(&self.inputs[i]).len();
+                    let answer_count = parse_key(&self.inputs[i], &self.labels).len();
                     ui.label(format!("({} answers)", answer_count));
                 });
             }
             self.key = self
                 .inputs
                 .iter()
-                .map(|input| convert_to_vector(input))
+                .map(|input| parse_key(input, &self.labels))
                 .collect();
 
             ui.label("If you enter a string like AB(CD)E, it means that in the third question both C and D would be graded as correct, whereas for the other questions only a single choice is counted as correct.");
+            ui.label("Answers to numeric questions come after all MCQ answers and are written in brackets: [2.5] for an exact value, [3.14+-0.01] for a value with tolerance and [1..2] for a range, so ABCDE[2.5][1..2] is a key for five MCQs and two numeric questions.");
+            ui.label("If the template uses other option labels (like T/F or Yes/No), use these labels instead of letters. Labels with more than one character are written in braces, for example T{Yes}(12).");
 
             download_button(
                 ui,
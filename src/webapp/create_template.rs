@@ -190,11 +190,9 @@ impl StateView for CreateTemplate {
                                 .button("🎯 Find precise circle center & radius")
                                 .clicked()
                             {
-                                let scan = Scan {
-                                    image: binary_image_from_image(
-                                        self.original_image.clone().unwrap(),
-                                    ),
-                                };
+                                let scan = Scan::new(binary_image_from_image(
+                                    self.original_image.clone().unwrap(),
+                                ));
 
                                 if let Some(circle_centers_with_radius) = scan
                                     .real_centers_with_radius(
@@ -211,11 +209,9 @@ impl StateView for CreateTemplate {
                     if self.original_image.is_some() {
                         if ui.button("📄 Preview!").clicked() {
                             let template = self.to_template();
-                            let scan = Scan {
-                                image: binary_image_from_image(
-                                    self.original_image.clone().unwrap(),
-                                ),
-                            };
+                            let scan = Scan::new(binary_image_from_image(
+                                self.original_image.clone().unwrap(),
+                            ));
 
                             let template_scan = TemplateScan {
                                 template: &template,
@@ -1,10 +1,22 @@
-use crate::image_container::{ImageContainer, PdfContainer, SingleImageContainer, TiffContainer};
+use crate::colours::ColourScheme;
+use crate::error::Error;
+use crate::feedback::FeedbackMode;
+use crate::grading::{GradingMode, GradingScale};
+use crate::image_container::{
+    ImageContainer, PdfContainer, SingleImageContainer, TiffContainer, ZipContainer,
+};
 use crate::image_helpers::rgb_to_egui_color_image;
-use crate::report::ImageReport;
+use crate::naming::NamingScheme;
+use crate::pipeline::{CancellationToken, GradingRun, Progress};
+use crate::report::{ImageReport, ReportSettings};
+use crate::report_pdf::{PageOrder, PdfSettings};
 use crate::scan::Scan;
+use crate::sink::{ImageSink, PdfSink, PngSink, RecordSink, ZipSink};
+use crate::survey::{SurveyResponse, SurveyTally};
 use crate::template::{are_compatible, ExamKey, Template};
-use crate::template_scan::TemplateScan;
-use crate::webapp::utils::{download_button, upload_button, FileType};
+use crate::template_scan::{TemplateScan, VersionFallback};
+use crate::webapp::blob_buffer::BlobBuffer;
+use crate::webapp::utils::{download_blob_button, upload_button, FileType};
 use crate::webapp::webapp::StateView;
 use egui::Context;
 use infer;
@@ -12,10 +24,8 @@ use itertools::Itertools;
 use serde_json;
 use std::cell::RefCell;
 use std::io::Cursor;
-use std::io::Write;
 use std::rc::Rc;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use zip::write::FileOptions;
 use zip::ZipWriter;
 
 use wasm_bindgen_futures::spawn_local;
@@ -23,8 +33,10 @@ use wasm_bindgen_futures::spawn_local;
 pub struct GenerateReport {
     pub template: Option<Template>,
     pub key: Option<ExamKey>,
-    raw_container_data: Option<Vec<u8>>,
-    zipped_results: Rc<RefCell<Option<Vec<u8>>>>,
+    // shared with the tasks that grade, so the upload is not copied
+    raw_container_data: Option<Rc<Vec<u8>>>,
+    // kept by the browser, however large it gets
+    zipped_results: Rc<RefCell<Option<web_sys::Blob>>>,
     data_channel: (
         Sender<(FileType, String, Vec<u8>)>,
         Receiver<(FileType, String, Vec<u8>)>,
@@ -32,7 +44,18 @@ pub struct GenerateReport {
     preview_image: Rc<RefCell<Option<image::RgbImage>>>,
     preview_texture: Option<egui::TextureHandle>,
     status: Rc<RefCell<Option<String>>>,
+    progress: Rc<RefCell<Option<Progress>>>,
+    cancel: CancellationToken,
     output_file_basename: Option<String>,
+    survey_with_ids: bool,
+    grading_mode: GradingMode,
+    grading_spec: String,
+    feedback_mode: Option<FeedbackMode>,
+    combined_pdf: Option<PdfSettings>,
+    naming_pattern: String,
+    colours: ColourScheme,
+    banner: bool,
+    version_fallback: VersionFallback,
 }
 
 impl Clone for GenerateReport {
@@ -46,7 +69,18 @@ impl Clone for GenerateReport {
             preview_image: self.preview_image.clone(),
             preview_texture: self.preview_texture.clone(),
             status: self.status.clone(),
+            progress: self.progress.clone(),
+            cancel: self.cancel.clone(),
             output_file_basename: self.output_file_basename.clone(),
+            survey_with_ids: self.survey_with_ids,
+            grading_mode: self.grading_mode,
+            grading_spec: self.grading_spec.clone(),
+            feedback_mode: self.feedback_mode,
+            combined_pdf: self.combined_pdf,
+            naming_pattern: self.naming_pattern.clone(),
+            colours: self.colours,
+            banner: self.banner,
+            version_fallback: self.version_fallback,
         }
     }
 }
@@ -62,17 +96,28 @@ impl Default for GenerateReport {
             preview_image: Rc::new(RefCell::new(None)),
             preview_texture: None,
             status: Rc::new(RefCell::new(None)),
+            progress: Rc::new(RefCell::new(None)),
+            cancel: CancellationToken::default(),
             output_file_basename: None,
+            survey_with_ids: false,
+            grading_mode: GradingMode::Percent,
+            grading_spec: String::new(),
+            feedback_mode: None,
+            combined_pdf: None,
+            naming_pattern: NamingScheme::DEFAULT_PATTERN.to_string(),
+            colours: ColourScheme::default(),
+            banner: false,
+            version_fallback: VersionFallback::default(),
         }
     }
 }
 
-fn raw_data_to_container(data: &Vec<u8>) -> Option<Box<dyn ImageContainer + '_>> {
-    let clonedata = data.clone();
-    if let Some(kind) = infer::get(&clonedata) {
+// all containers read the data in place
+fn raw_data_to_container(data: &[u8]) -> Option<Box<dyn ImageContainer + '_>> {
+    if let Some(kind) = infer::get(data) {
         match kind.mime_type() {
             "application/pdf" => {
-                let pdf = pdf::file::FileOptions::cached().load(clonedata);
+                let pdf = pdf::file::FileOptions::cached().load(data);
                 if let Ok(pdf) = pdf {
                     let container = PdfContainer { pdf_file: pdf };
                     return Some(Box::new(container));
@@ -82,25 +127,40 @@ fn raw_data_to_container(data: &Vec<u8>) -> Option<Box<dyn ImageContainer + '_>>
                 }
             }
             "image/tiff" => {
-                let buffer = std::io::Cursor::new(clonedata);
-                let tiff = tiff::decoder::Decoder::new(buffer).unwrap();
-                let container = TiffContainer { decoder: tiff };
-                return Some(Box::new(container));
+                let buffer = std::io::Cursor::new(data);
+                match TiffContainer::new(buffer) {
+                    Ok(tiff) => return Some(Box::new(tiff)),
+                    Err(e) => {
+                        log::error!("Invalid TIFF file: {}", e);
+                        return None;
+                    }
+                }
             }
             "image/png" => {
-                let container = SingleImageContainer::from_data_with_format(
-                    &clonedata,
-                    image::ImageFormat::Png,
-                );
-                return Some(Box::new(container));
+                match SingleImageContainer::from_data_with_format(data, image::ImageFormat::Png) {
+                    Ok(container) => return Some(Box::new(container)),
+                    Err(e) => {
+                        log::error!("Invalid PNG file: {}", e);
+                        return None;
+                    }
+                }
             }
             "image/jpeg" => {
-                let container = SingleImageContainer::from_data_with_format(
-                    &clonedata,
-                    image::ImageFormat::Jpeg,
-                );
-                return Some(Box::new(container));
+                match SingleImageContainer::from_data_with_format(data, image::ImageFormat::Jpeg) {
+                    Ok(container) => return Some(Box::new(container)),
+                    Err(e) => {
+                        log::error!("Invalid JPEG file: {}", e);
+                        return None;
+                    }
+                }
             }
+            "application/zip" => match ZipContainer::from_reader(Cursor::new(data)) {
+                Ok(container) => return Some(Box::new(container)),
+                Err(e) => {
+                    log::error!("Invalid ZIP file: {}", e);
+                    return None;
+                }
+            },
             _ => log::error!(
                 "{}",
                 format!("Unsupported container format {}", kind.mime_type())
@@ -111,6 +171,19 @@ fn raw_data_to_container(data: &Vec<u8>) -> Option<Box<dyn ImageContainer + '_>>
 }
 
 impl GenerateReport {
+    // no grading scale is fine, an invalid one is not
+    fn grading_scale(&self) -> Result<Option<GradingScale>, Error> {
+        if self.grading_spec.trim().is_empty() {
+            Ok(None)
+        } else {
+            GradingScale::parse(self.grading_mode, &self.grading_spec).map(Some)
+        }
+    }
+
+    fn naming(&self) -> Result<NamingScheme, Error> {
+        NamingScheme::parse(&self.naming_pattern)
+    }
+
     fn simplified_update_view(&mut self, ctx: &Context, width: f64, height: f64) {
         egui::CentralPanel::default().show(ctx, |ui| {
             upload_button(
@@ -126,26 +199,50 @@ impl GenerateReport {
                 }
                 if self.preview_texture.is_none() {
                     if let Some(mut container) =
-                        raw_data_to_container(&self.raw_container_data.clone().unwrap())
+                        raw_data_to_container(self.raw_container_data.as_ref().unwrap())
                     {
-                        let img = container.to_iter().next().expect("could not open image");
-                        let scan = Scan {
-                            image: img,
-                        };
-                        let template_scan = TemplateScan::new(self.template.as_ref().unwrap(), scan);
-                        let report = template_scan.generate_image_report(
-                            &self.key.clone().unwrap(),
-                            &String::new(),
-                        );
-                        *self.preview_image.borrow_mut() = Some(report.image.clone());
-                        *self.status.borrow_mut() = Some(format!(
-                            "{} points (version {}, student ID {})",
-                            report.score(),
-                            report.version.unwrap_or(0) + 1,
-                            report.sid.unwrap_or(0)
-                        ));
+                        match container.to_iter().next() {
+                            Some(Ok(img)) => {
+                                let scan = Scan::new(img);
+                                let template_scan =
+                                    TemplateScan::new(self.template.as_ref().unwrap(), scan);
+                                let report = template_scan.generate_image_report_with(
+                                    &self.key.clone().unwrap(),
+                                    &String::new(),
+                                    &self.colours,
+                                    self.version_fallback,
+                                );
+                                *self.preview_image.borrow_mut() = Some(report.image.clone());
+                                let mut status = format!(
+                                    "{} points (version {}, student ID {})",
+                                    report.score(),
+                                    report.version.unwrap_or(0) + 1,
+                                    report.sid.clone().unwrap_or_default()
+                                );
+                                if let Some(name) = &report.name {
+                                    status += &format!(", name {}", name);
+                                }
+                                *self.status.borrow_mut() = Some(status);
+                            }
+                            page => {
+                                let error = match page {
+                                    Some(Err(error)) => error,
+                                    _ => Error::Decode(
+                                        "the file does not contain any pages".to_string(),
+                                    ),
+                                };
+                                let report = ImageReport::unreadable(
+                                    self.template.as_ref().unwrap(),
+                                    "",
+                                    &error,
+                                );
+                                *self.preview_image.borrow_mut() = Some(report.image);
+                                *self.status.borrow_mut() =
+                                    Some(format!("Could not read the first page: {}", error));
+                            }
+                        }
                     }
-               }
+                }
             } else if self.status.borrow().is_none() {
                 *self.status.borrow_mut() = Some("Only use pictures that are roughly A4 with the whole visible area being covered by the bubble sheet.".into());
             }
@@ -226,6 +323,8 @@ impl GenerateReport {
                     if self.template.is_some()
                         && self.key.is_some()
                         && self.raw_container_data.is_some()
+                        && self.grading_scale().is_ok()
+                        && self.naming().is_ok()
                         && are_compatible(
                             self.template.as_ref().unwrap(),
                             self.key.as_ref().unwrap(),
@@ -236,6 +335,8 @@ impl GenerateReport {
                             self.preview_texture = None;
                             self.preview_image = Rc::new(RefCell::new(None));
                             self.zipped_results = Rc::new(RefCell::new(None));
+                            self.progress = Rc::new(RefCell::new(None));
+                            self.cancel = CancellationToken::default();
                             let mut cloned_self = self.clone();
                             let ctx = ctx.clone();
                             spawn_local(async move {
@@ -243,24 +344,194 @@ impl GenerateReport {
                             });
                         }
                     }
+                    // surveys have no key, so we only need a template
+                    if self.template.is_some() && self.raw_container_data.is_some() {
+                        if ui.button("📊 Tally survey").clicked() {
+                            log::info!("tallying survey");
+                            self.preview_texture = None;
+                            self.preview_image = Rc::new(RefCell::new(None));
+                            self.zipped_results = Rc::new(RefCell::new(None));
+                            let mut cloned_self = self.clone();
+                            let ctx = ctx.clone();
+                            spawn_local(async move {
+                                cloned_self.generate_survey(&ctx).await;
+                            });
+                        }
+                        ui.checkbox(&mut self.survey_with_ids, "Read IDs in surveys");
+                    }
                 });
 
                 columns[4].vertical(|ui| {
                     if let Some(zipped_data) = &*self.zipped_results.borrow() {
-                        download_button(
+                        download_blob_button(
                             ui,
                             "💾 Save results as zip file",
-                            self.output_file_basename.clone().unwrap() + ".zip",
-                            zipped_data.clone(),
+                            &(self.output_file_basename.clone().unwrap() + ".zip"),
+                            zipped_data,
                         );
                         self.status = Rc::new(RefCell::new(None));
                     }
+                    if let Some(progress) = *self.progress.borrow() {
+                        let total = progress.total.unwrap_or(0).max(1);
+                        ui.add(
+                            egui::ProgressBar::new(progress.done as f32 / total as f32).text(
+                                format!(
+                                    "{} sheets, {} to grade by hand",
+                                    progress.done, progress.issues
+                                ),
+                            ),
+                        );
+                        if ui.button("Cancel").clicked() {
+                            self.cancel.cancel();
+                        }
+                    }
                     if let Some(status) = &*self.status.borrow() {
                         ui.label(status);
                     }
                 });
             });
 
+            ui.horizontal(|ui| {
+                ui.label("Grading scale (optional):");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.grading_spec)
+                        .hint_text("A:90, B:80, C:70, D:60, F:0"),
+                );
+                egui::ComboBox::from_label("thresholds are")
+                    .selected_text(match self.grading_mode {
+                        GradingMode::Percent => "percentages",
+                        GradingMode::Points => "points",
+                        GradingMode::Rank => "top % of students",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut self.grading_mode,
+                            GradingMode::Percent,
+                            "percentages",
+                        );
+                        ui.selectable_value(&mut self.grading_mode, GradingMode::Points, "points");
+                        ui.selectable_value(
+                            &mut self.grading_mode,
+                            GradingMode::Rank,
+                            "top % of students",
+                        );
+                    });
+                if let Err(e) = self.grading_scale() {
+                    ui.colored_label(egui::Color32::RED, e.to_string());
+                }
+            });
+
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("feedback PDFs for students")
+                    .selected_text(match self.feedback_mode {
+                        None => "none",
+                        Some(FeedbackMode::PerStudent) => "one per student",
+                        Some(FeedbackMode::Merged) => "one merged PDF",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.feedback_mode, None, "none");
+                        ui.selectable_value(
+                            &mut self.feedback_mode,
+                            Some(FeedbackMode::PerStudent),
+                            "one per student",
+                        );
+                        ui.selectable_value(
+                            &mut self.feedback_mode,
+                            Some(FeedbackMode::Merged),
+                            "one merged PDF",
+                        );
+                    });
+            });
+
+            ui.horizontal(|ui| {
+                let mut single_pdf = self.combined_pdf.is_some();
+                ui.checkbox(
+                    &mut single_pdf,
+                    "All sheets in one PDF instead of PNG files",
+                );
+                if single_pdf != self.combined_pdf.is_some() {
+                    self.combined_pdf = single_pdf.then(PdfSettings::default);
+                }
+                if let Some(pdf) = &mut self.combined_pdf {
+                    egui::ComboBox::from_label("sorted by")
+                        .selected_text(match pdf.order {
+                            PageOrder::Scan => "scan order",
+                            PageOrder::Filename => "filename",
+                            PageOrder::Id => "student ID",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut pdf.order, PageOrder::Scan, "scan order");
+                            ui.selectable_value(&mut pdf.order, PageOrder::Filename, "filename");
+                            ui.selectable_value(&mut pdf.order, PageOrder::Id, "student ID");
+                        });
+                }
+            });
+
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("colours")
+                    .selected_text(
+                        ColourScheme::PRESETS
+                            .into_iter()
+                            .find(|name| ColourScheme::preset(name) == Some(self.colours))
+                            .unwrap_or("custom"),
+                    )
+                    .show_ui(ui, |ui| {
+                        for name in ColourScheme::PRESETS {
+                            if ui.selectable_label(false, name).clicked() {
+                                self.colours = ColourScheme::preset(name).unwrap();
+                            }
+                        }
+                    });
+                let colours = &mut self.colours;
+                for (colour, label) in [
+                    (&mut colours.correct_foreground, "correct"),
+                    (&mut colours.correct_background, ""),
+                    (&mut colours.wrong_foreground, "wrong"),
+                    (&mut colours.wrong_background, ""),
+                    (&mut colours.highlight_foreground, "attention"),
+                    (&mut colours.highlight_background, ""),
+                ] {
+                    ui.label(label);
+                    ui.color_edit_button_srgb(&mut colour.0);
+                }
+                ui.checkbox(&mut colours.cues, "ticks, crosses and hatching");
+                ui.checkbox(&mut self.banner, "summary banner on every image");
+            });
+
+            ui.horizontal(|ui| {
+                let versions = self.key.as_ref().map_or(0, |key| key.len()) as u32;
+                let label = |fallback: VersionFallback| match fallback {
+                    VersionFallback::Ungraded => "do not grade".to_string(),
+                    VersionFallback::Version(v) => format!("grade as v{}", v),
+                    VersionFallback::BestScore => "grade as the best version".to_string(),
+                };
+                egui::ComboBox::from_label("if the version is unclear")
+                    .selected_text(label(self.version_fallback))
+                    .show_ui(ui, |ui| {
+                        let options = [VersionFallback::Ungraded, VersionFallback::BestScore]
+                            .into_iter()
+                            .chain((0..versions).map(VersionFallback::Version));
+                        for option in options {
+                            ui.selectable_value(&mut self.version_fallback, option, label(option));
+                        }
+                    });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("File names:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.naming_pattern)
+                        .hint_text(NamingScheme::DEFAULT_PATTERN),
+                );
+                match self.naming() {
+                    Ok(_) => ui.label(
+                        "fields: {id} {name} {version} {score} {page} {issue} {status}, \
+                         / for folders",
+                    ),
+                    Err(e) => ui.colored_label(egui::Color32::RED, e.to_string()),
+                };
+            });
+
             if let Some(texture) = &self.preview_texture {
                 egui::ScrollArea::both().show(ui, |ui| {
                     ui.add(egui::Image::new(texture));
@@ -285,74 +556,155 @@ impl GenerateReport {
             "unnamed_container".to_string()
         };
 
-        if let Some(container_data) = self.raw_container_data.clone() {
-            let _ = async move {
-                let mut zip_buffer = Cursor::new(Vec::new());
-                let mut zip_writer = ZipWriter::new(&mut zip_buffer);
-                let mut csv_writer = csv::Writer::from_writer(std::io::Cursor::new(Vec::new()));
-                let header = template.to_csv_header();
-                let _ = csv_writer.write_record(&header);
-                log::info!("Output files are set up, starting to iterate over the input images!");
-
-                let mut container = raw_data_to_container(&container_data).unwrap();
-                let iterator = container.to_iter();
-
-                let mut turn = 0;
-                let chunksize = 20;
-
-                log::info!(
-                    "Working on images {}-{}",
-                    turn * chunksize,
-                    (turn + 1) * chunksize
-                );
-                *self.status.borrow_mut() =
-                    Some(format!("Working on the first {} scans", chunksize));
-                gloo_timers::future::TimeoutFuture::new(1).await;
-                for chunk in &iterator.chunks(chunksize) {
-                    let images: Vec<image::GrayImage> = chunk.collect();
-                    let results: Vec<ImageReport> = images
-                        .into_iter()
-                        .enumerate()
-                        .map(|(idx, img)| {
-                            log::info!("processing {}", turn * chunksize + idx);
-                            let scan = Scan { image: img };
-                            let template_scan = TemplateScan::new(&template, scan);
-                            template_scan.generate_image_report(
-                                &key,
-                                &format!("{}-page{}", prefix, idx + turn * chunksize + 1),
-                            )
-                        })
-                        .collect();
+        let settings = ReportSettings {
+            title: prefix.clone(),
+            grading: self.grading_scale().unwrap_or_default(),
+            feedback: self.feedback_mode,
+            combined_pdf: self.combined_pdf,
+            naming: self.naming().unwrap_or_default(),
+            colours: self.colours,
+            banner: self.banner,
+            version_fallback: self.version_fallback,
+            // the browser gives us a single thread
+            threads: 1,
+        };
 
-                    for r in &results {
-                        let _ = r.add_to_zip(&mut zip_writer, &mut csv_writer);
+        if let Some(container_data) = self.raw_container_data.clone() {
+            let mut sink = ZipSink::new(BlobBuffer::new(), &csv_file_name);
+            let result: Result<(), Error> = async {
+                match settings.combined_pdf {
+                    Some(pdf) => {
+                        let images = PdfSink::new(pdf, &settings.title);
+                        let run = GradingRun::new(&template, &key, &settings, images)?;
+                        self.grade_all(ctx, &container_data, &prefix, run, &mut sink)
+                            .await
                     }
+                    None => {
+                        let run = GradingRun::new(&template, &key, &settings, PngSink)?;
+                        self.grade_all(ctx, &container_data, &prefix, run, &mut sink)
+                            .await
+                    }
+                }
+            }
+            .await;
+
+            *self.progress.borrow_mut() = None;
+            match result
+                .and_then(|_| sink.finish())
+                .and_then(|buffer| buffer.into_blob("application/zip"))
+            {
+                Ok(blob) => {
+                    log::info!("The thing has been done!");
+                    *self.zipped_results.borrow_mut() = Some(blob);
+                }
+                Err(Error::Cancelled) => {
+                    *self.status.borrow_mut() = Some("Grading cancelled".to_string());
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    *self.status.borrow_mut() = Some(format!("Grading failed: {}", e));
+                }
+            }
+            ctx.request_repaint();
+        }
+    }
 
-                    if self.preview_image.borrow().is_none() {
-                        *self.preview_image.borrow_mut() = Some(results[0].image.clone());
+    // the pages are graded one by one, with a break after every chunk to
+    // update the status and the preview
+    async fn grade_all<I: ImageSink>(
+        &self,
+        ctx: &Context,
+        container_data: &[u8],
+        prefix: &str,
+        run: GradingRun<'_, I>,
+        sink: &mut dyn RecordSink,
+    ) -> Result<(), Error> {
+        let mut container = raw_data_to_container(container_data)
+            .ok_or_else(|| Error::Decode("unsupported file".to_string()))?;
+        let total = Some(container.page_count());
+        let iterator = container.to_iter();
+
+        let chunksize = 20;
+        *self.status.borrow_mut() = Some(format!("Working on the first {} scans", chunksize));
+        gloo_timers::future::TimeoutFuture::new(1).await;
+        for (turn, chunk) in (&iterator.chunks(chunksize)).into_iter().enumerate() {
+            for (idx, page) in chunk.enumerate() {
+                self.cancel.check()?;
+                log::info!("processing {}", turn * chunksize + idx);
+                let identifier = format!("{}-page{}", prefix, idx + turn * chunksize + 1);
+                let report = run.grade(&identifier, page);
+                if self.preview_image.borrow().is_none() {
+                    *self.preview_image.borrow_mut() = Some(report.image.clone());
+                }
+                run.add(run.encode(&report), sink)?;
+                *self.progress.borrow_mut() = Some(run.progress(total));
+            }
+
+            *self.status.borrow_mut() = Some(format!("processed {} scans", (turn + 1) * chunksize));
+            ctx.request_repaint();
+            gloo_timers::future::TimeoutFuture::new(1).await;
+        }
+
+        self.cancel.check()?;
+        *self.status.borrow_mut() = Some("writing feedback and reports".to_string());
+        ctx.request_repaint();
+        gloo_timers::future::TimeoutFuture::new(1).await;
+        run.finish(sink)
+    }
+
+    pub async fn generate_survey(&mut self, ctx: &Context) {
+        let template = self.template.clone().unwrap();
+        let prefix = if let Some(basename) = &self.output_file_basename {
+            basename.to_owned()
+        } else {
+            "unnamed_container".to_string()
+        };
+
+        if let Some(container_data) = self.raw_container_data.clone() {
+            let mut tally = SurveyTally::new(&template, self.survey_with_ids);
+            let mut container = raw_data_to_container(&container_data).unwrap();
+
+            for (idx, page) in container.to_iter().enumerate() {
+                log::info!("processing {}", idx);
+                let identifier = format!("{}-page{}", prefix, idx + 1);
+                match page.and_then(|image| TemplateScan::aligned(&template, Scan::new(image))) {
+                    Ok(template_scan) => {
+                        tally.add(SurveyResponse::from_scan(
+                            &template_scan,
+                            &identifier,
+                            self.survey_with_ids,
+                        ));
                     }
+                    Err(error) => {
+                        log::warn!("skipping {}: {}", identifier, error);
+                        tally.add_unreadable(&identifier, &error);
+                    }
+                }
 
-                    turn += 1;
-                    *self.status.borrow_mut() =
-                        Some(format!("processed {} scans", turn * chunksize));
+                if (idx + 1) % 20 == 0 {
+                    *self.status.borrow_mut() = Some(format!("processed {} scans", idx + 1));
                     ctx.request_repaint();
                     gloo_timers::future::TimeoutFuture::new(1).await;
                 }
+            }
 
-                let csv_data = csv_writer.into_inner().unwrap().into_inner();
-                let _ = zip_writer.start_file::<String, ()>(
-                    csv_file_name.to_string(),
-                    FileOptions::default().compression_method(zip::CompressionMethod::Deflated),
-                );
-                let _ = zip_writer.write_all(&csv_data);
-
-                let _ = zip_writer.finish();
-
-                log::info!("The thing has been done!");
+            if !tally.questions.is_empty() {
+                *self.preview_image.borrow_mut() = Some(tally.bar_chart(0));
+            }
 
-                *self.zipped_results.borrow_mut() = Some(zip_buffer.into_inner());
+            let mut zip_writer = ZipWriter::new(BlobBuffer::new());
+            let result = tally
+                .add_to_zip(&mut zip_writer, &format!("{}-", prefix))
+                .and_then(|_| zip_writer.finish().map_err(Error::from))
+                .and_then(|buffer| buffer.into_blob("application/zip"));
+            match result {
+                Ok(blob) => {
+                    log::info!("The survey has been tallied!");
+                    *self.zipped_results.borrow_mut() = Some(blob);
+                }
+                Err(e) => log::error!("could not write survey results: {}", e),
             }
-            .await;
+            ctx.request_repaint();
         }
     }
 }
@@ -400,7 +752,7 @@ impl StateView for GenerateReport {
                     if let Some(split) = split {
                         self.output_file_basename = Some(split.0.to_owned().replace(" ", ""));
                     }
-                    self.raw_container_data = Some(data);
+                    self.raw_container_data = Some(Rc::new(data));
                 }
                 _ => {}
             }
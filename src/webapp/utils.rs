@@ -5,7 +5,10 @@ use crate::template::Question;
 use crate::template::Template;
 use crate::webapp::create_template::{CircleSettings, LayoutSettings, PositionSettings};
 use std::future::Future;
+use std::rc::Rc;
 use tokio::sync::mpsc::Sender;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, HtmlAnchorElement, Url};
 
 pub enum FileType {
     Template,
@@ -64,9 +67,10 @@ pub fn download_button(
     ui: &mut egui::Ui,
     label: &str,
     default_save_name: impl Into<String>,
-    data: Vec<u8>,
+    data: impl Into<Rc<Vec<u8>>>,
 ) {
     if ui.button(label).clicked() {
+        let data = data.into();
         let task = rfd::AsyncFileDialog::new()
             .set_file_name(default_save_name)
             .save_file();
@@ -78,6 +82,34 @@ pub fn download_button(
         });
     }
 }
+
+/// Like `download_button`, for results that the browser keeps as a `Blob`.
+pub fn download_blob_button(ui: &mut egui::Ui, label: &str, default_save_name: &str, blob: &Blob) {
+    if ui.button(label).clicked() {
+        if let Err(e) = download_blob(default_save_name, blob) {
+            log::error!("could not download {}: {:?}", default_save_name, e);
+        }
+    }
+}
+
+// a link to the blob, clicked
+fn download_blob(name: &str, blob: &Blob) -> Result<(), JsValue> {
+    let url = Url::create_object_url_with_blob(blob)?;
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or(JsValue::NULL)?;
+    let link: HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    // the download has to start before the URL goes away
+    gloo_timers::callback::Timeout::new(60_000, move || {
+        let _ = Url::revoke_object_url(&url);
+    })
+    .forget();
+    Ok(())
+}
+
 pub fn template_from_settings(
     qss: &QuestionSettings,
     ls: &LayoutSettings,
@@ -107,12 +139,17 @@ pub fn template_from_settings(
 
     Template {
         id_questions: question_builder(first_idq, 10, w, h, pad_h, pad_v, idqs),
+        name_questions: Vec::new(),
         version: version,
         questions: question_builder(first_q, answers, w, h, pad_h, pad_v, qs),
+        numeric_questions: Vec::new(),
+        teacher_questions: Vec::new(),
+        option_labels: Vec::new(),
         circle_centers: cs.centers,
         circle_radius: cs.radius,
         height: ls.height,
         width: ls.width,
+        id_check_digit: None,
     }
 }
 
@@ -1,85 +1,76 @@
+use autograder::colours::ColourScheme;
+use autograder::feedback::{FeedbackEntry, FeedbackMode, FeedbackPdfs};
+use autograder::generate_reports_for_image_container;
+use autograder::image_container::{
+    DirectoryContainer, ImageContainer, PdfContainer, SingleImageContainer,
+};
 use autograder::image_helpers::binary_image_from_image;
-use autograder::report::ImageReport;
-use autograder::template::{are_compatible, CorrectAnswer, ExamKey, Question, Template};
-use autograder::typst_helpers::generate_form_and_template;
-use imageproc::drawing;
+use autograder::naming::NamingScheme;
+use autograder::pipeline::CancellationToken;
+use autograder::report::{ImageReport, Issue, ReportSettings};
+use autograder::report_pdf::{page_image, PageCompression, PageOrder, ReportPage, ReportPdf};
+use autograder::scan::Scan;
+use autograder::simulate::{fill_out, Noise, SheetAnswers, Simulation};
+use autograder::sink::MemorySink;
+use autograder::student_id::CheckDigit;
+use autograder::survey::{SurveyResponse, SurveyTally};
+use autograder::template::{
+    are_compatible, key_from_json, key_to_string, parse_key, CorrectAnswer, ExamKey, Question,
+    Template,
+};
+use autograder::template_scan::{TemplateScan, VersionFallback};
+use autograder::typst_helpers::{generate_form_and_template, parse_option_labels, FormSettings};
 use itertools::Itertools;
+use std::io::Read;
+use std::path::Path;
 
-const BLACK: image::Rgba<u8> = image::Rgba([0u8, 0u8, 0u8, 255u8]);
-fn box_to_center_and_radius(b: &autograder::template::Box) -> ((i32, i32), i32) {
-    let radius = (b.b.x - b.a.x) / 2;
-    let x = (b.b.x + b.a.x) / 2;
-    let y = (b.b.y + b.a.y) / 2;
-    ((x as i32, y as i32), radius as i32)
-}
+fn render_form(document: &typst::model::Document) -> image::DynamicImage {
+    let png_stream = typst_render::render(&document.pages[0], 3.0)
+        .encode_png()
+        .expect("typst to png error");
 
-fn check_box(image: &mut image::DynamicImage, b: &autograder::template::Box) {
-    let (center, radius) = box_to_center_and_radius(b);
-    drawing::draw_filled_circle_mut(image, center, radius, BLACK);
+    image::load_from_memory(&png_stream).expect("could not parse typst png into image crate")
 }
 
-fn fill_out(
-    image: &image::DynamicImage,
-    template: &Template,
-    id: u32,
-    version: u32,
-    choices: Vec<u32>,
-) -> image::DynamicImage {
-    let mut result = image.clone();
-    let id: Vec<u32> = id
-        .to_string()
-        .chars()
-        .map(|d| d.to_digit(10).unwrap())
-        .collect();
-
-    if let Some(vq) = &template.version {
-        // filling out the version
-        let thebox = vq.boxes.iter().find(|&b| b.value == version).unwrap();
-
-        check_box(&mut result, thebox);
+// a form with a single version and four options per question
+fn small_form(title: &str, num_qs: u32, num_id_qs: u32) -> FormSettings {
+    FormSettings {
+        title: title.into(),
+        num_qs,
+        num_id_qs,
+        num_versions: 1,
+        num_answers: 4,
+        ..Default::default()
     }
+}
 
-    // filling out the IDs
-    let id_qs: Vec<Question> = template
-        .id_questions
-        .iter()
-        .sorted_by(|a, b| {
-            let a_box = a.boxes.first().unwrap();
-            let b_box = b.boxes.first().unwrap();
-            a_box.a.x.cmp(&b_box.a.x).then(a_box.a.y.cmp(&b_box.a.y))
-        })
-        .cloned()
-        .collect();
-
-    for (idx, d) in id.iter().enumerate() {
-        let thebox = id_qs[idx].boxes.iter().find(|&b| b.value == *d).unwrap();
-        check_box(&mut result, thebox);
-    }
-
-    // filling out the questions
-    let qs: Vec<Question> = template
-        .questions
-        .iter()
-        .sorted_by(|a, b| {
-            let a_box = a.boxes.first().unwrap();
-            let b_box = b.boxes.first().unwrap();
-            a_box.a.y.cmp(&b_box.a.y).then(a_box.a.x.cmp(&b_box.a.x))
-        })
-        .cloned()
-        .collect();
-
-    for (idx, c) in choices.iter().enumerate() {
-        let thebox = qs[idx].boxes.iter().find(|&b| b.value == *c).unwrap();
-        check_box(&mut result, thebox);
-    }
+fn scan_sheet(template: &Template, filled_out: image::DynamicImage) -> TemplateScan<'_> {
+    TemplateScan::new(template, Scan::new(binary_image_from_image(filled_out)))
+}
 
-    result
+// fills out the rendered form with the answers and grades it
+fn grade_sheet(
+    template: &Template,
+    form_image: &image::DynamicImage,
+    key: &ExamKey,
+    answers: &SheetAnswers,
+) -> ImageReport {
+    scan_sheet(template, fill_out(form_image, template, answers))
+        .generate_image_report(key, &"".to_string())
 }
 
 #[test]
 fn generate_form_and_grade() {
     // we first create a form
-    let (document, template) = generate_form_and_template("Test Form", 5, 10, 4, 5, 3.0);
+    let settings = FormSettings {
+        title: "Test Form".into(),
+        num_qs: 5,
+        num_id_qs: 10,
+        num_versions: 4,
+        num_answers: 5,
+        ..Default::default()
+    };
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
 
     let _ =
         typst_pdf::pdf(&document, &typst_pdf::PdfOptions::default()).expect("typst to pdf error");
@@ -131,23 +122,20 @@ fn generate_form_and_grade() {
 
     // well filled out forms (student ID, version, answers, true score)
     let tests = [
-        (1234567890, 0, vec![0, 1, 2, 3, 4], 1),
-        (123456789, 1, vec![1, 1, 2, 2, 3], 2),
-        (999999, 2, vec![4, 1, 3, 2, 0], 1),
-        (1234554, 3, vec![0, 1, 1, 1, 1], 0),
-        (1234554, 3, vec![1, 4, 4, 4, 4], 1),
-        (1234554, 3, vec![2, 4, 4, 4, 4], 1),
+        ("1234567890", 0, vec![0, 1, 2, 3, 4], 1),
+        ("123456789", 1, vec![1, 1, 2, 2, 3], 2),
+        ("999999", 2, vec![4, 1, 3, 2, 0], 1),
+        ("1234554", 3, vec![0, 1, 1, 1, 1], 0),
+        ("1234554", 3, vec![1, 4, 4, 4, 4], 1),
+        ("1234554", 3, vec![2, 4, 4, 4, 4], 1),
+        ("0012345", 0, vec![0, 0, 0, 0, 0], 5),
     ];
     for test in tests {
-        let filled_out = fill_out(&form_image, &template, test.0, test.1, test.2);
-        let scan = autograder::scan::Scan {
-            image: binary_image_from_image(filled_out),
-        };
-        let template_scan = autograder::template_scan::TemplateScan::new(&template, scan);
-        let report = template_scan.generate_image_report(&key, &"".to_string());
-        assert_eq!(report.sid, Some(test.0));
+        let answers = SheetAnswers::new(test.0, test.1, &test.2);
+        let report = grade_sheet(&template, &form_image, &key, &answers);
+        assert_eq!(report.sid, Some(test.0.to_string()));
         assert_eq!(report.version, Some(test.1));
-        assert!(!report.issue);
+        assert!(!report.has_issues());
         assert_eq!(report.score(), test.3);
     }
 
@@ -155,16 +143,16 @@ fn generate_form_and_grade() {
     // unclear student IDs, version, answers.
     let tests = [
         [
-            (1234567890, 0, vec![0, 1, 2, 3, 4]),
-            (1234567891, 0, vec![0, 1, 2, 3, 4]),
+            ("1234567890", 0, vec![0, 1, 2, 3, 4]),
+            ("1234567891", 0, vec![0, 1, 2, 3, 4]),
         ],
         [
-            (123456789, 1, vec![1, 1, 2, 2, 3]),
-            (123456789, 3, vec![1, 1, 2, 2, 3]),
+            ("123456789", 1, vec![1, 1, 2, 2, 3]),
+            ("123456789", 3, vec![1, 1, 2, 2, 3]),
         ],
         [
-            (999999, 2, vec![4, 1, 3, 2, 0]),
-            (999999, 2, vec![4, 1, 3, 3, 0]),
+            ("999999", 2, vec![4, 1, 3, 2, 0]),
+            ("999999", 2, vec![4, 1, 3, 3, 0]),
         ],
     ];
 
@@ -174,17 +162,21 @@ fn generate_form_and_grade() {
             let fst = test[0].clone();
             let snd = test[1].clone();
 
-            let tmp = fill_out(&form_image, &template, fst.0, fst.1, fst.2);
-            let filled_out = fill_out(&tmp, &template, snd.0, snd.1, snd.2);
-            let scan = autograder::scan::Scan {
-                image: binary_image_from_image(filled_out),
-            };
-            let template_scan = autograder::template_scan::TemplateScan::new(&template, scan);
-            template_scan.generate_image_report(&key, &"".to_string())
+            let tmp = fill_out(
+                &form_image,
+                &template,
+                &SheetAnswers::new(fst.0, fst.1, &fst.2),
+            );
+            grade_sheet(
+                &template,
+                &tmp,
+                &key,
+                &SheetAnswers::new(snd.0, snd.1, &snd.2),
+            )
         })
         .collect();
 
-    assert!(reports[0].issue);
+    assert!(reports[0].has_issues());
     assert_eq!(reports[0].version, Some(0));
     assert_eq!(
         reports[0].scores,
@@ -194,13 +186,856 @@ fn generate_form_and_grade() {
 
     assert!(reports[1].version.is_none());
     assert_eq!(reports[1].scores, vec![None; 5]);
-    assert_eq!(reports[1].sid, Some(123456789));
+    assert_eq!(reports[1].sid, Some("123456789".to_string()));
 
-    assert!(reports[2].issue);
+    assert!(reports[2].has_issues());
     assert_eq!(reports[2].version, Some(2));
     assert_eq!(
         reports[2].scores,
         vec![Some(0), Some(0), Some(0), None, Some(0)]
     );
-    assert_eq!(reports[2].sid, Some(999999));
+    assert_eq!(reports[2].sid, Some("999999".to_string()));
+
+    // with a check digit scheme, IDs failing the test are flagged
+    let mut luhn_template = template.clone();
+    luhn_template.id_check_digit = Some(CheckDigit::Luhn);
+    for (id, valid) in [("12345674", true), ("12345675", false)] {
+        let answers = SheetAnswers::new(id, 0, &[0; 5]);
+        let report = grade_sheet(&luhn_template, &form_image, &key, &answers);
+        assert_eq!(report.sid, Some(id.to_string()));
+        assert_eq!(report.has_issues(), !valid);
+    }
+
+    // a sheet without a version can be graded as a given version or as the
+    // one with the best score, but stays flagged
+    let mut no_version = template.clone();
+    no_version.version = None;
+    let filled_out = fill_out(
+        &form_image,
+        &no_version,
+        &SheetAnswers::new("42", 0, &[2, 2, 2, 2, 3]),
+    );
+    let template_scan = scan_sheet(&template, filled_out);
+    assert_eq!(template_scan.version_scores(&key), vec![0, 0, 4, 2]);
+    for (fallback, version, score) in [
+        (VersionFallback::Ungraded, None, 0),
+        (VersionFallback::BestScore, Some(2), 4),
+        (VersionFallback::Version(3), Some(3), 2),
+    ] {
+        let report = template_scan.generate_image_report_with(
+            &key,
+            &"".to_string(),
+            &ColourScheme::default(),
+            fallback,
+        );
+        assert!(report.has_issues());
+        assert_eq!(report.version, version);
+        assert_eq!(report.score(), score);
+        assert_eq!(
+            report.issues,
+            vec![Issue::MissingVersion {
+                version_scores: vec![0, 0, 4, 2],
+                assumed: version,
+            }]
+        );
+    }
+    assert_eq!(
+        template_scan
+            .generate_image_report_with(
+                &key,
+                &"".to_string(),
+                &ColourScheme::default(),
+                VersionFallback::BestScore,
+            )
+            .issues[0]
+            .to_string(),
+        "no version marked, graded as v2 (v0: 0, v1: 0, v2: 4, v3: 2)"
+    );
+
+    // a fixed version never overrides a different marked one
+    let answers = SheetAnswers::new("42", 1, &[2, 2, 2, 2, 3]);
+    let filled_out = fill_out(&form_image, &template, &answers);
+    let report = scan_sheet(&template, filled_out).generate_image_report_with(
+        &key,
+        &"".to_string(),
+        &ColourScheme::default(),
+        VersionFallback::Version(2),
+    );
+    assert!(!report.has_issues(), "{:?}", report.issues);
+    assert_eq!(report.version, Some(1));
+    assert_eq!(report.score(), 0);
+
+    // with versions 1 and 3 marked, it only applies if it is one of them
+    let filled_out = fill_out(
+        &fill_out(&form_image, &template, &answers),
+        &template,
+        &SheetAnswers::new("42", 3, &[2, 2, 2, 2, 3]),
+    );
+    let template_scan = scan_sheet(&template, filled_out);
+    for (fallback, version, score) in [
+        (VersionFallback::Version(2), None, 0),
+        (VersionFallback::Version(3), Some(3), 2),
+        (VersionFallback::BestScore, Some(3), 2),
+    ] {
+        let report = template_scan.generate_image_report_with(
+            &key,
+            &"".to_string(),
+            &ColourScheme::default(),
+            fallback,
+        );
+        assert_eq!(report.version, version);
+        assert_eq!(report.score(), score);
+        assert_eq!(
+            report.issues,
+            vec![Issue::MultipleVersions {
+                version_scores: vec![0, 0, 4, 2],
+                assumed: version,
+            }]
+        );
+    }
+}
+
+#[test]
+fn name_grid() {
+    let settings = FormSettings {
+        num_name_cols: 12,
+        name_digits: true,
+        ..small_form("Name Grid", 5, 8)
+    };
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    assert_eq!(template.name_questions.len(), 12);
+    assert!(template.name_questions.iter().all(|q| q.boxes.len() == 36));
+    assert_eq!(template.to_csv_header()[2], "Name");
+
+    let form_image = render_form(&document);
+    let key: ExamKey = vec![vec![CorrectAnswer::Exactly(0); 5]];
+
+    for (name, read) in [("ADA LOVELACE", "ADA LOVELACE"), ("R2D2", "R2D2"), ("", "")] {
+        let answers = SheetAnswers {
+            name: name.to_string(),
+            ..SheetAnswers::new("1234", 0, &[0; 5])
+        };
+        let report = grade_sheet(&template, &form_image, &key, &answers);
+        assert_eq!(report.name, Some(read.to_string()));
+        assert_eq!(report.sid, Some("1234".to_string()));
+        assert_eq!(report.score(), 5);
+        assert_eq!(
+            report.to_serializable_vector(&NamingScheme::default())[2],
+            read
+        );
+    }
+
+    let error = autograder::Error::Decode("broken".into());
+    let report = ImageReport::unreadable(&template, "page4", &error);
+    assert!(report.has_issues());
+    let record = report.to_serializable_vector(&NamingScheme::default());
+    assert_eq!(record.len(), template.to_csv_header().len());
+    assert_eq!(
+        record.last().unwrap(),
+        "unreadable page: could not decode image: broken"
+    );
+}
+
+#[test]
+fn numeric_questions() {
+    let settings = FormSettings {
+        num_versions: 2,
+        num_numeric_qs: 3,
+        numeric_cols: 5,
+        ..small_form("Numeric", 3, 6)
+    };
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    assert_eq!(template.numeric_questions.len(), 3);
+    assert!(template
+        .numeric_questions
+        .iter()
+        .all(|nq| nq.columns.len() == 5));
+    let header = template.to_csv_header();
+    assert_eq!(header[header.len() - 3..], ["N003", "Answers", "Issues"]);
+
+    let numeric_key = vec![
+        CorrectAnswer::Value {
+            value: 42.0,
+            tolerance: 0.0,
+        },
+        CorrectAnswer::Value {
+            value: -1.25,
+            tolerance: 0.01,
+        },
+        CorrectAnswer::Range { min: 0.5, max: 1.0 },
+    ];
+    let mut version = vec![CorrectAnswer::Exactly(0); 3];
+    version.extend(numeric_key);
+    let key: ExamKey = vec![version.clone(), version];
+    assert!(are_compatible(&template, &key));
+    assert!(!are_compatible(
+        &template,
+        &vec![vec![CorrectAnswer::Exactly(0); 6]; 2]
+    ));
+
+    let form_image = render_form(&document);
+
+    // answers to the numeric questions, scores and whether there is an issue
+    let tests = [
+        (
+            ["  42", "-1.26", " .75"],
+            vec![Some(1), Some(1), Some(1)],
+            false,
+        ),
+        (
+            ["042", "-1.3", "1.5"],
+            vec![Some(1), Some(0), Some(0)],
+            false,
+        ),
+        (["4 2", "", "0.6"], vec![None, None, Some(1)], true),
+    ];
+    for (answers, scores, issue) in tests {
+        let answers = SheetAnswers {
+            numeric: answers.map(String::from).to_vec(),
+            ..SheetAnswers::new("123", 1, &[0; 3])
+        };
+        let report = grade_sheet(&template, &form_image, &key, &answers);
+        assert_eq!(report.scores[3..], scores);
+        assert_eq!(report.has_issues(), issue);
+    }
+}
+
+#[test]
+fn teacher_scores() {
+    let settings = FormSettings {
+        num_teacher_qs: 2,
+        teacher_max_points: 10,
+        ..small_form("Examiner", 4, 6)
+    };
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    assert_eq!(template.teacher_questions.len(), 2);
+    assert!(template
+        .teacher_questions
+        .iter()
+        .all(|q| q.boxes.iter().map(|b| b.value).eq(0..=10)));
+    let header = template.to_csv_header();
+    assert_eq!(header[header.len() - 3..], ["T002", "Answers", "Issues"]);
+    assert_eq!(template.max_score(), 4 + 2 * 10);
+
+    let key: ExamKey = vec![vec![CorrectAnswer::Exactly(1); 4]];
+    assert!(are_compatible(&template, &key));
+
+    let form_image = render_form(&document);
+
+    // points bubbled by the examiner, scores and whether there is an issue
+    let tests = [
+        (vec![vec![7], vec![0]], vec![Some(7), Some(0)], 9, false),
+        (vec![vec![10], vec![]], vec![Some(10), None], 12, true),
+        (vec![vec![3, 5], vec![4]], vec![None, Some(4)], 6, true),
+    ];
+    for (points, scores, total, issue) in tests {
+        let answers = SheetAnswers {
+            teacher: points,
+            ..SheetAnswers::new("42", 0, &[1, 1, 0, 0])
+        };
+        let report = grade_sheet(&template, &form_image, &key, &answers);
+        assert_eq!(report.scores[4..], scores);
+        assert_eq!(report.score(), total);
+        assert_eq!(report.has_issues(), issue);
+    }
+}
+
+#[test]
+fn simulated_fields() {
+    let base = FormSettings {
+        num_versions: 2,
+        ..small_form("Simulation", 6, 5)
+    };
+    // a name grid and numeric questions with examiner fields do not fit on
+    // one page together
+    let forms = [
+        FormSettings {
+            num_name_cols: 8,
+            name_digits: true,
+            ..base.clone()
+        },
+        FormSettings {
+            num_numeric_qs: 2,
+            numeric_cols: 5,
+            num_teacher_qs: 2,
+            teacher_max_points: 5,
+            ..base
+        },
+    ];
+    let noise = Noise {
+        blanks: 0.2,
+        multiple_marks: 0.2,
+        ..Default::default()
+    };
+    for settings in forms {
+        let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+        let mut version = vec![CorrectAnswer::Exactly(2); 6];
+        version.extend([
+            CorrectAnswer::Value {
+                value: -2.5,
+                tolerance: 0.0,
+            },
+            CorrectAnswer::Range { min: 1.0, max: 3.0 },
+        ]);
+        version.truncate(6 + template.numeric_questions.len());
+        let key: ExamKey = vec![version.clone(), version];
+        let form = render_form(&document);
+        let simulation = Simulation::new(&template, &key, &form, noise, 3).unwrap();
+
+        let answers: Vec<SheetAnswers> = (0..6).map(|index| simulation.answers(index)).collect();
+        assert!(answers.iter().all(|a| a.name.len() <= 8
+            && a.numeric.len() == template.numeric_questions.len()
+            && a.teacher.len() == template.teacher_questions.len()));
+        assert!(answers.iter().any(|a| a.needs_hand_grading(&template)));
+
+        let accuracy = simulation.run(6, 0, &mut |_| {}).unwrap();
+        let named = if template.name_questions.is_empty() {
+            0
+        } else {
+            6
+        };
+        assert_eq!((accuracy.named, accuracy.names), (named, named));
+        assert_eq!(
+            accuracy.questions,
+            6 * (6 + template.numeric_questions.len())
+        );
+        assert_eq!(accuracy.answers, accuracy.questions);
+        assert_eq!(accuracy.scores, 6);
+        assert!(accuracy.to_flag > 0);
+        assert_eq!(accuracy.flagged, accuracy.to_flag);
+        assert!(accuracy.misread.is_empty());
+    }
+}
+
+#[test]
+fn option_labels() {
+    let settings = FormSettings {
+        option_labels: parse_option_labels("1-2=T,F; 3=Yes,No,Maybe; 5=1,2,3,4,5", 5).unwrap(),
+        ..small_form("Labels", 5, 6)
+    };
+    assert!(parse_option_labels("0-2=T,F", 5).is_err());
+    assert!(parse_option_labels("1-6=T,F", 5).is_err());
+    assert!(parse_option_labels("T,,F", 5).is_err());
+
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    let num_options: Vec<usize> = template.questions.iter().map(|q| q.boxes.len()).collect();
+    assert_eq!(num_options, vec![2, 2, 3, 4, 5]);
+    assert_eq!(template.option_labels(2), vec!["Yes", "No", "Maybe"]);
+    assert_eq!(template.option_labels(3), vec!["A", "B", "C", "D"]);
+    let header = template.to_csv_header();
+    assert_eq!(header[header.len() - 2..], ["Answers", "Issues"]);
+
+    let labels = template.all_option_labels();
+    let key: ExamKey = vec![parse_key("TF{Maybe}(CD)5", &labels)];
+    assert!(are_compatible(&template, &key));
+    assert_eq!(key_to_string(&key[0], &labels), "TF{Maybe}(CD)5");
+
+    let form_image = render_form(&document);
+    let answers = SheetAnswers::new("42", 0, &[0, 0, 2, 3, 1]);
+    let report = grade_sheet(&template, &form_image, &key, &answers);
+    assert_eq!(report.answers.concat(), "TT{Maybe}D2");
+    assert_eq!(
+        report.scores,
+        vec![Some(1), Some(0), Some(1), Some(1), Some(0)]
+    );
+    let record = report.to_serializable_vector(&NamingScheme::default());
+    assert_eq!(record[record.len() - 2], "TT{Maybe}D2");
+}
+
+#[test]
+fn more_than_ten_options() {
+    let settings = FormSettings {
+        num_answers: 12,
+        ..small_form("Many Options", 3, 4)
+    };
+    let (_document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    assert!(template
+        .questions
+        .iter()
+        .all(|q| q.boxes.iter().map(|b| b.value).eq(0..12)));
+    assert_eq!(template.option_labels(0)[11], "L");
+    assert!(template.option_labels.is_empty());
+}
+
+#[test]
+fn survey() {
+    let settings = FormSettings {
+        option_labels: parse_option_labels("1-2=1,2,3,4,5", 3).unwrap(),
+        ..small_form("Course Evaluation", 3, 4)
+    };
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    let form_image = render_form(&document);
+
+    let mut tally = SurveyTally::new(&template, false);
+    for (idx, choices) in [vec![4, 0, 1], vec![3, 0, 1], vec![4, 2, 3]]
+        .into_iter()
+        .enumerate()
+    {
+        let filled_out = fill_out(&form_image, &template, &SheetAnswers::new("", 0, &choices));
+        let template_scan = scan_sheet(&template, filled_out);
+        tally.add(SurveyResponse::from_scan(
+            &template_scan,
+            &format!("page{}", idx + 1),
+            false,
+        ));
+    }
+
+    assert_eq!(tally.questions[0].counts, vec![0, 0, 0, 1, 2]);
+    assert!((tally.questions[0].mean.unwrap() - 14.0 / 3.0).abs() < 1e-9);
+    assert_eq!(tally.questions[1].counts, vec![2, 0, 1, 0, 0]);
+    assert_eq!(tally.questions[2].labels, vec!["A", "B", "C", "D"]);
+    assert_eq!(tally.questions[2].counts, vec![0, 2, 0, 1]);
+    assert!(tally.responses.iter().all(|r| r.sid.is_none()));
+    assert!(tally.responses_csv().unwrap().contains("page3,53D"));
+}
+
+#[test]
+fn feedback() {
+    let settings = FormSettings {
+        option_labels: parse_option_labels("3=Yes,No", 3).unwrap(),
+        ..small_form("Feedback", 3, 4)
+    };
+    let (document, template) = generate_form_and_template(&settings, 3.0).unwrap();
+    let key: ExamKey = vec![parse_key("A(BC){Yes}", &template.all_option_labels())];
+    let form_image = render_form(&document);
+
+    let entries: Vec<(FeedbackEntry, Vec<u8>)> = [("0042", vec![0, 2, 1]), ("0007", vec![1, 1, 0])]
+        .into_iter()
+        .map(|(id, choices)| {
+            let filled_out = fill_out(&form_image, &template, &SheetAnswers::new(id, 0, &choices));
+            let report = scan_sheet(&template, filled_out)
+                .generate_image_report(&key, &format!("page-{}", id));
+            let mut image = Vec::new();
+            report.write_to_buffer(&mut image);
+            (FeedbackEntry::new(&report, &template, &key), image)
+        })
+        .collect();
+
+    let rows: Vec<(&str, &str, &str, Option<u32>)> = entries[0]
+        .0
+        .rows
+        .iter()
+        .map(|r| {
+            (
+                r.question.as_str(),
+                r.response.as_str(),
+                r.correct.as_str(),
+                r.points,
+            )
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            ("Q1", "A", "A", Some(1)),
+            ("Q2", "C", "B or C", Some(1)),
+            ("Q3", "No", "Yes", Some(0)),
+        ]
+    );
+    assert_eq!(entries[1].0.score, 2);
+
+    let mut sink = MemorySink::default();
+    let mut per_student = FeedbackPdfs::new(FeedbackMode::PerStudent);
+    let mut merged = FeedbackPdfs::new(FeedbackMode::Merged);
+    for (entry, image) in entries {
+        per_student.add(entry.clone(), &image).unwrap();
+        merged.add(entry, &image).unwrap();
+    }
+    per_student.finish("Quiz 1", &mut sink).unwrap();
+    assert_eq!(sink.files.len(), 2);
+    assert_eq!(sink.files[0].0, "feedback-0042-page-0042.pdf");
+
+    merged.finish("Quiz 1", &mut sink).unwrap();
+    let data = sink.file("feedback.pdf").unwrap().to_vec();
+    assert!(data.starts_with(b"%PDF"));
+    // a page with the table and one with the sheet per student, sorted by ID
+    let pdf = pdf::file::FileOptions::cached().load(data.clone()).unwrap();
+    assert_eq!(pdf.num_pages(), 4);
+    let text = String::from_utf8_lossy(&data);
+    let bookmark = |sid: &str| {
+        let hex: String = sid.chars().map(|c| format!("{:04X}", c as u32)).collect();
+        text.find(&format!("/Title <FEFF{}", hex)).unwrap()
+    };
+    assert!(bookmark("0007") < bookmark("0042"));
+}
+
+#[test]
+fn combined_pdf() {
+    let (document, template) =
+        generate_form_and_template(&small_form("Combined", 3, 4), 3.0).unwrap();
+    let key: ExamKey = vec![vec![CorrectAnswer::Exactly(0); 3]];
+    let form_image = render_form(&document);
+
+    let filled_out = fill_out(
+        &form_image,
+        &template,
+        &SheetAnswers::new("0042", 0, &[0, 1, 0]),
+    );
+    let report =
+        scan_sheet(&template, filled_out).generate_image_report(&key, &"page1".to_string());
+    // the annotated binary scans are often smaller with Flate than as JPEG
+    let jpeg = page_image(&report, PageCompression::Jpeg { quality: 60 });
+    assert!(jpeg.starts_with(&[0xff, 0xd8]));
+    let flate = page_image(&report, PageCompression::Flate);
+    assert!(flate.starts_with(b"\x89PNG"));
+
+    for (compression, image) in [
+        (PageCompression::Jpeg { quality: 60 }, jpeg),
+        (PageCompression::Flate, flate),
+    ] {
+        let mut pdf = ReportPdf::new(compression);
+        for _ in 0..2 {
+            pdf.add(ReportPage::new(&report, &NamingScheme::default()), &image)
+                .unwrap();
+        }
+        let mut data = Vec::new();
+        pdf.finish(PageOrder::Id, "Combined")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        // the cover and both sheets
+        let pdf = pdf::file::FileOptions::cached().load(data).unwrap();
+        assert_eq!(pdf.num_pages(), 3);
+    }
+}
+
+// the number after `key` in the PDF, e.g. the object of `/Contents 15 0 R`
+fn pdf_number(pdf: &str, key: &str) -> usize {
+    let start = pdf.find(key).unwrap() + key.len();
+    pdf[start..]
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+// filled rectangles inside the bubbles, in points from the bottom left of a
+// page of the given height
+fn marks_in_points(boxes: &[&autograder::template::Box], scale: f32, height: f32) -> String {
+    boxes
+        .iter()
+        .map(|b| {
+            let (x, y) = (b.a.x as f32 / scale, height - b.b.y as f32 / scale);
+            let (w, h) = (
+                (b.b.x - b.a.x) as f32 / scale,
+                (b.b.y - b.a.y) as f32 / scale,
+            );
+            format!("{} {} {} {} re f\n", x, y, w, h)
+        })
+        .collect()
+}
+
+// Fills in a form the way PDF editors do, as an update appended to the file:
+// the page is stored turned on its side with `/Rotate 90` to show it upright,
+// its content moves into a form XObject that its matrix turns, and the marks
+// are the appearances of annotations. One of them has a state that picks the
+// right appearance, and a hidden one would mark `wrong`.
+fn fill_in_pdf(
+    pdf: &[u8],
+    marks: &[&autograder::template::Box],
+    version: &autograder::template::Box,
+    wrong: &autograder::template::Box,
+    scale: f32,
+) -> Vec<u8> {
+    // one character for every byte, so that offsets in the text are offsets
+    // in the file
+    let text: String = pdf
+        .iter()
+        .map(|&b| if b.is_ascii() { b as char } else { '?' })
+        .collect();
+    let page_start = text.find("/Type /Page\n").unwrap();
+    // the object starts after the last empty line
+    let page_id = pdf_number(text[..page_start].rsplit("\n\n").next().unwrap(), "");
+    let page = &text[page_start..];
+    let parent = pdf_number(page, "/Parent");
+    let resources = pdf_number(page, "/Resources");
+    let contents = pdf_number(page, "/Contents");
+    let size = pdf_number(&text, "/Size");
+    let root = pdf_number(&text, "/Root");
+    let previous = pdf_number(&text, "startxref");
+
+    // the raw content stream, still compressed
+    let start = text.find(&format!("\n{} 0 obj\n", contents)).unwrap();
+    let data_start = start + text[start..].find("stream\n").unwrap() + "stream\n".len();
+    let data_end = data_start + text[data_start..].find("\nendstream").unwrap();
+    let data = &pdf[data_start..data_end];
+
+    let (w, h) = (595.2756, 841.8898);
+    let turn = format!("[0 1 -1 0 {} 0]", h);
+    let on_side = |b: &autograder::template::Box| {
+        format!(
+            "[{} {} {} {}]",
+            b.a.y as f32 / scale,
+            b.a.x as f32 / scale,
+            b.b.y as f32 / scale,
+            b.b.x as f32 / scale
+        )
+    };
+    let stream = |dict: String, data: &[u8]| {
+        let mut object = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        object.extend_from_slice(data);
+        object.extend_from_slice(b"\nendstream");
+        object
+    };
+    let marks = marks_in_points(marks, scale, h);
+    let wrong = marks_in_points(&[wrong], scale, h);
+    let objects: Vec<(usize, Vec<u8>)> = vec![
+        (
+            page_id,
+            format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Rotate 90 \
+                 /Contents {} 0 R /Resources << /XObject << /Fm0 {} 0 R >> >> \
+                 /Annots [{} 0 R {} 0 R {} 0 R] >>",
+                parent,
+                h,
+                w,
+                size + 1,
+                size,
+                size + 2,
+                size + 3,
+                size + 4
+            )
+            .into_bytes(),
+        ),
+        (
+            size,
+            stream(
+                format!(
+                    "/Type /XObject /Subtype /Form /BBox [0 0 {} {}] /Matrix {} \
+                     /Resources {} 0 R /Filter /FlateDecode",
+                    w, h, turn, resources
+                ),
+                data,
+            ),
+        ),
+        (size + 1, stream(String::new(), b"/Fm0 Do")),
+        // the answers, on the whole page
+        (
+            size + 2,
+            format!(
+                "<< /Type /Annot /Subtype /Widget /Rect [0 0 {} {}] /F 4 /AP << /N {} 0 R >> >>",
+                h,
+                w,
+                size + 5
+            )
+            .into_bytes(),
+        ),
+        // a check box on the version, whose appearance is stretched to it
+        (
+            size + 3,
+            format!(
+                "<< /Type /Annot /Subtype /Widget /Rect {} /AS /Yes \
+                 /AP << /N << /Yes {} 0 R /Off {} 0 R >> >> >>",
+                on_side(version),
+                size + 6,
+                size + 7
+            )
+            .into_bytes(),
+        ),
+        (
+            size + 4,
+            format!(
+                "<< /Type /Annot /Subtype /Widget /Rect [0 0 {} {}] /F 2 /AP << /N {} 0 R >> >>",
+                h,
+                w,
+                size + 7
+            )
+            .into_bytes(),
+        ),
+        (
+            size + 5,
+            stream(
+                format!(
+                    "/Type /XObject /Subtype /Form /BBox [0 0 {} {}] /Matrix {}",
+                    w, h, turn
+                ),
+                marks.as_bytes(),
+            ),
+        ),
+        (
+            size + 6,
+            stream(
+                "/Type /XObject /Subtype /Form /BBox [0 0 1 1]".into(),
+                b"0 0 1 1 re f",
+            ),
+        ),
+        (
+            size + 7,
+            stream(
+                format!(
+                    "/Type /XObject /Subtype /Form /BBox [0 0 {} {}] /Matrix {}",
+                    w, h, turn
+                ),
+                wrong.as_bytes(),
+            ),
+        ),
+    ];
+
+    let mut result = pdf.to_vec();
+    let mut offsets = Vec::new();
+    for (id, object) in &objects {
+        result.push(b'\n');
+        offsets.push((*id, result.len()));
+        result.extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+        result.extend_from_slice(object);
+        result.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = result.len();
+    let entry = |offset: usize| format!("{:010} 00000 n \n", offset);
+    let mut table = format!("xref\n{} 1\n{}", page_id, entry(offsets[0].1));
+    table += &format!("{} {}\n", size, objects.len() - 1);
+    for (_, offset) in &offsets[1..] {
+        table += &entry(*offset);
+    }
+    table += &format!(
+        "trailer\n<< /Size {} /Root {} 0 R /Prev {} >>\nstartxref\n{}\n%%EOF\n",
+        size + objects.len() - 1,
+        root,
+        previous,
+        xref
+    );
+    result.extend_from_slice(table.as_bytes());
+    result
+}
+
+#[test]
+fn filled_in_pdf_form() {
+    let settings = FormSettings {
+        num_versions: 2,
+        ..small_form("Filled in", 3, 4)
+    };
+    let scale = 3.0;
+    let (document, template) = generate_form_and_template(&settings, scale).unwrap();
+    let pdf = autograder::typst_helpers::document_to_pdf(&document).unwrap();
+    let key: ExamKey = vec![
+        vec![CorrectAnswer::Exactly(3); 3],
+        vec![
+            CorrectAnswer::Exactly(0),
+            CorrectAnswer::Exactly(1),
+            CorrectAnswer::Exactly(2),
+        ],
+    ];
+
+    fn find(question: &Question, value: u32) -> &autograder::template::Box {
+        question.boxes.iter().find(|b| b.value == value).unwrap()
+    }
+    let id_questions: Vec<&Question> = template
+        .id_questions
+        .iter()
+        .sorted_by_key(|q| q.boxes[0].a.x)
+        .collect();
+    let mut marks: Vec<&autograder::template::Box> = "0042"
+        .chars()
+        .zip(id_questions)
+        .map(|(digit, question)| find(question, digit.to_digit(10).unwrap()))
+        .collect();
+    let questions: Vec<&Question> = template
+        .questions
+        .iter()
+        .sorted_by_key(|q| (q.boxes[0].a.y, q.boxes[0].a.x))
+        .collect();
+    marks.extend([0, 1, 3].iter().zip(&questions).map(|(&c, q)| find(q, c)));
+    let version = find(template.version.as_ref().unwrap(), 1);
+    let wrong = find(questions[0], 2);
+
+    let filled_in = fill_in_pdf(&pdf, &marks, version, wrong, scale as f32);
+    let file = pdf::file::FileOptions::cached().load(filled_in).unwrap();
+    let mut container = PdfContainer { pdf_file: file };
+    let page = container.get_page(0).unwrap();
+    // rendered upright at 200 dpi
+    assert_eq!(page.dimensions(), (1654, 2339));
+
+    let template_scan = TemplateScan::aligned(&template, Scan::new(page)).unwrap();
+    let report = template_scan.generate_image_report(&key, &"page1".to_string());
+    assert_eq!(report.sid, Some("0042".to_string()));
+    assert_eq!(report.version, Some(1));
+    assert!(!report.has_issues(), "{:?}", report.issues);
+    assert_eq!(report.score(), 2);
+}
+
+#[test]
+fn grade_into_memory() {
+    let template =
+        Template::from_json(std::fs::File::open("tests/assets/template.json").unwrap()).unwrap();
+    let key = key_from_json(std::fs::File::open("tests/assets/key.json").unwrap()).unwrap();
+    let mut container = SingleImageContainer {
+        image: image::open("tests/assets/scanner-jpg-0002.jpg").unwrap(),
+    };
+    let settings = ReportSettings::default();
+
+    let mut sink = MemorySink::default();
+    let mut progress = Vec::new();
+    generate_reports_for_image_container(
+        &mut container,
+        &template,
+        &key,
+        &settings,
+        &mut sink,
+        &mut |p| progress.push(p),
+        &CancellationToken::default(),
+    )
+    .unwrap();
+    assert_eq!(progress.len(), 1);
+    assert_eq!((progress[0].done, progress[0].total), (1, Some(1)));
+
+    assert_eq!(sink.header, template.to_csv_header());
+    assert_eq!(sink.records.len(), 1);
+    let image = sink.file(&sink.records[0][0]).unwrap();
+    assert!(image.starts_with(b"\x89PNG"));
+    assert!(sink.file("issues.json").is_some());
+
+    let cancel = CancellationToken::default();
+    cancel.cancel();
+    let mut sink = MemorySink::default();
+    let result = generate_reports_for_image_container(
+        &mut container,
+        &template,
+        &key,
+        &settings,
+        &mut sink,
+        &mut |_| {},
+        &cancel,
+    );
+    assert!(matches!(result, Err(autograder::Error::Cancelled)));
+    assert!(sink.files.is_empty());
+}
+
+#[test]
+fn grade_in_parallel() {
+    let template =
+        Template::from_json(std::fs::File::open("tests/assets/template.json").unwrap()).unwrap();
+    let key = key_from_json(std::fs::File::open("tests/assets/key.json").unwrap()).unwrap();
+
+    // the sheets end up in page order however many workers there are
+    let results: Vec<MemorySink> = [1, 4]
+        .into_iter()
+        .map(|threads| {
+            let mut container =
+                DirectoryContainer::open(Path::new("tests/assets/scanner-jpg-*.jpg")).unwrap();
+            let settings = ReportSettings {
+                threads,
+                ..Default::default()
+            };
+            let mut sink = MemorySink::default();
+            let mut done = Vec::new();
+            generate_reports_for_image_container(
+                &mut container,
+                &template,
+                &key,
+                &settings,
+                &mut sink,
+                &mut |p| done.push(p.done),
+                &CancellationToken::default(),
+            )
+            .unwrap();
+            assert_eq!(done, vec![1, 2, 3, 4]);
+            sink
+        })
+        .collect();
+
+    assert_eq!(results[0].records, results[1].records);
+    for (idx, record) in results[1].records.iter().enumerate() {
+        assert!(record[0].ends_with(&format!("page{}.png", idx + 1)));
+    }
 }